@@ -2,40 +2,41 @@
 extern crate criterion;
 
 use criterion::Criterion;
-use rsstable::sst::SSTable;
-use simple_logger;
+use rsstable::sst::{FileDisktableBuilder, SSTable};
 fn test_sstable_performance(c: &mut Criterion) {
-    let mut sst = SSTable::new("./test_bench", 1);
+    let mut sst = SSTable::new("./test_bench", 1).expect("failed to open");
     simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Info)
         .init()
         .unwrap();
-    sst.clear();
+    sst.clear().expect("failed to clear");
     // prepare
     let disk_key = "1";
     let mem_key = "999";
-    sst.insert(disk_key, disk_key);
-    sst.insert("hoge", "hoge");
-    sst.insert(mem_key, mem_key);
+    sst.insert(disk_key, disk_key).expect("failed to insert");
+    sst.insert("hoge", "hoge").expect("failed to insert");
+    sst.insert(mem_key, mem_key).expect("failed to insert");
 
     c.bench_function("sstable get from memtable", |b| {
         b.iter(|| {
             sst.get(mem_key)
-                .expect(&format!("failed to get value by key {}", mem_key));
+                .expect("failed to get")
+                .unwrap_or_else(|| panic!("failed to get value by key {}", mem_key));
         })
     });
 
     c.bench_function("sstable get from disktable", |b| {
         b.iter(|| {
             sst.get(disk_key)
-                .expect(&format!("failed to get value by key {}", disk_key));
+                .expect("failed to get")
+                .unwrap_or_else(|| panic!("failed to get value by key {}", disk_key));
         })
     });
 
     c.bench_function("sstable insert mem", |b| {
         b.iter(|| {
             sst.insert(mem_key, mem_key)
-                .expect(&format!("failed to insert value by key {}", mem_key));
+                .unwrap_or_else(|_| panic!("failed to insert value by key {}", mem_key));
         })
     });
 
@@ -44,15 +45,82 @@ fn test_sstable_performance(c: &mut Criterion) {
         b.iter(|| {
             if disk {
                 sst.insert(disk_key, disk_key)
-                    .expect(&format!("failed to insert value by key {}", disk_key));
+                    .unwrap_or_else(|_| panic!("failed to insert value by key {}", disk_key));
             } else {
                 sst.insert(mem_key, mem_key)
-                    .expect(&format!("failed to insert value by key {}", mem_key));
+                    .unwrap_or_else(|_| panic!("failed to insert value by key {}", mem_key));
             }
             disk = !disk;
         })
     });
 }
 
-criterion_group!(benches, test_sstable_performance);
+// lookups of keys spread over several generations, through buffered reads of index files
+// and, with the mmap feature, through memory mapped index files
+fn test_index_lookup_performance(c: &mut Criterion) {
+    let keys: Vec<_> = (0..10_000).map(|i| format!("key-{:05}", i)).collect();
+    let prepare = |builder: FileDisktableBuilder| {
+        let mut sst = SSTable::with_disktable(builder, 1_000).expect("failed to open");
+        sst.clear().expect("failed to clear");
+        keys.iter()
+            .for_each(|key| sst.insert(key, key).expect("failed to insert"));
+        sst
+    };
+    let lookup = |sst: &SSTable| {
+        keys.iter().step_by(97).for_each(|key| {
+            sst.get(key)
+                .expect("failed to get")
+                .unwrap_or_else(|| panic!("failed to get value by key {}", key));
+        })
+    };
+
+    let buffered = prepare(FileDisktableBuilder::new("./test_bench_buffered"));
+    c.bench_function("sstable get through buffered index", |b| {
+        b.iter(|| lookup(&buffered))
+    });
+
+    #[cfg(feature = "mmap")]
+    {
+        let mapped = prepare(FileDisktableBuilder::new("./test_bench_mmap").mmap_indexes(true));
+        c.bench_function("sstable get through mmap index", |b| {
+            b.iter(|| lookup(&mapped))
+        });
+    }
+}
+
+// a full scan merging several generations, reading data files by records and by
+// read ahead buffers of increasing sizes
+fn test_scan_read_ahead_performance(c: &mut Criterion) {
+    let dir = "./test_bench_scan_read_ahead";
+    let mut sst = SSTable::new(dir, 2_000).expect("failed to open");
+    sst.clear().expect("failed to clear");
+    (0..10_000).for_each(|i| {
+        sst.insert(format!("key-{:05}", (i * 7919) % 10_000), "v".repeat(100))
+            .expect("failed to insert")
+    });
+    drop(sst);
+
+    for read_ahead in [None, Some(4 << 10), Some(64 << 10), Some(1 << 20)] {
+        let builder = FileDisktableBuilder::new(dir);
+        let builder = match read_ahead {
+            Some(bytes) => builder.scan_read_ahead(bytes),
+            None => builder,
+        };
+        let sst = SSTable::with_disktable(builder, 2_000).expect("failed to open");
+        let name = match read_ahead {
+            Some(bytes) => format!("sstable full scan with read ahead of {} bytes", bytes),
+            None => "sstable full scan without read ahead".to_string(),
+        };
+        c.bench_function(&name, |b| {
+            b.iter(|| assert_eq!(sst.scan("", None).expect("failed to scan").len(), 10_000))
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    test_sstable_performance,
+    test_index_lookup_performance,
+    test_scan_read_ahead_performance
+);
 criterion_main!(benches);
@@ -1 +1,9 @@
+//! The memtable, its trait and `BTreeMemtable` of entries in memory only, builds without
+//! `std` on `alloc` with the default `std` feature disabled, while `sst`, which stores it
+//! on files with a WAL, needs `std`.
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+extern crate alloc;
+
+pub mod memtable;
+#[cfg(feature = "std")]
 pub mod sst;
@@ -1,8 +1,7 @@
-mod sst;
-use sst::SSTable;
+use rsstable::sst::SSTable;
 
 fn main() {
-    let mut sst = SSTable::new("./tmp", 3);
+    let mut sst = SSTable::new("./tmp", 3).expect("failed to open");
     // sst.clear().expect("failed to clear");
     (1..10).for_each(|i| {
         log::info!("i: {} =====", i);
@@ -13,5 +12,4 @@ fn main() {
         log::info!("get({}): {:?}", i, sst.get(key()));
         log::info!("get({}): {:?}", i + 4, sst.get(format!("key-{}", i + 4)));
     });
-    ()
 }
@@ -0,0 +1,342 @@
+//! Memtables and their in-memory entries and tombstones, which build without `std` on
+//! `alloc`. The memtables of `sst`, which write a WAL, keep their entries in a
+//! `BTreeMemtable`.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::ToString,
+    vec::Vec,
+};
+use core::ops::Bound;
+
+pub trait Memtable {
+    type Key;
+    type Value;
+    fn get(&self, key: &Self::Key) -> GetResult<&Self::Value>;
+    fn set(
+        &mut self,
+        key: Self::Key,
+        value: Self::Value,
+    ) -> MemtableOnFlush<Self::Key, Self::Value>;
+    fn delete(&mut self, key: Self::Key);
+    /// Write the value under `to` and a tombstone of `from` as a single write, which a WAL
+    /// keeps or loses whole. It's flushed like `set` when it's full.
+    fn rename(
+        &mut self,
+        from: Self::Key,
+        to: Self::Key,
+        value: Self::Value,
+    ) -> MemtableOnFlush<Self::Key, Self::Value>;
+    /// `delete` returning whether the memtable held a value for the key.
+    fn delete_returning(&mut self, key: Self::Key) -> bool;
+    /// Drop entries and tombstones of every key starting with the prefix.
+    fn delete_prefix(&mut self, prefix: &str);
+    fn clear(&mut self);
+    /// Hand over every entry and tombstone as a full memtable does on `set`, whatever its
+    /// threshold, leaving it empty and ready for writes.
+    fn freeze(&mut self) -> MemtableEntries<Self::Key, Self::Value>;
+    /// Entries and tombstones(as None) in [start, end) in key order.
+    fn range(
+        &self,
+        start: &Self::Key,
+        end: Option<&Self::Key>,
+    ) -> Vec<(Self::Key, Option<Self::Value>)>;
+    /// Keys deleted in the memtable in key order, which shadow their values on disk.
+    fn tombstones(&self) -> Box<dyn Iterator<Item = &Self::Key> + '_>;
+    /// Keys of the entries and then of the tombstones, each in key order.
+    fn keys(&self) -> Box<dyn Iterator<Item = &Self::Key> + '_>;
+}
+
+/// Entries a write has handed over to be flushed, if it filled the memtable.
+pub struct MemtableOnFlush<Key, Value> {
+    flushed: Option<MemtableEntries<Key, Value>>,
+}
+
+impl<Key, Value> MemtableOnFlush<Key, Value> {
+    pub fn is_flushed(&self) -> bool {
+        self.flushed.is_some()
+    }
+
+    pub fn on_flush<E>(
+        self,
+        f: impl FnOnce(MemtableEntries<Key, Value>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self.flushed {
+            Some(flushed) => f(flushed),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Memtable of entries and tombstones in memory only, which a set filling it up to
+/// `max_entry` entries hands over to be flushed.
+pub struct BTreeMemtable<K, V> {
+    max_entry: usize,
+    entries: MemtableEntries<K, V>,
+}
+
+impl<K: Ord + Clone, V: Clone> BTreeMemtable<K, V> {
+    /// A memtable flushed by the set filling it, so that a `max_entry` of 1 flushes on
+    /// every set. Panics for a `max_entry` of 0.
+    pub fn new(max_entry: usize) -> BTreeMemtable<K, V> {
+        Self::with_entries(max_entry, MemtableEntries::new())
+    }
+
+    /// `new` holding the entries and tombstones, e.g. restored from a WAL.
+    pub fn with_entries(max_entry: usize, entries: MemtableEntries<K, V>) -> BTreeMemtable<K, V> {
+        Self::check_max_entry(max_entry);
+        BTreeMemtable { max_entry, entries }
+    }
+
+    /// Have sets from now on flush once the memtable holds `max_entry` entries.
+    pub fn set_max_entry(&mut self, max_entry: usize) {
+        Self::check_max_entry(max_entry);
+        self.max_entry = max_entry;
+    }
+
+    pub fn entries(&self) -> &MemtableEntries<K, V> {
+        &self.entries
+    }
+
+    // a memtable of no entries would be flushed into a generation of an entry per set
+    fn check_max_entry(max_entry: usize) {
+        assert!(max_entry > 0, "max_entry must be at least 1");
+    }
+
+    fn flush_if_full(&mut self) -> MemtableOnFlush<K, V> {
+        if self.entries.entries.len() >= self.max_entry {
+            MemtableOnFlush {
+                flushed: Some(core::mem::take(&mut self.entries)),
+            }
+        } else {
+            MemtableOnFlush { flushed: None }
+        }
+    }
+}
+
+impl<K: Ord + Clone + ToString, V: Clone> Memtable for BTreeMemtable<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> GetResult<&V> {
+        self.entries.get(key)
+    }
+    fn set(&mut self, key: K, value: V) -> MemtableOnFlush<K, V> {
+        self.entries.set(key, value);
+        self.flush_if_full()
+    }
+    fn delete(&mut self, key: K) {
+        self.entries.delete(key);
+    }
+    fn rename(&mut self, from: K, to: K, value: V) -> MemtableOnFlush<K, V> {
+        self.entries.delete(from);
+        self.set(to, value)
+    }
+    fn delete_returning(&mut self, key: K) -> bool {
+        let existed = self.entries.entries.contains_key(&key);
+        self.entries.delete(key);
+        existed
+    }
+    fn delete_prefix(&mut self, prefix: &str) {
+        self.entries.delete_prefix(prefix);
+    }
+    fn clear(&mut self) {
+        self.entries = MemtableEntries::new();
+    }
+    fn freeze(&mut self) -> MemtableEntries<K, V> {
+        core::mem::take(&mut self.entries)
+    }
+    fn range(&self, start: &K, end: Option<&K>) -> Vec<(K, Option<V>)> {
+        self.entries.range(start, end)
+    }
+    fn tombstones(&self) -> Box<dyn Iterator<Item = &K> + '_> {
+        Box::new(self.entries.tombstones.iter())
+    }
+    fn keys(&self) -> Box<dyn Iterator<Item = &K> + '_> {
+        Box::new(self.entries.keys())
+    }
+}
+
+/// What a memtable knows of a key. `Deleted` shadows a value on disk, while `NotFound`
+/// leaves the key to be looked up there.
+pub enum GetResult<T> {
+    Found(T),
+    Deleted,
+    NotFound,
+}
+
+/// Values and tombstones by key, where a key lives in either of them at most.
+pub struct MemtableEntries<Key, Value> {
+    pub entries: BTreeMap<Key, Value>,
+    pub tombstones: BTreeSet<Key>,
+}
+
+// entries and tombstones in [start, end) merged in key order
+pub(crate) fn range_of<K: Ord + Clone, V: Clone>(
+    entries: &BTreeMap<K, V>,
+    tombstones: &BTreeSet<K>,
+    start: &K,
+    end: Option<&K>,
+) -> Vec<(K, Option<V>)> {
+    let bounds = (
+        Bound::Included(start),
+        end.map_or(Bound::Unbounded, Bound::Excluded),
+    );
+    let mut merged: BTreeMap<K, Option<V>> = entries
+        .range(bounds)
+        .map(|(k, v)| (k.clone(), Some(v.clone())))
+        .collect();
+    tombstones.range(bounds).for_each(|k| {
+        merged.insert(k.clone(), None);
+    });
+    merged.into_iter().collect()
+}
+
+// drop entries and tombstones of every key starting with the prefix
+pub(crate) fn delete_prefix_of<K: Ord + ToString, V>(
+    entries: &mut BTreeMap<K, V>,
+    tombstones: &mut BTreeSet<K>,
+    prefix: &str,
+) {
+    entries.retain(|key, _| !key.to_string().starts_with(prefix));
+    tombstones.retain(|key| !key.to_string().starts_with(prefix));
+}
+
+impl<K: Ord + Clone, V: Clone> MemtableEntries<K, V> {
+    pub fn new() -> MemtableEntries<K, V> {
+        MemtableEntries {
+            entries: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: K, value: V) {
+        self.tombstones.remove(&key);
+        self.entries.insert(key, value);
+    }
+
+    /// Drop the value of the key if any and leave a tombstone of it.
+    pub fn delete(&mut self, key: K) {
+        self.entries.remove(&key);
+        self.tombstones.insert(key);
+    }
+
+    /// Entries and tombstones(as None) in [start, end) in key order.
+    pub fn range(&self, start: &K, end: Option<&K>) -> Vec<(K, Option<V>)> {
+        range_of(&self.entries, &self.tombstones, start, end)
+    }
+
+    /// Keys of the entries and then of the tombstones, each in key order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys().chain(self.tombstones.iter())
+    }
+
+    pub fn get(&self, key: &K) -> GetResult<&V> {
+        if !self.tombstones.contains(key) {
+            self.entries
+                .get(key)
+                .map(GetResult::Found)
+                .unwrap_or(GetResult::NotFound)
+        } else {
+            GetResult::Deleted
+        }
+    }
+
+    /// Drop entries and tombstones of every key starting with the prefix.
+    pub fn delete_prefix(&mut self, prefix: &str)
+    where
+        K: ToString,
+    {
+        delete_prefix_of(&mut self.entries, &mut self.tombstones, prefix);
+    }
+
+    /// Entries and tombstones together.
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.tombstones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.tombstones.is_empty()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Default for MemtableEntries<K, V> {
+    fn default() -> MemtableEntries<K, V> {
+        MemtableEntries::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BTreeMemtable, GetResult, Memtable, MemtableEntries};
+    use alloc::{string::String, vec, vec::Vec};
+
+    #[test]
+    fn test_btree_memtable() {
+        let mut memtable: BTreeMemtable<String, String> = BTreeMemtable::new(3);
+        assert!(!memtable.set("a".into(), "1".into()).is_flushed());
+        assert!(memtable.delete_returning("a".into()));
+        assert!(!memtable.delete_returning("b".into()));
+        assert!(!memtable
+            .rename("c".into(), "d".into(), "1".into())
+            .is_flushed());
+        assert!(matches!(memtable.get(&"c".into()), GetResult::Deleted));
+        assert_eq!(
+            memtable.keys().cloned().collect::<Vec<String>>(),
+            vec!["d", "a", "b", "c"]
+        );
+
+        // tombstones don't fill it, the set of its third entry does
+        memtable.set("e".into(), "1".into());
+        let mut flushed = None;
+        memtable
+            .set("f".into(), "1".into())
+            .on_flush(|entries| -> Result<(), ()> {
+                flushed = Some(entries);
+                Ok(())
+            })
+            .unwrap();
+        let flushed = flushed.unwrap();
+        assert_eq!(flushed.entries.len(), 3);
+        assert_eq!(flushed.tombstones.len(), 3);
+        assert!(memtable.range(&String::new(), None).is_empty());
+
+        memtable.set_max_entry(1);
+        assert!(memtable.set("g".into(), "1".into()).is_flushed());
+        memtable.delete("h".into());
+        assert_eq!(memtable.freeze().len(), 1);
+        assert!(matches!(memtable.get(&"h".into()), GetResult::NotFound));
+    }
+
+    #[test]
+    fn test_memtable_entries() {
+        let mut entries: MemtableEntries<String, String> = MemtableEntries::new();
+        assert!(entries.is_empty());
+        entries.set("a".into(), "1".into());
+        entries.set("b".into(), "1".into());
+        entries.delete("b".into());
+        entries.delete("c".into());
+        entries.set("c".into(), "2".into());
+        entries.set("pre-1".into(), "1".into());
+        entries.delete("pre-2".into());
+        assert_eq!(entries.len(), 5);
+        assert!(matches!(entries.get(&"a".into()), GetResult::Found(value) if value == "1"));
+        assert!(matches!(entries.get(&"b".into()), GetResult::Deleted));
+        assert!(matches!(entries.get(&"d".into()), GetResult::NotFound));
+        assert_eq!(
+            entries.range(&"b".into(), Some(&"pre".into())),
+            vec![("b".into(), None), ("c".into(), Some("2".into()))]
+        );
+
+        entries.delete_prefix("pre");
+        assert_eq!(
+            entries.range(&String::new(), None),
+            vec![
+                ("a".into(), Some("1".into())),
+                ("b".into(), None),
+                ("c".into(), Some("2".into()))
+            ]
+        );
+    }
+}
@@ -2,90 +2,1576 @@
 //! Basically, this is a Key-Value store on top of local file storage.
 
 use log;
-use std::io;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{mpsc::Receiver, Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+mod background_flush;
+mod bloom;
+mod cancel;
+mod changelog;
+mod column_family;
+mod comparator;
 mod disktable;
+mod events;
+mod fault;
+mod key_transform;
 mod memtable;
+mod read_sketch;
 mod rich_file;
+mod scan;
+mod snapshot;
+mod timeout;
+
+pub use bloom::BloomFilter;
+pub use cancel::{CancellationToken, Cancelled};
+pub use changelog::{Changes, Op};
+pub use column_family::{ColumnFamilies, ColumnFamily};
+pub use comparator::{KeyComparator, Lexicographic, Numeric};
+pub use disktable::default::FileDisktableBuilder;
+pub use disktable::{
+    BlobGcReport, ClearFailure, CompactionReport, DataGen, DirEntry, DisktableStats, FileHandle,
+    FileIdentity, FileSystem, FindSource, GenerationInfo, HealthStatus, MemFileSystem,
+    ModedFileSystem, Offset, OsFileSystem, RetryPolicy, SizeHistogram, Throttled, ValueHandle,
+    VerifyProgress, WarmConfig,
+};
+pub use events::StoreEvent;
+pub use key_transform::{KeyTransform, Lowercase, Trim};
+pub use memtable::{AdaptiveThreshold, WalSyncPolicy};
+use scan::MergeIter;
+pub use scan::{Cursor, Diff, DiffIter};
+pub use snapshot::{SnapshotOptions, SnapshotReader};
+pub use timeout::Timeout;
+
+/// Key-values of a page of `SSTable::scan_paged` and the start of the next page if any.
+pub type Page = (Vec<(String, String)>, Option<String>);
 
 pub struct SSTable {
     // Sorted *String* Table :)
-    memtable: Box<dyn memtable::Memtable<Key = String, Value = String>>,
-    disktable: Box<dyn disktable::Disktable>,
+    memtable: Box<dyn memtable::WalMemtable<Key = String, Value = String>>,
+    // full memtables waiting for `flush_frozen`, newest first
+    frozen: VecDeque<memtable::MemtableEntries<String, String>>,
+    freeze_on_full: bool,
+    comparator: comparator::SharedComparator,
+    key_transform: Option<key_transform::SharedKeyTransform>,
+    changelog: Option<changelog::Changelog>,
+    fault_injector: Option<fault::SharedFaultInjector>,
+    // Some when reads of keys are counted
+    read_sketch: Option<read_sketch::ReadSketch>,
+    // Some(depth) of generations newer than the one `get_repairing` rewrites a key from
+    read_repair: Option<usize>,
+    // whether an insert of the value the memtables hold for the key writes nothing
+    skip_redundant_writes: bool,
+    // Some with entry timestamps, when the values of keys in the memtables were written,
+    // see `find_with_meta`
+    inserted_at: Option<HashMap<String, SystemTime>>,
+    // Some while full memtables are flushed by a background thread
+    background_flush: Option<background_flush::BackgroundFlush>,
+    // shared with the background flush thread
+    disktable: background_flush::SharedDisktable,
 }
 
 impl SSTable {
-    pub fn new(dir_name: &str, mem_max_entry: usize) -> SSTable {
+    pub const EXISTENCE_FILTER_FPR: f64 = 0.01;
+
+    /// Create an SSTable whose memtable holds up to `mem_max_entry` entries, which is
+    /// flushed by the insert filling it. Fails with `InvalidInput` for a `mem_max_entry`
+    /// of 0, and if the directory can't be created or the disktable in it can't be opened,
+    /// e.g. as its generations were written with a key transform.
+    pub fn new(dir_name: &str, mem_max_entry: usize) -> Result<SSTable, io::Error> {
+        Self::check_mem_max_entry(mem_max_entry)?;
+        Self::create_dir(dir_name)?;
+        Self::open(
+            dir_name,
+            memtable::default::BTreeMemtable::new(dir_name, mem_max_entry),
+        )
+    }
+
+    /// Create an SSTable whose memtable flush threshold adapts to the flush latency.
+    /// Fails as `new`.
+    pub fn with_adaptive_threshold(
+        dir_name: &str,
+        adaptive: AdaptiveThreshold,
+    ) -> Result<SSTable, io::Error> {
+        Self::create_dir(dir_name)?;
+        Self::open(
+            dir_name,
+            memtable::default::BTreeMemtable::with_adaptive_threshold(dir_name, adaptive),
+        )
+    }
+
+    /// Create an SSTable which never flushes on `insert`.
+    /// A full memtable is frozen and queued instead, and a new one takes writes
+    /// until `flush_frozen` writes the queued memtables into disk. Fails as `new`.
+    pub fn with_immutable_memtables(
+        dir_name: &str,
+        mem_max_entry: usize,
+    ) -> Result<SSTable, io::Error> {
+        Self::check_mem_max_entry(mem_max_entry)?;
+        Self::create_dir(dir_name)?;
+        let memtable =
+            memtable::default::BTreeMemtable::new(dir_name, mem_max_entry).keep_wal_on_flush(true);
+        Ok(SSTable {
+            freeze_on_full: true,
+            ..Self::open(dir_name, memtable)?
+        })
+    }
+
+    /// Create an SSTable which orders keys of scans and data files by the comparator.
+    /// Fails as `with_disktable`.
+    pub fn with_comparator(
+        dir_name: &str,
+        mem_max_entry: usize,
+        comparator: impl KeyComparator + 'static,
+    ) -> Result<SSTable, io::Error> {
+        Self::with_disktable(
+            FileDisktableBuilder::new(dir_name).comparator(comparator),
+            mem_max_entry,
+        )
+    }
+
+    /// Create an SSTable whose disktable is configured by the builder.
+    /// Fails if the builder holds an invalid combination of options, and with `InvalidInput`
+    /// for a `mem_max_entry` of 0.
+    /// A read only store never writes the WAL, and rejects writes with `PermissionDenied`.
+    pub fn with_disktable(
+        builder: FileDisktableBuilder,
+        mem_max_entry: usize,
+    ) -> Result<SSTable, io::Error> {
+        Self::check_mem_max_entry(mem_max_entry)?;
+        let dir_name = builder.dir_name().to_string();
+        let comparator = builder.shared_comparator();
+        let key_transform = builder.shared_key_transform();
+        let changelog_enabled = builder.changelog_enabled();
+        let fault_injector = builder.shared_fault_injector();
+        let wal_sync_policy = builder.configured_wal_sync_policy();
+        let follower = builder.is_follower();
+        let read_sketch = builder
+            .read_sketch_size()
+            .map(|(width, depth)| read_sketch::ReadSketch::new(width, depth));
+        let read_repair = builder.read_repair_depth();
+        let skip_redundant_writes = builder.skips_redundant_writes();
+        let stamps_entries = builder.stamps_entries();
+        let background_flush_capacity = builder.background_flush_capacity();
+        // the WAL is written on open unless the store is a follower
+        let disktable = if follower {
+            builder.build()?
+        } else {
+            builder.writes_on_open().build()?
+        };
+        // a read only file system is only found by opening the disktable
+        if background_flush_capacity.is_some() && disktable::Disktable::read_only(&disktable) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "background_flush cannot be set on a read only disktable",
+            ));
+        }
+        let changelog = if changelog_enabled {
+            Some(changelog::Changelog::open(&dir_name)?)
+        } else {
+            None
+        };
+        let memtable: memtable::default::BTreeMemtable<String, String> = if follower {
+            memtable::default::BTreeMemtable::detached(mem_max_entry)
+        } else if disktable::Disktable::read_only(&disktable) {
+            memtable::default::BTreeMemtable::read_only(&dir_name, mem_max_entry)
+        } else {
+            memtable::default::BTreeMemtable::new(&dir_name, mem_max_entry)
+                .keep_wal_on_flush(true)
+                .wal_sync_policy(wal_sync_policy)
+        };
+        let disktable: background_flush::SharedDisktable =
+            Arc::new(Mutex::new(Box::new(disktable)));
+        let background_flush = background_flush_capacity
+            .map(|capacity| background_flush::BackgroundFlush::start(disktable.clone(), capacity));
+        // values restored from the WAL are taken as written when the store is opened
+        let opened_at = SystemTime::now();
+        let inserted_at = if stamps_entries {
+            let restored = memtable::Memtable::keys(&memtable);
+            Some(restored.map(|key| (key.clone(), opened_at)).collect())
+        } else {
+            None
+        };
+        Ok(SSTable {
+            memtable: Box::new(memtable),
+            frozen: VecDeque::new(),
+            freeze_on_full: background_flush.is_some(),
+            comparator,
+            key_transform,
+            changelog,
+            fault_injector,
+            read_sketch,
+            read_repair,
+            skip_redundant_writes,
+            inserted_at,
+            background_flush,
+            disktable,
+        })
+    }
+
+    /// Open a directory another process writes to as a read only follower, which sees
+    /// the generations found on open and by each `refresh` but none of the writer's entries
+    /// which aren't flushed yet.
+    pub fn open_follower(dir_name: &str) -> Result<SSTable, io::Error> {
+        Self::with_disktable(FileDisktableBuilder::new(dir_name).follower(true), 1)
+    }
+
+    // a memtable of no entries would be flushed into a generation of an entry per insert
+    fn check_mem_max_entry(mem_max_entry: usize) -> Result<(), io::Error> {
+        if mem_max_entry == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mem_max_entry must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+
+    fn create_dir(dir_name: &str) -> Result<(), io::Error> {
         std::fs::create_dir_all(dir_name)
-            .expect(&format!("failed to create directory {}", dir_name));
-        SSTable {
-            memtable: Box::new(memtable::default::BTreeMemtable::new(
-                dir_name,
-                mem_max_entry,
-            )),
-            disktable: Box::new(disktable::default::FileDisktable::new(dir_name).unwrap()),
+    }
+
+    fn open(
+        dir_name: &str,
+        memtable: memtable::default::BTreeMemtable<String, String>,
+    ) -> Result<SSTable, io::Error> {
+        let disktable = disktable::default::FileDisktable::new(dir_name)?;
+        Ok(SSTable {
+            memtable: Box::new(memtable.keep_wal_on_flush(true)),
+            frozen: VecDeque::new(),
+            freeze_on_full: false,
+            comparator: Arc::new(Lexicographic),
+            key_transform: None,
+            changelog: None,
+            fault_injector: None,
+            read_sketch: None,
+            read_repair: None,
+            skip_redundant_writes: false,
+            inserted_at: None,
+            background_flush: None,
+            disktable: Arc::new(Mutex::new(Box::new(disktable))),
+        })
+    }
+
+    fn disktable(&self) -> MutexGuard<'_, Box<dyn disktable::Disktable + Send>> {
+        self.disktable.lock().unwrap()
+    }
+
+    // a write doesn't wait for a flush in progress on the background thread, which only
+    // adds a generation, unless the disktable was throttled as of the last flush or check,
+    // so `max_generations` is checked against the generations before the flush as it is
+    // for a write flushing synchronously
+    fn admit_write(&self) -> Result<(), io::Error> {
+        let background_flush = match self.background_flush.as_ref() {
+            Some(background_flush) => background_flush,
+            None => return self.disktable().admit_write(),
+        };
+        let disktable = match self.disktable.try_lock() {
+            Ok(disktable) => disktable,
+            Err(_) if !background_flush.throttled() => return Ok(()),
+            Err(_) => self.disktable(),
+        };
+        background_flush.set_throttled(disktable.throttled());
+        disktable.admit_write()
+    }
+
+    // a store flushing on the background thread is never read only
+    fn read_only(&self) -> bool {
+        self.background_flush.is_none() && self.disktable().read_only()
+    }
+
+    // drop the frozen memtables the background thread has flushed from the oldest,
+    // returning how many, and waiting for every queued one if asked to. a failed flush
+    // stops the thread, and its error is returned, leaving the memtables from it frozen
+    // until the next full memtable writes them as writes go back to flushing synchronously
+    fn reap_flushes(&mut self, wait: bool) -> Result<usize, io::Error> {
+        let flushed = self.reap_completed(wait)?;
+        if flushed > 0 && self.frozen.is_empty() {
+            // frozen entries are on disk now, so the WAL needs only the active memtable
+            self.memtable.checkpoint_wal()?;
+            self.forget_flushed_writes();
+        }
+        Ok(flushed)
+    }
+
+    // drop the times of writes which are on disk now, where they're stamped by the flush
+    fn forget_flushed_writes(&mut self) {
+        if let Some(mut inserted_at) = self.inserted_at.take() {
+            inserted_at.retain(|key, _| {
+                matches!(self.get_from_memory(key), memtable::GetResult::Found(_))
+            });
+            self.inserted_at = Some(inserted_at);
+        }
+    }
+
+    // `reap_flushes` leaving the WAL as it is
+    fn reap_completed(&mut self, wait: bool) -> Result<usize, io::Error> {
+        let completed = match self.background_flush.as_mut() {
+            Some(background_flush) => background_flush.completed(wait),
+            None => return Ok(0),
+        };
+        let mut flushed = 0;
+        for (result, latency) in completed {
+            if let Err(err) = result {
+                self.background_flush = None;
+                self.freeze_on_full = false;
+                return Err(err);
+            }
+            self.frozen.pop_back();
+            self.memtable.record_flush_latency(latency);
+            flushed += 1;
         }
+        Ok(flushed)
     }
-    pub fn get(&self, key: impl Into<String>) -> Option<String> {
+
+    // the active memtable first, then frozen ones from the newest
+    fn get_from_memory(&self, key: &String) -> memtable::GetResult<&String> {
+        match self.memtable.get(key) {
+            memtable::GetResult::NotFound => self
+                .frozen
+                .iter()
+                .map(|frozen| frozen.get(key))
+                .find(|found| !matches!(found, memtable::GetResult::NotFound))
+                .unwrap_or(memtable::GetResult::NotFound),
+            found => found,
+        }
+    }
+
+    // the key normalized by the key transform
+    fn key(&self, key: impl Into<String>) -> String {
         let key = key.into();
+        match self.key_transform.as_ref() {
+            Some(key_transform) => key_transform.transform(&key),
+            None => key,
+        }
+    }
+
+    fn record_read(&self, key: &str) {
+        if let Some(read_sketch) = self.read_sketch.as_ref() {
+            read_sketch.record(key);
+        }
+    }
+
+    /// About how many times the key has been read since the store was opened, which is never
+    /// fewer than it actually has, see `FileDisktableBuilder::track_reads`. Reads of keys
+    /// whose hashes collide inflate each other's estimates. 0 unless reads are tracked.
+    pub fn estimated_reads(&self, key: impl Into<String>) -> u64 {
+        let key = self.key(key);
+        self.read_sketch
+            .as_ref()
+            .map_or(0, |read_sketch| read_sketch.estimate(&key))
+    }
+
+    // record the op in the changelog if it's enabled, once the op is applied, so that
+    // an op which fails is never replayed by a follower
+    fn record(&mut self, op: Op) -> io::Result<()> {
+        match self.changelog.as_mut() {
+            Some(changelog) => changelog.append(&op).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// The value for the key, None if it's missing or deleted. Fails with the error of
+    /// an index or a data file which can't be read, rather than taking it for a miss,
+    /// and with `InvalidData` for an index pointing at no record of the key.
+    pub fn get(&self, key: impl Into<String>) -> Result<Option<String>, io::Error> {
+        let key = self.key(key);
+        self.record_read(&key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => Ok(Some(value.to_string())),
+            memtable::GetResult::Deleted => Ok(None),
+            memtable::GetResult::NotFound => self.disktable().find(&key),
+        }
+    }
+    /// `get` which borrows a value found in a memtable instead of copying it, and owns
+    /// a value read from disk. The borrow holds the store, so it's dropped before a write.
+    pub fn find_borrowed(&self, key: impl Into<String>) -> Result<Option<Cow<'_, str>>, io::Error> {
+        let key = self.key(key);
+        self.record_read(&key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => Ok(Some(Cow::Borrowed(value.as_str()))),
+            memtable::GetResult::Deleted => Ok(None),
+            memtable::GetResult::NotFound => Ok(self.disktable().find(&key)?.map(Cow::Owned)),
+        }
+    }
+
+    /// `get` into the buffer, which is overwritten by the value and keeps its capacity,
+    /// so that a loop of reads reuses a buffer instead of allocating a value per read.
+    /// Returns whether the key has a value, leaving the buffer empty if it doesn't.
+    /// Fails as `get`.
+    pub fn find_into(&self, key: impl Into<String>, buf: &mut String) -> Result<bool, io::Error> {
+        let key = self.key(key);
+        self.record_read(&key);
+        buf.clear();
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => {
+                buf.push_str(value);
+                Ok(true)
+            }
+            memtable::GetResult::Deleted => Ok(false),
+            memtable::GetResult::NotFound => self.disktable().find_into(&key, buf),
+        }
+    }
+
+    /// `get` which gives up with an error wrapping `Timeout` once the deadline passes before
+    /// a generation on disk is looked up, so that a read going through many generations fails
+    /// fast instead of blocking a caller on a deadline. The clock is checked before each
+    /// generation, so the one being read when the deadline passes is done first. Memtables
+    /// are read anyway. Fails as `get` otherwise.
+    pub fn find_with_deadline(
+        &self,
+        key: impl Into<String>,
+        deadline: Instant,
+    ) -> Result<Option<String>, io::Error> {
+        let key = self.key(key);
+        self.record_read(&key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => Ok(Some(value.to_string())),
+            memtable::GetResult::Deleted => Ok(None),
+            memtable::GetResult::NotFound => self.disktable().find_with_deadline(&key, deadline),
+        }
+    }
+
+    /// Values the key has had newest first, None where it was deleted. Every memtable and
+    /// generation holding the key gives one, and a compaction keeps as many as
+    /// `keep_versions` of the builder, so the first one is what `get` returns. Fails as `get`.
+    pub fn find_versions(&self, key: impl Into<String>) -> Result<Vec<Option<String>>, io::Error> {
+        let key = self.key(key);
+        let in_memory = std::iter::once(self.memtable.get(&key))
+            .chain(self.frozen.iter().map(|frozen| frozen.get(&key)))
+            .filter_map(|found| match found {
+                memtable::GetResult::Found(value) => Some(Some(value.to_string())),
+                memtable::GetResult::Deleted => Some(None),
+                memtable::GetResult::NotFound => None,
+            });
+        let in_memory: Vec<Option<String>> = in_memory.collect();
+        let on_disk = self.disktable().find_versions(&key)?;
+        Ok(in_memory.into_iter().chain(on_disk).collect())
+    }
+
+    /// Every record of the key on disk newest first with the generation holding it, None
+    /// for a tombstone or a record a range tombstone covers, to see which records shadow
+    /// which. Records in memtables aren't listed, as `find_versions` lists their values.
+    /// Fails as `get`.
+    pub fn find_all_versions(
+        &self,
+        key: impl Into<String>,
+    ) -> Result<Vec<(DataGen, Option<String>)>, io::Error> {
+        let key = self.key(key);
+        self.disktable().find_all_versions(&key)
+    }
+
+    /// `get` along with when the value was written, with `entry_timestamps` of the builder
+    /// set. A value still in a memtable gives when it was inserted, or when the store was
+    /// opened if it was restored from the WAL, and a value on disk when its record was
+    /// flushed. Fails as `get`.
+    pub fn find_with_meta(
+        &self,
+        key: impl Into<String>,
+    ) -> Result<Option<(String, SystemTime)>, io::Error> {
+        let key = self.key(key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => {
+                let inserted_at = self.inserted_at.as_ref().and_then(|at| at.get(&key));
+                return Ok(inserted_at.map(|&at| (value.to_string(), at)));
+            }
+            memtable::GetResult::Deleted => return Ok(None),
+            memtable::GetResult::NotFound => {}
+        }
+        self.disktable().find_with_meta(&key)
+    }
+
+    /// `get` along with where the newest entry for the key was found. Deleted keys give None
+    /// with the source of the tombstone. Fails as `get`.
+    pub fn find_detailed(
+        &self,
+        key: impl Into<String>,
+    ) -> Result<(Option<String>, FindSource), io::Error> {
+        let key = self.key(key);
+        self.record_read(&key);
         match self.memtable.get(&key) {
-            memtable::GetResult::Found(value) => Some(value.to_string()),
-            memtable::GetResult::Deleted => None,
-            memtable::GetResult::NotFound => self.disktable.find(&key),
+            memtable::GetResult::Found(value) => {
+                return Ok((Some(value.to_string()), FindSource::Memtable))
+            }
+            memtable::GetResult::Deleted => return Ok((None, FindSource::Memtable)),
+            memtable::GetResult::NotFound => {}
+        }
+        let frozen = self
+            .frozen
+            .iter()
+            .map(|frozen| frozen.get(&key))
+            .find(|found| !matches!(found, memtable::GetResult::NotFound));
+        match frozen {
+            Some(memtable::GetResult::Found(value)) => {
+                Ok((Some(value.to_string()), FindSource::Flushing))
+            }
+            Some(_) => Ok((None, FindSource::Flushing)),
+            None => self.disktable().find_detailed(&key),
+        }
+    }
+
+    /// `get` which inserts a value read from a generation deep enough into the memtable when
+    /// `read_repair` of the builder is set, so that the next reads of the key are served from
+    /// memory. A repair is an `insert`, which may flush the memtable and is recorded in the
+    /// changelog, and one which fails, e.g. with `Throttled`, is logged as the value is read.
+    /// Fails as `get`.
+    pub fn get_repairing(&mut self, key: impl Into<String>) -> Result<Option<String>, io::Error> {
+        let key = key.into();
+        let (value, source) = self.find_detailed(key.as_str())?;
+        if let (Some(depth), FindSource::Disk(data_gen), Some(value)) =
+            (self.read_repair, source, value.as_ref())
+        {
+            if self.disktable().newer_generations(data_gen) >= depth {
+                if let Err(err) = self.insert(key.as_str(), value.as_str()) {
+                    log::error!("failed to repair a read. key: {}, err: {:?}", key, err);
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Where the value for the key lives on disk as (generation, offset in the data file).
+    /// None if the key is missing, deleted, or still in a memtable. Fails with the error of
+    /// an index which can't be read.
+    pub fn locate(&self, key: impl Into<String>) -> Result<Option<(DataGen, Offset)>, io::Error> {
+        let key = self.key(key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::NotFound => self.disktable().locate(&key),
+            _ => Ok(None),
+        }
+    }
+
+    /// Bytes in [offset, offset + len) of the value for the key, clamped to the end of the value.
+    /// Only the requested bytes are read from a data file. Fails as `get`.
+    pub fn find_range(
+        &self,
+        key: impl Into<String>,
+        offset: usize,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, io::Error> {
+        let key = self.key(key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(value) => {
+                Ok(Some(disktable::value_range(value, offset, len)))
+            }
+            memtable::GetResult::Deleted => Ok(None),
+            memtable::GetResult::NotFound => self.disktable().find_range(&key, offset, len),
+        }
+    }
+
+    /// Whether the key has a value, which generations on disk answer from their bloom filters
+    /// and indexes without reading a data file, so it suits keys written by `set_exists`.
+    /// Fails with the error of an index which can't be read.
+    pub fn contains(&self, key: impl Into<String>) -> Result<bool, io::Error> {
+        let key = self.key(key);
+        match self.get_from_memory(&key) {
+            memtable::GetResult::Found(_) => Ok(true),
+            memtable::GetResult::Deleted => Ok(false),
+            memtable::GetResult::NotFound => self.disktable().contains(&key),
         }
     }
+
+    /// Write the key with no value, i.e. an empty one, which is flushed as an existence
+    /// record with `existence_records` of the builder set. `contains` tells it exists.
+    pub fn set_exists(&mut self, key: impl Into<String>) -> Result<(), io::Error> {
+        self.insert(key, "")
+    }
+
+    /// Fails with `Throttled` without writing while the disktable holds `max_generations`,
+    /// and with `InvalidInput` for a key which is empty after the key transform.
+    /// An empty value is stored as any other and reads back as `Some("")`, unlike a deleted key.
+    /// With `FileDisktableBuilder::skip_redundant_writes`, the value a memtable holds for the
+    /// key already is not written again.
     pub fn insert(
         &mut self,
         key: impl Into<String>,
         value: impl Into<String>,
     ) -> Result<(), io::Error> {
-        let key = key.into();
+        let key = self.key(key);
+        if key.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "key must not be empty",
+            ));
+        }
         let value = value.into();
-        self.memtable.set(key, value).on_flush(|mem| {
+        if self.skip_redundant_writes {
+            if let memtable::GetResult::Found(held) = self.get_from_memory(&key) {
+                if *held == value {
+                    return Ok(());
+                }
+            }
+        }
+        self.reap_flushes(false)?;
+        self.admit_write()?;
+        let op = Op::Set {
+            key: key.clone(),
+            value: value.clone(),
+        };
+        if let Some(inserted_at) = self.inserted_at.as_mut() {
+            inserted_at.insert(key.clone(), SystemTime::now());
+        }
+        let on_flush = self.memtable.set(key, value);
+        self.record(op)?;
+        self.handle_flush(on_flush)
+    }
+
+    // write a memtable a write has filled into disk, or freeze it along with queueing it
+    // for the background thread, then truncate the WAL once it's on disk. memtables left
+    // frozen by a failed background flush are older, so they're written before it
+    fn handle_flush(
+        &mut self,
+        on_flush: memtable::MemtableOnFlush<String, String>,
+    ) -> Result<(), io::Error> {
+        let mut latency = None;
+        let mut behind_frozen = false;
+        let disktable = &self.disktable;
+        let frozen = &mut self.frozen;
+        let background_flush = &mut self.background_flush;
+        let freeze_on_full = self.freeze_on_full;
+        let result = on_flush.on_flush(|mem| {
             log::trace!(
                 "flush! memtable: {:?}, tombstones: {:?}",
                 mem.entries,
                 mem.tombstones
             );
-            self.disktable.flush(mem)
-        })
+            if freeze_on_full {
+                if let Some(background_flush) = background_flush.as_mut() {
+                    // the frozen memtable is read until the thread has flushed its copy
+                    background_flush.push(memtable::MemtableEntries {
+                        entries: mem.entries.clone(),
+                        tombstones: mem.tombstones.clone(),
+                    });
+                }
+                frozen.push_front(mem);
+                return Ok(());
+            }
+            if !frozen.is_empty() {
+                frozen.push_front(mem);
+                behind_frozen = true;
+                return Ok(());
+            }
+            let started = Instant::now();
+            let result = disktable.lock().unwrap().flush(mem);
+            latency = Some(started.elapsed());
+            result
+        });
+        if let Some(latency) = latency {
+            self.memtable.record_flush_latency(latency);
+        }
+        result?;
+        if behind_frozen {
+            self.write_frozen()?;
+        }
+        if latency.is_some() || behind_frozen {
+            // the WAL is truncated only once the flushed generation is durable and published,
+            // so a crash in between replays entries which are on disk already at worst
+            fault::inject(self.fault_injector.as_ref(), fault::CrashPoint::Published)?;
+            self.memtable.checkpoint_wal()?;
+            self.forget_flushed_writes();
+        }
+        Ok(())
+    }
+
+    /// Move the value of `from` to `to`, both normalized by the key transform, returning
+    /// whether `from` had one. The value is read first, then `to` is written with it and
+    /// `from` is deleted as a single write of the memtable and a single record of the WAL
+    /// and the changelog, so neither a read nor a crash sees one without the other, and
+    /// a flush writes both into the same generation. A missing `from` writes nothing.
+    /// Fails as `insert` does, and with the error of reading the value.
+    pub fn rename(
+        &mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> Result<bool, io::Error> {
+        let (from, to) = (self.key(from), self.key(to));
+        if to.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "key must not be empty",
+            ));
+        }
+        self.reap_flushes(false)?;
+        self.admit_write()?;
+        let value = match self.get_from_memory(&from) {
+            memtable::GetResult::Found(value) => value.to_string(),
+            memtable::GetResult::Deleted => return Ok(false),
+            memtable::GetResult::NotFound => match self.disktable().find(&from)? {
+                Some(value) => value,
+                None => return Ok(false),
+            },
+        };
+        if from == to {
+            return Ok(true);
+        }
+        let op = Op::Rename {
+            from: from.clone(),
+            to: to.clone(),
+        };
+        if let Some(inserted_at) = self.inserted_at.as_mut() {
+            inserted_at.insert(to.clone(), SystemTime::now());
+        }
+        let on_flush = self.memtable.rename(from, to, value);
+        self.record(op)?;
+        self.handle_flush(on_flush)?;
+        Ok(true)
+    }
+
+    /// The value for the key, or the value computed by `f` which is inserted for the key
+    /// if it's missing or deleted. `f` isn't called when the key has a value.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: impl Into<String>,
+        f: impl FnOnce() -> String,
+    ) -> Result<String, io::Error> {
+        let key = key.into();
+        if let Some(value) = self.get(key.as_str())? {
+            return Ok(value);
+        }
+        let value = f();
+        self.insert(key, value.as_str())?;
+        Ok(value)
+    }
+
+    /// An empty key never holds a value, so deleting it does nothing.
+    /// Fails with `PermissionDenied` on a read only store, and with the error of writing
+    /// the changelog, after the key is deleted.
+    pub fn delete(&mut self, key: impl Into<String>) -> Result<(), io::Error> {
+        let key = self.key(key);
+        if key.is_empty() {
+            return Ok(());
+        }
+        if self.read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("a delete on a read only store. key: {}", key),
+            ));
+        }
+        self.memtable.delete(key.clone());
+        self.record(Op::Delete { key })
+    }
+
+    /// Delete every key starting with the prefix, normalized by the key transform, with
+    /// a single range tombstone on disk instead of a tombstone per key. Keys are compared
+    /// byte-wise against the prefix whatever the comparator, and keys written afterwards
+    /// are kept. The tombstone is dropped once a full `compact` has dropped the keys.
+    pub fn delete_prefix(&mut self, prefix: impl Into<String>) -> Result<(), io::Error> {
+        let prefix = self.key(prefix);
+        // a range tombstone covers the generations on disk, so queued memtables go first
+        self.reap_flushes(true)?;
+        self.disktable().delete_prefix(&prefix)?;
+        self.frozen
+            .iter_mut()
+            .for_each(|frozen| frozen.delete_prefix(&prefix));
+        self.memtable.delete_prefix(&prefix);
+        self.record(Op::DeletePrefix { prefix })
+    }
+
+    /// Delete every key in [start, end) in the order of the comparator, normalized by the
+    /// key transform, with a single range tombstone on disk like `delete_prefix`. Keys in
+    /// memory get a tombstone each instead, as the WAL is restored without the comparator.
+    /// Fails with `InvalidInput` if the comparator orders start after end.
+    pub fn delete_range(
+        &mut self,
+        start: impl Into<String>,
+        end: impl Into<String>,
+    ) -> Result<(), io::Error> {
+        let (start, end) = (self.key(start), self.key(end));
+        self.reap_flushes(true)?;
+        self.disktable().delete_range(&start, &end)?;
+        let all = String::new();
+        let mut in_memory: Vec<String> = self
+            .frozen
+            .iter()
+            .flat_map(|frozen| frozen.range(&all, None))
+            .chain(self.memtable.range(&all, None))
+            .filter(|(key, value)| {
+                value.is_some()
+                    && self.comparator.compare(key, &start) != std::cmp::Ordering::Less
+                    && self.comparator.compare(key, &end) == std::cmp::Ordering::Less
+            })
+            .map(|(key, _)| key)
+            .collect();
+        in_memory.sort_unstable();
+        in_memory.dedup();
+        in_memory
+            .into_iter()
+            .for_each(|key| self.memtable.delete(key));
+        self.record(Op::DeleteRange { start, end })
+    }
+
+    /// `delete` returning whether the key had a live value in memory or on disk.
+    /// The value is read before the tombstone is written, so it's best effort against
+    /// another process writing the same directory in between. False for a value which
+    /// fails to be read, which is logged as the key is deleted anyway. Fails as `delete`.
+    pub fn delete_returning(&mut self, key: impl Into<String>) -> Result<bool, io::Error> {
+        let key = key.into();
+        if self.read_only() {
+            return self.delete(key).map(|_| false);
+        }
+        let existed = match self.get(key.as_str()) {
+            Ok(value) => value.is_some(),
+            Err(err) => {
+                log::error!(
+                    "failed to read a deleted value. key: {}, err: {:?}",
+                    key,
+                    err
+                );
+                false
+            }
+        };
+        self.delete(key)?;
+        Ok(existed)
+    }
+
+    /// Mutations after the sequence number in order, so that a follower which has applied
+    /// up to it can catch up. Keys are the ones normalized by the key transform.
+    /// Fails with `InvalidInput` unless the changelog is enabled by the builder.
+    pub fn changes_since(&self, seq: u64) -> Result<Changes, io::Error> {
+        match self.changelog.as_ref() {
+            Some(changelog) => changelog.since(seq),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "changelog isn't enabled",
+            )),
+        }
+    }
+
+    /// Sequence number of the latest mutation, 0 before any. None unless the changelog
+    /// is enabled.
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.changelog
+            .as_ref()
+            .map(|changelog| changelog.last_seq())
+    }
+
+    /// Keys deleted in the live memtable in key order, normalized by the key transform.
+    /// Deletes of frozen memtables and of the disktable aren't included.
+    pub fn memtable_tombstones(&self) -> impl Iterator<Item = &str> {
+        self.memtable.tombstones().map(String::as_str)
+    }
+
+    /// Write frozen memtables into disk from the oldest, returning how many were flushed.
+    /// With `background_flush` of the builder, it waits for the queued memtables to be
+    /// flushed by the thread instead.
+    pub fn flush_frozen(&mut self) -> Result<usize, io::Error> {
+        let flushed = self.reap_flushes(true)? + self.write_frozen()?;
+        // frozen entries are on disk now, so the WAL needs only the active memtable
+        self.memtable.checkpoint_wal()?;
+        self.forget_flushed_writes();
+        Ok(flushed)
+    }
+
+    // write frozen memtables into disk from the oldest, leaving the WAL as it is
+    fn write_frozen(&mut self) -> Result<usize, io::Error> {
+        let mut flushed = 0;
+        while let Some(oldest) = self.frozen.pop_back() {
+            let started = Instant::now();
+            // MemtableEntries is handed over, so keep a copy to queue it again on failure
+            let retained = memtable::MemtableEntries {
+                entries: oldest.entries.clone(),
+                tombstones: oldest.tombstones.clone(),
+            };
+            let result = self.disktable().flush(oldest);
+            if let Err(err) = result {
+                self.frozen.push_back(retained);
+                return Err(err);
+            }
+            self.memtable.record_flush_latency(started.elapsed());
+            flushed += 1;
+        }
+        Ok(flushed)
+    }
+
+    /// Wait for the background thread of `background_flush` to flush every queued memtable
+    /// and stop it, e.g. on shutdown, returning how many it flushed. Full memtables are
+    /// frozen for `flush_frozen` from then on. Dropping the store drains the queue as well,
+    /// but a flush which fails then is only covered by the WAL.
+    pub fn stop_background_flush(&mut self) -> Result<usize, io::Error> {
+        let flushed = self.reap_flushes(true)?;
+        self.background_flush = None;
+        Ok(flushed)
+    }
+
+    /// Flush frozen memtables and the active one, then sync every generation on disk.
+    /// Returns the newest generation, so generations up to and including it are a durable
+    /// prefix of the store, e.g. for a backup to copy. Nothing is flushed if the memtables
+    /// are empty, and the newest generation on disk is returned. It isn't throttled by
+    /// `max_generations` as writes are, as it adds a generation at most, so that a durable
+    /// point can be taken while writes are held back. Fails with `PermissionDenied` on a
+    /// read only store.
+    pub fn checkpoint(&mut self) -> Result<DataGen, io::Error> {
+        if self.read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "a checkpoint of a read only store",
+            ));
+        }
+        self.reap_completed(true)?;
+        self.write_frozen()?;
+        let active = self.memtable.freeze();
+        if !active.entries.is_empty() || !active.tombstones.is_empty() {
+            let started = Instant::now();
+            let retained = memtable::MemtableEntries {
+                entries: active.entries.clone(),
+                tombstones: active.tombstones.clone(),
+            };
+            // queued as the newest frozen memtable, which the WAL still covers
+            let result = self.disktable().flush(active);
+            if let Err(err) = result {
+                self.frozen.push_front(retained);
+                return Err(err);
+            }
+            self.memtable.record_flush_latency(started.elapsed());
+        }
+        // the WAL is truncated only once the generations it covered are synced, along with
+        // the directory, so a crash in between replays them at worst
+        let newest = self.disktable().sync_all()?;
+        self.memtable.checkpoint_wal()?;
+        self.forget_flushed_writes();
+        Ok(newest)
+    }
+
+    /// Retry disk reads failing with transient errors according to the policy.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.disktable().set_retry_policy(retry_policy);
+    }
+
+    /// Merge two adjacent disk generations into a new one above the others, which keep
+    /// their records, and return it. Readers of the directory see either the two or the
+    /// merged one with a manifest.
+    pub fn merge_generations(&mut self, a: DataGen, b: DataGen) -> Result<DataGen, io::Error> {
+        self.disktable().merge_generations(a, b)
+    }
+
+    /// Live key-values in [start, end) in key order. `end` of None means no upper bound,
+    /// and an empty `start` means no lower bound.
+    pub fn scan(&self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>, io::Error> {
+        self.cursor(start, end)?.collect()
+    }
+
+    /// Cheap estimate of the records a `scan` of [start, end) goes through, e.g. to choose
+    /// between a scan and point lookups. Memtables are counted, while generations on disk
+    /// are estimated from their skip indexes, reading only the keys past the last one
+    /// sampled, so it's off by about the skip index interval of each generation.
+    /// Overwritten and deleted keys count.
+    pub fn estimate_range_count(&self, start: &str, end: Option<&str>) -> Result<u64, io::Error> {
+        let (start, end) = self.bounds(start, end);
+        let (start, end) = (start.as_str(), end.as_deref());
+        let in_range = |key: &&String| scan::in_range(&self.comparator, key, start, end);
+        let in_memory = self.memtable.keys().filter(in_range).count()
+            + self
+                .frozen
+                .iter()
+                .map(|frozen| frozen.keys().filter(in_range).count())
+                .sum::<usize>();
+        Ok(in_memory as u64 + self.disktable().estimate_range_count(start, end)?)
+    }
+
+    /// The live key-value of the least key at least `key` in the order of the comparator.
+    pub fn ceiling(&self, key: &str) -> Result<Option<(String, String)>, io::Error> {
+        self.cursor(key, None)?.next().transpose()
+    }
+
+    /// The live key-value of the greatest key at most `key` in the order of the comparator,
+    /// which goes past deleted keys to the live one before them. Keys are scanned from
+    /// the nearest one a skip index points at before the key, and from the ones before it
+    /// in turn while the keys in between are all deleted, rather than from the first key.
+    /// Under a byte-wise comparator each scan seeks the data files to its first key through
+    /// their indexes, while under others they're read from the beginning.
+    pub fn floor(&self, key: &str) -> Result<Option<(String, String)>, io::Error> {
+        let (target, _) = self.bounds(key, None);
+        let at_most =
+            |key: &str| self.comparator.compare(key, &target) != std::cmp::Ordering::Greater;
+        let mut starts: Vec<String> = self
+            .disktable()
+            .skip_sampled_keys()?
+            .into_iter()
+            .filter(|sampled| !sampled.is_empty() && at_most(sampled))
+            .collect();
+        // an empty start is no lower bound, which the last window down to the first key has
+        starts.insert(0, String::new());
+        let mut end: Option<String> = None;
+        for start in starts.iter().rev() {
+            let cursor = Cursor::new(
+                self.bounded_sources(start, end.as_deref())?,
+                self.comparator.clone(),
+                None,
+            );
+            let mut floor = None;
+            for record in cursor {
+                let (key, value) = record?;
+                if !at_most(&key) {
+                    break;
+                }
+                floor = Some((key, value));
+            }
+            if floor.is_some() {
+                return Ok(floor);
+            }
+            end = Some(start.clone());
+        }
+        Ok(None)
+    }
+
+    /// At most `limit` live key-values of `scan`, and the key to pass as `start` of the call
+    /// for the next page, which is None once the last page is returned.
+    /// Fails with `InvalidInput` for a `limit` of 0.
+    pub fn scan_paged(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> Result<Page, io::Error> {
+        if limit == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "limit of scan_paged must be at least 1",
+            ));
+        }
+        let mut page = self
+            .cursor(start, end)?
+            .take(limit + 1)
+            .collect::<Result<Vec<_>, _>>()?;
+        let next = if page.len() > limit {
+            page.pop().map(|(key, _)| key)
+        } else {
+            None
+        };
+        Ok((page, next))
+    }
+
+    /// Live key-values of `scan` gathered until `budget` from the call runs out, and the key
+    /// to pass as `start` of the call resuming right after them, which is None once the
+    /// scan is done, e.g. for an interactive tool showing what it can of a range in 100ms
+    /// at a time. The budget counts from the call, so opening the sources of the scan
+    /// counts against it, and it's checked at every key merged from them, deleted ones and
+    /// ones with shadowed versions included. The page may then be empty along with the key
+    /// to resume at, if the budget ran out on deleted keys. A key is gone past at least, so
+    /// that every call makes progress.
+    pub fn scan_timed(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        budget: Duration,
+    ) -> Result<Page, io::Error> {
+        let deadline = Instant::now() + budget;
+        let mut page = vec![];
+        let mut passed = false;
+        for record in MergeIter::new(self.sources(start, end)?, self.comparator.clone()) {
+            let (key, value) = record?;
+            if passed && Timeout::check(deadline).is_err() {
+                return Ok((page, Some(key)));
+            }
+            passed = true;
+            if let Some(value) = value {
+                page.push((key, value));
+            }
+        }
+        Ok((page, None))
+    }
+
+    /// `scan` which stops with `Err(Cancelled)` once the token is cancelled.
+    pub fn scan_cancellable(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(String, String)>, io::Error> {
+        self.cursor_cancellable(start, end, cancel)?.collect()
+    }
+
+    /// Iterate live key-values in [start, end) in key order as of the call, see `Cursor`.
+    pub fn cursor(&self, start: &str, end: Option<&str>) -> Result<Cursor, io::Error> {
+        Ok(Cursor::new(
+            self.sources(start, end)?,
+            self.comparator.clone(),
+            None,
+        ))
+    }
+
+    /// `Diff`s of the live key-values of the store against another stream of key-values
+    /// sorted by the store's comparator, e.g. for a bulk reconciliation, by a merge-join
+    /// which loads neither side in full. Keys are compared as the store holds them, after
+    /// any key transform. The store is read as of the call, see `Cursor`.
+    pub fn diff_against<I>(&self, other: I) -> Result<DiffIter<I::IntoIter>, io::Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        Ok(DiffIter::new(
+            self.cursor("", None)?,
+            other.into_iter(),
+            self.comparator.clone(),
+        ))
+    }
+
+    /// `cursor` which stops with `Err(Cancelled)` once the token is cancelled.
+    pub fn cursor_cancellable(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        cancel: &CancellationToken,
+    ) -> Result<Cursor, io::Error> {
+        Ok(Cursor::new(
+            self.sources(start, end)?,
+            self.comparator.clone(),
+            Some(cancel.clone()),
+        ))
+    }
+
+    /// `scan` of the whole store split into up to `ranges` key ranges, which are scanned
+    /// concurrently on a thread each. Records are yielded in key order as `scan` returns them,
+    /// range after range, and a range's records once it's scanned in full. Ranges are split
+    /// at the min and max keys of generations on disk, so fewer ranges are scanned when there
+    /// are few generations. Fails with `InvalidInput` for `ranges` of 0.
+    pub fn par_scan(
+        &self,
+        ranges: usize,
+    ) -> Result<impl Iterator<Item = io::Result<(String, String)>>, io::Error> {
+        if ranges == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ranges of par_scan must be at least 1",
+            ));
+        }
+        let boundaries = self.partition(ranges)?;
+        let starts = std::iter::once(String::new()).chain(boundaries.iter().cloned());
+        let ends = boundaries
+            .iter()
+            .cloned()
+            .map(Some)
+            .chain(std::iter::once(None));
+        let mut scans = vec![];
+        // sources are taken here, so every range reads the store as of the call
+        for (start, end) in starts.zip(ends) {
+            let cursor = Cursor::new(
+                self.sources(&start, end.as_deref())?,
+                self.comparator.clone(),
+                None,
+            );
+            scans.push(std::thread::spawn(move || {
+                cursor.collect::<Result<Vec<_>, _>>()
+            }));
+        }
+        Ok(scans.into_iter().flat_map(|scan| {
+            let scanned: Box<dyn Iterator<Item = _>> = match scan.join() {
+                Ok(Ok(records)) => Box::new(records.into_iter().map(Ok)),
+                Ok(Err(err)) => Box::new(std::iter::once(Err(err))),
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+            scanned
+        }))
+    }
+
+    // keys in the order of the comparator which split the key space into up to `ranges`
+    // ranges, picked evenly among the min and max keys of generations on disk
+    fn partition(&self, ranges: usize) -> Result<Vec<String>, io::Error> {
+        let mut bounds: Vec<String> = self
+            .disktable()
+            .generations()?
+            .into_iter()
+            .flat_map(|info| vec![info.min_key, info.max_key])
+            .flatten()
+            .collect();
+        bounds.sort_by(|a, b| self.comparator.compare(a, b));
+        bounds.dedup();
+        let mut boundaries: Vec<String> = (1..ranges)
+            .filter_map(|n| bounds.get(n * bounds.len() / ranges).cloned())
+            .collect();
+        boundaries.dedup();
+        Ok(boundaries)
+    }
+
+    /// `scan` which yields a handle instead of each value, so that values on disk are read
+    /// only for the keys whose handles are loaded. Handles load the values as of the call,
+    /// even after flushes and compactions.
+    pub fn scan_lazy(
+        &self,
+        start: &str,
+        end: Option<&str>,
+    ) -> Result<Vec<(String, ValueHandle)>, io::Error> {
+        self.lazy_records(start, end)?.collect()
+    }
+
+    /// `scan` of the keys the predicate passes, which is called on each live key before
+    /// its value is read, so that values on disk are read only for the keys it passes.
+    /// Keys deleted or overwritten are resolved first, and the predicate sees every key
+    /// as normalized by the key transform.
+    pub fn scan_filter(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        pred: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(String, String)>, io::Error> {
+        self.lazy_records(start, end)?
+            .filter(|record| record.as_ref().map_or(true, |(key, _)| pred(key)))
+            .map(|record| record.and_then(|(key, handle)| handle.load().map(|value| (key, value))))
+            .collect()
+    }
+
+    /// The last `limit` live key-values of keys starting with the prefix, in reverse order
+    /// of the comparator, e.g. the latest entries of a user under keys of a user and
+    /// a timestamp. Under a byte-wise comparator, only index entries under the prefix are
+    /// read, from the end of it, and values are read only for the ones returned. Keys under
+    /// a prefix aren't next to each other in other orders, so every index is read then.
+    /// Fails with `InvalidInput` for a `limit` of 0.
+    pub fn scan_prefix_limit_rev(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>, io::Error> {
+        if limit == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "limit of scan_prefix_limit_rev must be at least 1",
+            ));
+        }
+        let (prefix, _) = self.bounds(prefix, None);
+        let (start, end) = if self.comparator.is_bytewise() {
+            let end = scan::prefix_successor(&prefix);
+            (prefix.clone(), end)
+        } else {
+            (String::new(), None)
+        };
+        self.bounded_lazy_records(&start, end.as_deref(), true)?
+            .filter(|record| {
+                record
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&prefix))
+            })
+            .take(limit)
+            .map(|record| record.and_then(|(key, handle)| handle.load().map(|value| (key, value))))
+            .collect()
+    }
+
+    // live keys in [start, end) with handles of their values, in key order
+    fn lazy_records(
+        &self,
+        start: &str,
+        end: Option<&str>,
+    ) -> Result<impl Iterator<Item = io::Result<(String, ValueHandle)>>, io::Error> {
+        let (start, end) = self.bounds(start, end);
+        self.bounded_lazy_records(&start, end.as_deref(), false)
+    }
+
+    // `lazy_records` of bounds normalized already, in reverse key order if `rev`
+    fn bounded_lazy_records(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        rev: bool,
+    ) -> Result<impl Iterator<Item = io::Result<(String, ValueHandle)>>, io::Error> {
+        let sorted = |records| {
+            if rev {
+                scan::sorted_rev(records, start, end, &self.comparator)
+            } else {
+                scan::sorted(records, start, end, &self.comparator)
+            }
+        };
+        let loaded = |records: Vec<scan::Record>| {
+            records
+                .into_iter()
+                .map(|(key, value)| (key, value.map(ValueHandle::loaded)))
+                .collect()
+        };
+        let mut sources = vec![sorted(loaded(self.memtable_records(start, end)))];
+        self.frozen.iter().for_each(|frozen| {
+            let records = scan::in_memory(
+                frozen.keys(),
+                |key| frozen.get(key),
+                start,
+                end,
+                &self.comparator,
+            );
+            sources.push(sorted(loaded(records)))
+        });
+        let (disktable, comparator): (_, comparator::SharedComparator) = if rev {
+            let reversed = comparator::Reversed(self.comparator.clone());
+            (
+                self.disktable().lazy_sources_rev(start, end)?,
+                Arc::new(reversed),
+            )
+        } else {
+            (
+                self.disktable().lazy_sources(start, end)?,
+                self.comparator.clone(),
+            )
+        };
+        sources.extend(disktable);
+        Ok(
+            MergeIter::new(sources, comparator).filter_map(|record| match record {
+                Ok((key, Some(handle))) => Some(Ok((key, handle))),
+                Ok((_, None)) => None,
+                Err(err) => Some(Err(err)),
+            }),
+        )
+    }
+
+    // bounds of a scan normalized by the key transform, where an empty start stays
+    // no lower bound
+    fn bounds(&self, start: &str, end: Option<&str>) -> (String, Option<String>) {
+        let start = if start.is_empty() {
+            String::new()
+        } else {
+            self.key(start)
+        };
+        (start, end.map(|end| self.key(end)))
+    }
+
+    // the memtable comes first as the newest source.
+    // memtables are ordered byte-wise, so their records are bounded and sorted by the comparator
+    fn sources(&self, start: &str, end: Option<&str>) -> Result<Vec<scan::Source>, io::Error> {
+        let (start, end) = self.bounds(start, end);
+        self.bounded_sources(&start, end.as_deref())
+    }
+
+    // `sources` of bounds normalized already
+    fn bounded_sources(
+        &self,
+        start: &str,
+        end: Option<&str>,
+    ) -> Result<Vec<scan::Source>, io::Error> {
+        let memtable = self.memtable_records(start, end);
+        let mut sources = vec![scan::sorted(memtable, start, end, &self.comparator)];
+        self.frozen.iter().for_each(|frozen| {
+            let records = scan::in_memory(
+                frozen.keys(),
+                |key| frozen.get(key),
+                start,
+                end,
+                &self.comparator,
+            );
+            sources.push(scan::sorted(records, start, end, &self.comparator));
+        });
+        sources.extend(self.disktable().sources(start, end)?);
+        Ok(sources)
+    }
+
+    // records of the memtable in [start, end) of bounds normalized already, see `in_memory`
+    fn memtable_records(&self, start: &str, end: Option<&str>) -> Vec<scan::Record> {
+        scan::in_memory(
+            self.memtable.keys(),
+            |key| self.memtable.get(key),
+            start,
+            end,
+            &self.comparator,
+        )
+    }
+
+    /// `BloomFilter` of every live key, serialized by `BloomFilter::to_bytes`.
+    /// It's sized for a false positive rate of `EXISTENCE_FILTER_FPR`, i.e. about 1%
+    /// of keys which don't exist test positive, while live keys always test positive.
+    pub fn existence_filter(&self) -> Result<Vec<u8>, io::Error> {
+        let keys = self
+            .cursor("", None)?
+            .map(|record| record.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut filter = BloomFilter::new(keys.len(), Self::EXISTENCE_FILTER_FPR);
+        keys.iter().for_each(|key| filter.insert(key));
+        Ok(filter.to_bytes())
+    }
+
+    /// Merge all disk generations into one, reclaiming space of overwritten and deleted entries.
+    pub fn compact(&mut self) -> Result<(), io::Error> {
+        self.compact_with_report().map(|_| ())
+    }
+
+    /// `compact` returning the report which lists the keys whose tombstones and older
+    /// versions were dropped.
+    pub fn compact_with_report(&mut self) -> Result<CompactionReport, io::Error> {
+        self.disktable().compact(None)
+    }
+
+    /// `compact` which is aborted with `Err(Cancelled)` once the token is cancelled.
+    /// Nothing is changed on disk by an aborted compaction.
+    pub fn compact_cancellable(&mut self, cancel: &CancellationToken) -> Result<(), io::Error> {
+        self.disktable().compact(Some(cancel)).map(|_| ())
+    }
+
+    /// `compact` of the newest generation along with only the generations whose key ranges
+    /// overlap it, so that generations of other key ranges aren't rewritten, e.g. of keys
+    /// written in order. Fails with `InvalidInput` under `keep_versions` over 1.
+    pub fn compact_incremental(&mut self) -> Result<CompactionReport, io::Error> {
+        self.disktable().compact_incremental()
+    }
+
+    /// Reclaim space of the records in the generation which newer generations shadow,
+    /// by rewriting it alone without them. Reads are the same before and after, as they
+    /// never reach the records dropped. The report lists the keys dropped. The records left
+    /// go into a new generation above the others, which readers of the directory see in
+    /// place of the old one at once with a manifest.
+    pub fn vacuum(&mut self, data_gen: DataGen) -> Result<CompactionReport, io::Error> {
+        self.disktable().vacuum(data_gen)
     }
 
-    pub fn delete(&mut self, key: impl Into<String>) -> () {
-        self.memtable.delete(key.into());
+    /// Reclaim space of values in blob files which no record on disk references any more,
+    /// see `FileDisktableBuilder::blob_threshold`. Overwritten and deleted values are
+    /// reclaimed once a compaction has dropped their records.
+    pub fn collect_blobs(&mut self) -> Result<BlobGcReport, io::Error> {
+        self.disktable().collect_blobs()
     }
 
+    /// Pick up the generations the writer has flushed or compacted since the follower was
+    /// opened or last refreshed, returning the ones added or changed.
+    /// Fails with `InvalidInput` unless the store is read only, as one opened by
+    /// `open_follower` is.
+    pub fn refresh(&mut self) -> Result<Vec<DataGen>, io::Error> {
+        self.disktable().refresh()
+    }
+
+    /// Write every live entry, in memory or on disk, into `out_dir` on the local file system
+    /// in the format data and index files had before any option changed them: one generation
+    /// of headerless entries with no tombstones, inlined values or blobs, indexed in byte-wise
+    /// key order. Returns how many entries were written. Fails with `AlreadyExists` if
+    /// `out_dir` holds a data file, and with `InvalidInput` for a key holding a tab or a
+    /// newline, which the legacy skip index can't hold. Entries are collected in memory.
+    pub fn export_legacy(&self, out_dir: &str) -> Result<usize, io::Error> {
+        let entries = self.scan("", None)?.into_iter().collect();
+        disktable::export_legacy(&OsFileSystem, out_dir, &entries)
+    }
+
+    /// Write every live entry, in memory or on disk, into a single snapshot file at `out` on
+    /// the local file system, along with a sidecar index named after it with `.index`
+    /// appended, for `SnapshotReader` to read without the store. The format, laid out in
+    /// `snapshot.rs`, is sorted byte-wise by key, checksummed by block and front coded with
+    /// `compressed`, whatever the generations and options of the store. Keys are written as
+    /// stored, i.e. after the key transform. Returns how many entries were written. Fails
+    /// with `AlreadyExists` if `out` exists. Entries are collected in memory.
+    pub fn export_snapshot(
+        &self,
+        out: &std::path::Path,
+        options: &SnapshotOptions,
+    ) -> Result<usize, io::Error> {
+        let entries = self.scan("", None)?.into_iter().collect();
+        snapshot::write(&OsFileSystem, out, &entries, options)
+    }
+
+    /// Distribution of key and value lengths of entries on disk, bucketed by `boundaries`.
+    pub fn size_histogram(&self, boundaries: &[usize]) -> Result<SizeHistogram, io::Error> {
+        self.disktable().size_histogram(boundaries)
+    }
+
+    /// Preload indexes on disk so that the first reads after opening don't pay for them.
+    pub fn warm(&self, config: &WarmConfig) -> Result<(), io::Error> {
+        self.disktable().warm(config)
+    }
+
+    /// Channel of `StoreEvent`s from now on. Events are dropped while the channel is full,
+    /// so a slow receiver never blocks the store.
+    pub fn subscribe(&mut self) -> Receiver<StoreEvent> {
+        self.disktable().subscribe()
+    }
+
+    pub fn stats(&self) -> Result<DisktableStats, io::Error> {
+        self.disktable().stats()
+    }
+
+    /// What's in the generation on disk, which is printed in detail by `{:#?}`.
+    pub fn describe_generation(&self, data_gen: DataGen) -> Result<GenerationInfo, io::Error> {
+        self.disktable().describe_generation(data_gen)
+    }
+
+    /// `describe_generation` of every generation on disk from the oldest, which reads
+    /// indexes and file metadata only. Generations aren't leveled, as `compact` merges
+    /// all of them into the newest one, and `compact_incremental` the ones overlapping it.
+    pub fn generations(&self) -> Result<Vec<GenerationInfo>, io::Error> {
+        self.disktable().generations()
+    }
+
+    /// Quick check of the store for a liveness or readiness probe, e.g. behind `/healthz`.
+    /// Unless read only, a sentinel file is written into the directory, read back and
+    /// removed, and the manifest and the files of the newest generation are loaded. Keys
+    /// and memtables are left as they are. It doesn't wait for a flush or a compaction
+    /// holding the disktable, reporting it `busy` instead, so that a probe isn't held up.
+    pub fn health_check(&self) -> HealthStatus {
+        match self.disktable.try_lock() {
+            Ok(disktable) => disktable.health_check(),
+            Err(std::sync::TryLockError::WouldBlock) => HealthStatus {
+                busy: true,
+                problems: vec!["the disktable is busy, e.g. flushing or compacting".to_string()],
+                ..HealthStatus::default()
+            },
+            Err(std::sync::TryLockError::Poisoned(_)) => HealthStatus {
+                problems: vec!["the disktable was left by a panic".to_string()],
+                ..HealthStatus::default()
+            },
+        }
+    }
+
+    /// `clear_strict`.
     pub fn clear(&mut self) -> Result<(), io::Error> {
-        self.disktable.clear()?;
+        self.clear_strict()
+    }
+
+    /// Remove every key, stopping at the first generation on disk which fails to be removed
+    /// and returning its error. Memtables are kept then, and generations older than the one
+    /// which failed are removed.
+    pub fn clear_strict(&mut self) -> Result<(), io::Error> {
+        self.reap_flushes(true)?;
+        self.disktable().clear()?;
+        self.frozen.clear();
         self.memtable.clear();
-        Ok(())
+        self.forget_flushed_writes();
+        self.record(Op::Clear)
+    }
+
+    /// `clear_strict` which goes on past generations failing to be removed, e.g. as their
+    /// files are locked or not writable to the process, and returns them. Memtables are
+    /// cleared whatever fails, while the generations left keep their keys readable.
+    pub fn clear_best_effort(&mut self) -> Result<Vec<ClearFailure>, io::Error> {
+        self.reap_flushes(true)?;
+        let failures = self.disktable().clear_best_effort()?;
+        self.frozen.clear();
+        self.memtable.clear();
+        self.forget_flushed_writes();
+        self.record(Op::Clear)?;
+        Ok(failures)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sst::SSTable;
+    use crate::sst::fault::{CrashPoint, FaultInjector};
+    use crate::sst::{
+        BloomFilter, CancellationToken, Cancelled, ColumnFamilies, Diff, DirEntry,
+        FileDisktableBuilder, FileHandle, FileSystem, FindSource, GenerationInfo, HealthStatus,
+        Lowercase, MemFileSystem, Numeric, Op, SSTable, SnapshotOptions, SnapshotReader,
+        StoreEvent, Throttled, Timeout, Trim,
+    };
+    use std::{
+        io,
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
     #[test]
     fn test_sstable() {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i);
 
-        let mut sst = SSTable::new("./test_tmp", 200);
+        let mut sst = SSTable::new("./test_tmp", 200).unwrap();
         assert!(sst.clear().is_ok());
         // get -> set -> get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(key(i)).unwrap(), None);
             sst.insert(key(i), value(i)).expect("success");
-            assert_eq!(sst.get(key(i)), Some(value(i)));
+            assert_eq!(sst.get(key(i)).unwrap(), Some(value(i)));
         });
         // get -> delete -> get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), Some(value(i)));
-            sst.delete(key(i));
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(key(i)).unwrap(), Some(value(i)));
+            sst.delete(key(i)).unwrap();
+            assert_eq!(sst.get(key(i)).unwrap(), None);
         });
         // get
         (1..300).for_each(|i| {
-            assert_eq!(sst.get(key(i)), None);
+            assert_eq!(sst.get(key(i)).unwrap(), None);
         });
     }
 
@@ -93,19 +1579,2499 @@ mod tests {
     fn test_sstabl_tombstones() {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i);
-        let mut sst = SSTable::new("./test_tmp2", 3);
+        let mut sst = SSTable::new("./test_tmp2", 3).unwrap();
         assert!(sst.clear().is_ok());
         (1..=5).for_each(|i| {
             sst.insert(key(i), value(i)).expect("success");
         });
-        sst.delete(key(2));
+        sst.delete(key(2)).unwrap();
         // restore WAL
         // memtable: [4, 5], tombstone: [2], disktable: [1, 2, 3]
-        let sst = SSTable::new("./test_tmp2", 3);
-        assert_eq!(sst.get(key(1)), Some(value(1)));
-        assert_eq!(sst.get(key(2)), None);
-        assert_eq!(sst.get(key(3)), Some(value(3)));
-        assert_eq!(sst.get(key(4)), Some(value(4)));
-        assert_eq!(sst.get(key(5)), Some(value(5)));
+        let sst = SSTable::new("./test_tmp2", 3).unwrap();
+        assert_eq!(sst.get(key(1)).unwrap(), Some(value(1)));
+        assert_eq!(sst.get(key(2)).unwrap(), None);
+        assert_eq!(sst.get(key(3)).unwrap(), Some(value(3)));
+        assert_eq!(sst.get(key(4)).unwrap(), Some(value(4)));
+        assert_eq!(sst.get(key(5)).unwrap(), Some(value(5)));
+    }
+
+    #[test]
+    fn test_sstable_merge_generations() {
+        let key = |i| format!("key-{}", i);
+        let mut sst = SSTable::new("./test_tmp_merge", 2).unwrap();
+        assert!(sst.clear().is_ok());
+        // a memtable is flushed by the insert filling it, which leaves
+        // gen 1: [1, 2], gen 2: [2, 3], tombstone: [1], gen 3: [4, 5],
+        // gen 4: [3, 6], tombstone: [4], gen 5: [5, 7], gen 6: [8, 9]
+        (1..=3).for_each(|i| sst.insert(key(i), "gen1").expect("success"));
+        sst.delete(key(1)).unwrap();
+        [2, 4, 5]
+            .iter()
+            .for_each(|&i| sst.insert(key(i), "gen2").expect("success"));
+        sst.delete(key(4)).unwrap();
+        [3, 6, 7]
+            .iter()
+            .for_each(|&i| sst.insert(key(i), "gen3").expect("success"));
+        [5, 8, 9]
+            .iter()
+            .for_each(|&i| sst.insert(key(i), "gen4").expect("success"));
+
+        let before = (1..=9)
+            .map(|i| sst.get(key(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(before[0], None);
+        assert_eq!(before[3], None);
+
+        // 3, 4 and 5 are dropped from the merge, as gens 4 and 5 above it hold them
+        assert_eq!(sst.merge_generations(3, 2).expect("success"), 7);
+        let after = (1..=9)
+            .map(|i| sst.get(key(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+        let merged = sst.describe_generation(7).unwrap();
+        assert_eq!((merged.entries, merged.tombstones), (2, 1));
+
+        // gens 2 and 3 have been retired, and 1 and 7 are not adjacent
+        assert!(sst.merge_generations(2, 3).is_err());
+        assert!(sst.merge_generations(1, 7).is_err());
+        assert_eq!(sst.merge_generations(6, 7).expect("success"), 8);
+        let after = (1..=9)
+            .map(|i| sst.get(key(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_sstable_locate() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let mut sst = SSTable::new("./test_tmp_locate", 3).unwrap();
+        assert!(sst.clear().is_ok());
+        // gen 1: [1, 2, 3]
+        (1..=3).for_each(|i| sst.insert(key(i), value(i)).expect("success"));
+        // gen 2: [2, 4, 5]
+        [2, 4, 5]
+            .iter()
+            .for_each(|&i| sst.insert(key(i), value(i)).expect("success"));
+        // memtable: [6], tombstone: [3]
+        sst.insert(key(6), value(6)).expect("success");
+        sst.delete(key(3)).unwrap();
+
+        // every entry takes 12 bytes header + "key-N" + "value-N" + \0
+        let entry_size = 12 + 5 + 7 + 1;
+        assert_eq!(sst.locate(key(1)).unwrap(), Some((1, 0)));
+        assert_eq!(sst.locate(key(2)).unwrap(), Some((2, 0)));
+        assert_eq!(sst.locate(key(5)).unwrap(), Some((2, 2 * entry_size)));
+        assert_eq!(sst.locate(key(3)).unwrap(), None);
+        assert_eq!(sst.locate(key(6)).unwrap(), None);
+        assert_eq!(sst.locate(key(7)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sstable_find_range() {
+        let key = |i| format!("key-{}", i);
+        let blob = |i| {
+            (0..100)
+                .map(|n| format!("{}:{:03};", i, n))
+                .collect::<String>()
+        };
+        let mut sst = SSTable::new("./test_tmp_find_range", 3).unwrap();
+        assert!(sst.clear().is_ok());
+        (1..=4).for_each(|i| sst.insert(key(i), blob(i)).expect("success"));
+        sst.delete(key(2)).unwrap();
+
+        // key-1 is on disk, key-4 is in the memtable
+        assert!(sst.locate(key(1)).unwrap().is_some());
+        assert!(sst.locate(key(4)).unwrap().is_none());
+        [1, 4].iter().for_each(|&i| {
+            let full = blob(i).into_bytes();
+            assert_eq!(
+                sst.find_range(key(i), 120, 42).unwrap(),
+                Some(full[120..162].to_vec())
+            );
+            // clamped to the end of the value
+            assert_eq!(
+                sst.find_range(key(i), 590, 42).unwrap(),
+                Some(full[590..].to_vec())
+            );
+            assert_eq!(sst.find_range(key(i), 1000, 42).unwrap(), Some(vec![]));
+        });
+        assert_eq!(sst.find_range(key(2), 0, 10).unwrap(), None);
+        assert_eq!(sst.find_range(key(5), 0, 10).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sstable_scan_and_compact() {
+        let key = |i| format!("key-{:02}", i);
+        let value = |i| format!("value-{}", i);
+        let mut sst = SSTable::new("./test_tmp_scan", 3).unwrap();
+        assert!(sst.clear().is_ok());
+        (1..=20).for_each(|i| sst.insert(key(i), value(i)).unwrap());
+        (1..=20)
+            .filter(|i| i % 3 == 0)
+            .for_each(|i| sst.insert(key(i), value(i * 100)).unwrap());
+        (1..=20)
+            .filter(|i| i % 5 == 0)
+            .for_each(|i| sst.delete(key(i)).unwrap());
+        let expected: Vec<_> = (1..=20)
+            .filter(|i| i % 5 != 0)
+            .map(|i| (key(i), value(if i % 3 == 0 { i * 100 } else { i })))
+            .collect();
+
+        assert_eq!(sst.scan("", None).unwrap(), expected);
+        assert_eq!(
+            sst.scan(&key(4), Some(&key(10))).unwrap(),
+            expected[3..8].to_vec()
+        );
+
+        sst.compact().unwrap();
+        assert_eq!(sst.scan("", None).unwrap(), expected);
+        (1..=20).for_each(|i| {
+            let found = expected.iter().find(|(k, _)| *k == key(i));
+            assert_eq!(sst.get(key(i)).unwrap(), found.map(|(_, v)| v.to_string()));
+        });
+    }
+
+    #[test]
+    fn test_sstable_numeric_comparator() {
+        let value = |i| format!("value-{}", i);
+        let mut sst = SSTable::with_comparator("./test_tmp_numeric", 3, Numeric).unwrap();
+        assert!(sst.clear().is_ok());
+        // shuffled so that every generation holds keys which interleave numerically
+        [
+            7, 12, 1, 20, 3, 15, 9, 2, 18, 10, 5, 14, 11, 4, 19, 6, 13, 8, 17, 16,
+        ]
+        .iter()
+        .for_each(|i| sst.insert(i.to_string(), value(*i)).unwrap());
+        sst.delete("10".to_string()).unwrap();
+        let expected: Vec<_> = (1..=20)
+            .filter(|i| *i != 10)
+            .map(|i| (i.to_string(), value(i)))
+            .collect();
+
+        assert_eq!(sst.scan("", None).unwrap(), expected);
+        // "9" < "15" numerically, not byte-wise
+        assert_eq!(sst.scan("9", Some("15")).unwrap(), expected[8..13].to_vec());
+
+        sst.compact().unwrap();
+        assert_eq!(sst.scan("", None).unwrap(), expected);
+        assert_eq!(sst.scan("9", Some("15")).unwrap(), expected[8..13].to_vec());
+    }
+
+    #[test]
+    fn test_sstable_delete_range_numeric() {
+        let dir = "./test_tmp_delete_range";
+        let open = || SSTable::with_comparator(dir, 4, Numeric).unwrap();
+        let mut sst = open();
+        assert!(sst.clear().is_ok());
+        let keys: Vec<u32> = (1..=25).chain(vec![100, 150]).collect();
+        keys.iter()
+            .for_each(|i| sst.insert(i.to_string(), format!("value-{}", i)).unwrap());
+        // byte-wise, "9" is after "10", but numerically it's an inverted range
+        assert_eq!(
+            sst.delete_range("20", "9").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        sst.delete_range("10", "20").unwrap();
+        // byte-wise, [10, 20) would cover 2, 100 and 150 and leave 10 to 19 alone
+        let expected: Vec<String> = keys
+            .iter()
+            .filter(|i| !(10..20).contains(*i))
+            .map(|i| i.to_string())
+            .collect();
+        let scanned = |sst: &SSTable| -> Vec<String> {
+            sst.scan("", None)
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect()
+        };
+
+        assert_eq!(scanned(&sst), expected);
+        assert_eq!(sst.get("15").unwrap(), None);
+        assert_eq!(sst.get("2").unwrap(), Some("value-2".to_string()));
+        assert_eq!(sst.get("100").unwrap(), Some("value-100".to_string()));
+        // keys in memory when the range was deleted stay deleted after a restart
+        drop(sst);
+        let mut sst = open();
+        assert_eq!(scanned(&sst), expected);
+        sst.insert("12", "revived").unwrap();
+        sst.compact().unwrap();
+        assert_eq!(sst.get("12").unwrap(), Some("revived".to_string()));
+        assert_eq!(sst.get("13").unwrap(), None);
+        assert_eq!(sst.get("150").unwrap(), Some("value-150".to_string()));
+    }
+
+    #[test]
+    fn test_sstable_events() {
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i);
+        let mut sst = SSTable::new("./test_tmp_events", 4).unwrap();
+        assert!(sst.clear().is_ok());
+        let events = sst.subscribe();
+        (1..=4).for_each(|i| sst.insert(key(i), value(i)).expect("success"));
+
+        // every entry takes 12 bytes header + "key-N" + "value-N" + \0
+        assert_eq!(
+            events.try_recv(),
+            Ok(StoreEvent::Flushed {
+                gen: 1,
+                keys: 4,
+                bytes: 4 * 25
+            })
+        );
+        assert!(events.try_recv().is_err());
+
+        sst.compact().unwrap();
+        assert_eq!(
+            events.try_recv(),
+            Ok(StoreEvent::Compacted {
+                merged: vec![1],
+                result: 1
+            })
+        );
+        sst.clear().unwrap();
+        assert_eq!(events.try_recv(), Ok(StoreEvent::Cleared));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_sstable_existence_filter() {
+        let key = |i| format!("key-{}", i);
+        let mut sst = SSTable::new("./test_tmp_existence", 10).unwrap();
+        assert!(sst.clear().is_ok());
+        (1..=100).for_each(|i| sst.insert(key(i), "value").unwrap());
+        (1..=100)
+            .filter(|i| i % 7 == 0)
+            .for_each(|i| sst.delete(key(i)).unwrap());
+
+        let filter = BloomFilter::from_bytes(&sst.existence_filter().unwrap()).unwrap();
+        (1..=100)
+            .filter(|i| i % 7 != 0)
+            .for_each(|i| assert!(filter.contains(&key(i)), "{} is live", key(i)));
+    }
+
+    #[test]
+    fn test_sstable_throttle() {
+        let key = |i| format!("key-{:03}", i);
+        let dir = "./test_tmp_throttle";
+        let mut sst =
+            SSTable::with_disktable(FileDisktableBuilder::new(dir).max_generations(3), 3).unwrap();
+        assert!(sst.clear().is_ok());
+        // a flood of writes without any compaction
+        let throttled = (1..=100)
+            .filter(|i| match sst.insert(key(*i), "value") {
+                Ok(()) => false,
+                Err(err) => {
+                    assert!(Throttled::is_throttled(&err));
+                    true
+                }
+            })
+            .count();
+        let stats = sst.stats().unwrap();
+        assert_eq!(stats.generations, 3);
+        assert_eq!(stats.throttled_writes, throttled as u64);
+        // the first 9 writes are 3 flushes of 3 entries
+        assert_eq!(throttled, 100 - 9);
+        assert_eq!(sst.get(key(10)).unwrap(), None);
+
+        sst.compact().unwrap();
+        assert_eq!(sst.stats().unwrap().generations, 1);
+        assert!(sst.insert(key(10), "value").is_ok());
+        assert_eq!(sst.get(key(10)).unwrap(), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_column_families() {
+        let dir = "./test_tmp_column_families";
+        let _ = std::fs::remove_dir_all(dir);
+        let mut families = ColumnFamilies::open(dir, 3).unwrap();
+        let users = families.column_family("users").unwrap();
+        let sessions = families.column_family("sessions").unwrap();
+        assert!(families.column_family("../escape").is_err());
+
+        (1..=6).for_each(|i| {
+            let key = format!("key-{}", i);
+            families
+                .insert(&users, &key, format!("user-{}", i))
+                .unwrap();
+        });
+        families.insert(&sessions, "key-1", "session-1").unwrap();
+        families.delete(&sessions, "key-2").unwrap();
+        assert_eq!(
+            families.get(&users, "key-1").unwrap(),
+            Some("user-1".to_string())
+        );
+        assert_eq!(
+            families.get(&sessions, "key-1").unwrap(),
+            Some("session-1".to_string())
+        );
+        assert_eq!(
+            families.get(&users, "key-2").unwrap(),
+            Some("user-2".to_string())
+        );
+        assert_eq!(families.get(&sessions, "key-3").unwrap(), None);
+
+        let generations = |families: &ColumnFamilies, cf| {
+            families.table(cf).unwrap().stats().unwrap().generations
+        };
+        assert_eq!(generations(&families, &users), 2);
+        assert_eq!(generations(&families, &sessions), 0);
+        families.compact(&users).unwrap();
+        assert_eq!(generations(&families, &users), 1);
+        assert_eq!(generations(&families, &sessions), 0);
+
+        // both are reopened from the directory
+        drop(families);
+        let families = ColumnFamilies::open(dir, 3).unwrap();
+        let mut names = families.names();
+        names.sort_unstable();
+        assert_eq!(names, vec!["sessions", "users"]);
+        assert_eq!(
+            families.get(&users, "key-6").unwrap(),
+            Some("user-6".to_string())
+        );
+        assert_eq!(
+            families.get(&sessions, "key-1").unwrap(),
+            Some("session-1".to_string())
+        );
+
+        // a handle of another directory's column family
+        let other_dir = "./test_tmp_column_families_other";
+        let mut others = ColumnFamilies::open(other_dir, 3).unwrap();
+        let orders = others.column_family("orders").unwrap();
+        let err = families.get(&orders, "key-1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(families.table(&orders).is_err());
+        std::fs::remove_dir_all(other_dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_key_transform() {
+        let dir = "./test_tmp_key_transform";
+        let _ = std::fs::remove_dir_all(dir);
+        let open =
+            || SSTable::with_disktable(FileDisktableBuilder::new(dir).key_transform(Lowercase), 2);
+        let mut sst = open().unwrap();
+        sst.insert("key", "value").unwrap();
+        (1..=3).for_each(|i| sst.insert(format!("Disk-{}", i), "disk").unwrap());
+        sst.insert("Deleted", "value").unwrap();
+        sst.delete("DELETED").unwrap();
+
+        assert_eq!(sst.get("KEY").unwrap(), Some("value".to_string()));
+        assert_eq!(sst.get("Key").unwrap(), Some("value".to_string()));
+        assert_eq!(sst.get("DISK-1").unwrap(), Some("disk".to_string()));
+        assert_eq!(sst.get("deleted").unwrap(), None);
+        let keys: Vec<_> = sst
+            .scan("DISK-2", Some("KEY"))
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["disk-2", "disk-3"]);
+
+        // the store can't be read with another normalization
+        drop(sst);
+        assert!(open().is_ok());
+        let other = SSTable::with_disktable(FileDisktableBuilder::new(dir).key_transform(Trim), 2);
+        assert_eq!(
+            other.err().map(|err| err.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+        assert!(SSTable::with_disktable(FileDisktableBuilder::new(dir), 2).is_err());
+        assert!(SSTable::new(dir, 2).is_err());
+    }
+
+    #[test]
+    fn test_sstable_find_detailed() {
+        let key = |i| format!("key-{}", i);
+        let dir = "./test_tmp_find_detailed";
+        let mut sst = SSTable::with_immutable_memtables(dir, 2).unwrap();
+        assert!(sst.clear().is_ok());
+        // gen 1: [1, 2, 3]
+        (1..=3).for_each(|i| sst.insert(key(i), "value").unwrap());
+        sst.flush_frozen().unwrap();
+        // frozen: [4, 5, 6], memtable: [7]
+        (4..=7).for_each(|i| sst.insert(key(i), "value").unwrap());
+        sst.delete(key(1)).unwrap();
+
+        let found = Some("value".to_string());
+        assert_eq!(
+            sst.find_detailed(key(2)).unwrap(),
+            (found.clone(), FindSource::Disk(1))
+        );
+        assert_eq!(
+            sst.find_detailed(key(5)).unwrap(),
+            (found.clone(), FindSource::Flushing)
+        );
+        assert_eq!(
+            sst.find_detailed(key(7)).unwrap(),
+            (found, FindSource::Memtable)
+        );
+        assert_eq!(
+            sst.find_detailed(key(1)).unwrap(),
+            (None, FindSource::Memtable)
+        );
+        assert_eq!(sst.find_detailed(key(8)).unwrap(), (None, FindSource::Miss));
+    }
+
+    #[test]
+    fn test_sstable_cancellation() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_cancel";
+        let mut sst = SSTable::new(dir, 3).unwrap();
+        assert!(sst.clear().is_ok());
+        (1..=20).for_each(|i| sst.insert(key(i), "value").unwrap());
+
+        let cancel = CancellationToken::new();
+        let mut cursor = sst.cursor_cancellable("", None, &cancel).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().0, key(1));
+        assert_eq!(cursor.next().unwrap().unwrap().0, key(2));
+        cancel.cancel();
+        assert!(Cancelled::is_cancelled(
+            &cursor.next().unwrap().unwrap_err()
+        ));
+        assert!(cursor.next().is_none());
+        assert!(Cancelled::is_cancelled(
+            &sst.scan_cancellable("", None, &cancel).unwrap_err()
+        ));
+
+        let files = || {
+            let mut files: Vec<_> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            files.sort();
+            files
+        };
+        let before = files();
+        let err = sst.compact_cancellable(&cancel).unwrap_err();
+        assert!(Cancelled::is_cancelled(&err));
+        assert_eq!(files(), before);
+        assert!(!files().iter().any(|file| file.starts_with("tmp_")));
+        (1..=20).for_each(|i| assert_eq!(sst.get(key(i)).unwrap(), Some("value".to_string())));
+    }
+
+    #[test]
+    fn test_sstable_immutable_memtables() {
+        let key = |i| format!("key-{}", i);
+        let dir = "./test_tmp_frozen";
+        let mut sst = SSTable::with_immutable_memtables(dir, 3).unwrap();
+        assert!(sst.clear().is_ok());
+        // frozen 1: [1, 2, 3]
+        (1..=3).for_each(|i| sst.insert(key(i), "frozen1").unwrap());
+        // frozen 2: [2, 4, 5], tombstone: [1]
+        sst.delete(key(1)).unwrap();
+        [2, 4, 5]
+            .iter()
+            .for_each(|&i| sst.insert(key(i), "frozen2").unwrap());
+        // active: [4], tombstone: [3]
+        sst.insert(key(4), "active").unwrap();
+        sst.delete(key(3)).unwrap();
+        assert_eq!(sst.frozen.len(), 2);
+
+        let expected = vec![
+            None,
+            Some("frozen2".to_string()),
+            None,
+            Some("active".to_string()),
+            Some("frozen2".to_string()),
+        ];
+        let reads = |sst: &SSTable| {
+            (1..=5)
+                .map(|i| sst.get(key(i)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(reads(&sst), expected);
+        assert_eq!(
+            sst.scan("", None).unwrap(),
+            vec![
+                (key(2), "frozen2".to_string()),
+                (key(4), "active".to_string()),
+                (key(5), "frozen2".to_string())
+            ]
+        );
+
+        // frozen memtables survive a restart through the WAL
+        let reopened = SSTable::with_immutable_memtables(dir, 3).unwrap();
+        assert_eq!(reads(&reopened), expected);
+
+        assert_eq!(sst.flush_frozen().unwrap(), 2);
+        assert!(sst.frozen.is_empty());
+        assert_eq!(reads(&sst), expected);
+        let reopened = SSTable::with_immutable_memtables(dir, 3).unwrap();
+        assert_eq!(reads(&reopened), expected);
+    }
+
+    #[test]
+    fn test_sstable_empty_keys_and_values() {
+        let dir = "./test_tmp_empty_keys_and_values";
+        // empty values are inlined in indexes with a threshold
+        for inline_threshold in [None, Some(4)] {
+            let open = || {
+                let builder = FileDisktableBuilder::new(dir);
+                let builder = match inline_threshold {
+                    Some(threshold) => builder.inline_threshold(threshold),
+                    None => builder,
+                };
+                SSTable::with_disktable(builder, 2).unwrap()
+            };
+            let mut sst = open();
+            assert!(sst.clear().is_ok());
+            let err = sst.insert("", "value").unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            sst.delete("").unwrap();
+            assert_eq!(sst.get("").unwrap(), None);
+
+            ["a", "b", "c"]
+                .iter()
+                .for_each(|key| sst.insert(*key, "").unwrap());
+            sst.insert("deleted", "").unwrap();
+            sst.delete("deleted").unwrap();
+            sst.insert("x", "value").unwrap();
+            let check = |sst: &SSTable| {
+                ["a", "b", "c"].iter().for_each(|key| {
+                    assert_eq!(sst.get(*key).unwrap(), Some(String::new()), "{}", key);
+                    assert_eq!(
+                        sst.find_range(*key, 0, 10).unwrap(),
+                        Some(vec![]),
+                        "{}",
+                        key
+                    );
+                });
+                assert_eq!(sst.get("deleted").unwrap(), None);
+                assert_eq!(
+                    sst.scan("", None).unwrap(),
+                    vec![
+                        ("a".to_string(), String::new()),
+                        ("b".to_string(), String::new()),
+                        ("c".to_string(), String::new()),
+                        ("x".to_string(), "value".to_string()),
+                    ]
+                );
+            };
+            check(&sst);
+            drop(sst);
+
+            let mut sst = open();
+            check(&sst);
+            sst.compact().unwrap();
+            check(&sst);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_into() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_find_into";
+        // references of the deduplicated values and padding records are read through as well
+        let builders = [
+            FileDisktableBuilder::new(dir),
+            FileDisktableBuilder::new(dir).block_size(128),
+            FileDisktableBuilder::new(dir).dedup_values(true),
+            FileDisktableBuilder::new(dir).inline_threshold(8),
+        ];
+        for builder in builders {
+            let mut sst = SSTable::with_disktable(builder, 4).unwrap();
+            assert!(sst.clear().is_ok());
+            (0..20).for_each(|i| {
+                let value = if i % 2 == 0 {
+                    "shared value".repeat(3)
+                } else {
+                    "v".repeat(i * 5)
+                };
+                sst.insert(key(i), value).unwrap()
+            });
+            (0..20).step_by(3).for_each(|i| sst.delete(key(i)).unwrap());
+            sst.compact().unwrap();
+            sst.insert(key(1), "in memory").unwrap();
+
+            let mut buf = String::with_capacity(256);
+            let capacity = buf.capacity();
+            (0..22).for_each(|i| {
+                let found = sst.find_into(key(i), &mut buf).unwrap();
+                assert_eq!(found, sst.get(key(i)).unwrap().is_some(), "{}", key(i));
+                assert_eq!(
+                    buf,
+                    sst.get(key(i)).unwrap().unwrap_or_default(),
+                    "{}",
+                    key(i)
+                );
+            });
+            assert_eq!(buf.capacity(), capacity, "the buffer is reused");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_get_or_insert_with() {
+        let dir = "./test_tmp_get_or_insert";
+        let mut sst = SSTable::new(dir, 2).unwrap();
+        assert!(sst.clear().is_ok());
+        sst.insert("disk", "on-disk").unwrap();
+        sst.insert("deleted", "old").unwrap();
+        // flushed with the two above
+        sst.insert("mem", "in-memory").unwrap();
+        sst.delete("deleted").unwrap();
+        assert_eq!(sst.locate("disk").unwrap().map(|(gen, _)| gen), Some(1));
+
+        let calls = std::cell::Cell::new(0);
+        let compute = |value: &str| {
+            calls.set(calls.get() + 1);
+            value.to_string()
+        };
+        // hits don't call the closure nor overwrite the value
+        assert_eq!(
+            sst.get_or_insert_with("mem", || compute("new")).unwrap(),
+            "in-memory"
+        );
+        assert_eq!(
+            sst.get_or_insert_with("disk", || compute("new")).unwrap(),
+            "on-disk"
+        );
+        assert_eq!(calls.get(), 0);
+
+        // missing and deleted keys take the computed value
+        assert_eq!(
+            sst.get_or_insert_with("missing", || compute("computed"))
+                .unwrap(),
+            "computed"
+        );
+        assert_eq!(
+            sst.get_or_insert_with("deleted", || compute("revived"))
+                .unwrap(),
+            "revived"
+        );
+        assert_eq!(calls.get(), 2);
+        assert_eq!(sst.get("missing").unwrap(), Some("computed".to_string()));
+        assert_eq!(sst.get("deleted").unwrap(), Some("revived".to_string()));
+        assert_eq!(
+            sst.get_or_insert_with("missing", || compute("again"))
+                .unwrap(),
+            "computed"
+        );
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_sstable_changes_since() {
+        let key = |i| format!("key-{:02}", i);
+        let leader_dir = "./test_tmp_changelog_leader";
+        let follower_dir = "./test_tmp_changelog_follower";
+        let _ = std::fs::remove_dir_all(leader_dir);
+        let open_leader =
+            || SSTable::with_disktable(FileDisktableBuilder::new(leader_dir).changelog(true), 5);
+        let mut leader = open_leader().unwrap();
+        let mut follower = SSTable::new(follower_dir, 3).unwrap();
+        assert!(follower.clear().is_ok());
+        let replicate = |leader: &SSTable, follower: &mut SSTable, from: u64| {
+            leader.changes_since(from).unwrap().fold(from, |_, change| {
+                let (seq, op) = change.unwrap();
+                match op {
+                    Op::Set { key, value } => follower.insert(key, value).unwrap(),
+                    Op::Delete { key } => follower.delete(key).unwrap(),
+                    Op::Clear => follower.clear().unwrap(),
+                    Op::DeletePrefix { prefix } => follower.delete_prefix(prefix).unwrap(),
+                    Op::DeleteRange { start, end } => follower.delete_range(start, end).unwrap(),
+                    Op::Rename { from, to } => assert!(follower.rename(from, to).unwrap()),
+                }
+                seq
+            })
+        };
+
+        assert_eq!(leader.last_sequence(), Some(0));
+        (0..20).for_each(|i| leader.insert(key(i), format!("value-{}", i)).unwrap());
+        (0..20)
+            .step_by(3)
+            .for_each(|i| leader.delete(key(i)).unwrap());
+        let applied = replicate(&leader, &mut follower, 0);
+        assert_eq!(applied, 27);
+        assert_eq!(
+            follower.scan("", None).unwrap(),
+            leader.scan("", None).unwrap()
+        );
+
+        // the sequence continues after a restart and the follower catches up from it
+        drop(leader);
+        let mut leader = open_leader().unwrap();
+        assert_eq!(leader.last_sequence(), Some(27));
+        leader.insert(key(0), "revived").unwrap();
+        leader.insert(key(1), "updated").unwrap();
+        leader.delete(key(2)).unwrap();
+        assert!(leader.rename(key(4), key(40)).unwrap());
+        assert_eq!(
+            leader
+                .changes_since(applied + 1)
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap(),
+            (
+                29,
+                Op::Set {
+                    key: key(1),
+                    value: "updated".to_string()
+                }
+            )
+        );
+        assert_eq!(replicate(&leader, &mut follower, applied), 31);
+        assert_eq!(
+            follower.scan("", None).unwrap(),
+            leader.scan("", None).unwrap()
+        );
+        assert_eq!(follower.get(key(0)).unwrap(), Some("revived".to_string()));
+
+        // changes are read from the sampled record before the first one, across restarts
+        (0..600).for_each(|i| leader.insert(key(i % 20), i.to_string()).unwrap());
+        let changes_from = |leader: &SSTable, seq: u64| {
+            leader
+                .changes_since(seq)
+                .unwrap()
+                .map(|change| change.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(changes_from(&leader, 511), (512..=631).collect::<Vec<_>>());
+        drop(leader);
+        let leader = open_leader().unwrap();
+        assert_eq!(changes_from(&leader, 600), (601..=631).collect::<Vec<_>>());
+        assert_eq!(changes_from(&leader, 255).len(), 376);
+        assert!(changes_from(&leader, 631).is_empty());
+
+        assert!(follower.changes_since(0).is_err());
+        assert_eq!(follower.last_sequence(), None);
+    }
+
+    #[test]
+    fn test_sstable_delete_returning() {
+        let dir = "./test_tmp_delete_returning";
+        let mut sst = SSTable::new(dir, 2).unwrap();
+        assert!(sst.clear().is_ok());
+        (1..=3).for_each(|i| sst.insert(format!("key-{}", i), "value").unwrap());
+        // key-1 and key-2 have been flushed
+        assert!(sst.locate("key-1").unwrap().is_some());
+
+        assert!(sst.delete_returning("key-1").unwrap());
+        assert!(sst.delete_returning("key-3").unwrap());
+        assert!(!sst.delete_returning("key-1").unwrap());
+        assert!(!sst.delete_returning("missing").unwrap());
+        assert_eq!(sst.get("key-1").unwrap(), None);
+        assert_eq!(sst.get("key-2").unwrap(), Some("value".to_string()));
+        assert_eq!(sst.get("key-3").unwrap(), None);
+    }
+
+    #[test]
+    fn test_sstable_cursor_across_flush_and_compaction() {
+        let key = |i| format!("key-{:03}", i);
+        let mut sst = SSTable::new("./test_tmp_cursor_stability", 10).unwrap();
+        assert!(sst.clear().is_ok());
+        (0..100).for_each(|i| sst.insert(key(i), "before".to_string()).unwrap());
+        (0..100)
+            .step_by(10)
+            .for_each(|i| sst.delete(key(i)).unwrap());
+        let expected = sst.scan("", None).unwrap();
+
+        let mut cursor = sst.cursor("", None).unwrap();
+        let mut read: Vec<_> = cursor.by_ref().take(30).map(Result::unwrap).collect();
+        // flushes add generations and overwrite keys the cursor has yet to reach
+        (0..150).for_each(|i| sst.insert(key(i), "after".to_string()).unwrap());
+        read.extend(cursor.by_ref().take(30).map(Result::unwrap));
+        sst.compact().unwrap();
+        // the rest is read while files the cursor reads are replaced and removed
+        let reader = std::thread::spawn(move || cursor.collect::<Result<Vec<_>, _>>());
+        (150..200).for_each(|i| sst.insert(key(i), "after".to_string()).unwrap());
+        sst.compact().unwrap();
+        sst.clear().unwrap();
+        read.extend(reader.join().unwrap().unwrap());
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn test_sstable_scan_paged() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_scan_paged";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        (0..20).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        (0..20).step_by(7).for_each(|i| sst.delete(key(i)).unwrap());
+        let full = sst.scan("", None).unwrap();
+        assert_eq!(full.len(), 17);
+
+        [1, 3, 5, 17, 100].iter().for_each(|&limit| {
+            let mut pages = vec![];
+            let mut start = String::new();
+            loop {
+                let (page, next) = sst.scan_paged(&start, None, limit).unwrap();
+                assert!(!page.is_empty() && page.len() <= limit);
+                pages.extend(page);
+                match next {
+                    Some(next) => start = next,
+                    None => break,
+                }
+            }
+            assert_eq!(pages, full, "limit: {}", limit);
+        });
+
+        // the limit lands exactly on the last key
+        let (page, next) = sst.scan_paged(&key(17), Some(&key(20)), 3).unwrap();
+        assert_eq!(page.len(), 3);
+        assert_eq!(next, None);
+        let (_, next) = sst.scan_paged(&key(16), Some(&key(20)), 3).unwrap();
+        assert_eq!(next, Some(key(19)));
+        assert!(sst.scan_paged("", None, 0).is_err());
+    }
+
+    #[test]
+    fn test_sstable_scan_lazy() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_scan_lazy";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        (0..20).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        (0..20).step_by(7).for_each(|i| sst.delete(key(i)).unwrap());
+        let data_reads = |sst: &SSTable| sst.stats().unwrap().data_reads;
+
+        let reads = data_reads(&sst);
+        let handles = sst.scan_lazy("", None).unwrap();
+        assert_eq!(data_reads(&sst), reads, "nothing is loaded by the scan");
+        assert_eq!(
+            handles
+                .iter()
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>(),
+            sst.scan("", None)
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        );
+
+        // only the handles which are loaded read the data files
+        let mut handles: Vec<_> = handles
+            .into_iter()
+            .filter(|(key, _)| key.ends_with('3') || key.ends_with('5'))
+            .collect();
+        let (last_key, last) = handles.pop().unwrap();
+        let on_disk = handles
+            .iter()
+            .filter(|(_, handle)| handle.location().is_some())
+            .count() as u64;
+        assert!(on_disk > 0);
+        for (key, handle) in handles {
+            let i: usize = key[4..].parse().unwrap();
+            assert_eq!(handle.load().unwrap(), format!("value-{}", i));
+        }
+        assert_eq!(data_reads(&sst), reads + on_disk);
+
+        // a handle loads the value as of the scan after a compaction
+        assert!(last.location().is_some());
+        sst.insert(last_key.as_str(), "overwritten").unwrap();
+        sst.compact().unwrap();
+        assert_eq!(last.load().unwrap(), "value-15");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_describe_generation() {
+        let dir = "./test_tmp_describe_generation";
+        let mut sst = SSTable::with_comparator(dir, 4, Numeric).unwrap();
+        assert!(sst.clear().is_ok());
+        ["10", "2", "33"]
+            .iter()
+            .for_each(|key| sst.insert(*key, "v").unwrap());
+        sst.delete("7").unwrap();
+        // flushes the 4 keys and the tombstone
+        sst.insert("5", "v").unwrap();
+
+        let info = sst.describe_generation(1).unwrap();
+        assert_eq!(
+            info,
+            GenerationInfo {
+                data_gen: 1,
+                entries: 5,
+                tombstones: 1,
+                // by the comparator rather than byte-wise
+                min_key: Some("2".to_string()),
+                max_key: Some("33".to_string()),
+                // 13 bytes of an entry besides its key and value
+                data_file_bytes: 4 * 13 + 6 + 4 + 13 + 1,
+                // 17 bytes of an index entry besides its key
+                index_file_bytes: 5 * 17 + 7,
+                block_size: None,
+                encrypted: false,
+            }
+        );
+        assert!(format!("{:#?}", info).contains("min_key: Some(\n        \"2\","));
+        assert_eq!(
+            sst.describe_generation(2).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_sstable_generations() {
+        let dir = "./test_tmp_generations";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        assert!(sst.generations().unwrap().is_empty());
+        // each fourth write flushes the memtable
+        (0..12).for_each(|i| sst.insert(format!("key-{:02}", i % 6), "v").unwrap());
+        let listed = sst.generations().unwrap();
+        assert_eq!(
+            listed.iter().map(|info| info.data_gen).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        listed.iter().for_each(|info| {
+            assert_eq!(info, &sst.describe_generation(info.data_gen).unwrap());
+            assert_eq!(info.entries, 4);
+        });
+        assert_eq!(listed[0].min_key, Some("key-00".to_string()));
+        assert_eq!(listed[0].max_key, Some("key-03".to_string()));
+
+        sst.compact().unwrap();
+        let listed = sst.generations().unwrap();
+        assert_eq!(listed.len(), 1);
+        let info = &listed[0];
+        assert_eq!(info.data_gen, 3);
+        assert_eq!((info.entries, info.tombstones), (6, 0));
+        assert_eq!(info.min_key, Some("key-00".to_string()));
+        assert_eq!(info.max_key, Some("key-05".to_string()));
+        assert!(info.data_file_bytes > 0 && info.index_file_bytes > 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_versions() {
+        let dir = "./test_tmp_find_versions";
+        let builder = FileDisktableBuilder::new(dir).keep_versions(2);
+        let mut sst = SSTable::with_disktable(builder, 1).unwrap();
+        assert!(sst.clear().is_ok());
+        // every second insert flushes the memtable
+        (1..=3).for_each(|i| {
+            sst.insert("key", format!("v{}", i)).unwrap();
+            sst.insert(format!("filler-{}", i), "v").unwrap();
+        });
+        sst.compact().unwrap();
+        sst.delete("key").unwrap();
+
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(sst.get("key").unwrap(), None);
+        assert_eq!(
+            sst.find_versions("key").unwrap(),
+            vec![None, some("v3"), some("v2")]
+        );
+        assert_eq!(sst.find_versions("filler-1").unwrap(), vec![some("v")]);
+    }
+
+    // fails at the point once armed, as a crash there would stop the flush
+    struct CrashAt {
+        point: CrashPoint,
+        armed: Arc<AtomicBool>,
+    }
+
+    impl FaultInjector for CrashAt {
+        fn at(&self, point: CrashPoint) -> std::io::Result<()> {
+            if point == self.point && self.armed.swap(false, Ordering::SeqCst) {
+                return Err(std::io::Error::other(format!("crash at {:?}", point)));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sstable_crash_during_flush() {
+        let points = [
+            CrashPoint::Written,
+            CrashPoint::Synced,
+            CrashPoint::Renamed,
+            CrashPoint::Published,
+        ];
+        for point in points.iter() {
+            let dir = format!("./test_tmp_crash_during_flush_{:?}", point);
+            let armed = Arc::new(AtomicBool::new(false));
+            let builder = FileDisktableBuilder::new(&dir).fault_injector(CrashAt {
+                point: *point,
+                armed: armed.clone(),
+            });
+            let mut sst = SSTable::with_disktable(builder, 4).unwrap();
+            assert!(sst.clear().is_ok());
+            // flushes 0 to 3 into the first generation
+            (0..4).for_each(|i| sst.insert(format!("{}", i), "v1").unwrap());
+            sst.delete("1").unwrap();
+            (4..7).for_each(|i| sst.insert(format!("{}", i), "v2").unwrap());
+            armed.store(true, Ordering::SeqCst);
+            // 7 is in the WAL though the flush it triggers crashes
+            assert!(sst.insert("7", "v2").is_err(), "{:?}", point);
+            drop(sst);
+
+            let expected: Vec<(String, String)> = [0, 2, 3, 4, 5, 6, 7]
+                .iter()
+                .map(|i| (i.to_string(), if *i < 4 { "v1" } else { "v2" }.to_string()))
+                .collect();
+            let mut sst = SSTable::with_disktable(FileDisktableBuilder::new(&dir), 4).unwrap();
+            assert_eq!(sst.get("1").unwrap(), None, "{:?}", point);
+            assert_eq!(sst.scan("", None).unwrap(), expected, "{:?}", point);
+            // replayed entries which were on disk already are written once more at worst
+            sst.insert("8", "v3").unwrap();
+            sst.compact().unwrap();
+            assert_eq!(sst.get("1").unwrap(), None, "{:?}", point);
+            let mut scanned = sst.scan("", None).unwrap();
+            assert_eq!(scanned.pop(), Some(("8".to_string(), "v3".to_string())));
+            assert_eq!(scanned, expected, "{:?}", point);
+            assert_eq!(sst.stats().unwrap().tombstone_ratio, 0.0, "{:?}", point);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sstable_delete_prefix() {
+        let dir = "./test_tmp_delete_prefix";
+        let mut sst = SSTable::with_immutable_memtables(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        let deleted = ["user:123:a", "user:123:b", "user:123:c", "user:123:d"];
+        let siblings = ["user:12", "user:123", "user:1234:a", "user:124:a"];
+        // keys on disk, in a frozen memtable and in the active one
+        [
+            "user:123:a",
+            "user:12",
+            "user:1234:a",
+            "user:123:b",
+            "user:123",
+            "user:124:a",
+        ]
+        .iter()
+        .for_each(|key| sst.insert(*key, "old").unwrap());
+        assert_eq!(sst.flush_frozen().unwrap(), 1);
+        sst.insert("user:123:c", "old").unwrap();
+        sst.insert("user:123:d", "old").unwrap();
+        sst.insert("user:12", "new").unwrap();
+        sst.delete("user:124:a").unwrap();
+        assert!(!sst.frozen.is_empty());
+
+        sst.delete_prefix("user:123:").unwrap();
+        let check = |sst: &SSTable| {
+            deleted
+                .iter()
+                .for_each(|key| assert_eq!(sst.get(*key).unwrap(), None, "{}", key));
+            assert_eq!(sst.get("user:12").unwrap(), Some("new".to_string()));
+            assert_eq!(sst.get("user:123").unwrap(), Some("old".to_string()));
+            assert_eq!(sst.get("user:1234:a").unwrap(), Some("old".to_string()));
+            assert_eq!(sst.get("user:124:a").unwrap(), None);
+            assert_eq!(
+                sst.scan("user:", None).unwrap(),
+                vec![
+                    ("user:12".to_string(), "new".to_string()),
+                    ("user:123".to_string(), "old".to_string()),
+                    ("user:1234:a".to_string(), "old".to_string()),
+                ]
+            );
+        };
+        check(&sst);
+        assert_eq!(sst.stats().unwrap().tombstone_ratio, 0.0);
+
+        // the range tombstone and the purge of the memtables survive a restart
+        let mut reopened = SSTable::with_immutable_memtables(dir, 4).unwrap();
+        check(&reopened);
+        reopened.flush_frozen().unwrap();
+        check(&reopened);
+        reopened.compact().unwrap();
+        check(&reopened);
+        drop(reopened);
+
+        // keys written after the delete are kept, on disk as well as in memory
+        let mut sst = SSTable::with_immutable_memtables(dir, 4).unwrap();
+        sst.insert("user:123:a", "new").unwrap();
+        sst.flush_frozen().unwrap();
+        sst.insert("user:123:b", "new").unwrap();
+        assert_eq!(sst.get("user:123:a").unwrap(), Some("new".to_string()));
+        assert_eq!(sst.get("user:123:b").unwrap(), Some("new".to_string()));
+        assert_eq!(sst.get("user:123:c").unwrap(), None);
+        siblings.iter().for_each(|key| {
+            assert_eq!(
+                sst.get(*key).unwrap().is_some(),
+                *key != "user:124:a",
+                "{}",
+                key
+            )
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_compaction_report() {
+        let dir = "./test_tmp_compaction_report";
+        let audit_log = format!("{}/compaction_audit_log", dir);
+        let mut sst = SSTable::with_disktable(
+            FileDisktableBuilder::new(dir).compaction_audit_log(&audit_log),
+            3,
+        )
+        .unwrap();
+        assert!(sst.clear().is_ok());
+        // the log is kept by clears
+        let _ = std::fs::remove_file(&audit_log);
+        sst.insert("alive", "v1").unwrap();
+        sst.insert("deleted", "v1").unwrap();
+        sst.insert("updated", "v1").unwrap();
+        sst.insert("updated", "v2").unwrap();
+        sst.delete("deleted").unwrap();
+        sst.insert("other", "v1").unwrap();
+        sst.insert("another", "v1").unwrap();
+        assert_eq!(sst.stats().unwrap().generations, 2);
+
+        let report = sst.compact_with_report().unwrap();
+        assert_eq!(report.merged, vec![1, 2]);
+        assert_eq!(report.collected_tombstones, vec!["deleted".to_string()]);
+        assert_eq!(
+            report.dropped_versions,
+            vec!["deleted".to_string(), "updated".to_string()]
+        );
+        assert_eq!(report.cleared_range_tombstones, 0);
+        assert!(report.bytes_reclaimed > 0);
+        assert_eq!(sst.get("deleted").unwrap(), None);
+        assert_eq!(sst.get("updated").unwrap(), Some("v2".to_string()));
+        assert_eq!(
+            report.to_json(),
+            format!(
+                "{{\"merged\":[1,2],\"collected_tombstones\":[\"deleted\"],\
+                 \"dropped_versions\":[\"deleted\",\"updated\"],\
+                 \"cleared_range_tombstones\":0,\"bytes_reclaimed\":{}}}",
+                report.bytes_reclaimed
+            )
+        );
+
+        // nothing is left to drop, and every report is appended to the log
+        let report = sst.compact_with_report().unwrap();
+        assert!(report.collected_tombstones.is_empty());
+        assert!(report.dropped_versions.is_empty());
+        let logged = std::fs::read_to_string(&audit_log).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged.starts_with("{\"merged\":[1,2],\"collected_tombstones\":[\"deleted\"]"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_read_only_file_system() {
+        let dir = "./test_tmp_read_only_fs";
+        let fs = MemFileSystem::default();
+        // the WAL is a file of the local file system
+        std::fs::create_dir_all(dir).unwrap();
+        let mut writer =
+            SSTable::with_disktable(FileDisktableBuilder::new(dir).file_system(fs.clone()), 3)
+                .unwrap();
+        assert!(writer.clear().is_ok());
+        ["a", "b", "c", "d"]
+            .iter()
+            .for_each(|key| writer.insert(*key, "value").unwrap());
+        assert_eq!(writer.stats().unwrap().generations, 1);
+        drop(writer);
+        let wal = std::fs::read(format!("{}/wal.log", dir)).unwrap();
+
+        fs.set_read_only(true);
+        let open = |builder: FileDisktableBuilder| {
+            SSTable::with_disktable(builder.file_system(fs.clone()), 3)
+        };
+        let mut reader = open(FileDisktableBuilder::new(dir)).unwrap();
+        // from the generation on disk and from the WAL
+        assert_eq!(reader.get("a").unwrap(), Some("value".to_string()));
+        assert_eq!(reader.get("d").unwrap(), Some("value".to_string()));
+        assert_eq!(reader.scan("", None).unwrap().len(), 4);
+        let denied = |result: Result<(), io::Error>| {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied)
+        };
+        denied(reader.insert("e", "value"));
+        denied(reader.delete_prefix("a"));
+        denied(reader.delete_range("a", "b"));
+        denied(reader.compact().map(|_| ()));
+        denied(reader.clear());
+        denied(reader.delete_returning("a").map(|_| ()));
+        denied(reader.delete("b"));
+        assert_eq!(reader.get("b").unwrap(), Some("value".to_string()));
+        assert_eq!(reader.get("e").unwrap(), None);
+        assert_eq!(std::fs::read(format!("{}/wal.log", dir)).unwrap(), wal);
+
+        // options which write on open can't be honored
+        vec![
+            FileDisktableBuilder::new(dir).read_only(false),
+            FileDisktableBuilder::new(dir).compact_on_open(true),
+        ]
+        .into_iter()
+        .for_each(|builder| {
+            let err = open(builder).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ReadOnlyFilesystem, "{}", err);
+        });
+        fs.set_read_only(false);
+        assert!(open(FileDisktableBuilder::new(dir).read_only(false)).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_follower_refresh() {
+        let dir = "./test_tmp_follower";
+        std::fs::create_dir_all(dir).unwrap();
+        let mut writer = SSTable::with_disktable(FileDisktableBuilder::new(dir), 2).unwrap();
+        writer.clear().unwrap();
+        writer.insert("a", "1").unwrap();
+        let mut follower = SSTable::open_follower(dir).unwrap();
+        // unflushed entries of the writer aren't visible
+        assert_eq!(follower.get("a").unwrap(), None);
+        assert_eq!(
+            follower.insert("b", "1").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        ["b", "c"]
+            .iter()
+            .for_each(|key| writer.insert(*key, "1").unwrap());
+        let generations = writer.stats().unwrap().generations;
+        assert!(generations > 0);
+        assert_eq!(follower.get("a").unwrap(), None);
+        assert_eq!(follower.refresh().unwrap().len(), generations);
+        assert_eq!(follower.get("a").unwrap(), Some("1".to_string()));
+        assert_eq!(follower.refresh().unwrap(), vec![]);
+
+        // generations written and compacted away between refreshes are never looked up
+        ["a", "b", "c", "d"]
+            .iter()
+            .for_each(|key| writer.insert(*key, "2").unwrap());
+        writer.compact().unwrap();
+        ["e", "f", "g"]
+            .iter()
+            .for_each(|key| writer.insert(*key, "2").unwrap());
+        assert!(!follower.refresh().unwrap().is_empty());
+        assert_eq!(follower.get("a").unwrap(), Some("2".to_string()));
+        assert_eq!(follower.get("e").unwrap(), Some("2".to_string()));
+        for (key, value) in follower.scan("", None).unwrap() {
+            assert_eq!(writer.get(key).unwrap(), Some(value));
+        }
+
+        assert_eq!(
+            writer.refresh().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let builder = FileDisktableBuilder::new(dir)
+            .follower(true)
+            .read_only(false);
+        assert_eq!(
+            SSTable::with_disktable(builder, 2).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_checkpoint() {
+        let dir = "./test_tmp_checkpoint";
+        let fs = MemFileSystem::default();
+        std::fs::create_dir_all(dir).unwrap();
+        // nothing is synced on flush, so only the checkpoint makes generations durable
+        let open = || {
+            let builder = FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .sync_on_flush(false);
+            SSTable::with_disktable(builder, 10).unwrap()
+        };
+        let mut sst = open();
+        sst.clear().unwrap();
+        sst.insert("a", "1").unwrap();
+        sst.insert("b", "1").unwrap();
+        sst.delete("b").unwrap();
+        let checkpoint = sst.checkpoint().unwrap();
+        assert_eq!(checkpoint, 1);
+        // nothing to flush
+        assert_eq!(sst.checkpoint().unwrap(), checkpoint);
+        sst.insert("c", "1").unwrap();
+        drop(sst);
+
+        // generations up to the checkpoint survive a crash, and the WAL holds only "c" then
+        fs.crash();
+        let sst = open();
+        assert_eq!(sst.stats().unwrap().generations, 1);
+        assert_eq!(
+            sst.find_detailed("a").unwrap(),
+            (Some("1".to_string()), FindSource::Disk(checkpoint))
+        );
+        assert_eq!(sst.get("b").unwrap(), None);
+        assert_eq!(sst.get("c").unwrap(), Some("1".to_string()));
+        drop(sst);
+
+        // a checkpoint failing to sync leaves the WAL covering what it flushed
+        let failing = FailingDirSyncs {
+            fs: fs.clone(),
+            failing: Arc::new(AtomicBool::new(false)),
+        };
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(failing.clone())
+            .sync_on_flush(false);
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.insert("d", "1").unwrap();
+        failing.failing.store(true, Ordering::SeqCst);
+        assert!(sst.checkpoint().is_err());
+        drop(sst);
+        fs.crash();
+        let mut sst = open();
+        assert_eq!(sst.get("d").unwrap(), Some("1".to_string()));
+        sst.clear().unwrap();
+        drop(sst);
+
+        // a checkpoint goes on while writes are throttled
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(fs.clone())
+            .max_generations(1);
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.insert("e", "1").unwrap();
+        let checkpoint = sst.checkpoint().unwrap();
+        assert!(sst.insert("f", "1").is_err());
+        assert_eq!(sst.checkpoint().unwrap(), checkpoint);
+        sst.clear().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_with_meta() {
+        let dir = "./test_tmp_sstable_find_with_meta";
+        let fs = MemFileSystem::default();
+        std::fs::create_dir_all(dir).unwrap();
+        let open = |entry_timestamps| {
+            let builder = FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .entry_timestamps(entry_timestamps);
+            SSTable::with_disktable(builder, 10).unwrap()
+        };
+        let mut sst = open(true);
+        sst.clear().unwrap();
+        sst.insert("a", "1").unwrap();
+        sst.checkpoint().unwrap();
+        let (value, flushed) = sst.find_with_meta("a").unwrap().unwrap();
+        assert_eq!(value, "1");
+
+        // an overwrite still in the memtable gives its value and when it was inserted
+        let before = SystemTime::now();
+        sst.insert("a", "2").unwrap();
+        let (value, inserted) = sst.find_with_meta("a").unwrap().unwrap();
+        assert_eq!(value, "2");
+        assert!(flushed <= before && before <= inserted);
+        // and keeps it until it's flushed, which stamps the record
+        sst.checkpoint().unwrap();
+        let (value, stamped) = sst.find_with_meta("a").unwrap().unwrap();
+        assert_eq!(value, "2");
+        assert!(inserted <= stamped);
+        sst.delete("a").unwrap();
+        assert_eq!(sst.find_with_meta("a").unwrap(), None);
+
+        // a value restored from the WAL is taken as written on open
+        sst.insert("b", "1").unwrap();
+        drop(sst);
+        let before = SystemTime::now();
+        let sst = open(true);
+        let (value, opened) = sst.find_with_meta("b").unwrap().unwrap();
+        assert_eq!(value, "1");
+        assert!(before <= opened);
+        drop(sst);
+
+        // without entry timestamps nothing is stamped
+        let mut sst = open(false);
+        sst.insert("c", "1").unwrap();
+        assert_eq!(sst.find_with_meta("b").unwrap(), None);
+        assert_eq!(sst.find_with_meta("c").unwrap(), None);
+        sst.clear().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // MemFileSystem failing syncs of directories while set
+    #[derive(Clone)]
+    struct FailingDirSyncs {
+        fs: MemFileSystem,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl FileSystem for FailingDirSyncs {
+        fn open(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.open(path)
+        }
+        fn create(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.create(path)
+        }
+        fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+            self.fs.rename(from, to)
+        }
+        fn remove(&self, path: &std::path::Path) -> io::Result<()> {
+            self.fs.remove(path)
+        }
+        fn sync_dir(&self, dir: &std::path::Path) -> io::Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(io::Error::other("sync failed"));
+            }
+            self.fs.sync_dir(dir)
+        }
+        fn read_dir(&self, dir: &std::path::Path) -> io::Result<Vec<DirEntry>> {
+            self.fs.read_dir(dir)
+        }
+        fn create_dir_all(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.create_dir_all(dir)
+        }
+    }
+
+    #[test]
+    fn test_sstable_export_legacy() {
+        let dir = "./test_tmp_export_legacy";
+        let out_dir = "./test_tmp_export_legacy_out";
+        let _ = std::fs::remove_dir_all(out_dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .block_size(256)
+            .inline_threshold(4)
+            .blob_threshold(64);
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.clear().unwrap();
+        (0..100).for_each(|i| {
+            let value = format!("{:0width$}", i, width = i % 80);
+            sst.insert(format!("key-{:03}", i), value).unwrap();
+        });
+        (0..100)
+            .filter(|i| i % 7 == 0)
+            .for_each(|i| sst.delete(format!("key-{:03}", i)).unwrap());
+        sst.delete_prefix("key-09").unwrap();
+        let live = sst.scan("", None).unwrap();
+        assert_eq!(sst.export_legacy(out_dir).unwrap(), live.len());
+        assert_eq!(
+            sst.export_legacy(out_dir).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        // read back as the original disktable did, by the offsets in the index
+        let fs: Arc<dyn FileSystem> = Arc::new(crate::sst::OsFileSystem);
+        assert_eq!(
+            crate::sst::disktable::read_legacy(&fs, out_dir).unwrap(),
+            live
+        );
+        let skip_index = std::fs::read_to_string(format!("{}/index_1_skip", out_dir)).unwrap();
+        assert_eq!(skip_index.lines().count(), live.len() / 30);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_par_scan() {
+        let dir = "./test_tmp_par_scan";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .comparator(Numeric);
+        let mut sst = SSTable::with_disktable(builder, 20).unwrap();
+        sst.clear().unwrap();
+        // generations of overlapping keys, and some of them deleted or only in memory
+        (0..200).for_each(|i| {
+            let key = ((i * 37) % 150).to_string();
+            sst.insert(key, format!("value-{}", i)).unwrap();
+        });
+        (0..150)
+            .filter(|i| i % 11 == 0)
+            .for_each(|i| sst.delete(i.to_string()).unwrap());
+        sst.insert("not-a-number", "value").unwrap();
+        assert!(sst.stats().unwrap().generations > 3);
+
+        let serial = sst.scan("", None).unwrap();
+        for ranges in [1, 2, 3, 8, 100] {
+            let parallel = sst
+                .par_scan(ranges)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(parallel, serial, "ranges: {}", ranges);
+        }
+        assert_eq!(
+            sst.par_scan(0).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_estimated_reads() {
+        let dir = "./test_tmp_estimated_reads";
+        std::fs::create_dir_all(dir).unwrap();
+        let (width, depth) = (256, 4);
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .track_reads(width, depth);
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.clear().unwrap();
+        (0..100).for_each(|i| sst.insert(format!("key-{}", i), "value").unwrap());
+        (0..100).for_each(|i| {
+            sst.get(format!("key-{}", i)).unwrap();
+        });
+        let mut buf = String::new();
+        (0..500).for_each(|_| {
+            sst.find_into("key-7", &mut buf).unwrap();
+        });
+        sst.find_detailed("missing").unwrap();
+
+        // over by at most e / width of all reads, with a probability of 1 - e^-depth
+        let reads = 100 + 500 + 1;
+        let bound = (std::f64::consts::E / width as f64 * reads as f64).ceil() as u64;
+        let hot = sst.estimated_reads("key-7");
+        assert!((501..=501 + bound).contains(&hot), "{}", hot);
+        let cold = sst.estimated_reads("key-42");
+        assert!((1..=1 + bound).contains(&cold), "{}", cold);
+        assert!(hot > cold);
+        assert!(sst.estimated_reads("missing") >= 1);
+        assert!(sst.estimated_reads("never-read") <= bound);
+
+        let untracked = SSTable::with_disktable(
+            FileDisktableBuilder::new(dir).file_system(MemFileSystem::default()),
+            10,
+        )
+        .unwrap();
+        untracked.get("key-7").unwrap();
+        assert_eq!(untracked.estimated_reads("key-7"), 0);
+        assert_eq!(
+            SSTable::with_disktable(FileDisktableBuilder::new(dir).track_reads(0, 4), 10)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_borrowed() {
+        let dir = "./test_tmp_find_borrowed";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 3).unwrap();
+        sst.clear().unwrap();
+        ["a", "b", "c", "d"]
+            .iter()
+            .for_each(|key| sst.insert(*key, format!("value-{}", key)).unwrap());
+        sst.insert("e", "value-e").unwrap();
+        sst.delete("d").unwrap();
+
+        // "a" to "d" are flushed, while "e" and the tombstone of "d" are in the memtable
+        match sst.find_borrowed("e").unwrap() {
+            Some(std::borrow::Cow::Borrowed(value)) => assert_eq!(value, "value-e"),
+            found => panic!("not borrowed from the memtable: {:?}", found),
+        }
+        match sst.find_borrowed("a").unwrap() {
+            Some(std::borrow::Cow::Owned(value)) => assert_eq!(value, "value-a"),
+            found => panic!("not owned from the disk: {:?}", found),
+        }
+        assert_eq!(sst.find_borrowed("d").unwrap(), None);
+        assert_eq!(sst.find_borrowed("missing").unwrap(), None);
+        assert_eq!(
+            sst.find_borrowed("b").unwrap().as_deref(),
+            sst.get("b").unwrap().as_deref()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_read_repair() {
+        let dir = "./test_tmp_read_repair";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .read_repair(2);
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.clear().unwrap();
+        (1..=3).for_each(|gen| {
+            sst.insert(format!("key-{}", gen), "value").unwrap();
+            sst.checkpoint().unwrap();
+        });
+
+        // generation 1 has two generations above it, so the key is rewritten forward
+        assert_eq!(sst.find_detailed("key-1").unwrap().1, FindSource::Disk(1));
+        assert_eq!(
+            sst.get_repairing("key-1").unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(
+            sst.find_detailed("key-1").unwrap(),
+            (Some("value".to_string()), FindSource::Memtable)
+        );
+        assert_eq!(
+            sst.get_repairing("key-1").unwrap(),
+            Some("value".to_string())
+        );
+        // while generation 2 isn't deep enough
+        assert_eq!(
+            sst.get_repairing("key-2").unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(sst.find_detailed("key-2").unwrap().1, FindSource::Disk(2));
+        assert_eq!(sst.get_repairing("missing").unwrap(), None);
+        assert_eq!(sst.find_detailed("missing").unwrap().1, FindSource::Miss);
+
+        // the repaired key is flushed into the newest generation
+        sst.checkpoint().unwrap();
+        assert_eq!(sst.find_detailed("key-1").unwrap().1, FindSource::Disk(4));
+
+        // reads aren't repaired unless it's set
+        let plain_dir = "./test_tmp_read_repair_plain";
+        std::fs::create_dir_all(plain_dir).unwrap();
+        let mut plain = SSTable::with_disktable(
+            FileDisktableBuilder::new(plain_dir).file_system(MemFileSystem::default()),
+            10,
+        )
+        .unwrap();
+        plain.clear().unwrap();
+        plain.insert("key", "value").unwrap();
+        plain.checkpoint().unwrap();
+        plain.insert("other", "value").unwrap();
+        plain.checkpoint().unwrap();
+        plain.insert("another", "value").unwrap();
+        plain.checkpoint().unwrap();
+        assert_eq!(
+            plain.get_repairing("key").unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(plain.find_detailed("key").unwrap().1, FindSource::Disk(1));
+
+        for builder in [
+            FileDisktableBuilder::new(dir).read_repair(0),
+            FileDisktableBuilder::new(dir)
+                .read_only(true)
+                .read_repair(1),
+        ] {
+            assert_eq!(
+                SSTable::with_disktable(builder, 10).err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        sst.clear().unwrap();
+        plain.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_scan_prefix_limit_rev() {
+        let dir = "./test_tmp_scan_prefix_limit_rev";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 100).unwrap();
+        sst.clear().unwrap();
+        let key = |user: usize, ts: usize| format!("user-{}/{:04}", user, ts);
+        for ts in 0..1000 {
+            sst.insert(key(6, ts), "older user").unwrap();
+            sst.insert(key(7, ts), format!("value-{}", ts)).unwrap();
+            sst.insert(key(8, ts), "newer user").unwrap();
+        }
+        // the newest entries are in the memtable, and those before on disk
+        sst.delete(key(7, 998)).unwrap();
+        sst.insert(key(7, 990), "overwritten").unwrap();
+        sst.checkpoint().unwrap();
+        sst.insert(key(7, 999), "latest").unwrap();
+
+        let data_reads = |sst: &SSTable| sst.stats().unwrap().data_reads;
+        let before = data_reads(&sst);
+        let latest = sst.scan_prefix_limit_rev("user-7/", 10).unwrap();
+        let expected: Vec<(String, String)> = std::iter::once((key(7, 999), "latest".to_string()))
+            .chain((989..=997).rev().map(|ts| {
+                let value = if ts == 990 {
+                    "overwritten".to_string()
+                } else {
+                    format!("value-{}", ts)
+                };
+                (key(7, ts), value)
+            }))
+            .collect();
+        assert_eq!(latest, expected);
+        let limited_reads = data_reads(&sst) - before;
+        assert!(limited_reads <= 9, "{}", limited_reads);
+
+        // a full scan of the prefix reads every value under it
+        let before = data_reads(&sst);
+        let all = sst.scan_lazy("user-7/", Some("user-70")).unwrap();
+        assert_eq!(all.len(), 999);
+        all.into_iter().for_each(|(_, handle)| {
+            handle.load().unwrap();
+        });
+        let full_reads = data_reads(&sst) - before;
+        assert!(full_reads >= 990, "{}", full_reads);
+
+        assert_eq!(
+            sst.scan_prefix_limit_rev("user-7/09", 2000).unwrap().len(),
+            99
+        );
+        assert!(sst.scan_prefix_limit_rev("user-9/", 10).unwrap().is_empty());
+        assert_eq!(
+            sst.scan_prefix_limit_rev("user-7/", 0).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        sst.clear().unwrap();
+
+        // keys under a prefix interleave with others in numeric order
+        let builder = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .comparator(Numeric);
+        let mut sst = SSTable::with_disktable(builder, 100).unwrap();
+        (1..=250).for_each(|i| sst.insert(i.to_string(), "value").unwrap());
+        sst.checkpoint().unwrap();
+        sst.insert("19", "latest").unwrap();
+        let keys: Vec<String> = sst
+            .scan_prefix_limit_rev("1", 4)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["199", "198", "197", "196"]);
+        assert_eq!(sst.scan_prefix_limit_rev("1", 200).unwrap().len(), 111);
+        assert_eq!(
+            sst.scan_prefix_limit_rev("19", 11).unwrap().last(),
+            Some(&("19".to_string(), "latest".to_string()))
+        );
+        sst.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_all_versions() {
+        let dir = "./test_tmp_find_all_versions";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.clear().unwrap();
+        sst.insert("key", "first").unwrap();
+        sst.insert("other", "value").unwrap();
+        sst.checkpoint().unwrap();
+        sst.delete("key").unwrap();
+        sst.checkpoint().unwrap();
+        sst.insert("key", "third").unwrap();
+        sst.checkpoint().unwrap();
+        // the memtable holds a newer value, which isn't on disk yet
+        sst.insert("key", "fourth").unwrap();
+
+        assert_eq!(
+            sst.find_all_versions("key").unwrap(),
+            vec![
+                (3, Some("third".to_string())),
+                (2, None),
+                (1, Some("first".to_string()))
+            ]
+        );
+        assert_eq!(
+            sst.find_all_versions("other").unwrap(),
+            vec![(1, Some("value".to_string()))]
+        );
+        assert!(sst.find_all_versions("missing").unwrap().is_empty());
+        sst.delete_prefix("oth").unwrap();
+        assert_eq!(sst.find_all_versions("other").unwrap(), vec![(1, None)]);
+        sst.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_degenerate_thresholds() {
+        let dir = "./test_tmp_degenerate_thresholds";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = || FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let err = SSTable::with_disktable(builder(), 0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "mem_max_entry must be at least 1");
+        let other = "./test_tmp_degenerate_thresholds_other";
+        for opened in [
+            SSTable::new(other, 0),
+            SSTable::with_immutable_memtables(other, 0),
+        ] {
+            assert_eq!(opened.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!Path::new(other).exists());
+
+        // the memtable holds a single entry, so every insert flushes
+        let mut sst = SSTable::with_disktable(builder(), 1).unwrap();
+        sst.clear().unwrap();
+        sst.insert("a", "1").unwrap();
+        assert_eq!(
+            sst.find_detailed("a").unwrap(),
+            (Some("1".to_string()), FindSource::Disk(1))
+        );
+        sst.insert("a", "2").unwrap();
+        assert_eq!(
+            sst.find_detailed("a").unwrap(),
+            (Some("2".to_string()), FindSource::Disk(2))
+        );
+        (0..10).for_each(|i| sst.insert(format!("key-{}", i), "value").unwrap());
+        assert_eq!(sst.stats().unwrap().generations, 12);
+        assert_eq!(sst.scan("", None).unwrap().len(), 11);
+        sst.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_find_with_deadline() {
+        let dir = "./test_tmp_find_with_deadline";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 10).unwrap();
+        sst.clear().unwrap();
+        (1..=3).for_each(|gen| {
+            sst.insert(format!("key-{}", gen), "value").unwrap();
+            sst.checkpoint().unwrap();
+        });
+        sst.insert("in-memory", "value").unwrap();
+
+        // nothing on disk is read once the deadline has passed
+        let index_reads = sst.stats().unwrap().index_reads;
+        let passed = std::time::Instant::now();
+        let err = sst.find_with_deadline("key-1", passed).unwrap_err();
+        assert!(Timeout::is_timeout(&err));
+        assert_eq!(sst.stats().unwrap().index_reads, index_reads);
+        assert_eq!(
+            sst.find_with_deadline("in-memory", passed).unwrap(),
+            Some("value".to_string())
+        );
+
+        let later = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            sst.find_with_deadline("key-1", later).unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(sst.find_with_deadline("missing", later).unwrap(), None);
+        assert_eq!(sst.stats().unwrap().index_reads, index_reads + 6);
+
+        let err: io::Error = Timeout.into();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(Timeout::is_timeout(&err));
+        sst.clear().unwrap();
+    }
+
+    // MemFileSystem holding renames of data files into place while the gate is closed,
+    // and failing them while armed
+    #[derive(Clone, Default)]
+    struct GatedRenames {
+        fs: MemFileSystem,
+        gate: Arc<(std::sync::Mutex<Gate>, std::sync::Condvar)>,
+        failing: Arc<AtomicBool>,
+    }
+
+    // notified on every change
+    #[derive(Default)]
+    struct Gate {
+        closed: bool,
+        // renames waiting at the gate
+        held: usize,
+        // renames let through while it's closed
+        passes: usize,
+    }
+
+    impl GatedRenames {
+        fn close_gate(&self) {
+            self.gate.0.lock().unwrap().closed = true;
+        }
+        fn open_gate(&self) {
+            self.gate.0.lock().unwrap().closed = false;
+            self.gate.1.notify_all();
+        }
+        fn pass_one(&self) {
+            self.gate.0.lock().unwrap().passes += 1;
+            self.gate.1.notify_all();
+        }
+        // until a rename waits at the gate and every pass is used
+        fn wait_for_held_rename(&self) {
+            let (gate, changed) = &*self.gate;
+            let _held = changed
+                .wait_while(gate.lock().unwrap(), |gate| {
+                    gate.held == 0 || gate.passes > 0
+                })
+                .unwrap();
+        }
+    }
+
+    impl FileSystem for GatedRenames {
+        fn open(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.open(path)
+        }
+        fn create(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.create(path)
+        }
+        fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+            let data_file = to
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("data_"));
+            if data_file && self.failing.load(Ordering::SeqCst) {
+                return Err(io::Error::other("rename failed"));
+            }
+            if data_file {
+                let (gate, changed) = &*self.gate;
+                let mut gate = gate.lock().unwrap();
+                gate.held += 1;
+                changed.notify_all();
+                let mut gate = changed
+                    .wait_while(gate, |gate| gate.closed && gate.passes == 0)
+                    .unwrap();
+                if gate.closed {
+                    gate.passes -= 1;
+                }
+                gate.held -= 1;
+                changed.notify_all();
+            }
+            self.fs.rename(from, to)
+        }
+        fn remove(&self, path: &std::path::Path) -> io::Result<()> {
+            self.fs.remove(path)
+        }
+        fn sync_dir(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.sync_dir(dir)
+        }
+        fn read_dir(&self, dir: &std::path::Path) -> io::Result<Vec<DirEntry>> {
+            self.fs.read_dir(dir)
+        }
+        fn create_dir_all(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.create_dir_all(dir)
+        }
+    }
+
+    #[test]
+    fn test_sstable_background_flush() {
+        let dir = "./test_tmp_background_flush";
+        std::fs::create_dir_all(dir).unwrap();
+        let fs = GatedRenames::default();
+        let builder = || FileDisktableBuilder::new(dir).file_system(fs.clone());
+        assert_eq!(
+            SSTable::with_disktable(builder().background_flush(0), 2)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let mut sst = SSTable::with_disktable(builder().background_flush(4), 2).unwrap();
+        sst.clear().unwrap();
+        let key = |i: usize| format!("key-{:02}", i);
+
+        // inserts and reads don't wait for flushes, which can't publish a generation
+        fs.close_gate();
+        (0..9).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        (0..9).for_each(|i| assert_eq!(sst.get(key(i)).unwrap(), Some(format!("value-{}", i))));
+        let queued = sst.frozen.len();
+        assert!(queued >= 2);
+        fs.open_gate();
+        assert_eq!(sst.stop_background_flush().unwrap(), queued);
+        assert!(sst.frozen.is_empty());
+        assert_eq!(sst.generations().unwrap().len(), queued);
+        (0..9).for_each(|i| assert_eq!(sst.get(key(i)).unwrap(), Some(format!("value-{}", i))));
+        sst.clear().unwrap();
+
+        // dropping the store flushes the queued memtables rather than leaving them to the WAL
+        let mut sst = SSTable::with_disktable(builder().background_flush(4), 2).unwrap();
+        fs.close_gate();
+        (0..9).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        let frozen_keys: Vec<String> = sst
+            .frozen
+            .iter()
+            .flat_map(|frozen| frozen.entries.keys().cloned())
+            .collect();
+        assert!(!frozen_keys.is_empty());
+        let opening = {
+            let fs = fs.clone();
+            std::thread::spawn(move || {
+                fs.wait_for_held_rename();
+                fs.open_gate();
+            })
+        };
+        drop(sst);
+        opening.join().unwrap();
+        let mut sst = SSTable::with_disktable(builder(), 2).unwrap();
+        frozen_keys
+            .iter()
+            .for_each(|key| assert_eq!(sst.find_all_versions(key.as_str()).unwrap().len(), 1));
+        sst.clear().unwrap();
+
+        // a failed flush leaves its memtable frozen until the next full memtable, which is
+        // written after it as flushes go back to the writing thread
+        let mut sst = SSTable::with_disktable(builder().background_flush(4), 2).unwrap();
+        fs.failing.store(true, Ordering::SeqCst);
+        sst.insert(key(0), "old").unwrap();
+        sst.insert(key(1), "old").unwrap();
+        assert!(sst.flush_frozen().is_err());
+        assert_eq!(sst.frozen.len(), 1);
+        fs.failing.store(false, Ordering::SeqCst);
+        sst.insert(key(0), "new").unwrap();
+        sst.insert(key(2), "new").unwrap();
+        assert!(sst.frozen.is_empty());
+        assert_eq!(sst.generations().unwrap().len(), 2);
+        assert_eq!(sst.get(key(0)).unwrap(), Some("new".to_string()));
+        assert_eq!(sst.get(key(1)).unwrap(), Some("old".to_string()));
+        sst.clear().unwrap();
+
+        // a write during a flush goes by the generations before it, so it's admitted during
+        // the first flush and throttled during the second
+        let mut sst =
+            SSTable::with_disktable(builder().background_flush(4).max_generations(1), 2).unwrap();
+        fs.close_gate();
+        (0..4).for_each(|i| sst.insert(key(i), "value").unwrap());
+        fs.wait_for_held_rename();
+        fs.pass_one();
+        fs.wait_for_held_rename();
+        let opening = {
+            let fs = fs.clone();
+            std::thread::spawn(move || fs.open_gate())
+        };
+        assert!(Throttled::is_throttled(
+            &sst.insert(key(4), "value").err().unwrap()
+        ));
+        opening.join().unwrap();
+        sst.flush_frozen().unwrap();
+        assert_eq!(sst.generations().unwrap().len(), 2);
+        sst.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_rename() {
+        let dir = "./test_tmp_sstable_rename";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        sst.clear().unwrap();
+        sst.insert("from", "value").unwrap();
+        assert!(sst.rename("from", "to").unwrap());
+        assert_eq!(sst.get("from").unwrap(), None);
+        assert_eq!(sst.get("to").unwrap(), Some("value".to_string()));
+        assert!(!sst.rename("from", "elsewhere").unwrap());
+        assert!(!sst.rename("missing", "elsewhere").unwrap());
+        assert_eq!(sst.get("elsewhere").unwrap(), None);
+
+        // the WAL restores both halves of the rename
+        drop(sst);
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert_eq!(sst.get("from").unwrap(), None);
+        assert_eq!(sst.get("to").unwrap(), Some("value".to_string()));
+
+        // a value on disk is moved as well, and the flush the rename fills the memtable up
+        // to writes both halves into the same generation
+        sst.checkpoint().unwrap();
+        sst.insert("a", "1").unwrap();
+        sst.insert("b", "2").unwrap();
+        sst.insert("c", "3").unwrap();
+        assert!(sst.rename("to", "moved").unwrap());
+        assert_eq!(sst.get("to").unwrap(), None);
+        assert_eq!(sst.get("moved").unwrap(), Some("value".to_string()));
+        let (newest, tombstone) = sst.find_all_versions("to").unwrap()[0].clone();
+        assert_eq!(tombstone, None);
+        assert_eq!(
+            sst.find_all_versions("moved").unwrap(),
+            vec![(newest, Some("value".to_string()))]
+        );
+        sst.clear().unwrap();
+    }
+
+    #[test]
+    fn test_sstable_estimate_range_count() {
+        let dir = "./test_tmp_estimate_range_count";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 500).unwrap();
+        sst.clear().unwrap();
+        // generations of 500 keys, and the last ones in the memtable
+        (0..3200).for_each(|i| {
+            sst.insert(format!("key-{:05}", i), format!("value-{}", i))
+                .unwrap();
+        });
+        assert!(sst.generations().unwrap().len() >= 6);
+        let ranges: Vec<(&str, Option<&str>)> = vec![
+            ("", None),
+            ("key-00000", Some("key-01000")),
+            ("key-00450", Some("key-00800")),
+            ("key-01234", Some("key-02500")),
+            ("key-02900", None),
+        ];
+        for (start, end) in ranges {
+            let actual = sst.scan(start, end).unwrap().len() as f64;
+            let estimate = sst.estimate_range_count(start, end).unwrap() as f64;
+            assert!(
+                actual / 1.5 <= estimate && estimate <= actual * 1.5,
+                "estimated {} of {} in [{}, {:?})",
+                estimate,
+                actual,
+                start,
+                end
+            );
+        }
+        assert_eq!(sst.estimate_range_count("key-10000", None).unwrap(), 0);
+        drop(sst);
+
+        // generations smaller than the skip index interval have their keys counted
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 5).unwrap();
+        sst.clear().unwrap();
+        (0..23).for_each(|i| {
+            sst.insert(format!("key-{:05}", i), format!("value-{}", i))
+                .unwrap();
+        });
+        assert!(sst.generations().unwrap().len() >= 3);
+        assert_eq!(
+            sst.estimate_range_count("key-00003", Some("key-00019"))
+                .unwrap(),
+            16
+        );
+        assert_eq!(sst.estimate_range_count("", None).unwrap(), 23);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_floor_ceiling() {
+        let dir = "./test_tmp_floor_ceiling";
+        std::fs::create_dir_all(dir).unwrap();
+        let builder = FileDisktableBuilder::new(dir).file_system(MemFileSystem::default());
+        let mut sst = SSTable::with_disktable(builder, 50).unwrap();
+        sst.clear().unwrap();
+        let key = |i: usize| format!("key-{:03}", i);
+        (0..200).for_each(|i| sst.insert(key(i), format!("v{}", i)).unwrap());
+        // deleted keys run across several keys of the skip indexes
+        (50..130).for_each(|i| sst.delete(key(i)).unwrap());
+        sst.insert("key-090x", "memtable").unwrap();
+        sst.delete(key(199)).unwrap();
+        assert!(sst.generations().unwrap().len() >= 2);
+
+        let record = |i: usize| Some((key(i), format!("v{}", i)));
+        // a deleted key goes to the live one before or after it
+        assert_eq!(sst.floor(&key(50)).unwrap(), record(49));
+        assert_eq!(
+            sst.ceiling(&key(50)).unwrap(),
+            Some(("key-090x".to_string(), "memtable".to_string()))
+        );
+        assert_eq!(sst.floor(&key(80)).unwrap(), record(49));
+        assert_eq!(
+            sst.floor(&key(129)).unwrap(),
+            Some(("key-090x".to_string(), "memtable".to_string()))
+        );
+        assert_eq!(sst.ceiling(&key(91)).unwrap(), record(130));
+        assert_eq!(sst.floor(&key(130)).unwrap(), record(130));
+        assert_eq!(sst.ceiling(&key(130)).unwrap(), record(130));
+        assert_eq!(sst.floor(&key(49)).unwrap(), record(49));
+        assert_eq!(sst.floor("key-048z").unwrap(), record(48));
+        assert_eq!(sst.ceiling("key-048z").unwrap(), record(49));
+        // the ends of the keys
+        assert_eq!(sst.floor("a").unwrap(), None);
+        assert_eq!(sst.ceiling("a").unwrap(), record(0));
+        assert_eq!(sst.floor("z").unwrap(), record(198));
+        assert_eq!(sst.ceiling(&key(199)).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_export_snapshot() {
+        let dir = "./test_tmp_export_snapshot";
+        let out_dir = "./test_tmp_export_snapshot_out";
+        let _ = std::fs::remove_dir_all(out_dir);
+        std::fs::create_dir_all(out_dir).unwrap();
+        let mut sst = SSTable::with_disktable(FileDisktableBuilder::new(dir), 20).unwrap();
+        sst.clear().unwrap();
+        let key = |i: usize| format!("user:{:04}", i);
+        (0..100).for_each(|i| sst.insert(key(i), format!("v{}", i)).unwrap());
+        // overwritten and deleted keys on disk and in the memtable
+        (0..100)
+            .step_by(7)
+            .for_each(|i| sst.insert(key(i), "new").unwrap());
+        (0..100)
+            .step_by(5)
+            .for_each(|i| sst.delete(key(i)).unwrap());
+        assert!(sst.generations().unwrap().len() > 1);
+
+        let path = |name: &str| std::path::Path::new(out_dir).join(name);
+        for compressed in [false, true].iter() {
+            let path = path(&format!("snapshot_{}", compressed));
+            let options = SnapshotOptions {
+                compressed: *compressed,
+                block_records: 16,
+            };
+            let written = sst.export_snapshot(&path, &options).unwrap();
+            assert_eq!(written, sst.scan("", None).unwrap().len());
+            let reader = SnapshotReader::open(&path).unwrap();
+            assert_eq!(reader.blocks(), written.div_ceil(16));
+            (0..110).for_each(|i| {
+                assert_eq!(
+                    reader.get(&key(i)).unwrap(),
+                    sst.get(key(i)).unwrap(),
+                    "{}",
+                    i
+                )
+            });
+            assert_eq!(reader.get("a").unwrap(), None);
+            assert_eq!(
+                reader.iter().collect::<io::Result<Vec<_>>>().unwrap(),
+                sst.scan("", None).unwrap()
+            );
+            let err = sst.export_snapshot(&path, &options).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        }
+        let len = |name: &str| std::fs::metadata(path(name)).unwrap().len();
+        assert!(len("snapshot_true") < len("snapshot_false"));
+
+        // a flipped byte fails the block holding it
+        let mut bytes = std::fs::read(path("snapshot_true")).unwrap();
+        let at = bytes.len() / 2;
+        bytes[at] ^= 0xff;
+        std::fs::write(path("snapshot_true"), bytes).unwrap();
+        let reader = SnapshotReader::open(path("snapshot_true")).unwrap();
+        let err = reader.iter().collect::<io::Result<Vec<_>>>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let options = SnapshotOptions {
+            block_records: 0,
+            ..SnapshotOptions::default()
+        };
+        let err = sst.export_snapshot(&path("empty"), &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        sst.clear().unwrap();
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_skip_redundant_writes() {
+        let dir = "./test_tmp_skip_redundant_writes";
+        let _ = std::fs::remove_dir_all(dir);
+        let mut sst = SSTable::with_disktable(
+            FileDisktableBuilder::new(dir)
+                .changelog(true)
+                .skip_redundant_writes(true),
+            4,
+        )
+        .unwrap();
+        (0..3).for_each(|i| sst.insert(format!("key-{}", i), "1").unwrap());
+        assert_eq!(sst.last_sequence(), Some(3));
+
+        // a value the memtable holds is neither logged nor counted toward the threshold
+        (0..10).for_each(|_| sst.insert("key-0", "1").unwrap());
+        assert_eq!(sst.last_sequence(), Some(3));
+        assert!(sst.generations().unwrap().is_empty());
+        sst.insert("key-0", "2").unwrap();
+        assert_eq!(sst.last_sequence(), Some(4));
+        assert_eq!(sst.get("key-0").unwrap(), Some("2".to_string()));
+        sst.delete("key-1").unwrap();
+        sst.insert("key-1", "1").unwrap();
+        assert_eq!(sst.get("key-1").unwrap(), Some("1".to_string()));
+
+        // a flushed value is written again, as the disk isn't read
+        sst.insert("key-3", "1").unwrap();
+        assert_eq!(sst.generations().unwrap().len(), 1);
+        let before = sst.last_sequence();
+        sst.insert("key-0", "2").unwrap();
+        assert_eq!(sst.last_sequence(), before.map(|seq| seq + 1));
+        sst.clear().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_scan_filter() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_scan_filter";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        (0..40).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        sst.insert(key(13), "overwritten").unwrap();
+        sst.delete(key(23)).unwrap();
+        let passes = |key: &str| key.ends_with('3');
+        let data_reads = |sst: &SSTable| sst.stats().unwrap().data_reads;
+
+        let on_disk = sst
+            .scan_lazy("", None)
+            .unwrap()
+            .into_iter()
+            .filter(|(key, handle)| passes(key) && handle.location().is_some())
+            .count() as u64;
+        assert!(on_disk > 0);
+        let reads = data_reads(&sst);
+        let filtered = sst.scan_filter("", None, passes).unwrap();
+        // values are read for the keys passing the predicate only
+        assert_eq!(data_reads(&sst), reads + on_disk);
+        assert_eq!(
+            filtered,
+            vec![
+                (key(3), "value-3".to_string()),
+                (key(13), "overwritten".to_string()),
+                (key(33), "value-33".to_string()),
+            ]
+        );
+        assert_eq!(
+            sst.scan_filter(&key(10), Some(&key(30)), passes).unwrap(),
+            vec![(key(13), "overwritten".to_string())]
+        );
+        assert!(sst.scan_filter("", None, |_| false).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_health_check() {
+        let dir = "./test_tmp_health_check";
+        let fs = MemFileSystem::default();
+        // the WAL is a file of the local file system
+        std::fs::create_dir_all(dir).unwrap();
+        let open = |builder: FileDisktableBuilder| {
+            SSTable::with_disktable(builder.file_system(fs.clone()), 2).unwrap()
+        };
+        let mut sst = open(FileDisktableBuilder::new(dir));
+        assert!(sst.clear().is_ok());
+        let healthy = HealthStatus {
+            read_only: false,
+            writable: Some(true),
+            generations_loadable: true,
+            busy: false,
+            problems: vec![],
+        };
+        assert_eq!(sst.health_check(), healthy);
+        ["a", "b", "c", "d"]
+            .iter()
+            .for_each(|key| sst.insert(*key, "value").unwrap());
+        let status = sst.health_check();
+        assert!(status.is_healthy(), "{:?}", status);
+        // the sentinel is gone, and the keys are left as they were
+        let sentinel = Path::new(dir).join("health_check");
+        assert_eq!(
+            fs.open(&sentinel).err().map(|err| err.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        assert_eq!(sst.scan("", None).unwrap().len(), 4);
+
+        fs.set_read_only(true);
+        let status = sst.health_check();
+        assert!(!status.is_healthy());
+        assert_eq!(status.writable, Some(false));
+        assert!(status.generations_loadable);
+        assert_eq!(status.problems.len(), 1, "{:?}", status.problems);
+        // nothing is written into a store opened read only
+        let reader = open(FileDisktableBuilder::new(dir));
+        assert_eq!(
+            reader.health_check(),
+            HealthStatus {
+                read_only: true,
+                writable: None,
+                ..healthy.clone()
+            }
+        );
+        fs.set_read_only(false);
+        assert_eq!(sst.health_check(), healthy);
+
+        // a check doesn't wait for the disktable
+        let held = sst.disktable();
+        let status = sst.health_check();
+        assert!(status.busy && !status.is_healthy(), "{:?}", status);
+        drop(held);
+        assert_eq!(sst.health_check(), healthy);
+
+        let manifest = fs.create(&Path::new(dir).join("MANIFEST")).unwrap();
+        manifest.write(b"not a manifest\n").unwrap();
+        let status = sst.health_check();
+        assert_eq!(status.writable, Some(true));
+        assert!(!status.generations_loadable);
+        assert!(!status.is_healthy());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_diff_against() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_diff_against";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        // on disk and in the memtable
+        (0..20).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        sst.insert(key(5), "changed").unwrap();
+        sst.delete(key(7)).unwrap();
+        let other: Vec<(String, String)> = (2..24)
+            .filter(|i| *i != 11)
+            .map(|i| (key(i), format!("value-{}", i)))
+            .collect();
+
+        let diffs: Vec<Diff> = sst
+            .diff_against(other.clone())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let only_in_store = |i| Diff::OnlyInStore {
+            key: key(i),
+            value: format!("value-{}", i),
+        };
+        let only_in_other = |i| Diff::OnlyInOther {
+            key: key(i),
+            value: format!("value-{}", i),
+        };
+        assert_eq!(
+            diffs,
+            vec![
+                only_in_store(0),
+                only_in_store(1),
+                Diff::Differs {
+                    key: key(5),
+                    store: "changed".to_string(),
+                    other: "value-5".to_string(),
+                },
+                // deleted from the store
+                only_in_other(7),
+                only_in_store(11),
+                only_in_other(20),
+                only_in_other(21),
+                only_in_other(22),
+                only_in_other(23),
+            ]
+        );
+        assert_eq!(
+            sst.diff_against(sst.scan("", None).unwrap())
+                .unwrap()
+                .count(),
+            0
+        );
+        assert_eq!(sst.diff_against(vec![]).unwrap().count(), 19);
+
+        // the other side is checked to be sorted as it's read
+        let mut unsorted = sst
+            .diff_against(vec![
+                (key(0), "value-0".to_string()),
+                (key(3), "value-3".to_string()),
+                (key(2), "value-2".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(unsorted.next().unwrap().unwrap(), only_in_store(1));
+        // key 2 of the store comes before key 3 of the other side, and key 2 of the other side
+        // is read once key 3 is matched
+        assert_eq!(unsorted.next().unwrap().unwrap(), only_in_store(2));
+        assert_eq!(
+            unsorted.next().unwrap().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(unsorted.next().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sstable_scan_timed() {
+        let key = |i| format!("key-{:02}", i);
+        let dir = "./test_tmp_scan_timed";
+        let mut sst = SSTable::new(dir, 4).unwrap();
+        assert!(sst.clear().is_ok());
+        (0..30).for_each(|i| sst.insert(key(i), format!("value-{}", i)).unwrap());
+        sst.delete(key(1)).unwrap();
+        sst.delete(key(17)).unwrap();
+        let scanned = sst.scan("", None).unwrap();
+
+        // a budget run out already still goes past a key, and the token resumes at the next
+        // one, which may be deleted
+        let (page, next) = sst.scan_timed("", None, Duration::ZERO).unwrap();
+        assert_eq!(page, scanned[..1].to_vec());
+        assert_eq!(next, Some(key(1)));
+        assert_eq!(
+            sst.scan_timed(&key(1), None, Duration::ZERO).unwrap(),
+            (vec![], Some(key(2)))
+        );
+        let mut resumed = vec![];
+        let mut start = Some(String::new());
+        while let Some(from) = start {
+            let (page, next) = sst.scan_timed(&from, None, Duration::ZERO).unwrap();
+            assert!(next.as_ref().is_none_or(|next| *next > from));
+            resumed.extend(page);
+            start = next;
+        }
+        assert_eq!(resumed, scanned);
+
+        // a budget long enough gathers a scan in full
+        let budget = Duration::from_secs(60);
+        assert_eq!(sst.scan_timed("", None, budget).unwrap(), (scanned, None));
+        let (page, next) = sst.scan_timed(&key(10), Some(&key(20)), budget).unwrap();
+        assert_eq!(page, sst.scan(&key(10), Some(&key(20))).unwrap());
+        assert_eq!(next, None);
+        let (page, next) = sst
+            .scan_timed(&key(10), Some(&key(20)), Duration::ZERO)
+            .unwrap();
+        assert_eq!(page, vec![(key(10), "value-10".to_string())]);
+        assert_eq!(next, Some(key(11)));
+        assert_eq!(
+            sst.scan_timed("zzz", None, Duration::ZERO).unwrap(),
+            (vec![], None)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,115 @@
+use super::{disktable::Disktable, memtable::MemtableEntries};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub(crate) type SharedDisktable = Arc<Mutex<Box<dyn Disktable + Send>>>;
+
+/// A thread flushing memtables into the disktable in the order they're queued, through
+/// a bounded queue which makes `push` wait while it's full. The thread stops at the first
+/// flush which fails and drops the memtables queued after it, so that a generation is never
+/// written before an older one. Dropping it waits for the queued memtables to be flushed.
+pub(crate) struct BackgroundFlush {
+    queue: Option<SyncSender<MemtableEntries<String, String>>>,
+    // the outcome and the latency of each flush in the order of the queue
+    flushed: Receiver<(io::Result<()>, Duration)>,
+    // memtables pushed whose outcome hasn't been received yet
+    in_flight: usize,
+    // whether a write would be throttled as of the last flush or check of the disktable,
+    // which writes go by while a flush holds it
+    throttled: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl BackgroundFlush {
+    pub fn start(disktable: SharedDisktable, capacity: usize) -> BackgroundFlush {
+        let (queue, queued) = mpsc::sync_channel::<MemtableEntries<String, String>>(capacity);
+        let (done, flushed) = mpsc::channel();
+        let throttled = Arc::new(AtomicBool::new(disktable.lock().unwrap().throttled()));
+        let throttled_after_flush = throttled.clone();
+        let worker = thread::spawn(move || {
+            for mem in queued {
+                let started = Instant::now();
+                let result = {
+                    let mut disktable = disktable.lock().unwrap();
+                    let result = disktable.flush(mem);
+                    throttled_after_flush.store(disktable.throttled(), Ordering::SeqCst);
+                    result
+                };
+                let failed = result.is_err();
+                if done.send((result, started.elapsed())).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        BackgroundFlush {
+            queue: Some(queue),
+            flushed,
+            in_flight: 0,
+            throttled,
+            worker: Some(worker),
+        }
+    }
+
+    // false once the thread has stopped on a failed flush, which leaves the memtable unqueued
+    pub fn push(&mut self, mem: MemtableEntries<String, String>) -> bool {
+        let queue = self.queue.as_ref().expect("queue is open until dropped");
+        let pushed = queue.send(mem).is_ok();
+        if pushed {
+            self.in_flight += 1;
+        }
+        pushed
+    }
+
+    // whether a write would be throttled as of the last flush or `set_throttled`
+    pub fn throttled(&self) -> bool {
+        self.throttled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_throttled(&self, throttled: bool) {
+        self.throttled.store(throttled, Ordering::SeqCst);
+    }
+
+    // outcomes of the flushes completed since the last call in the order of the queue,
+    // or of every memtable pushed when waiting. none follows a failed one
+    pub fn completed(&mut self, wait: bool) -> Vec<(io::Result<()>, Duration)> {
+        let mut completed = vec![];
+        while self.in_flight > 0 {
+            let next = if wait {
+                self.flushed.recv().ok()
+            } else {
+                self.flushed.try_recv().ok()
+            };
+            let (result, latency) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            self.in_flight -= 1;
+            let failed = result.is_err();
+            completed.push((result, latency));
+            if failed {
+                self.in_flight = 0;
+            }
+        }
+        completed
+    }
+}
+
+impl Drop for BackgroundFlush {
+    fn drop(&mut self) {
+        // the thread drains the queue and stops once it's closed
+        drop(self.queue.take());
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("background flush thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{convert::TryInto, io};
+
+/// Bloom filter over keys, which never misses an inserted key and wrongly reports
+/// a key which wasn't inserted with a false positive rate around the one it was sized for.
+///
+/// Serialized by `to_bytes` as `[hash count: u32][bit count: u64][bits]` in little endian.
+/// Bit `i` is bit `i % 8` of byte `i / 8`, and a key sets bits `(h1 + n * h2) % bit count`
+/// for `n` in `0..hash count`, where `h1` and `h2` are 64 bit FNV-1a hashes of the key
+/// with offset bases `FNV_OFFSET` and `FNV_OFFSET ^ SEED`.
+///
+/// Keys are hashed as their bytes whatever the comparator of the store orders them by,
+/// as two keys are the same key only when their bytes are, e.g. "7" and "007" under `Numeric`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    num_hashes: u32,
+    num_bits: u64,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    pub const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    const HEADER_LEN: usize = 4 + 8;
+
+    /// A filter sized for `expected_keys` keys at the false positive rate `fpr`.
+    pub fn new(expected_keys: usize, fpr: f64) -> BloomFilter {
+        assert!(0.0 < fpr && fpr < 1.0, "invalid fpr: {}", fpr);
+        let n = expected_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * fpr.ln() / (ln2 * ln2)).ceil().max(8.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            num_hashes,
+            num_bits,
+            bits: vec![0; num_bits.div_ceil(8) as usize],
+        }
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for bit in self.bit_positions(Self::hash(key)) {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Bits the filter was sized with.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// false if the key was never inserted, true if it probably was.
+    pub fn contains(&self, key: &str) -> bool {
+        self.contains_hashed(Self::hash(key))
+    }
+
+    // the hashes of the key its bits are set by, which `contains_hashed` tests against
+    // filters of any size, so that a key tested against many filters is hashed once
+    pub(crate) fn hash(key: &str) -> (u64, u64) {
+        (
+            Self::fnv1a(key.as_bytes(), Self::FNV_OFFSET),
+            Self::fnv1a(key.as_bytes(), Self::FNV_OFFSET ^ Self::SEED),
+        )
+    }
+
+    pub(crate) fn contains_hashed(&self, hash: (u64, u64)) -> bool {
+        self.bit_positions(hash)
+            .all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            &self.num_hashes.to_le_bytes()[..],
+            &self.num_bits.to_le_bytes(),
+            &self.bits,
+        ]
+        .concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<BloomFilter> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(invalid("bloom filter is too short"));
+        }
+        let num_hashes = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let num_bits = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        let bits = bytes[Self::HEADER_LEN..].to_vec();
+        if num_hashes == 0 || num_bits == 0 || bits.len() as u64 != num_bits.div_ceil(8) {
+            return Err(invalid("bloom filter header doesn't match its bits"));
+        }
+        Ok(BloomFilter {
+            num_hashes,
+            num_bits,
+            bits,
+        })
+    }
+
+    fn bit_positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = u64> {
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |n| h1.wrapping_add(n.wrapping_mul(h2)) % num_bits)
+    }
+
+    fn fnv1a(bytes: &[u8], offset: u64) -> u64 {
+        bytes.iter().fold(offset, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(Self::FNV_PRIME)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        (0..1000).for_each(|i| filter.insert(&format!("key-{}", i)));
+        let restored = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(restored, filter);
+        assert!((0..1000).all(|i| restored.contains(&format!("key-{}", i))));
+
+        let false_positives = (0..10000)
+            .filter(|i| restored.contains(&format!("other-{}", i)))
+            .count();
+        assert!(
+            false_positives < 300,
+            "false positives: {}",
+            false_positives
+        );
+        assert!(BloomFilter::from_bytes(&[1, 2, 3]).is_err());
+    }
+}
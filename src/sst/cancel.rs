@@ -0,0 +1,58 @@
+use std::{
+    error::Error,
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Shared flag to abort long running scans and compactions, e.g. on shutdown.
+/// Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+    // Err(Cancelled) once the token has been cancelled
+    pub(crate) fn check(token: Option<&CancellationToken>) -> io::Result<()> {
+        match token {
+            Some(token) if token.is_cancelled() => Err(Cancelled.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The error wrapped in `io::Error` when an operation was cancelled by a `CancellationToken`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Cancelled {
+    pub fn is_cancelled(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> io::Error {
+        io::Error::other(cancelled)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::sst::rich_file::*;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+/// A mutation recorded in the changelog, see `SSTable::changes_since`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Set { key: String, value: String },
+    Delete { key: String },
+    Clear,
+    DeletePrefix { prefix: String },
+    DeleteRange { start: String, end: String },
+    Rename { from: String, to: String },
+}
+
+/// Every mutation of a store in order along with its sequence number, which starts at 1
+/// and survives restarts. The log is never truncated, unlike the WAL.
+pub(crate) struct Changelog {
+    dir_name: String,
+    writer: BufWriter<File>,
+    last_seq: u64,
+    // bytes of the records written
+    len: u64,
+    // (sequence, offset) of every `SAMPLE_INTERVAL`-th record, which `since` seeks from
+    samples: Vec<(u64, u64)>,
+}
+
+impl Changelog {
+    const FILE_NAME: &'static str = "changelog";
+    const TAG_SET: u8 = 0;
+    const TAG_DELETE: u8 = 1;
+    const TAG_CLEAR: u8 = 2;
+    const TAG_DELETE_PREFIX: u8 = 3;
+    const TAG_DELETE_RANGE: u8 = 4;
+    const TAG_RENAME: u8 = 5;
+    const SAMPLE_INTERVAL: u64 = 256;
+
+    // a record torn by a crash at the end of the log is cut off
+    pub fn open(dir_name: &str) -> io::Result<Changelog> {
+        let file = RichFile::open_file(dir_name, Self::FILE_NAME, FileOption::Append)?;
+        let mut reader = BufReader::new(&file.underlying);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut last_seq = 0;
+        let mut valid_len = 0;
+        let mut samples = vec![];
+        while let Some((seq, op)) = Self::read_next(&mut reader)? {
+            if seq.is_multiple_of(Self::SAMPLE_INTERVAL) {
+                samples.push((seq, valid_len));
+            }
+            last_seq = seq;
+            valid_len += Self::encode(seq, &op).len() as u64;
+        }
+        file.underlying.set_len(valid_len)?;
+        Ok(Changelog {
+            dir_name: dir_name.to_string(),
+            writer: BufWriter::new(file.underlying),
+            last_seq,
+            len: valid_len,
+            samples,
+        })
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    // record the op under the next sequence number
+    pub fn append(&mut self, op: &Op) -> io::Result<u64> {
+        let seq = self.last_seq + 1;
+        let record = Self::encode(seq, op);
+        self.writer.write_all(&record)?;
+        self.writer.flush()?;
+        if seq.is_multiple_of(Self::SAMPLE_INTERVAL) {
+            self.samples.push((seq, self.len));
+        }
+        self.last_seq = seq;
+        self.len += record.len() as u64;
+        Ok(seq)
+    }
+
+    // ops recorded after the sequence number, read lazily from the log from the last
+    // sampled record up to the first of them
+    pub fn since(&self, seq: u64) -> io::Result<Changes> {
+        let file = RichFile::open_file(&self.dir_name, Self::FILE_NAME, FileOption::ReadOnly)?;
+        let start = match self
+            .samples
+            .binary_search_by_key(&seq.saturating_add(1), |(sampled, _)| *sampled)
+        {
+            Ok(at) => self.samples[at].1,
+            Err(0) => 0,
+            Err(at) => self.samples[at - 1].1,
+        };
+        let mut reader = BufReader::new(file.underlying);
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(Changes {
+            reader,
+            after: seq,
+            done: false,
+        })
+    }
+
+    /*
+    Record Layout:
+    [sequence][tag][key length][key data][value length][value data]
+    <-8 byte-><1b-><--4 byte--><key_len-><--4 byte----><value_len->
+    tag is 0 for a set, 1 for a delete without value, 2 for a clear without key and value,
+    3 for a prefix delete with the prefix as the key and without value,
+    4 for a range delete with the start as the key and the end as the value,
+    and 5 for a rename with the key renamed from as the key and the one to as the value.
+    */
+    fn encode(seq: u64, op: &Op) -> Vec<u8> {
+        let field = |bytes: &[u8]| [&(bytes.len() as u32).to_le_bytes()[..], bytes].concat();
+        let mut record = seq.to_le_bytes().to_vec();
+        match op {
+            Op::Set { key, value } => {
+                record.push(Self::TAG_SET);
+                record.extend(field(key.as_bytes()));
+                record.extend(field(value.as_bytes()));
+            }
+            Op::Delete { key } => {
+                record.push(Self::TAG_DELETE);
+                record.extend(field(key.as_bytes()));
+            }
+            Op::Clear => record.push(Self::TAG_CLEAR),
+            Op::DeletePrefix { prefix } => {
+                record.push(Self::TAG_DELETE_PREFIX);
+                record.extend(field(prefix.as_bytes()));
+            }
+            Op::DeleteRange { start, end } => {
+                record.push(Self::TAG_DELETE_RANGE);
+                record.extend(field(start.as_bytes()));
+                record.extend(field(end.as_bytes()));
+            }
+            Op::Rename { from, to } => {
+                record.push(Self::TAG_RENAME);
+                record.extend(field(from.as_bytes()));
+                record.extend(field(to.as_bytes()));
+            }
+        }
+        record
+    }
+
+    // None at the end of the log, including a torn record
+    fn read_next(reader: &mut impl Read) -> io::Result<Option<(u64, Op)>> {
+        let mut head = [0u8; 9];
+        if !Self::read_exact_or_eof(reader, &mut head)? {
+            return Ok(None);
+        }
+        let seq = u64::from_le_bytes(head[0..8].try_into().unwrap());
+        let field = |reader: &mut dyn Read| -> io::Result<Option<String>> {
+            let mut len = [0u8; 4];
+            if !Self::read_exact_or_eof(reader, &mut len)? {
+                return Ok(None);
+            }
+            let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+            if !Self::read_exact_or_eof(reader, &mut bytes)? {
+                return Ok(None);
+            }
+            String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        };
+        let op = match head[8] {
+            Self::TAG_SET => match (field(reader)?, field(reader)?) {
+                (Some(key), Some(value)) => Op::Set { key, value },
+                _ => return Ok(None),
+            },
+            Self::TAG_DELETE => match field(reader)? {
+                Some(key) => Op::Delete { key },
+                None => return Ok(None),
+            },
+            Self::TAG_CLEAR => Op::Clear,
+            Self::TAG_DELETE_PREFIX => match field(reader)? {
+                Some(prefix) => Op::DeletePrefix { prefix },
+                None => return Ok(None),
+            },
+            Self::TAG_DELETE_RANGE => match (field(reader)?, field(reader)?) {
+                (Some(start), Some(end)) => Op::DeleteRange { start, end },
+                _ => return Ok(None),
+            },
+            Self::TAG_RENAME => match (field(reader)?, field(reader)?) {
+                (Some(from), Some(to)) => Op::Rename { from, to },
+                _ => return Ok(None),
+            },
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown tag({}) of the changelog record {}", tag, seq),
+                ))
+            }
+        };
+        Ok(Some((seq, op)))
+    }
+
+    fn read_exact_or_eof(reader: &mut (impl Read + ?Sized), buf: &mut [u8]) -> io::Result<bool> {
+        match reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Ops of the changelog after a sequence number with their sequence numbers, in order.
+pub struct Changes {
+    reader: BufReader<File>,
+    after: u64,
+    done: bool,
+}
+
+impl Iterator for Changes {
+    type Item = io::Result<(u64, Op)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match Changelog::read_next(&mut self.reader) {
+                Ok(Some((seq, _))) if seq <= self.after => continue,
+                Ok(Some(change)) => return Some(Ok(change)),
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,133 @@
+use crate::sst::SSTable;
+use std::{collections::HashMap, io, path::Path};
+
+/// Handle of a keyspace opened by `ColumnFamilies`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ColumnFamily {
+    name: String,
+}
+
+impl ColumnFamily {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Separate keyspaces under one directory. Each column family lives in a subdirectory
+/// named after it, with its own memtable, WAL and generations, so that it's flushed and
+/// compacted independently of the others.
+///
+/// A subdirectory stands for the file-name prefix of a column family. A store names its
+/// WAL, manifest, changelog and range tombstones by fixed names, and lists its directory
+/// to find generations, pick up those of a writer and sync them, so a prefix would have
+/// to be threaded through each of them, while a subdirectory keeps them apart as they are.
+pub struct ColumnFamilies {
+    dir_name: String,
+    mem_max_entry: usize,
+    families: HashMap<String, SSTable>,
+}
+
+impl ColumnFamilies {
+    /// Open every column family which already exists in the directory.
+    pub fn open(dir_name: &str, mem_max_entry: usize) -> Result<ColumnFamilies, io::Error> {
+        std::fs::create_dir_all(dir_name)?;
+        let mut families = HashMap::new();
+        for entry in std::fs::read_dir(dir_name)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() && Self::is_valid_name(&name) {
+                let sst = SSTable::new(&Self::family_dir(dir_name, &name), mem_max_entry)?;
+                families.insert(name, sst);
+            }
+        }
+        Ok(ColumnFamilies {
+            dir_name: dir_name.to_string(),
+            mem_max_entry,
+            families,
+        })
+    }
+
+    // a name has to be usable as a directory name
+    fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    fn family_dir(dir_name: &str, name: &str) -> String {
+        Path::new(dir_name).join(name).to_string_lossy().to_string()
+    }
+
+    /// The handle of the column family, which is created if it doesn't exist yet.
+    pub fn column_family(&mut self, name: &str) -> Result<ColumnFamily, io::Error> {
+        if !Self::is_valid_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid column family name: {:?}, use [A-Za-z0-9_-] only",
+                    name
+                ),
+            ));
+        }
+        if !self.families.contains_key(name) {
+            let sst = SSTable::new(&Self::family_dir(&self.dir_name, name), self.mem_max_entry)?;
+            self.families.insert(name.to_string(), sst);
+        }
+        Ok(ColumnFamily {
+            name: name.to_string(),
+        })
+    }
+
+    /// Names of the opened column families in no particular order.
+    pub fn names(&self) -> Vec<&str> {
+        self.families.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// The SSTable behind the column family, for operations other than reads and writes.
+    /// Fails with `NotFound` for a handle of a column family this one hasn't opened, e.g.
+    /// one of another directory.
+    pub fn table(&self, cf: &ColumnFamily) -> Result<&SSTable, io::Error> {
+        self.families
+            .get(&cf.name)
+            .ok_or_else(|| Self::not_opened(cf))
+    }
+
+    pub fn table_mut(&mut self, cf: &ColumnFamily) -> Result<&mut SSTable, io::Error> {
+        self.families
+            .get_mut(&cf.name)
+            .ok_or_else(|| Self::not_opened(cf))
+    }
+
+    fn not_opened(cf: &ColumnFamily) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("column family {:?} is not opened", cf.name),
+        )
+    }
+
+    pub fn get(
+        &self,
+        cf: &ColumnFamily,
+        key: impl Into<String>,
+    ) -> Result<Option<String>, io::Error> {
+        self.table(cf)?.get(key)
+    }
+
+    pub fn insert(
+        &mut self,
+        cf: &ColumnFamily,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), io::Error> {
+        self.table_mut(cf)?.insert(key, value)
+    }
+
+    pub fn delete(&mut self, cf: &ColumnFamily, key: impl Into<String>) -> Result<(), io::Error> {
+        self.table_mut(cf)?.delete(key)
+    }
+
+    pub fn compact(&mut self, cf: &ColumnFamily) -> Result<(), io::Error> {
+        self.table_mut(cf)?.compact()
+    }
+}
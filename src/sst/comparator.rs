@@ -0,0 +1,55 @@
+use std::{cmp::Ordering, sync::Arc};
+
+/// Order of keys in scans and in data files.
+/// A store has to be reopened with the comparator it was written with,
+/// as data files written with another order would be merged out of order.
+pub trait KeyComparator: Send + Sync {
+    fn compare(&self, a: &str, b: &str) -> Ordering;
+
+    /// Whether the order is the byte-wise one, which indexes are written in, so that scans
+    /// seek through them to the start of a range rather than read them whole.
+    fn is_bytewise(&self) -> bool {
+        false
+    }
+}
+
+pub(crate) type SharedComparator = Arc<dyn KeyComparator>;
+
+/// Byte-wise order of keys, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lexicographic;
+
+impl KeyComparator for Lexicographic {
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn is_bytewise(&self) -> bool {
+        true
+    }
+}
+
+// the reverse of an order, for merges walking sources from their last keys
+pub(crate) struct Reversed(pub SharedComparator);
+
+impl KeyComparator for Reversed {
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.0.compare(b, a)
+    }
+}
+
+/// Keys which parse as integers in numeric order, followed by any other keys in byte-wise order.
+/// Keys of the same number, e.g. "7" and "007", are ordered byte-wise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Numeric;
+
+impl KeyComparator for Numeric {
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        match (a.parse::<i64>(), b.parse::<i64>()) {
+            (Ok(a_num), Ok(b_num)) => a_num.cmp(&b_num).then_with(|| a.cmp(b)),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.cmp(b),
+        }
+    }
+}
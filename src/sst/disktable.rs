@@ -1,140 +1,7416 @@
+mod blob_file;
+mod bloom_file;
 mod byte_utils;
+#[cfg(feature = "encryption")]
+mod cipher;
+#[cfg(feature = "compression")]
+mod compression;
 mod data_file;
+mod file_system;
+mod generation_pin;
+mod index_cache;
 mod index_file;
+mod legacy;
+mod manifest;
+#[cfg(feature = "mmap")]
+mod mapped_index;
+mod partitioned_bloom;
+mod range_tombstone;
+mod rate_limiter;
+mod retry;
+mod stats;
+mod throttle;
+mod value_cache;
+mod value_handle;
 
-use super::memtable::MemtableEntries;
+use super::{
+    cancel::CancellationToken,
+    comparator::SharedComparator,
+    events::{StoreEvent, Subscribers},
+    key_transform::SharedKeyTransform,
+    memtable::{MemtableEntries, WalSyncPolicy},
+    scan::Source,
+    timeout::Timeout,
+};
+pub use file_system::{
+    DirEntry, FileHandle, FileIdentity, FileSystem, MemFileSystem, ModedFileSystem, OsFileSystem,
+};
+pub use index_cache::WarmConfig;
+pub(crate) use legacy::export as export_legacy;
+#[cfg(test)]
+pub(crate) use legacy::read as read_legacy;
 use log;
-use std::{collections::BTreeMap, io};
+pub use retry::RetryPolicy;
+pub use stats::{
+    BlobGcReport, ClearFailure, CompactionReport, DisktableStats, GenerationInfo, HealthStatus,
+    SizeHistogram, VerifyProgress,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read},
+    sync::mpsc::Receiver,
+    time::{Instant, SystemTime},
+};
+pub use throttle::Throttled;
+pub use value_handle::ValueHandle;
 
 pub(crate) trait Disktable {
-    fn find(&self, key: &str) -> Option<String>;
+    /// The value for the key, failing with the error of an index or a data file which can't
+    /// be read rather than taking it for a miss, and with `InvalidData` for an index entry
+    /// pointing at no record of the key.
+    fn find(&self, key: &str) -> io::Result<Option<String>>;
+    /// `find` along with where the newest entry for the key was found. Fails as `find`.
+    fn find_detailed(&self, key: &str) -> io::Result<(Option<String>, FindSource)>;
+    /// `find` into the buffer, which is overwritten by the value and keeps its capacity,
+    /// and is left empty for a miss. Returns whether the key has a value. Fails as `find`.
+    fn find_into(&self, key: &str, buf: &mut String) -> io::Result<bool>;
+    /// `find` which fails with an error wrapping `Timeout` once the deadline passes before
+    /// a generation is looked up.
+    fn find_with_deadline(&self, key: &str, deadline: Instant) -> io::Result<Option<String>>;
+    /// Whether the key has a value, resolved from bloom filters and indexes without a read
+    /// of a data file. Fails with the error of an index which can't be read.
+    fn contains(&self, key: &str) -> io::Result<bool>;
+    /// Values of the records of the key newest first, None for a tombstone. A generation
+    /// holds a record of a key at most, and a compaction keeps `keep_versions` of them.
+    /// Fails as `find`.
+    fn find_versions(&self, key: &str) -> io::Result<Vec<Option<String>>>;
+    /// Records of the key on disk newest first along with the generations holding them,
+    /// None for a tombstone or a record a range tombstone covers. Fails as `find`.
+    fn find_all_versions(&self, key: &str) -> io::Result<Vec<(DataGen, Option<String>)>>;
+    /// `find` along with when the record of the value was written, which is None unless
+    /// entries carry timestamps, and for a value not on disk yet. Fails as `find`.
+    fn find_with_meta(&self, key: &str) -> io::Result<Option<(String, SystemTime)>>;
+    /// Where the value for the key lives on disk, None if it's missing or deleted. Fails as
+    /// `contains`.
+    fn locate(&self, key: &str) -> io::Result<Option<(DataGen, Offset)>>;
+    /// Bytes in [offset, offset + len) of the value for the key, clamped to the end of the
+    /// value. Fails as `find`.
+    fn find_range(&self, key: &str, offset: usize, len: usize) -> io::Result<Option<Vec<u8>>>;
     fn flush(&mut self, memtable_entries: MemtableEntries<String, String>)
         -> Result<(), io::Error>;
+    /// Remove every generation, stopping at the first one which fails to be removed
+    /// and returning its error. Generations older than it are removed by then.
     fn clear(&mut self) -> Result<(), io::Error>;
+    /// `clear` which goes on past generations failing to be removed, and returns them.
+    /// Range tombstones and blob files are kept while any generation is left, as they
+    /// may still cover or hold its records.
+    fn clear_best_effort(&mut self) -> io::Result<Vec<ClearFailure>>;
+    /// Merge two adjacent generations into a new one above the newest generation, which
+    /// takes the place of both, dropping the records of keys generations above them hold.
+    /// Returns the new generation. Under `manifest` a single manifest commits it, so that
+    /// readers see either the two generations or the merged one. Fails with `InvalidInput`
+    /// along with `keep_versions` over 1, which the records dropped would be versions of.
+    fn merge_generations(&mut self, a: DataGen, b: DataGen) -> io::Result<DataGen>;
+    fn set_retry_policy(&mut self, retry_policy: RetryPolicy);
+    /// Key-sorted sources of records in [start, end), newest first.
+    /// They hold the data files of the generations open, so they read the generations
+    /// as they are now even if flushes, compactions or clears replace or remove them later.
+    /// Under a byte-wise comparator data files are read from the record of the start, which
+    /// their indexes point at, rather than from the beginning.
+    fn sources(&self, start: &str, end: Option<&str>) -> io::Result<Vec<Source>>;
+    /// `sources` whose values are handles, which read values on disk only once loaded.
+    /// Index files are read instead of data files, which are held open by the handles.
+    fn lazy_sources(&self, start: &str, end: Option<&str>) -> io::Result<Vec<Source<ValueHandle>>>;
+    /// `lazy_sources` each in reverse order of the comparator, which index entries in the
+    /// range are read in full for.
+    fn lazy_sources_rev(
+        &self,
+        start: &str,
+        end: Option<&str>,
+    ) -> io::Result<Vec<Source<ValueHandle>>>;
+    /// Merge all generations into the newest one, dropping shadowed entries and tombstones.
+    /// With `keep_versions` of N, up to N newest records of each key are kept in as many
+    /// generations below and including the newest one. With `compaction_fan_in`, runs of
+    /// adjacent generations are merged in waves first. The report is empty if there's no
+    /// generation to merge.
+    fn compact(&mut self, cancel: Option<&CancellationToken>) -> io::Result<CompactionReport>;
+    /// Merge the newest generation with the older ones whose key ranges overlap it, and with
+    /// those overlapping the range merged until none left does, into the newest one.
+    /// Generations left hold no key of the range, so tombstones are dropped, while they're
+    /// neither read nor rewritten. Only a version of each key is kept, so it fails with
+    /// `InvalidInput` under `keep_versions` over 1. The report is empty if there's no
+    /// generation the newest one overlaps.
+    fn compact_incremental(&mut self) -> io::Result<CompactionReport>;
+    /// Rewrite the generation without the records of keys a newer generation holds, which
+    /// reads never reach, while the other generations are left as they are. The report is
+    /// empty if no record is shadowed. Fails with `NotFound` if the generation doesn't exist,
+    /// and with `InvalidInput` under `keep_versions` over 1, as older versions are shadowed.
+    ///
+    /// The records left go into a new generation above the newest one, which may move past
+    /// the newer ones as none of them holds the keys, and which takes the place of the
+    /// generation merged in the report. Under `manifest` a single manifest commits it as it
+    /// does a compaction, so readers see either the old generation or the new one.
+    fn vacuum(&mut self, data_gen: DataGen) -> io::Result<CompactionReport>;
+    /// Reclaim blob files which values are dropped from. Files no entry in data files
+    /// references are removed, and files whose live bytes are below `blob_gc_ratio` of
+    /// their size have their live values moved into a new file. Values overwritten or
+    /// deleted stay live until a compaction drops their records. Files readers opened
+    /// before it may still read are removed once the readers are done.
+    fn collect_blobs(&mut self) -> io::Result<BlobGcReport>;
+    /// Distribution of key and value lengths of every entry in the data files.
+    fn size_histogram(&self, boundaries: &[usize]) -> io::Result<SizeHistogram>;
+    /// Entries of generations in [start, end) estimated from their skip indexes without
+    /// reading them, each key the skip index points at in the range standing for the
+    /// interval of entries up to it. Shadowed entries and tombstones count as well.
+    fn estimate_range_count(&self, start: &str, end: Option<&str>) -> io::Result<u64>;
+    /// Keys the skip indexes of the generations scanned point at, in the order of
+    /// the comparator without duplicates, e.g. to start scans near a key.
+    fn skip_sampled_keys(&self) -> io::Result<Vec<String>>;
+    /// Load indexes of the generations selected by `config` into memory so that
+    /// lookups in them don't read index files.
+    fn warm(&self, config: &WarmConfig) -> io::Result<()>;
+    fn stats(&self) -> io::Result<DisktableStats>;
+    /// Err(NotFound) if the generation doesn't exist.
+    fn describe_generation(&self, data_gen: DataGen) -> io::Result<GenerationInfo>;
+    /// `describe_generation` of every generation on disk from the oldest.
+    fn generations(&self) -> io::Result<Vec<GenerationInfo>>;
+    /// Err(Throttled) while a write could add a generation beyond the limit,
+    /// and Err(PermissionDenied) if the disktable is read only.
+    fn admit_write(&self) -> io::Result<()>;
+    /// Whether `admit_write` would fail with `Throttled` now, without counting a throttled
+    /// write.
+    fn throttled(&self) -> bool;
+    /// Whether it was opened read only, as set or found on a read-only filesystem.
+    fn read_only(&self) -> bool;
+    /// How many of the generations a lookup goes through are newer than the one.
+    fn newer_generations(&self, data_gen: DataGen) -> usize;
+    /// Channel of events for flushes, compactions and clears from now on.
+    fn subscribe(&mut self) -> Receiver<StoreEvent>;
+    /// Delete every key on disk starting with the prefix by a single range tombstone,
+    /// which lives until a `compact` drops the keys it covers.
+    fn delete_prefix(&mut self, prefix: &str) -> io::Result<()>;
+    /// `delete_prefix` of every key in [start, end) in the order of the comparator. Fails
+    /// with `InvalidInput` if the comparator orders start after end.
+    fn delete_range(&mut self, start: &str, end: &str) -> io::Result<()>;
+    /// Pick up the generations the writer of the directory has written or replaced since
+    /// the last refresh, returning the ones added or changed. Fails with `InvalidInput`
+    /// unless it's read only, as a follower is.
+    fn refresh(&mut self) -> io::Result<Vec<DataGen>>;
+    /// Sync every file in the directory and the directory itself, returning the newest
+    /// generation, so generations up to it are durable whether or not `sync_on_flush` is set.
+    fn sync_all(&self) -> io::Result<DataGen>;
+    /// Check the directory is writable, or listable if read only, by writing, reading back
+    /// and removing a sentinel file, and that the manifest and the files of the newest
+    /// generation load, without reading data or touching generations.
+    fn health_check(&self) -> HealthStatus;
+}
+pub type DataGen = i32; // data generation
+pub type Offset = u64;
+
+/// Where `find_detailed` found the newest entry for a key, which may be a tombstone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindSource {
+    Memtable,
+    /// A memtable which is being written into disk, or is frozen waiting for it.
+    Flushing,
+    /// The data file of the generation.
+    Disk(DataGen),
+    Miss,
+}
+
+// bytes in [offset, offset + len) of an in-memory value, clamped to the end of the value
+pub(crate) fn value_range(value: &str, offset: usize, len: usize) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let start = offset.min(bytes.len());
+    let end = offset.saturating_add(len).min(bytes.len());
+    bytes[start..end].to_vec()
 }
-type DataGen = i32; // data generation
-type Offset = u64;
 
 pub(crate) mod default {
-    use super::{data_file::*, index_file::*, *};
-    use crate::sst::memtable::{self, MemtableEntries};
+    #[cfg(feature = "encryption")]
+    use super::cipher::DataCipher;
+    #[cfg(feature = "compression")]
+    use super::compression::ValueCompression;
+    #[cfg(feature = "mmap")]
+    use super::mapped_index::MappedIndexes;
+    use super::{
+        blob_file::{BlobFiles, BlobRef},
+        bloom_file::BloomFiles,
+        data_file::*,
+        file_system::{HandleReader, SharedFileSystem},
+        generation_pin::GenerationPins,
+        index_cache::IndexCache,
+        index_file::*,
+        manifest::{KeyRange, ListedGenerations, Manifest},
+        range_tombstone::{RangeTombstone, RangeTombstones},
+        rate_limiter::RateLimiter,
+        stats::{Counters, EntryCounts},
+        value_cache::ValueCache,
+        *,
+    };
+    use crate::sst::{
+        bloom::BloomFilter,
+        comparator::{KeyComparator, Lexicographic},
+        fault::{self, CrashPoint, SharedFaultInjector},
+        key_transform::KeyTransform,
+        memtable::{self, MemtableEntries},
+        scan::{self, MergeIter},
+    };
     use regex::Regex;
-    use std::{collections::HashMap, io};
+    use std::{
+        cmp::Ordering,
+        collections::HashMap,
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    // what tells the files of a generation apart from ones the writer replaced them with:
+    // their sizes and identities, and the hash of the index bytes where the file system
+    // has no identities
+    #[derive(Debug, Clone, PartialEq)]
+    struct GenerationStamp {
+        sizes: (u64, u64),
+        identities: (Option<FileIdentity>, Option<FileIdentity>),
+        index_hash: Option<u64>,
+    }
+
+    // Err(InvalidInput) of options or arguments which don't go together
+    fn invalid<T>(message: impl Into<String>) -> io::Result<T> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, message.into()))
+    }
+
+    // generations a lookup goes through newest first, along with whether the filters left
+    // each of them to be looked up, counting the ones ruled out as they're passed
+    struct Routed<'a> {
+        data_gens: Vec<(DataGen, bool)>,
+        bloom_skips: &'a std::sync::atomic::AtomicU64,
+    }
+
+    impl Iterator for Routed<'_> {
+        type Item = DataGen;
+
+        fn next(&mut self) -> Option<DataGen> {
+            while let Some((data_gen, candidate)) = self.data_gens.pop() {
+                if candidate {
+                    return Some(data_gen);
+                }
+                Counters::incr(self.bloom_skips);
+            }
+            None
+        }
+    }
 
     pub(crate) struct FileDisktable {
+        fs: SharedFileSystem,
         dir_name: String,
         data_gen: DataGen,
         flushing: Option<MemtableEntries<String, String>>,
+        retry_policy: RetryPolicy,
         data_files: HashMap<DataGen, DataFile>,
+        index_cache: IndexCache,
+        // Some when index files are searched through memory mappings
+        #[cfg(feature = "mmap")]
+        mapped_indexes: Option<MappedIndexes>,
+        // shared with value handles, which count their reads as well
+        counters: Arc<Counters>,
+        comparator: SharedComparator,
+        read_only: bool,
+        subscribers: Subscribers,
+        // generations on disk, kept to admit writes and to look keys up without listing the
+        // directory, or probing generations missing from it
+        live_gens: BTreeSet<DataGen>,
+        max_generations: Option<usize>,
+        data_file_options: DataFileOptions,
+        dedup_values: bool,
+        entry_counts: EntryCounts,
+        compact_on_tombstone_ratio: Option<f64>,
+        // generations written by a compaction the tombstone ratio triggered, which kept
+        // enough tombstones to stay over it, e.g. older versions under `keep_versions`
+        tombstones_kept: BTreeSet<DataGen>,
+        // Some(bytes per second) compactions read and write at most
+        compaction_rate_limit: Option<u64>,
+        sync_on_flush: bool,
+        verify_after_write: bool,
+        fault_injector: Option<SharedFaultInjector>,
+        keep_versions: usize,
+        double_buffered_compaction: bool,
+        // Some(n) to merge generations n at a time in waves before the final merge
+        compaction_fan_in: Option<usize>,
+        range_tombstones: RangeTombstones,
+        // Some(rate) to write a bloom filter for every new generation
+        bloom_fpr: Option<f64>,
+        // route lookups by the filters of all generations partitioned by key range
+        partitioned_bloom_filters: bool,
+        // test the filters of all generations before reading any of their indexes
+        batch_bloom_checks: bool,
+        // values read from data files, by where their records sit
+        value_cache: Option<ValueCache>,
+        // Some(fraction) of the data file the skip index of a new generation is sized for
+        index_size_budget: Option<f64>,
+        // Some of a writer keeping a manifest of the generations
+        manifest: Option<Mutex<Manifest>>,
+        // the generations the manifest of the directory lists, read again once it's replaced
+        listed_gens: ListedGenerations,
+        // Some of a writer keeping the generations open scans read from until they're dropped
+        generation_pins: Option<Arc<GenerationPins>>,
+        bloom_files: BloomFiles,
+        // bytes read at once from a data file merged by a scan
+        scan_read_ahead: Option<usize>,
+        verify_index_order: bool,
+        front_coded_indexes: bool,
+        // Some for a read only disktable, as a follower is, holding the stamps of every
+        // generation found by the last refresh
+        followed: Option<HashMap<DataGen, GenerationStamp>>,
+        // Some(path) to append the report of every compaction to
+        compaction_audit_log: Option<std::path::PathBuf>,
+        blob_gc_ratio: f64,
     }
 
-    impl FileDisktable {
-        pub fn new(dir_name: &str) -> Result<impl Disktable, io::Error> {
-            std::fs::create_dir_all(&dir_name).expect("failed to create directory");
-            let data_gen = Self::get_latest_data_gen(dir_name)?;
-            let flushing = None;
+    /// Options of the disktable of an `SSTable`, which are validated when it's opened.
+    pub struct FileDisktableBuilder {
+        dir_name: String,
+        comparator: SharedComparator,
+        retry_policy: RetryPolicy,
+        warm_on_open: Option<WarmConfig>,
+        // None to open read only if the directory is found on a read-only filesystem
+        read_only: Option<bool>,
+        follower: bool,
+        compact_on_open: bool,
+        max_generations: Option<usize>,
+        verify_on_open: Option<VerifyOnOpen>,
+        verify_index_order: bool,
+        front_coded_indexes: bool,
+        #[cfg(feature = "mmap")]
+        mmap_indexes: bool,
+        key_transform: Option<SharedKeyTransform>,
+        block_size: Option<usize>,
+        #[cfg(feature = "encryption")]
+        cipher: Option<Arc<DataCipher>>,
+        #[cfg(feature = "compression")]
+        compression_level: Option<i32>,
+        // Some(max bytes) of the dictionary trained for every new generation
+        #[cfg(feature = "compression")]
+        compression_dictionary: Option<usize>,
+        dedup_values: bool,
+        entry_timestamps: bool,
+        existence_records: bool,
+        changelog: bool,
+        // whether the store opening the disktable writes into the directory on open, e.g. its
+        // WAL, so that a read-only filesystem is probed for then
+        writes_on_open: bool,
+        wal_sync_policy: WalSyncPolicy,
+        compact_on_tombstone_ratio: Option<f64>,
+        compaction_rate_limit: Option<u64>,
+        inline_threshold: Option<usize>,
+        blob_threshold: Option<usize>,
+        blob_gc_ratio: f64,
+        sync_on_flush: bool,
+        verify_after_write: bool,
+        fault_injector: Option<SharedFaultInjector>,
+        // None for the local file system
+        file_system: Option<SharedFileSystem>,
+        // Some to create files and directories on the local file system with the modes
+        file_mode: Option<u32>,
+        dir_mode: Option<u32>,
+        keep_versions: usize,
+        double_buffered_compaction: bool,
+        // Some(n) to merge generations n at a time in waves before the final merge
+        compaction_fan_in: Option<usize>,
+        bloom_fpr: Option<f64>,
+        partitioned_bloom_filters: bool,
+        batch_bloom_checks: bool,
+        // Some(bytes) of values read from data files kept in memory
+        value_cache_bytes: Option<usize>,
+        // Some(fraction) of the cache capacity above which values aren't cached
+        value_cache_admission: Option<f64>,
+        index_size_budget: Option<f64>,
+        manifest: bool,
+        pin_scanned_generations: bool,
+        scan_read_ahead: Option<usize>,
+        compaction_audit_log: Option<std::path::PathBuf>,
+        // Some((width, depth)) of the sketch of reads per key
+        track_reads: Option<(usize, usize)>,
+        // Some(depth) of generations newer than the one a read repair rewrites a key from
+        read_repair: Option<usize>,
+        // Some(capacity) of the queue of frozen memtables a background thread flushes
+        background_flush: Option<usize>,
+        skip_redundant_writes: bool,
+    }
 
-            Ok(Self {
-                data_gen,
+    // the callback of `verify_on_open` and how many generations it's called every
+    struct VerifyOnOpen {
+        progress: Box<dyn FnMut(&VerifyProgress)>,
+        every: usize,
+    }
+
+    impl FileDisktableBuilder {
+        const KEY_TRANSFORM_FILE_NAME: &'static str = "key_transform";
+        const MIN_BLOCK_SIZE: usize = 64;
+
+        pub fn new(dir_name: &str) -> FileDisktableBuilder {
+            FileDisktableBuilder {
                 dir_name: dir_name.to_string(),
-                flushing,
-                data_files: HashMap::new(),
-            })
+                comparator: Arc::new(Lexicographic),
+                retry_policy: RetryPolicy::default(),
+                warm_on_open: None,
+                read_only: None,
+                follower: false,
+                compact_on_open: false,
+                max_generations: None,
+                verify_on_open: None,
+                verify_index_order: cfg!(debug_assertions),
+                front_coded_indexes: false,
+                #[cfg(feature = "mmap")]
+                mmap_indexes: false,
+                key_transform: None,
+                block_size: None,
+                #[cfg(feature = "encryption")]
+                cipher: None,
+                #[cfg(feature = "compression")]
+                compression_level: None,
+                #[cfg(feature = "compression")]
+                compression_dictionary: None,
+                dedup_values: false,
+                entry_timestamps: false,
+                existence_records: false,
+                changelog: false,
+                writes_on_open: false,
+                wal_sync_policy: WalSyncPolicy::Never,
+                compact_on_tombstone_ratio: None,
+                compaction_rate_limit: None,
+                inline_threshold: None,
+                blob_threshold: None,
+                blob_gc_ratio: 0.5,
+                sync_on_flush: true,
+                verify_after_write: false,
+                fault_injector: None,
+                file_system: None,
+                file_mode: None,
+                dir_mode: None,
+                keep_versions: 1,
+                double_buffered_compaction: false,
+                compaction_fan_in: None,
+                bloom_fpr: None,
+                partitioned_bloom_filters: false,
+                batch_bloom_checks: false,
+                value_cache_bytes: None,
+                value_cache_admission: None,
+                index_size_budget: None,
+                manifest: false,
+                pin_scanned_generations: false,
+                scan_read_ahead: None,
+                compaction_audit_log: None,
+                track_reads: None,
+                read_repair: None,
+                background_flush: None,
+                skip_redundant_writes: false,
+            }
         }
 
-        fn get_data_gens(dir_name: &str) -> io::Result<Vec<DataGen>> {
-            std::fs::read_dir(dir_name).map(|dir| {
-                let mut list = dir.fold(vec![], |mut acc, entry| {
-                    let file_name = entry.unwrap().file_name();
-                    let file_name = file_name.to_string_lossy();
-                    match Regex::new(&format!("{}_(?P<gen>\\d+)", DataFile::FILE_NAME_PREFIX))
-                        .unwrap()
-                        .captures(&file_name)
-                    {
-                        Some(cap) => {
-                            acc.push(cap["gen"].parse::<DataGen>().unwrap());
-                            acc
-                        }
-                        None => acc,
-                    }
-                });
-                list.sort();
-                list
-            })
+        /// Order of keys in data files, byte-wise by default.
+        pub fn comparator(
+            mut self,
+            comparator: impl KeyComparator + 'static,
+        ) -> FileDisktableBuilder {
+            self.comparator = Arc::new(comparator);
+            self
         }
 
-        fn get_latest_data_gen(dir_name: &str) -> io::Result<DataGen> {
-            Self::get_data_gens(dir_name).map(|list| *list.last().unwrap_or(&0))
+        pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> FileDisktableBuilder {
+            self.retry_policy = retry_policy;
+            self
         }
 
-        fn with_data_file<T>(&self, gen: DataGen, f: impl Fn(&DataFile) -> T) -> T {
-          match self.data_files.get(&gen) {
-            Some(found) => f(found),
-            None => f(&DataFile::of(&self.dir_name, gen))
-          }
+        /// `warm` with the config once the disktable is opened.
+        pub fn warm_on_open(mut self, config: WarmConfig) -> FileDisktableBuilder {
+            self.warm_on_open = Some(config);
+            self
         }
 
-        fn index_file(&self, data_gen: DataGen) -> IndexFile {
-            IndexFile::of(data_gen, &self.dir_name)
+        /// Reject every operation which changes files, and never create the directory.
+        /// Unless it's set, a directory on a read-only filesystem is opened read only, which
+        /// is found by creating a file in it on open if anything writes on open, as a store
+        /// does into its WAL. Otherwise nothing is written on open, and the first write fails
+        /// with the error of the filesystem. Opening such a directory fails with
+        /// `ReadOnlyFilesystem` if it's set false or another option writes on open.
+        /// Generations the writer of the directory adds after it's opened are picked up
+        /// by `refresh`.
+        pub fn read_only(mut self, read_only: bool) -> FileDisktableBuilder {
+            self.read_only = Some(read_only);
+            self
         }
 
-        fn fetch(&self, data_gen: DataGen, offset: Offset) -> Option<(String, String)> {
-            let entry = self.with_data_file(data_gen, |df| df.read_entry(offset));
-            entry.map(|entry| (entry.key, entry.value))
+        /// Open a directory another process writes to as a follower, which is read only and
+        /// whose store never reads the WAL of the writer. It cannot be set along with
+        /// `read_only(false)`.
+        pub fn follower(mut self, follower: bool) -> FileDisktableBuilder {
+            self.follower = follower;
+            self
         }
-    }
 
-    impl Disktable for FileDisktable {
-        fn find(&self, key: &str) -> Option<String> {
-            let find_from_disk = || {
-                (0..=self.data_gen).rev().find_map(|data_gen| {
-                    self.index_file(data_gen)
-                        .find_index(key)
-                        .and_then(|index_entry| {
-                            self.fetch(index_entry.data_gen, index_entry.offset)
-                                .filter(|(_key, _)| _key == key)
-                                .map(|(_, value)| value)
-                        })
-                })
+        /// `compact` all generations once the disktable is opened.
+        pub fn compact_on_open(mut self, compact_on_open: bool) -> FileDisktableBuilder {
+            self.compact_on_open = compact_on_open;
+            self
+        }
+
+        /// `compact` all generations after a flush once tombstones make up more than
+        /// the ratio of entries of the generations, however few generations there are.
+        /// Opening fails with `InvalidInput` unless the ratio is in [0, 1).
+        pub fn compact_on_tombstone_ratio(mut self, ratio: f64) -> FileDisktableBuilder {
+            self.compact_on_tombstone_ratio = Some(ratio);
+            self
+        }
+
+        /// Have compactions, merges and vacuums sleep as needed to move at most
+        /// `bytes_per_sec` bytes a second, counting the keys and values they read and the
+        /// records they write as encoded, throttled record by record, so that they leave disk
+        /// bandwidth to foreground reads at the cost of running longer. The throughput of
+        /// the latest one is in `stats`. It has to be at least 1.
+        pub fn compaction_rate_limit(mut self, bytes_per_sec: u64) -> FileDisktableBuilder {
+            self.compaction_rate_limit = Some(bytes_per_sec);
+            self
+        }
+
+        /// Sync the files of a new generation before it's published and the WAL covering its
+        /// entries is truncated, so that a crash loses no acknowledged write. On by default;
+        /// turning it off trades that for faster flushes.
+        pub fn sync_on_flush(mut self, sync_on_flush: bool) -> FileDisktableBuilder {
+            self.sync_on_flush = sync_on_flush;
+            self
+        }
+
+        /// Read the data file of a flushed generation back before it's published, and fail
+        /// the flush with `InvalidData` unless it holds exactly the records of the memtable
+        /// which the index points at, so that a write corrupted on its way to disk is caught
+        /// by the flush rather than by a later read. It reads every flushed record once more.
+        pub fn verify_after_write(mut self, verify_after_write: bool) -> FileDisktableBuilder {
+            self.verify_after_write = verify_after_write;
+            self
+        }
+
+        /// Keep data and index files in the file system rather than the local one,
+        /// e.g. in memory with `MemFileSystem`. The WAL and the changelog of an `SSTable`
+        /// stay in the directory on the local file system. Opening fails with `InvalidInput`
+        /// along with `mmap_indexes`, which maps local files.
+        pub fn file_system(
+            mut self,
+            file_system: impl FileSystem + 'static,
+        ) -> FileDisktableBuilder {
+            self.file_system = Some(Arc::new(file_system));
+            self
+        }
+
+        /// Mode bits on Unix of the data, index and other files the disktable creates on
+        /// the local file system, less the bits the umask clears. 0o600 by default, so that
+        /// data isn't readable to other users. Files which exist already and the WAL and
+        /// the changelog of an `SSTable` keep the modes they have or are created with.
+        /// Opening fails with `InvalidInput` along with `file_system`.
+        pub fn file_mode(mut self, mode: u32) -> FileDisktableBuilder {
+            self.file_mode = Some(mode);
+            self
+        }
+
+        /// Mode bits on Unix of the directory of the disktable and its parents when they're
+        /// created, as `file_mode` of files. 0o700 by default.
+        pub fn dir_mode(mut self, mode: u32) -> FileDisktableBuilder {
+            self.dir_mode = Some(mode);
+            self
+        }
+
+        #[cfg(test)]
+        pub(crate) fn fault_injector(
+            mut self,
+            fault_injector: impl fault::FaultInjector + 'static,
+        ) -> FileDisktableBuilder {
+            self.fault_injector = Some(Arc::new(fault_injector));
+            self
+        }
+
+        /// Keep up to this many newest records of each key through `compact` rather than
+        /// the newest one only, e.g. for a short history of values by `find_versions`.
+        /// A tombstone is kept while an older record is kept below it. 1 by default,
+        /// and opening fails with `InvalidInput` for 0.
+        pub fn keep_versions(mut self, keep_versions: usize) -> FileDisktableBuilder {
+            self.keep_versions = keep_versions;
+            self
+        }
+
+        /// Write the generations of `compact` under new numbers above the newest one,
+        /// and remove the generations merged once all of them are published, rather than
+        /// rewriting the newest generations in place. Read only disktables on the directory
+        /// see either the old or the new generations throughout a compaction, never a mix.
+        /// Off by default.
+        pub fn double_buffered_compaction(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.double_buffered_compaction = enabled;
+            self
+        }
+
+        /// Have `compact` merge at most `fan_in` generations at once, so that the data files
+        /// it holds open and the records it buffers stay bounded however many generations
+        /// there are. Runs of `fan_in` adjacent generations are merged into the newest of
+        /// each, keeping tombstones, in waves until no more than `fan_in` are left, which
+        /// are merged as usual. It has to be at least 2, and cannot be set along with
+        /// `keep_versions` over 1 or `double_buffered_compaction`, as waves keep a version
+        /// of each key and replace generations in place.
+        pub fn compaction_fan_in(mut self, fan_in: usize) -> FileDisktableBuilder {
+            self.compaction_fan_in = Some(fan_in);
+            self
+        }
+
+        /// Write a bloom filter of the keys of every new generation, sized for the number
+        /// of keys it holds at the false positive rate, so that lookups skip generations
+        /// without the key rather than searching their indexes. Keys of tombstones are held
+        /// as well, so a generation of deletes only shadows the values it deletes. Generations
+        /// written before are searched as they are. Opening fails with `InvalidInput` unless
+        /// the rate is in (0, 1).
+        pub fn bloom_filters(mut self, fpr: f64) -> FileDisktableBuilder {
+            self.bloom_fpr = Some(fpr);
+            self
+        }
+
+        /// Hold the bloom filters of all generations together, partitioned by the key range
+        /// of each, so that a lookup hashes the key once and goes only to the generations
+        /// whose range covers it and whose filter may hold it, rather than testing every
+        /// generation in turn. Where the ranges don't overlap, as after incremental
+        /// compactions, a key is routed to the one generation owning it by a binary search.
+        /// Ranges of generations written before opening are read from their indexes once.
+        /// Opening fails with `InvalidInput` unless `bloom_filters` is set.
+        pub fn partitioned_bloom_filters(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.partitioned_bloom_filters = enabled;
+            self
+        }
+
+        /// Test the bloom filters of all generations a lookup goes through in memory first,
+        /// hashing the key once, and probe the indexes of only the generations left from the
+        /// newest, rather than testing each filter right before reading its index. A miss deep
+        /// in many generations is then a pass over the filters and few index reads, if any.
+        /// `partitioned_bloom_filters` batches its checks already, and goes by key ranges as
+        /// well. Opening fails with `InvalidInput` unless `bloom_filters` is set.
+        pub fn batch_bloom_checks(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.batch_bloom_checks = enabled;
+            self
+        }
+
+        /// Keep values `find` and `find_into` read from data files in memory, up to
+        /// `capacity_bytes` of them, evicting the ones read least recently. Values inlined in
+        /// index entries aren't cached, as they're in memory along with their indexes.
+        /// Opening fails with `InvalidInput` for a capacity of 0.
+        pub fn value_cache(mut self, capacity_bytes: usize) -> FileDisktableBuilder {
+            self.value_cache_bytes = Some(capacity_bytes);
+            self
+        }
+
+        /// Admit into `value_cache` only values of at most `max_fraction` of its capacity,
+        /// so that reading a large value doesn't evict the small ones read often. Values of
+        /// up to the whole capacity are admitted by default. Opening fails with `InvalidInput`
+        /// unless `value_cache` is set, and for a fraction outside (0, 1].
+        pub fn value_cache_admission(mut self, max_fraction: f64) -> FileDisktableBuilder {
+            self.value_cache_admission = Some(max_fraction);
+            self
+        }
+
+        /// Space the entries the skip index of every new generation points at as densely
+        /// as its skip index stays within the fraction of the bytes of its data file,
+        /// going by the number of keys and their average length, rather than every 30.
+        /// Small generations get denser skip indexes, and large ones sparser, which lookups
+        /// scan less or more of the index after. Each skip index records its interval, so
+        /// generations written before are read as they are. Opening fails with
+        /// `InvalidInput` unless the fraction is in (0, 1].
+        pub fn index_size_budget(mut self, fraction: f64) -> FileDisktableBuilder {
+            self.index_size_budget = Some(fraction);
+            self
+        }
+
+        /// Keep a `MANIFEST` file listing the generations and their key ranges, replaced
+        /// whole on every flush, compaction and clear, so that the directory is read as the
+        /// generations it lists rather than as the data files found in it. A generation
+        /// left unlisted by a crash, or a stray file, is ignored, and generations stop
+        /// being listed before their files are removed. Opening without a manifest lists
+        /// the directory as before and writes one. Opening a writer without it fails with
+        /// `InvalidInput` while the directory has a manifest, which it wouldn't keep up to
+        /// date, while readers of the directory follow a manifest whether or not they set it.
+        ///
+        /// With a manifest the generations `compact` and `compact_incremental` write go under
+        /// new numbers above the newest one, as with `double_buffered_compaction`, and a single
+        /// manifest replacing the ones merged with them commits the compaction, so that a crash
+        /// leaves either the old or the new generations listed, never a mix. So do the waves
+        /// of `compaction_fan_in`, `merge_generations` and `vacuum`. The files of the
+        /// generations merged are removed only after that, while readers which have them open
+        /// keep reading them, and a writer opening the directory removes the ones a crash left.
+        pub fn manifest(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.manifest = enabled;
+            self
+        }
+
+        /// Keep the data and index files of the generations an open scan of `sources` or
+        /// `lazy_sources` reads from until the scan is dropped, rather than having compactions
+        /// remove them under it. Generations a compaction merges away are unlisted at once by
+        /// renaming their files with a `retired_` prefix, which are removed once the last scan
+        /// reading them is dropped, by a `clear`, or by the next writer opening the directory
+        /// if the process goes first. Generations rewritten in place are replaced by a rename either way, which
+        /// the files scans have open outlive. Off by default, as open files outlive their
+        /// removal as well on file systems which allow it.
+        pub fn pin_scanned_generations(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.pin_scanned_generations = enabled;
+            self
+        }
+
+        /// Reject writes with `Throttled` once this many generations wait for compaction,
+        /// so that writes stall rather than generations growing without bound.
+        pub fn max_generations(mut self, max_generations: usize) -> FileDisktableBuilder {
+            self.max_generations = Some(max_generations);
+            self
+        }
+
+        /// Check every generation on open, i.e. that its data file can be read through
+        /// and its index points at entries of the same keys. `Err(InvalidData)` is returned
+        /// from opening on a mismatch. `progress` is called every `every` generations
+        /// and once all generations are checked.
+        pub fn verify_on_open(
+            mut self,
+            every: usize,
+            progress: impl FnMut(&VerifyProgress) + 'static,
+        ) -> FileDisktableBuilder {
+            self.verify_on_open = Some(VerifyOnOpen {
+                progress: Box::new(progress),
+                every: every.max(1),
+            });
+            self
+        }
+
+        /// Check that the keys of an index are in order whenever it's loaded whole, e.g. by
+        /// `warm`, scans, compactions or a memory mapping, failing the load with `InvalidData`
+        /// naming the entry out of order. Without it, lookups miss keys of such an index.
+        /// It's on by default in debug builds.
+        pub fn verify_index_order(mut self, verify_index_order: bool) -> FileDisktableBuilder {
+            self.verify_index_order = verify_index_order;
+            self
+        }
+
+        /// Write index entries as the suffix of the key after the prefix it shares with the
+        /// previous key, which shrinks indexes of keys with long common prefixes. Every 30th
+        /// entry holds its whole key as a restart point lookups seek to. Indexes are read
+        /// whether or not they were written with it.
+        pub fn front_coded_indexes(mut self, front_coded: bool) -> FileDisktableBuilder {
+            self.front_coded_indexes = front_coded;
+            self
+        }
+
+        /// Binary search index files through memory mappings rather than reading them.
+        #[cfg(feature = "mmap")]
+        pub fn mmap_indexes(mut self, mmap_indexes: bool) -> FileDisktableBuilder {
+            self.mmap_indexes = mmap_indexes;
+            self
+        }
+
+        /// Normalize keys of the SSTable by the transform. Opening fails with `InvalidInput`
+        /// if the store was written with another transform or without one.
+        pub fn key_transform(
+            mut self,
+            key_transform: impl KeyTransform + 'static,
+        ) -> FileDisktableBuilder {
+            self.key_transform = Some(Arc::new(key_transform));
+            self
+        }
+
+        /// Pack entries of data files into blocks of the size and read them by whole blocks,
+        /// so that reads line up with the blocks of the device. An entry never straddles
+        /// a block unless it's larger than a block, and data files are padded up to
+        /// a multiple of the size. Opening fails with `InvalidInput` below 64 bytes.
+        pub fn block_size(mut self, block_size: usize) -> FileDisktableBuilder {
+            self.block_size = Some(block_size);
+            self
+        }
+
+        /// Read data files of generations by at least the bytes at once in scans, cursors and
+        /// compactions, which merge the generations record by record. It only changes how
+        /// data files are read, not what's read. Opening fails with `InvalidInput` for 0.
+        pub fn scan_read_ahead(mut self, bytes: usize) -> FileDisktableBuilder {
+            self.scan_read_ahead = Some(bytes);
+            self
+        }
+
+        /// Append the `CompactionReport` of every compaction to the file as a line of JSON,
+        /// which is created if it's missing and kept by `clear`. The report is appended once
+        /// the merged generations are written and before they're committed in place of the
+        /// ones merged, so a failure to append fails the compaction with nothing replaced.
+        pub fn compaction_audit_log(
+            mut self,
+            path: impl AsRef<std::path::Path>,
+        ) -> FileDisktableBuilder {
+            self.compaction_audit_log = Some(path.as_ref().to_path_buf());
+            self
+        }
+
+        /// Inline values shorter than the threshold in index entries as well, so that
+        /// lookups of them don't read data files. Index files grow by the inlined values.
+        /// It cannot be set along with `encryption_key`, as index files are in plaintext.
+        pub fn inline_threshold(mut self, inline_threshold: usize) -> FileDisktableBuilder {
+            self.inline_threshold = Some(inline_threshold);
+            self
+        }
+
+        /// Write values longer than the threshold into blob files next to data files, and
+        /// hold only a reference to them in data files, so that compactions copy the
+        /// reference instead of rewriting large values. Space of values dropped from data
+        /// files is reclaimed by `collect_blobs`. It cannot be set along with
+        /// `encryption_key`, as blob files are in plaintext, and must be at least
+        /// `inline_threshold`.
+        pub fn blob_threshold(mut self, blob_threshold: usize) -> FileDisktableBuilder {
+            self.blob_threshold = Some(blob_threshold);
+            self
+        }
+
+        /// Move live values out of a blob file during `collect_blobs` when they take less
+        /// than the ratio of the file, in (0, 1]. 0.5 by default.
+        pub fn blob_gc_ratio(mut self, ratio: f64) -> FileDisktableBuilder {
+            self.blob_gc_ratio = ratio;
+            self
+        }
+
+        /// Write each distinct value once in the output of `compact`, and let other keys
+        /// of an equal value refer to it. This shrinks data files whose keys share large
+        /// values, at the cost of holding every distinct value in memory while compacting
+        /// and of a second read for a referring key.
+        pub fn dedup_values(mut self, dedup_values: bool) -> FileDisktableBuilder {
+            self.dedup_values = dedup_values;
+            self
+        }
+
+        /// Write the time an entry is flushed into its header as 8 bytes of nanoseconds since
+        /// the unix epoch, which `find_with_meta` reads. Compactions keep the timestamp of
+        /// the record they keep. Every record tells whether it carries a timestamp, so a
+        /// directory written with either setting can be opened with the other.
+        pub fn entry_timestamps(mut self, entry_timestamps: bool) -> FileDisktableBuilder {
+            self.entry_timestamps = entry_timestamps;
+            self
+        }
+
+        /// Write keys with empty values, e.g. set by `SSTable::set_exists`, as existence
+        /// records, whose index entries hold the key alone and read as an empty value without
+        /// a read of the data file. It suits a set of keys, which `contains` looks up from
+        /// indexes. It changes the format of indexes, which are read either way.
+        pub fn existence_records(mut self, existence_records: bool) -> FileDisktableBuilder {
+            self.existence_records = existence_records;
+            self
+        }
+
+        /// Record every mutation of the SSTable with a sequence number in a changelog,
+        /// which `SSTable::changes_since` replays. It cannot be set on a read only disktable.
+        pub fn changelog(mut self, changelog: bool) -> FileDisktableBuilder {
+            self.changelog = changelog;
+            self
+        }
+
+        /// Count reads of keys by `get`, `find_borrowed`, `find_into` and `find_detailed`
+        /// of the SSTable in a count-min sketch of `depth` rows of `width` counters, which
+        /// `SSTable::estimated_reads` queries. Memory is bounded by the counters, 8 bytes
+        /// each, and estimates are over by at most about `2.7 / width` of all reads.
+        /// Both have to be at least 1.
+        pub fn track_reads(mut self, width: usize, depth: usize) -> FileDisktableBuilder {
+            self.track_reads = Some((width, depth));
+            self
+        }
+
+        /// Have `SSTable::get_repairing` insert a value it reads from a generation with at least
+        /// `depth` generations newer than it into the memtable, so that later reads of the key
+        /// are served from memory and the next flush writes it into the newest generation.
+        /// Keys found in the generations above are left where they are, which keeps keys read
+        /// about as often as they're flushed from being rewritten over and over. It has to be
+        /// at least 1, and cannot be set on a read only disktable.
+        pub fn read_repair(mut self, depth: usize) -> FileDisktableBuilder {
+            self.read_repair = Some(depth);
+            self
+        }
+
+        /// Freeze a full memtable of the SSTable and queue it for a background thread to
+        /// flush, so that inserts don't wait for disk unless `capacity` memtables are queued
+        /// already. Queued memtables are read before generations on disk, whose reads wait
+        /// for a flush in progress. A flush which fails stops the thread, and the next write
+        /// fails with its error, leaving its memtable and the ones after it frozen. Flushes
+        /// are synchronous from then on, and the next full memtable is written after them,
+        /// unless `SSTable::flush_frozen` writes them first. It has to be at least 1, and
+        /// cannot be set on a read only disktable.
+        pub fn background_flush(mut self, capacity: usize) -> FileDisktableBuilder {
+            self.background_flush = Some(capacity);
+            self
+        }
+
+        /// Have `SSTable::insert` write nothing, neither to the memtable nor to the WAL or the
+        /// changelog, when the memtables hold the value for the key already. Only memtables
+        /// are checked, so that an insert never reads the disk, and a value which has been
+        /// flushed is written again.
+        pub fn skip_redundant_writes(mut self, enabled: bool) -> FileDisktableBuilder {
+            self.skip_redundant_writes = enabled;
+            self
+        }
+
+        /// When appends to the WAL of the SSTable are synced, see `WalSyncPolicy` for
+        /// the writes which survive a crash under each policy. `Never` by default.
+        pub fn wal_sync_policy(mut self, sync_policy: WalSyncPolicy) -> FileDisktableBuilder {
+            self.wal_sync_policy = sync_policy;
+            self
+        }
+
+        /// Encrypt records of data files with ChaCha20-Poly1305 under the key, which is
+        /// never written anywhere. Keys in index files are left in plaintext. Reading
+        /// a record with another key fails with `InvalidData` as its tag doesn't match.
+        #[cfg(feature = "encryption")]
+        pub fn encryption_key(mut self, key: [u8; 32]) -> FileDisktableBuilder {
+            self.cipher = Some(Arc::new(DataCipher::new(&key)));
+            self
+        }
+
+        /// Compress the value of every entry of new generations with zstd at the level, and
+        /// keep the ones it makes shorter compressed in data files. Values inlined in index
+        /// entries or written into blob files are left as they are. Records of compressed
+        /// values say so, so generations are read the same whether or not it's set. Opening
+        /// fails with `InvalidInput` for a level zstd doesn't have.
+        #[cfg(feature = "compression")]
+        pub fn compress_values(mut self, level: i32) -> FileDisktableBuilder {
+            self.compression_level = Some(level);
+            self
+        }
+
+        /// Train a zstd dictionary of at most `max_bytes` for every new generation from
+        /// a sample of its first values, about 100 times the dictionary in bytes, and
+        /// compress the values of the generation with it, which shrinks many small values
+        /// alike far more than compressing each on its own. The dictionary is written as
+        /// the header of the data file, and is loaded once per generation to read its values.
+        /// A generation whose values are too few to train a dictionary on is compressed
+        /// without one. Opening fails with `InvalidInput` unless `compress_values` is set,
+        /// and for `max_bytes` under 256.
+        #[cfg(feature = "compression")]
+        pub fn compression_dictionary(mut self, max_bytes: usize) -> FileDisktableBuilder {
+            self.compression_dictionary = Some(max_bytes);
+            self
+        }
+
+        pub(crate) fn shared_key_transform(&self) -> Option<SharedKeyTransform> {
+            self.key_transform.clone()
+        }
+
+        // the name of the transform the store was written with must match the configured one
+        fn check_key_transform(
+            &self,
+            fs: &dyn FileSystem,
+            data_gens: &[DataGen],
+            read_only: bool,
+        ) -> io::Result<()> {
+            let path = std::path::Path::new(&self.dir_name).join(Self::KEY_TRANSFORM_FILE_NAME);
+            let recorded = match fs.open(&path) {
+                Ok(file) => {
+                    let mut recorded = String::new();
+                    HandleReader::new(file.as_ref()).read_to_string(&mut recorded)?;
+                    Some(recorded.trim().to_string())
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
             };
-            match self.flushing.as_ref() {
-                Some(mem_entries) => match mem_entries.get(&key.to_string()) {
-                    memtable::GetResult::Found(value) => Some(value.to_string()),
-                    memtable::GetResult::Deleted => None,
-                    memtable::GetResult::NotFound => find_from_disk(),
-                },
-                None => find_from_disk(),
+            let configured = self.key_transform.as_ref().map(|t| t.name().to_string());
+            let mismatch = |recorded: &str, configured: &str| {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "store in {} was written with key transform {}, not {}",
+                        self.dir_name, recorded, configured
+                    ),
+                ))
+            };
+            match (recorded, configured) {
+                (Some(recorded), Some(configured)) if recorded != configured => {
+                    mismatch(&recorded, &configured)
+                }
+                (Some(recorded), None) => mismatch(&recorded, "none"),
+                (None, Some(configured)) if !data_gens.is_empty() => mismatch("none", &configured),
+                (None, Some(configured)) if !read_only => {
+                    fs.create(&path)?.write(configured.as_bytes())
+                }
+                _ => Ok(()),
             }
         }
 
-        fn flush(
-            &mut self,
-            memtable_entries: MemtableEntries<String, String>,
-        ) -> Result<(), io::Error> {
-            self.flushing = Some(memtable_entries);
+        // read only as set, or as the directory is found on a read-only filesystem. the
+        // directory is created unless it's set read only
+        fn open_read_only(&self, fs: &dyn FileSystem) -> io::Result<bool> {
+            if self.read_only == Some(true) {
+                return Ok(true);
+            }
+            let dir = std::path::Path::new(&self.dir_name);
+            let writes_on_open = self.read_only == Some(false)
+                || self.writes_on_open
+                || self.compact_on_open
+                || self.changelog
+                || self.manifest;
+            let writable = match fs.create_dir_all(dir) {
+                Ok(()) if writes_on_open => fs.writable(dir)?,
+                Ok(()) => true,
+                Err(err) if file_system::denies_writes(&err) => false,
+                Err(err) => return Err(err),
+            };
+            if writable {
+                return Ok(false);
+            }
+            if self.read_only == Some(false) || self.compact_on_open || self.changelog {
+                return Err(io::Error::new(
+                    io::ErrorKind::ReadOnlyFilesystem,
+                    format!(
+                        "{} is on a read-only filesystem and can only be opened read only",
+                        self.dir_name
+                    ),
+                ));
+            }
+            log::info!(
+                "{} is on a read-only filesystem, so it has been opened read only",
+                self.dir_name
+            );
+            Ok(true)
+        }
 
-            let next_data_gen = self.data_gen + 1;
-            let new_data_file = DataFile::of(&self.dir_name, next_data_gen);
-            let new_index = new_data_file.create(self.flushing.as_ref().unwrap())?;
-            let new_index_file = IndexFile::of(next_data_gen, &self.dir_name);
-            new_index_file.create_index(&new_index)?;
+        pub(crate) fn dir_name(&self) -> &str {
+            &self.dir_name
+        }
 
-            self.data_gen = next_data_gen;
-            self.flushing = None;
-            log::trace!(
-                "Disktable#flush has completed. next_data_gen: {}",
-                next_data_gen
-            );
-            Ok(())
+        pub(crate) fn shared_comparator(&self) -> SharedComparator {
+            self.comparator.clone()
         }
 
-        fn clear(&mut self) -> Result<(), io::Error> {
-            (0..=self.data_gen).for_each(|gen| {
-                DataFile::clear(&self.dir_name, gen).unwrap();
-                IndexFile::clear(gen, &self.dir_name).unwrap();
+        // the store opening the disktable writes into the directory on open
+        pub(crate) fn writes_on_open(mut self) -> FileDisktableBuilder {
+            self.writes_on_open = true;
+            self
+        }
+
+        pub(crate) fn is_follower(&self) -> bool {
+            self.follower
+        }
+
+        pub(crate) fn changelog_enabled(&self) -> bool {
+            self.changelog
+        }
+
+        pub(crate) fn configured_wal_sync_policy(&self) -> WalSyncPolicy {
+            self.wal_sync_policy
+        }
+
+        pub(crate) fn read_sketch_size(&self) -> Option<(usize, usize)> {
+            self.track_reads
+        }
+
+        pub(crate) fn read_repair_depth(&self) -> Option<usize> {
+            self.read_repair
+        }
+
+        pub(crate) fn background_flush_capacity(&self) -> Option<usize> {
+            self.background_flush
+        }
+
+        pub(crate) fn skips_redundant_writes(&self) -> bool {
+            self.skip_redundant_writes
+        }
+
+        pub(crate) fn stamps_entries(&self) -> bool {
+            self.entry_timestamps
+        }
+
+        pub(crate) fn shared_fault_injector(&self) -> Option<SharedFaultInjector> {
+            self.fault_injector.clone()
+        }
+
+        pub(crate) fn build(mut self) -> io::Result<FileDisktable> {
+            if let Some((width, depth)) = self.track_reads {
+                if width == 0 || depth == 0 {
+                    return invalid(format!(
+                        "track_reads needs a width and a depth of at least 1, not {} and {}",
+                        width, depth
+                    ));
+                }
+            }
+            if self.follower {
+                if self.read_only == Some(false) {
+                    return invalid("follower cannot be set along with read_only(false)");
+                }
+                self.read_only = Some(true);
+            }
+            if self.read_only == Some(true) && self.compact_on_open {
+                return invalid("compact_on_open cannot be set on a read only disktable");
+            }
+            if let Some(ratio) = self
+                .compact_on_tombstone_ratio
+                .filter(|ratio| !(0.0..1.0).contains(ratio))
+            {
+                return invalid(format!(
+                    "compact_on_tombstone_ratio must be in [0, 1), not {}",
+                    ratio
+                ));
+            }
+            if self.compaction_rate_limit == Some(0) {
+                return invalid("compaction_rate_limit must be at least 1");
+            }
+            #[cfg(feature = "encryption")]
+            if self.cipher.is_some() && self.inline_threshold.is_some() {
+                return invalid("inline_threshold cannot be set along with encryption_key");
+            }
+            #[cfg(feature = "encryption")]
+            if self.cipher.is_some() && self.blob_threshold.is_some() {
+                return invalid("blob_threshold cannot be set along with encryption_key");
+            }
+            #[cfg(feature = "compression")]
+            match (self.compression_level, self.compression_dictionary) {
+                (Some(level), _) if !zstd::compression_level_range().contains(&level) => {
+                    return invalid(format!("compress_values has no level {}", level))
+                }
+                (None, Some(_)) => {
+                    return invalid("compression_dictionary cannot be set without compress_values")
+                }
+                (_, Some(max_bytes)) if max_bytes < 256 => {
+                    return invalid(format!(
+                        "compression_dictionary must be at least 256 bytes, not {}",
+                        max_bytes
+                    ))
+                }
+                _ => {}
+            }
+            if !(0.0 < self.blob_gc_ratio && self.blob_gc_ratio <= 1.0) {
+                return invalid(format!(
+                    "blob_gc_ratio must be in (0, 1], not {}",
+                    self.blob_gc_ratio
+                ));
+            }
+            if self.blob_threshold == Some(0) {
+                return invalid("blob_threshold must be at least 1 byte");
+            }
+            if let (Some(blob), Some(inline)) = (self.blob_threshold, self.inline_threshold) {
+                if blob < inline {
+                    return invalid(format!(
+                        "blob_threshold must be at least inline_threshold {}, not {}",
+                        inline, blob
+                    ));
+                }
+            }
+            if self.read_only == Some(true) && self.changelog {
+                return invalid("changelog cannot be set on a read only disktable");
+            }
+            if self.read_repair == Some(0) {
+                return invalid("read_repair must be at least 1");
+            }
+            if self.read_only == Some(true) && self.read_repair.is_some() {
+                return invalid("read_repair cannot be set on a read only disktable");
+            }
+            if self.background_flush == Some(0) {
+                return invalid("background_flush needs a capacity of at least 1");
+            }
+            if self.read_only == Some(true) && self.background_flush.is_some() {
+                return invalid("background_flush cannot be set on a read only disktable");
+            }
+            if self.keep_versions == 0 {
+                return invalid("keep_versions must be at least 1");
+            }
+            match self.compaction_fan_in {
+                Some(fan_in) if fan_in < 2 => {
+                    return invalid(format!(
+                        "compaction_fan_in must be at least 2, not {}",
+                        fan_in
+                    ))
+                }
+                Some(_) if self.keep_versions > 1 || self.double_buffered_compaction => {
+                    return invalid(
+                        "compaction_fan_in cannot be set along with keep_versions over 1 \
+                         or double_buffered_compaction",
+                    )
+                }
+                _ => {}
+            }
+            if let Some(fpr) = self.bloom_fpr.filter(|fpr| !(0.0 < *fpr && *fpr < 1.0)) {
+                return invalid(format!("bloom_filters must be in (0, 1), not {}", fpr));
+            }
+            if self.partitioned_bloom_filters && self.bloom_fpr.is_none() {
+                return invalid("partitioned_bloom_filters cannot be set without bloom_filters");
+            }
+            if self.batch_bloom_checks && self.bloom_fpr.is_none() {
+                return invalid("batch_bloom_checks cannot be set without bloom_filters");
+            }
+            match (self.value_cache_bytes, self.value_cache_admission) {
+                (Some(0), _) => return invalid("value_cache must be at least 1 byte"),
+                (None, Some(_)) => {
+                    return invalid("value_cache_admission cannot be set without value_cache")
+                }
+                (_, Some(fraction)) if !(0.0 < fraction && fraction <= 1.0) => {
+                    return invalid(format!(
+                        "value_cache_admission must be in (0, 1], not {}",
+                        fraction
+                    ))
+                }
+                _ => {}
+            }
+            if let Some(fraction) = self
+                .index_size_budget
+                .filter(|fraction| !(0.0 < *fraction && *fraction <= 1.0))
+            {
+                return invalid(format!(
+                    "index_size_budget must be in (0, 1], not {}",
+                    fraction
+                ));
+            }
+            if self.scan_read_ahead == Some(0) {
+                return invalid("scan_read_ahead must be at least 1 byte");
+            }
+            if self.max_generations == Some(0) {
+                return invalid("max_generations must be at least 1");
+            }
+            if let Some(block_size) = self.block_size.filter(|size| *size < Self::MIN_BLOCK_SIZE) {
+                return invalid(format!(
+                    "block_size must be at least {} bytes, not {}",
+                    Self::MIN_BLOCK_SIZE,
+                    block_size
+                ));
+            }
+            #[cfg(feature = "mmap")]
+            if self.mmap_indexes && self.file_system.is_some() {
+                return invalid("mmap_indexes cannot be set along with file_system");
+            }
+            if self.file_system.is_some() && (self.file_mode.is_some() || self.dir_mode.is_some()) {
+                return invalid("file_mode and dir_mode cannot be set along with file_system");
+            }
+            let fs = self.file_system.clone().unwrap_or_else(|| {
+                Arc::new(OsFileSystem::with_modes(
+                    self.file_mode.unwrap_or(OsFileSystem::DEFAULT_FILE_MODE),
+                    self.dir_mode.unwrap_or(OsFileSystem::DEFAULT_DIR_MODE),
+                ))
             });
-            self.data_gen = 0;
+            let mut read_only = self.open_read_only(fs.as_ref())?;
+            if !read_only {
+                let recovered = IndexFile::recover(&fs, &self.dir_name).and_then(|_| {
+                    if !self.manifest && Manifest::exists(fs.as_ref(), &self.dir_name)? {
+                        return invalid("the directory has a manifest, which a writer keeps only with manifest set");
+                    }
+                    Ok(())
+                });
+                match recovered {
+                    // the first write of an open which didn't probe the directory
+                    Err(err) if self.read_only.is_none() && file_system::denies_writes(&err) => {
+                        read_only = true
+                    }
+                    recovered => recovered?,
+                }
+            }
+            if !read_only {
+                GenerationPins::remove_leftovers(&fs, &self.dir_name)?;
+            }
+            let data_gens = FileDisktable::get_data_gens(fs.as_ref(), &self.dir_name)?;
+            self.check_key_transform(fs.as_ref(), &data_gens, read_only)?;
+            // merging generations apart from the ones around them leaves gaps in the numbers,
+            // which lookups skip as they only go through the generations found here
+            let gaps = data_gens
+                .windows(2)
+                .filter(|pair| pair[1] > pair[0] + 1)
+                .count();
+            if gaps > 0 {
+                log::debug!(
+                    "generations in {} have {} gaps. data_gens: {:?}",
+                    self.dir_name,
+                    gaps,
+                    data_gens
+                );
+            }
+            let blob_files = BlobFiles::open(fs.clone(), &self.dir_name)?;
+            let generation_pins = if self.pin_scanned_generations && !read_only {
+                Some(Arc::new(GenerationPins::new(fs.clone(), &self.dir_name)))
+            } else {
+                None
+            };
+            #[cfg(feature = "compression")]
+            let dictionary_bytes = self.compression_dictionary;
+            let value_cache_admission = self.value_cache_admission.unwrap_or(1.0);
+            let mut disktable = FileDisktable {
+                bloom_files: BloomFiles::new(fs.clone(), &self.dir_name),
+                range_tombstones: RangeTombstones::load(
+                    fs.clone(),
+                    &self.dir_name,
+                    self.comparator.clone(),
+                )?,
+                fs,
+                data_gen: *data_gens.last().unwrap_or(&0),
+                dir_name: self.dir_name,
+                flushing: None,
+                data_files: HashMap::new(),
+                retry_policy: self.retry_policy,
+                index_cache: IndexCache::default(),
+                #[cfg(feature = "mmap")]
+                mapped_indexes: if self.mmap_indexes {
+                    Some(MappedIndexes::default())
+                } else {
+                    None
+                },
+                counters: Arc::new(Counters::default()),
+                comparator: self.comparator,
+                read_only,
+                subscribers: Subscribers::default(),
+                live_gens: data_gens.iter().copied().collect(),
+                max_generations: self.max_generations,
+                data_file_options: DataFileOptions {
+                    block_size: self.block_size,
+                    #[cfg(feature = "compression")]
+                    compression: self.compression_level.map(|level| ValueCompression {
+                        level,
+                        dictionary_bytes,
+                    }),
+                    #[cfg(feature = "encryption")]
+                    cipher: self.cipher,
+                    #[cfg(feature = "compression")]
+                    dictionaries: Arc::default(),
+                    inline_threshold: self.inline_threshold,
+                    blob_threshold: self.blob_threshold,
+                    blob_files: Some(Arc::new(blob_files)),
+                    entry_timestamps: self.entry_timestamps,
+                    existence_records: self.existence_records,
+                },
+                dedup_values: self.dedup_values,
+                entry_counts: EntryCounts::default(),
+                compact_on_tombstone_ratio: self.compact_on_tombstone_ratio,
+                tombstones_kept: BTreeSet::new(),
+                compaction_rate_limit: self.compaction_rate_limit,
+                sync_on_flush: self.sync_on_flush,
+                verify_after_write: self.verify_after_write,
+                fault_injector: self.fault_injector,
+                keep_versions: self.keep_versions,
+                double_buffered_compaction: self.double_buffered_compaction,
+                compaction_fan_in: self.compaction_fan_in,
+                bloom_fpr: self.bloom_fpr,
+                partitioned_bloom_filters: self.partitioned_bloom_filters,
+                batch_bloom_checks: self.batch_bloom_checks,
+                value_cache: self
+                    .value_cache_bytes
+                    .map(|capacity| ValueCache::new(capacity, value_cache_admission)),
+                index_size_budget: self.index_size_budget,
+                manifest: None,
+                listed_gens: ListedGenerations::default(),
+                generation_pins,
+                scan_read_ahead: self.scan_read_ahead,
+                compaction_audit_log: self.compaction_audit_log,
+                blob_gc_ratio: self.blob_gc_ratio,
+                verify_index_order: self.verify_index_order,
+                front_coded_indexes: self.front_coded_indexes,
+                followed: if read_only {
+                    Some(HashMap::new())
+                } else {
+                    None
+                },
+            };
+            if self.manifest && !read_only {
+                disktable.open_manifest()?;
+            }
+            if read_only {
+                disktable.refresh()?;
+            }
+            if let Some(mut verify) = self.verify_on_open {
+                disktable.verify(&data_gens, verify.every, &mut verify.progress)?;
+            }
+            if self.compact_on_open {
+                disktable.compact(None)?;
+            }
+            if let Some(config) = self.warm_on_open {
+                disktable.warm(&config)?;
+            }
+            Ok(disktable)
+        }
+    }
+
+    impl FileDisktable {
+        /// Open a disktable with the default options of `FileDisktableBuilder`.
+        pub fn new(dir_name: &str) -> Result<Self, io::Error> {
+            FileDisktableBuilder::new(dir_name).build()
+        }
+
+        fn verify(
+            &self,
+            data_gens: &[DataGen],
+            every: usize,
+            progress: &mut dyn FnMut(&VerifyProgress),
+        ) -> io::Result<()> {
+            let mut report = VerifyProgress {
+                total_generations: data_gens.len(),
+                ..VerifyProgress::default()
+            };
+            for data_gen in data_gens {
+                let mut entries = HashMap::new();
+                let checked = report.bytes_checked;
+                for entry in self.data_file(*data_gen).entries() {
+                    let entry = entry?;
+                    report.bytes_checked = checked + entry.next_offset();
+                    entries.insert(entry.offset, entry.key);
+                }
+                let index = self.index_file(*data_gen).read_all()?;
+                let mismatched = index.iter().find(|(key, index_entry)| {
+                    index_entry.data_gen == *data_gen
+                        && entries.get(&index_entry.offset) != Some(*key)
+                });
+                if let Some((key, index_entry)) = mismatched {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "index of generation {} points {} at offset {} of no such entry",
+                            data_gen, key, index_entry.offset
+                        ),
+                    ));
+                }
+                report.generations_checked += 1;
+                if report.generations_checked.is_multiple_of(every)
+                    || report.generations_checked == report.total_generations
+                {
+                    progress(&report);
+                }
+            }
+            log::debug!("Disktable#verify has completed. {:?}", report);
             Ok(())
         }
+
+        fn check_writable(&self) -> io::Result<()> {
+            if self.read_only {
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("disktable in {} is read only", self.dir_name),
+                ))
+            } else {
+                Ok(())
+            }
+        }
+
+        // generations of the data files in the directory. anything but a file named exactly
+        // like a data file is skipped, including names which aren't valid UTF-8
+        fn get_data_gens(fs: &dyn FileSystem, dir_name: &str) -> io::Result<Vec<DataGen>> {
+            let mut list = Self::find_data_gens(fs, dir_name)?;
+            // generations written or being removed which the manifest doesn't list are left
+            // out, as are the ones it lists whose data files are gone
+            if let Some(manifest) = Manifest::load(fs, dir_name)? {
+                list.retain(|data_gen| manifest.contains(*data_gen));
+            }
+            Ok(list)
+        }
+
+        // `get_data_gens` of the directory other than the ones waiting for scans to be dropped
+        fn data_gens(&self) -> io::Result<Vec<DataGen>> {
+            let mut data_gens = Self::find_data_gens(self.fs.as_ref(), &self.dir_name)?;
+            if let Some(listed) = self.listed_gens.load(self.fs.as_ref(), &self.dir_name)? {
+                data_gens.retain(|data_gen| listed.contains(data_gen));
+            }
+            if let Some(pins) = self.generation_pins.as_ref() {
+                data_gens.retain(|data_gen| !pins.is_retired(*data_gen));
+            }
+            Ok(data_gens)
+        }
+
+        // the generations of the data files in the directory, listed or not
+        fn find_data_gens(fs: &dyn FileSystem, dir_name: &str) -> io::Result<Vec<DataGen>> {
+            let pattern =
+                Regex::new(&format!("^{}_(?P<gen>\\d+)$", DataFile::FILE_NAME_PREFIX)).unwrap();
+            let mut list = vec![];
+            for entry in fs.read_dir(std::path::Path::new(dir_name))? {
+                let file_name = &entry.name;
+                let file_name = match file_name.to_str() {
+                    Some(file_name) => file_name,
+                    None => {
+                        log::warn!(
+                            "skipped a file whose name isn't UTF-8. dir: {}, name: {:?}",
+                            dir_name,
+                            file_name
+                        );
+                        continue;
+                    }
+                };
+                let gen = match pattern.captures(file_name) {
+                    Some(cap) => cap["gen"].parse::<DataGen>(),
+                    None => continue,
+                };
+                if entry.is_dir {
+                    log::warn!(
+                        "skipped a directory named like a data file. dir: {}, name: {}",
+                        dir_name,
+                        file_name
+                    );
+                    continue;
+                }
+                match gen {
+                    Ok(gen) => list.push(gen),
+                    Err(err) => log::warn!(
+                        "skipped a data file of an invalid generation. dir: {}, name: {}, err: {:?}",
+                        dir_name,
+                        file_name,
+                        err
+                    ),
+                }
+            }
+            list.sort_unstable();
+            Ok(list)
+        }
+
+        fn with_data_file<T>(
+            &self,
+            gen: DataGen,
+            f: impl FnOnce(&DataFile) -> io::Result<T>,
+        ) -> io::Result<T> {
+            match self.data_files.get(&gen) {
+                Some(found) => f(found),
+                // a missing data file isn't created, as a reader may race with its removal
+                None => f(&DataFile::open(&self.fs, &self.dir_name, gen)?
+                    .with_options(&self.data_file_options)),
+            }
+        }
+
+        fn data_file(&self, data_gen: DataGen) -> DataFile {
+            DataFile::of(&self.fs, &self.dir_name, data_gen).with_options(&self.data_file_options)
+        }
+
+        fn index_file(&self, data_gen: DataGen) -> IndexFile {
+            IndexFile::of(&self.fs, data_gen, &self.dir_name)
+                .verify_order(self.verify_index_order)
+                .front_coded(self.front_coded_indexes)
+                .existence_records(self.data_file_options.existence_records)
+        }
+
+        fn fetch(
+            &self,
+            data_gen: DataGen,
+            offset: Offset,
+        ) -> io::Result<Option<(String, Option<String>)>> {
+            Ok(self
+                .fetch_entry(data_gen, offset)?
+                .map(|entry| (entry.key, entry.value)))
+        }
+
+        // the error of the last attempt once retries run out
+        fn fetch_entry(&self, data_gen: DataGen, offset: Offset) -> io::Result<Option<DataEntry>> {
+            Counters::incr(&self.counters.data_reads);
+            self.with_data_file(data_gen, |df| {
+                self.retry_policy.run(|| df.read_entry(offset))
+            })
+        }
+
+        // bytes of a compaction are limited from when it starts, see `RateLimiter`
+        fn compaction_rate_limiter(&self) -> RateLimiter {
+            RateLimiter::start(self.compaction_rate_limit, self.counters.clone())
+        }
+
+        // merge the adjacent generations into `result` as `merge_generations` does, streaming
+        // their records rather than reading them all in. `result` is the newest of them or
+        // a generation of its own, which the manifest commits in place of them. returns the
+        // keys whose older records have been dropped
+        fn merge_wave(
+            &mut self,
+            data_gens: &[DataGen],
+            result: DataGen,
+            cancel: Option<&CancellationToken>,
+            rate_limiter: &mut RateLimiter,
+        ) -> io::Result<Vec<String>> {
+            let sources = data_gens
+                .iter()
+                .rev()
+                .map(|data_gen| self.compaction_source(*data_gen))
+                .collect();
+            let mut merged = MergeIter::new(sources, self.comparator.clone());
+            let mut writer =
+                DataFileWriter::create(&self.fs, &self.dir_name, &self.data_file_options)?;
+            if self.dedup_values {
+                writer = writer.dedup_values();
+            }
+            let mut dropped = vec![];
+            // tombstones are kept as older generations may still have the keys
+            let written = std::iter::from_fn(|| merged.next_versions(data_gens.len()))
+                .try_for_each(|versions| {
+                    CancellationToken::check(cancel)?;
+                    let (key, values) = versions?;
+                    let read: usize = values
+                        .iter()
+                        .map(|value| key.len() + value.as_ref().map_or(0, StoredValue::len))
+                        .sum();
+                    rate_limiter.acquire(read);
+                    let before = writer.written_bytes();
+                    writer.append_stored(&key, values[0].as_ref())?;
+                    rate_limiter.acquire((writer.written_bytes() - before) as usize);
+                    if values.len() > 1 {
+                        dropped.push(key);
+                    }
+                    Ok(())
+                });
+            drop(merged);
+            if let Err(err) = written {
+                writer.abort()?;
+                return Err(err);
+            }
+            data_gens
+                .iter()
+                .for_each(|data_gen| self.forget_index(*data_gen));
+            let data_file = writer.finish(result)?;
+            self.entry_counts.record(result, &data_file.index);
+            self.publish_generation(result, data_file, None)?;
+            let replaced: Vec<DataGen> = data_gens
+                .iter()
+                .copied()
+                .filter(|data_gen| *data_gen != result)
+                .collect();
+            replaced.iter().for_each(|data_gen| {
+                self.live_gens.remove(data_gen);
+            });
+            self.live_gens.insert(result);
+            self.write_manifest()?;
+            for data_gen in replaced {
+                self.retire_generation(data_gen)?;
+                self.bloom_files.remove(data_gen)?;
+                self.entry_counts.forget(data_gen);
+            }
+            self.subscribers.publish(StoreEvent::Compacted {
+                merged: data_gens.to_vec(),
+                result,
+            });
+            Ok(dropped)
+        }
+
+        // entries of the given generations as they're stored, newer generations win
+        // the newest records of keys in the generations, along with the keys whose older
+        // records are shadowed by them
+        fn read_generations(
+            &self,
+            data_gens: &[DataGen],
+            rate_limiter: &mut RateLimiter,
+        ) -> io::Result<(MemtableEntries<String, StoredValue>, Vec<String>)> {
+            let mut merged = MemtableEntries {
+                entries: BTreeMap::new(),
+                tombstones: BTreeSet::new(),
+            };
+            let mut shadowed = BTreeSet::new();
+            let mut data_gens = data_gens.to_vec();
+            data_gens.sort_unstable();
+            for data_gen in data_gens {
+                for entry in self.data_file(data_gen).stored_entries() {
+                    let (key, value) = entry?.into_stored();
+                    rate_limiter.acquire(key.len() + value.as_ref().map_or(0, StoredValue::len));
+                    if self.range_tombstones.covers(&key, data_gen) {
+                        continue;
+                    }
+                    if merged.entries.contains_key(&key) || merged.tombstones.contains(&key) {
+                        shadowed.insert(key.clone());
+                    }
+                    match value {
+                        Some(value) => {
+                            merged.tombstones.remove(&key);
+                            merged.entries.insert(key, value);
+                        }
+                        None => {
+                            merged.entries.remove(&key);
+                            merged.tombstones.insert(key);
+                        }
+                    }
+                }
+            }
+            Ok((merged, shadowed.into_iter().collect()))
+        }
+
+        // records of the generation in [start, end), read from the record of the first key
+        // at least the start which the index points at, see `data_offset_from`
+        fn generation_source(&self, data_gen: DataGen, start: &str, end: Option<&str>) -> Source {
+            let from = match self.data_offset_from(data_gen, start) {
+                Ok(from) => from,
+                Err(err) => return Box::new(std::iter::once(Err(err))),
+            };
+            let entries = self
+                .generation_entries(data_gen, true, from)
+                .map(|entry| entry.map(|entry| (entry.key, entry.value)));
+            scan::bounded(entries, start, end, &self.comparator)
+        }
+
+        // where a scan from the start reads the data file of the generation from. a generation
+        // holds a record of a key at most, in key order, so under a byte-wise comparator the
+        // records before the one the index points at for the first key at least the start
+        // are of keys before it. it's the beginning otherwise, or if the entry points into
+        // another generation or the index is gone, which the scan of the data file reports
+        fn data_offset_from(&self, data_gen: DataGen, start: &str) -> io::Result<Offset> {
+            if start.is_empty() || !self.comparator.is_bytewise() {
+                return Ok(0);
+            }
+            let first = match self.index_cache.get(data_gen) {
+                Some(index) => index
+                    .range(start.to_string()..)
+                    .next()
+                    .map(|(_, entry)| entry.clone()),
+                None => match IndexFile::open(&self.fs, data_gen, &self.dir_name) {
+                    Ok(index_file) => index_file.first_from(start)?,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                    Err(err) => return Err(err),
+                },
+            };
+            Ok(match first {
+                Some(entry) if entry.data_gen == data_gen => entry.offset,
+                _ => 0,
+            })
+        }
+
+        // the source holding the generations it reads until it's dropped, see `GenerationPins`
+        fn pinned<V: 'static>(&self, data_gens: &[DataGen], source: Source<V>) -> Source<V> {
+            match self.generation_pins.as_ref() {
+                Some(pins) => {
+                    let pins = data_gens
+                        .iter()
+                        .map(|data_gen| pins.pin(*data_gen))
+                        .collect();
+                    Box::new(GenerationPins::hold(source, pins))
+                }
+                None => source,
+            }
+        }
+
+        // `generation_source` of values as they're stored, which compactions copy
+        fn compaction_source(&self, data_gen: DataGen) -> Source<StoredValue> {
+            let entries = self
+                .generation_entries(data_gen, false, 0)
+                .map(|entry| entry.map(DataEntry::into_stored));
+            Box::new(entries)
+        }
+
+        // entries of the generation not covered by range tombstones, from the record at `from`
+        fn generation_entries(
+            &self,
+            data_gen: DataGen,
+            read_blobs: bool,
+            from: Offset,
+        ) -> Box<dyn Iterator<Item = io::Result<DataEntry>> + Send + 'static> {
+            let range_deleted = self.range_tombstones.of_generation(data_gen);
+            // a reader doesn't create the data file of a generation the writer has removed
+            // since it was listed, which is scanned as empty
+            let data_file = if self.read_only {
+                match DataFile::open(&self.fs, &self.dir_name, data_gen) {
+                    Ok(data_file) => data_file.with_options(&self.data_file_options),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        return Box::new(std::iter::empty())
+                    }
+                    Err(err) => return Box::new(std::iter::once(Err(err))),
+                }
+            } else {
+                self.data_file(data_gen)
+            };
+            let data_file = match self.scan_read_ahead {
+                Some(bytes) => data_file.read_ahead(bytes),
+                None => data_file,
+            };
+            // a scan reads blobs of the generation as it is now, see `sources`
+            let entries = if read_blobs {
+                data_file.pin_blobs().entries()
+            } else {
+                data_file.stored_entries()
+            }
+            .from(from);
+            Box::new(entries.filter(move |entry| match entry {
+                Ok(entry) => !range_deleted(&entry.key),
+                Err(_) => true,
+            }))
+        }
+
+        // the newest index entry for the key, which may be a tombstone, failing with the error
+        // of an index which can't be read
+        fn try_find_index_entry(&self, key: &str) -> io::Result<Option<IndexEntry>> {
+            self.try_find_index_entry_in(&self.looked_up_gens(), key)
+        }
+
+        fn try_find_index_entry_in(
+            &self,
+            data_gens: &[DataGen],
+            key: &str,
+        ) -> io::Result<Option<IndexEntry>> {
+            for data_gen in self.routed(data_gens, key) {
+                if let Some(index_entry) = self.try_find_in_generation(data_gen, key)? {
+                    return Ok(Some(index_entry));
+                }
+            }
+            Ok(None)
+        }
+
+        // generations to look up, oldest first. a read only disktable looks up the ones found
+        // by the last refresh, which picks up the ones the writer of the directory has written
+        // since it was opened
+        fn looked_up_gens(&self) -> Vec<DataGen> {
+            self.live_gens.iter().copied().collect()
+        }
+
+        // the generations in the directory if the writer retired any of the ones a read only
+        // disktable looked up, e.g. amid a double buffered compaction. that only shows as
+        // a miss or a failed read, so the read is retried over the ones listed then. the
+        // directory is listed for misses alone, generations added since the last refresh
+        // aren't looked up otherwise, and a failed listing leaves the read as it is
+        fn relisted(&self, data_gens: &[DataGen]) -> Option<Vec<DataGen>> {
+            if !self.read_only {
+                return None;
+            }
+            match self.data_gens() {
+                Ok(listed) if data_gens.iter().any(|data_gen| !listed.contains(data_gen)) => {
+                    Some(listed)
+                }
+                Ok(_) => None,
+                Err(err) => {
+                    log::error!("failed to list generations. err: {:?}", err);
+                    None
+                }
+            }
+        }
+
+        // the lookup over the generations looked up, retried over the ones `relisted` gives
+        // while it fails or finds nothing
+        fn relisting<T>(
+            &self,
+            found: impl Fn(&T) -> bool,
+            mut lookup: impl FnMut(&[DataGen]) -> io::Result<T>,
+        ) -> io::Result<T> {
+            let mut data_gens = self.looked_up_gens();
+            loop {
+                let looked_up = lookup(&data_gens);
+                if looked_up.as_ref().is_ok_and(&found) {
+                    return looked_up;
+                }
+                match self.relisted(&data_gens) {
+                    Some(listed) => data_gens = listed,
+                    None => return looked_up,
+                }
+            }
+        }
+
+        // the value of the newest record of the key in the generations, failing as `find`
+        fn try_find_in(&self, data_gens: &[DataGen], key: &str) -> io::Result<Option<String>> {
+            for data_gen in self.routed(data_gens, key) {
+                if let Some(index_entry) = self.try_find_in_generation(data_gen, key)? {
+                    return self.try_value_of(key, index_entry);
+                }
+            }
+            Ok(None)
+        }
+
+        // the generations which may hold the key, newest first, which are all of them unless
+        // `partitioned_bloom_filters` or `batch_bloom_checks` rules out the others. those
+        // ruled out count as skips once a lookup goes past them, so that the ones older than
+        // the generation it stops at don't, as they wouldn't have been checked one at a time
+        fn routed(&self, data_gens: &[DataGen], key: &str) -> Routed<'_> {
+            let routed = |candidates: BTreeSet<DataGen>| Routed {
+                data_gens: data_gens
+                    .iter()
+                    .map(|data_gen| (*data_gen, candidates.contains(data_gen)))
+                    .collect(),
+                bloom_skips: &self.counters.bloom_skips,
+            };
+            if !self.partitioned_bloom_filters {
+                if !self.batch_bloom_checks {
+                    return routed(data_gens.iter().copied().collect());
+                }
+                let hash = BloomFilter::hash(key);
+                return routed(
+                    data_gens
+                        .iter()
+                        .copied()
+                        .filter(|data_gen| {
+                            self.bloom_files
+                                .get(*data_gen)
+                                .is_none_or(|filter| filter.contains_hashed(hash))
+                        })
+                        .collect(),
+                );
+            }
+            let comparator = self.comparator.as_ref();
+            let partitioned = self
+                .bloom_files
+                .partitioned(data_gens, comparator, |data_gen| {
+                    let index = self.index_file(data_gen).read_all();
+                    match index {
+                        Ok(index) => Some(self.key_range(index.keys())),
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => Some(None),
+                        Err(err) => {
+                            log::warn!(
+                                "failed to read the key range of a generation. data_gen: {}, err: {:?}",
+                                data_gen,
+                                err
+                            );
+                            None
+                        }
+                    }
+                });
+            routed(
+                partitioned
+                    .candidates(key, comparator)
+                    .into_iter()
+                    .collect(),
+            )
+        }
+
+        fn try_find_in_generation(
+            &self,
+            data_gen: DataGen,
+            key: &str,
+        ) -> io::Result<Option<IndexEntry>> {
+            let found = match self.try_find_in_index(data_gen, key)? {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            if self.range_tombstones.covers(key, data_gen) {
+                return Ok(Some(IndexEntry {
+                    data_gen,
+                    offset: found.offset,
+                    kind: EntryKind::Tombstone,
+                    inline_value: None,
+                }));
+            }
+            Ok(Some(found))
+        }
+
+        // the keys which any of the generations holds a record of, failing with the error of
+        // an index which can't be read rather than taking it for a miss
+        fn shadowed_keys<'a>(
+            &self,
+            newer: &[DataGen],
+            keys: impl Iterator<Item = &'a String>,
+        ) -> io::Result<Vec<String>> {
+            keys.into_iter().try_fold(vec![], |mut shadowed, key| {
+                for data_gen in self.routed(newer, key) {
+                    if self.try_find_in_index(data_gen, key)?.is_some() {
+                        shadowed.push(key.clone());
+                        break;
+                    }
+                }
+                Ok(shadowed)
+            })
+        }
+
+        // the entry of the index of the generation regardless of range tombstones
+        fn try_find_in_index(
+            &self,
+            data_gen: DataGen,
+            key: &str,
+        ) -> io::Result<Option<IndexEntry>> {
+            // filters hold the bytes of keys rather than anything derived from the comparator,
+            // so they never rule out a key the index holds whatever the order. generations
+            // are routed past the filters as a whole already when they're partitioned or
+            // their checks are batched
+            if self.bloom_fpr.is_some()
+                && !self.partitioned_bloom_filters
+                && !self.batch_bloom_checks
+            {
+                if let Some(filter) = self.bloom_files.get(data_gen) {
+                    if !filter.contains(key) {
+                        Counters::incr(&self.counters.bloom_skips);
+                        return Ok(None);
+                    }
+                }
+            }
+            if let Some(index) = self.index_cache.get(data_gen) {
+                return Ok(index.get(key).cloned());
+            }
+            #[cfg(feature = "mmap")]
+            if let Some(mapped_indexes) = self.mapped_indexes.as_ref() {
+                return mapped_indexes
+                    .get_or_map(&self.index_file(data_gen), data_gen)
+                    .map(|mapped| mapped.find(key));
+            }
+            Counters::incr(&self.counters.index_reads);
+            let found = self
+                .retry_policy
+                .run(|| IndexFile::open(&self.fs, data_gen, &self.dir_name)?.find_index(key));
+            match found {
+                // no generation has the number, e.g. it was merged into a newer one
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                found => found,
+            }
+        }
+
+        // `lazy_sources`, each in reverse order of the comparator if `rev`
+        fn lazy_sources_in(
+            &self,
+            start: &str,
+            end: Option<&str>,
+            rev: bool,
+        ) -> io::Result<Vec<Source<ValueHandle>>> {
+            let sorted = |records| {
+                if rev {
+                    scan::sorted_rev(records, start, end, &self.comparator)
+                } else {
+                    scan::sorted(records, start, end, &self.comparator)
+                }
+            };
+            let mut sources: Vec<Source<ValueHandle>> = vec![];
+            if let Some(flushing) = self.flushing.as_ref() {
+                let records = scan::in_memory(
+                    flushing.keys(),
+                    |key| flushing.get(key),
+                    start,
+                    end,
+                    &self.comparator,
+                )
+                .into_iter()
+                .map(|(key, value)| (key, value.map(ValueHandle::loaded)))
+                .collect();
+                sources.push(sorted(records));
+            }
+            // an index entry may point into another generation's data file
+            let mut data_files: HashMap<DataGen, Arc<DataFile>> = HashMap::new();
+            for data_gen in self.scanned_data_gens()?.into_iter().rev() {
+                let index = match self.index_entries_from(data_gen, start, end) {
+                    Ok(index) => index,
+                    // the writer merged it into a newer generation since it was listed
+                    Err(err) if self.read_only && err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+                let mut records = Vec::with_capacity(index.len());
+                for (key, index_entry) in index {
+                    if self.range_tombstones.covers(&key, data_gen) {
+                        continue;
+                    }
+                    let handle = match (index_entry.kind, index_entry.inline_value) {
+                        (EntryKind::Tombstone, _) => None,
+                        (EntryKind::Value, Some(value)) => Some(ValueHandle::loaded(value)),
+                        (EntryKind::Value, None) => {
+                            let data_file = match data_files.get(&index_entry.data_gen) {
+                                Some(data_file) => data_file.clone(),
+                                None => {
+                                    let data_file = Arc::new(
+                                        DataFile::open(
+                                            &self.fs,
+                                            &self.dir_name,
+                                            index_entry.data_gen,
+                                        )?
+                                        .with_options(&self.data_file_options)
+                                        .pin_blobs(),
+                                    );
+                                    data_files.insert(index_entry.data_gen, data_file.clone());
+                                    data_file
+                                }
+                            };
+                            Some(ValueHandle::on_disk(
+                                key.clone(),
+                                index_entry.data_gen,
+                                index_entry.offset,
+                                data_file,
+                                self.retry_policy.clone(),
+                                self.counters.clone(),
+                            ))
+                        }
+                    };
+                    records.push((key, handle));
+                }
+                let mut read_gens: Vec<DataGen> = data_files.keys().copied().collect();
+                read_gens.push(data_gen);
+                let source = sorted(records);
+                sources.push(self.pinned(&read_gens, source));
+            }
+            Ok(sources)
+        }
+
+        // entries of the index of the generation which may be in [start, end), from the index
+        // cache if it holds the index. indexes are in byte-wise order, so they're read from
+        // the restart point before the start under a byte-wise comparator and whole otherwise
+        fn index_entries_from(
+            &self,
+            data_gen: DataGen,
+            start: &str,
+            end: Option<&str>,
+        ) -> io::Result<Vec<(String, IndexEntry)>> {
+            let bytewise = self.comparator.is_bytewise();
+            let cloned = |(key, entry): (&String, &IndexEntry)| (key.clone(), entry.clone());
+            if let Some(index) = self.index_cache.get(data_gen) {
+                return Ok(if bytewise {
+                    index
+                        .range(start.to_string()..)
+                        .take_while(|(key, _)| end.is_none_or(|end| key.as_str() < end))
+                        .map(cloned)
+                        .collect()
+                } else {
+                    index.iter().map(cloned).collect()
+                });
+            }
+            let index_file = IndexFile::open(&self.fs, data_gen, &self.dir_name)?
+                .verify_order(self.verify_index_order);
+            if bytewise {
+                index_file.read_range(start, end)
+            } else {
+                Ok(index_file.read_all()?.into_iter().collect())
+            }
+        }
+
+        // the value of the entry for the key, None for a tombstone, failing with the error of
+        // the data file, and with `InvalidData` unless a record of the key sits at the offset
+        fn try_value_of(&self, key: &str, index_entry: IndexEntry) -> io::Result<Option<String>> {
+            match index_entry.kind {
+                EntryKind::Tombstone => return Ok(None),
+                EntryKind::Value if index_entry.inline_value.is_some() => {
+                    return Ok(index_entry.inline_value)
+                }
+                EntryKind::Value => {}
+            }
+            let (data_gen, offset) = (index_entry.data_gen, index_entry.offset);
+            let value_cache = self.value_cache.as_ref();
+            if let Some(value) = value_cache.and_then(|cache| cache.get(data_gen, offset)) {
+                return Ok(Some(value));
+            }
+            Counters::incr(&self.counters.data_reads);
+            let entry = self.with_data_file(data_gen, |df| {
+                self.retry_policy.run(|| df.read_entry(offset))
+            })?;
+            match entry {
+                Some(entry) if entry.key == key => {
+                    if let (Some(cache), Some(value)) = (value_cache, entry.value.as_ref()) {
+                        cache.insert(data_gen, offset, value);
+                    }
+                    Ok(entry.value)
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "no record of the key at the offset. key: {}, data_gen: {}, offset: {}",
+                        key, data_gen, offset
+                    ),
+                )),
+            }
+        }
+
+        // `try_value_of` into the buffer, which is left empty unless it's a value
+        fn value_into(
+            &self,
+            key: &str,
+            index_entry: IndexEntry,
+            buf: &mut String,
+        ) -> io::Result<bool> {
+            match index_entry.kind {
+                EntryKind::Tombstone => Ok(false),
+                EntryKind::Value => {
+                    if let Some(value) = index_entry.inline_value.as_ref() {
+                        buf.push_str(value);
+                        return Ok(true);
+                    }
+                    let (data_gen, offset) = (index_entry.data_gen, index_entry.offset);
+                    let value_cache = self.value_cache.as_ref();
+                    if let Some(value) = value_cache.and_then(|cache| cache.get(data_gen, offset)) {
+                        buf.push_str(&value);
+                        return Ok(true);
+                    }
+                    Counters::incr(&self.counters.data_reads);
+                    let start = buf.len();
+                    let read = self.with_data_file(data_gen, |df| {
+                        self.retry_policy
+                            .run(|| df.read_value_into(offset, key, buf))
+                    });
+                    match read {
+                        Ok(true) => {
+                            if let Some(cache) = value_cache {
+                                cache.insert(data_gen, offset, &buf[start..]);
+                            }
+                            Ok(true)
+                        }
+                        Ok(false) => {
+                            // a tombstone of the key reads as nothing, as does no record of
+                            // it, which fails as `try_value_of`
+                            buf.clear();
+                            self.try_value_of(key, index_entry).map(|_| false)
+                        }
+                        Err(err) => {
+                            buf.clear();
+                            Err(err)
+                        }
+                    }
+                }
+            }
+        }
+
+        // drop the cached and mapped index of the generation before its index file goes away
+        fn forget_index(&self, data_gen: DataGen) {
+            self.index_cache.evict(data_gen);
+            self.bloom_files.evict(data_gen);
+            if let Some(value_cache) = self.value_cache.as_ref() {
+                value_cache.evict(data_gen);
+            }
+            #[cfg(feature = "compression")]
+            self.data_file_options
+                .dictionaries
+                .evict(&DataFile::path(&self.dir_name, data_gen));
+            #[cfg(feature = "mmap")]
+            if let Some(mapped_indexes) = self.mapped_indexes.as_ref() {
+                mapped_indexes.unmap(data_gen);
+            }
+        }
+
+        fn forget_indexes(&self) {
+            self.index_cache.clear();
+            self.bloom_files.clear();
+            if let Some(value_cache) = self.value_cache.as_ref() {
+                value_cache.clear();
+            }
+            #[cfg(feature = "compression")]
+            self.data_file_options.dictionaries.clear();
+            #[cfg(feature = "mmap")]
+            if let Some(mapped_indexes) = self.mapped_indexes.as_ref() {
+                mapped_indexes.clear();
+            }
+        }
+
+        // remove the data and index files of a generation merged away, or have them removed
+        // once the scans reading them are dropped
+        fn retire_generation(&self, data_gen: DataGen) -> io::Result<()> {
+            match self.generation_pins.as_ref() {
+                Some(pins) => pins.retire(data_gen),
+                None => {
+                    DataFile::clear(&self.fs, &self.dir_name, data_gen)?;
+                    IndexFile::clear(&self.fs, data_gen, &self.dir_name)
+                }
+            }
+        }
+
+        // the manifest of the directory, or one of the generations found in it if there's none.
+        // the files of generations it doesn't list, left by a crash amid a flush or after a
+        // compaction was committed, are removed
+        fn open_manifest(&mut self) -> io::Result<()> {
+            let manifest = match Manifest::load(self.fs.as_ref(), &self.dir_name)? {
+                Some(manifest) => {
+                    for data_gen in Self::find_data_gens(self.fs.as_ref(), &self.dir_name)? {
+                        if !manifest.contains(data_gen) {
+                            log::debug!(
+                                "removed generation {} which the manifest doesn't list. dir: {}",
+                                data_gen,
+                                self.dir_name
+                            );
+                            DataFile::clear(&self.fs, &self.dir_name, data_gen)?;
+                            IndexFile::clear(&self.fs, data_gen, &self.dir_name)?;
+                            self.bloom_files.remove(data_gen)?;
+                        }
+                    }
+                    manifest
+                }
+                None => {
+                    let mut generations = BTreeMap::new();
+                    for data_gen in &self.live_gens {
+                        let index = self.index_file(*data_gen).read_all()?;
+                        generations.insert(*data_gen, self.key_range(index.keys()));
+                    }
+                    let mut manifest = Manifest::new(generations);
+                    manifest.write(
+                        self.fs.as_ref(),
+                        &self.dir_name,
+                        &self.live_gens,
+                        self.sync_on_flush,
+                    )?;
+                    manifest
+                }
+            };
+            self.manifest = Some(Mutex::new(manifest));
+            Ok(())
+        }
+
+        // list the live generations in the manifest if there's one, which is what makes
+        // generations published or retired for readers of the directory
+        fn write_manifest(&self) -> io::Result<()> {
+            match self.manifest.as_ref() {
+                Some(manifest) => manifest.lock().unwrap().write(
+                    self.fs.as_ref(),
+                    &self.dir_name,
+                    &self.live_gens,
+                    self.sync_on_flush,
+                ),
+                None => Ok(()),
+            }
+        }
+
+        fn key_range<'a>(&self, keys: impl Iterator<Item = &'a String> + Clone) -> KeyRange {
+            let comparator = self.comparator.as_ref();
+            let min_key = keys.clone().min_by(|a, b| comparator.compare(a, b))?;
+            let max_key = keys.max_by(|a, b| comparator.compare(a, b))?;
+            Some((min_key.clone(), max_key.clone()))
+        }
+
+        // a generation is listed by its data file and read up to `data_gen`, and read through
+        // its index, so a half-written generation is never visible and one whose index isn't
+        // renamed into place yet reads as empty
+        fn write_generation(
+            &self,
+            data_gen: DataGen,
+            memtable_entries: &MemtableEntries<String, String>,
+        ) -> io::Result<()> {
+            let data_file = DataFile::write(
+                &self.fs,
+                &self.dir_name,
+                data_gen,
+                memtable_entries,
+                self.comparator.as_ref(),
+                &self.data_file_options,
+            )?;
+            self.entry_counts.record(data_gen, &data_file.index);
+            self.publish_generation(data_gen, data_file, Some(memtable_entries))
+        }
+
+        // the generation is made durable before it's renamed into place, so that a crash
+        // leaves either no data file for it or a complete one. its index and skip index are
+        // renamed after the data file, which a crash in between leaves `IndexFile::recover`
+        // to finish, so that a data file rewritten in place is never read through the index
+        // of the one it replaced
+        // a generation written from the memtable entries is checked against them first
+        // with `verify_after_write`
+        fn publish_generation(
+            &self,
+            data_gen: DataGen,
+            data_file: UnpublishedDataFile,
+            written: Option<&MemtableEntries<String, String>>,
+        ) -> io::Result<()> {
+            let mut index_file = self.index_file(data_gen);
+            if let Some(budget) = self.index_size_budget {
+                let data_bytes = data_file.size()?;
+                index_file = index_file.skip_interval(IndexFile::skip_interval_within(
+                    budget,
+                    &data_file.index,
+                    data_bytes,
+                ));
+            }
+            let mut index = index_file.write_unpublished(&data_file.index)?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Written)?;
+            if self.sync_on_flush {
+                data_file.sync()?;
+                index.sync()?;
+            }
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Synced)?;
+            if let (true, Some(written)) = (self.verify_after_write, written) {
+                self.verify_written(data_gen, &data_file, &index, written)?;
+            }
+            // the filter of a generation rewritten in place goes before its data file is
+            // replaced, so it never rules out keys of the data file it sits beside
+            self.bloom_files.remove(data_gen)?;
+            if let Some(manifest) = self.manifest.as_ref() {
+                let key_range = self.key_range(data_file.index.keys());
+                manifest.lock().unwrap().insert(data_gen, key_range);
+            }
+            // the filter and the key range go over tombstones along with values, so that
+            // a generation of nothing but tombstones is never skipped for the keys it deletes
+            let filter = self
+                .bloom_fpr
+                .map(|fpr| BloomFiles::sized_for(data_file.index.keys(), fpr));
+            let partitioned_range = if self.partitioned_bloom_filters {
+                Some(self.key_range(data_file.index.keys()))
+            } else {
+                None
+            };
+            index.intend(data_file.tmp_path(), self.sync_on_flush)?;
+            data_file.publish()?;
+            if self.sync_on_flush {
+                self.fs.sync_dir(std::path::Path::new(&self.dir_name))?;
+            }
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Renamed)?;
+            index.publish()?;
+            if let Some(filter) = filter {
+                self.bloom_files
+                    .write(data_gen, filter, self.sync_on_flush)?;
+            }
+            if let Some(key_range) = partitioned_range {
+                self.bloom_files.record_range(data_gen, key_range);
+            }
+            if self.sync_on_flush {
+                self.fs.sync_dir(std::path::Path::new(&self.dir_name))?;
+            }
+            Ok(())
+        }
+
+        // the records read back from the written data file, which are the memtable entries
+        // and tombstones with the index pointing at each of them, both as it's built in
+        // memory and as it's written, read back through the written skip index
+        fn verify_written(
+            &self,
+            data_gen: DataGen,
+            data_file: &UnpublishedDataFile,
+            index: &UnpublishedIndex,
+            written: &MemtableEntries<String, String>,
+        ) -> io::Result<()> {
+            let mismatched = |detail: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "generation {} doesn't read back as written: {}",
+                        data_gen, detail
+                    ),
+                )
+            };
+            let written_index = index.read_back()?;
+            let mut records = 0;
+            for entry in data_file.read_back(&self.data_file_options)?.entries() {
+                let entry = entry?;
+                let expected = match written.entries.get(&entry.key) {
+                    Some(value) => Some(value),
+                    None if written.tombstones.contains(&entry.key) => None,
+                    None => return Err(mismatched(format!("{} was never written", entry.key))),
+                };
+                if entry.value.as_ref() != expected {
+                    return Err(mismatched(format!("the record of {} differs", entry.key)));
+                }
+                if data_file
+                    .index
+                    .get(&entry.key)
+                    .map(|indexed| indexed.offset)
+                    != Some(entry.offset)
+                {
+                    return Err(mismatched(format!(
+                        "the index doesn't point at {} at offset {}",
+                        entry.key, entry.offset
+                    )));
+                }
+                let found = written_index.find_index(&entry.key)?;
+                if found.map(|indexed| (indexed.data_gen, indexed.offset))
+                    != Some((data_gen, entry.offset))
+                {
+                    return Err(mismatched(format!(
+                        "the written index doesn't point at {} at offset {}",
+                        entry.key, entry.offset
+                    )));
+                }
+                records += 1;
+            }
+            let expected = written.entries.len() + written.tombstones.len();
+            if records != expected {
+                return Err(mismatched(format!(
+                    "{} records of {} are read",
+                    records, expected
+                )));
+            }
+            let indexed = written_index.read_all()?.len();
+            if indexed != expected {
+                return Err(mismatched(format!(
+                    "the written index holds {} entries of {}",
+                    indexed, expected
+                )));
+            }
+            Ok(())
+        }
+
+        // what's in the generation, which must exist
+        fn generation_info(&self, data_gen: DataGen) -> io::Result<GenerationInfo> {
+            let index_file = self.index_file(data_gen);
+            let index = index_file.read_all()?;
+            let (entries, tombstones) = self.entry_counts.record(data_gen, &index);
+            let comparator = self.comparator.as_ref();
+            let min_key = index
+                .keys()
+                .min_by(|a, b| comparator.compare(a, b))
+                .cloned();
+            let max_key = index
+                .keys()
+                .max_by(|a, b| comparator.compare(a, b))
+                .cloned();
+            let file_bytes = |path: std::path::PathBuf| self.fs.open(&path)?.size();
+            #[cfg(feature = "encryption")]
+            let encrypted = self.data_file_options.cipher.is_some();
+            #[cfg(not(feature = "encryption"))]
+            let encrypted = false;
+            Ok(GenerationInfo {
+                data_gen,
+                entries,
+                tombstones,
+                min_key,
+                max_key,
+                data_file_bytes: file_bytes(self.data_file(data_gen).file.path())?,
+                index_file_bytes: file_bytes(index_file.path())?,
+                block_size: self.data_file_options.block_size,
+                encrypted,
+            })
+        }
+
+        // write a sentinel file into the directory, read it back and remove it
+        fn sentinel_round_trip(&self) -> io::Result<()> {
+            const HEALTH_CHECK_FILE_NAME: &str = "health_check";
+            // removes the sentinel if a step fails before it's removed
+            struct Sentinel<'a> {
+                fs: &'a SharedFileSystem,
+                path: std::path::PathBuf,
+                left: bool,
+            }
+            impl Drop for Sentinel<'_> {
+                fn drop(&mut self) {
+                    if !self.left {
+                        return;
+                    }
+                    match self.fs.remove(&self.path) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => log::warn!(
+                            "failed to remove the health check sentinel. path: {:?}, err: {:?}",
+                            self.path,
+                            err
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+            let mut sentinel = Sentinel {
+                fs: &self.fs,
+                path: std::path::Path::new(&self.dir_name).join(HEALTH_CHECK_FILE_NAME),
+                left: true,
+            };
+            let written = format!("{:?}", SystemTime::now());
+            let file = self.fs.create(&sentinel.path)?;
+            file.write(written.as_bytes())?;
+            file.sync()?;
+            let mut read = String::new();
+            HandleReader::new(self.fs.open(&sentinel.path)?.as_ref()).read_to_string(&mut read)?;
+            sentinel.left = false;
+            self.fs.remove(&sentinel.path)?;
+            if read != written {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} was read back as {:?}", written, read),
+                ));
+            }
+            Ok(())
+        }
+
+        // what tells the files of the generation apart from ones the writer replaced them
+        // with, see `GenerationStamp`
+        fn generation_stamp(&self, data_gen: DataGen) -> io::Result<GenerationStamp> {
+            use std::hash::{Hash, Hasher};
+            use std::io::Read;
+            let data_file = DataFile::open(&self.fs, &self.dir_name, data_gen)?;
+            let index_file = self.fs.open(&self.index_file(data_gen).path())?;
+            let identities = (data_file.file.handle.identity()?, index_file.identity()?);
+            // the index holds the keys and offsets of the data file, so it's read in full
+            // only where a replaced one can't be told otherwise
+            let index_hash = match identities.1 {
+                Some(_) => None,
+                None => {
+                    let mut bytes = vec![];
+                    HandleReader::new(index_file.as_ref()).read_to_end(&mut bytes)?;
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    bytes.hash(&mut hasher);
+                    Some(hasher.finish())
+                }
+            };
+            Ok(GenerationStamp {
+                sizes: (data_file.file.handle.size()?, index_file.size()?),
+                identities,
+                index_hash,
+            })
+        }
+
+        // remove generations from the oldest, stopping at the first one which fails unless
+        // `best_effort`. the data file goes first, so a generation is no longer listed even if
+        // its other files are left. generations left keep `data_gen` from being reused
+        fn clear_generations(&mut self, best_effort: bool) -> io::Result<Vec<ClearFailure>> {
+            self.check_writable()?;
+            self.forget_indexes();
+            // generations waiting for scans to be dropped go along with the rest
+            if let Some(pins) = self.generation_pins.as_ref() {
+                pins.remove_retired()?;
+            }
+            // a manifest listing none publishes the clear at once, before any file goes
+            if self.manifest.is_some() {
+                self.live_gens.clear();
+                self.write_manifest()?;
+            }
+            let mut failures = vec![];
+            // generations go from the oldest, each by its data file first, which unlists it.
+            // a reader of the directory listing generations amid a clear then sees the newest
+            // of them and finds the newest record of a key or none at all, and a lookup
+            // failing on a generation removed under it is retried as the listing changed
+            for data_gen in 0..=self.data_gen {
+                let removed = DataFile::clear(&self.fs, &self.dir_name, data_gen)
+                    .and_then(|_| IndexFile::clear(&self.fs, data_gen, &self.dir_name))
+                    .and_then(|_| self.bloom_files.remove(data_gen));
+                match removed {
+                    Ok(()) => self.entry_counts.forget(data_gen),
+                    Err(error) => {
+                        log::error!(
+                            "failed to clear a generation. data_gen: {}, err: {:?}",
+                            data_gen,
+                            error
+                        );
+                        failures.push(ClearFailure { data_gen, error });
+                        if !best_effort {
+                            break;
+                        }
+                    }
+                }
+            }
+            self.live_gens = self.data_gens()?.into_iter().collect();
+            if !failures.is_empty() {
+                return Ok(failures);
+            }
+            self.range_tombstones.clear()?;
+            if let Some(blob_files) = self.data_file_options.blob_files.as_ref() {
+                blob_files.clear()?;
+            }
+            self.data_gen = 0;
+            self.entry_counts.clear();
+            self.subscribers.publish(StoreEvent::Cleared);
+            Ok(failures)
+        }
+
+        // generations a scan reads, which are the ones found by the last refresh for a read
+        // only disktable
+        fn scanned_data_gens(&self) -> io::Result<Vec<DataGen>> {
+            match self.followed.as_ref() {
+                Some(followed) => {
+                    let mut data_gens: Vec<DataGen> = followed.keys().copied().collect();
+                    data_gens.sort_unstable();
+                    Ok(data_gens)
+                }
+                None => self.data_gens(),
+            }
+        }
+
+        // append the report of a compaction to the audit log if there's one
+        fn audit(&self, report: &CompactionReport) -> io::Result<()> {
+            if let Some(path) = self.compaction_audit_log.as_ref() {
+                let log = self.fs.open_or_create(path)?;
+                log.write(format!("{}\n", report.to_json()).as_bytes())?;
+                if self.sync_on_flush {
+                    log.sync()?;
+                }
+            }
+            Ok(())
+        }
+
+        // bytes of the data and index files of the generation
+        fn generation_bytes(&self, data_gen: DataGen) -> io::Result<u64> {
+            let index_file = self.fs.open(&self.index_file(data_gen).path())?;
+            Ok(self.data_file(data_gen).file.handle.size()? + index_file.size()?)
+        }
+
+        // the sizes of the data and index files of the generation, which change when the
+        // writer replaces it, e.g. after a `clear`
+        fn generation_sizes(&self, data_gen: DataGen) -> io::Result<(u64, u64)> {
+            let data_file = DataFile::open(&self.fs, &self.dir_name, data_gen)?;
+            let index_file = self.fs.open(&self.index_file(data_gen).path())?;
+            Ok((data_file.file.handle.size()?, index_file.size()?))
+        }
+
+        // tombstones out of all entries of the generations looked up, from the counts kept
+        // as generations are written and removed. a generation written before the disktable
+        // was opened, or by another writer, is counted from its index the first time only
+        fn tombstone_ratio(&self) -> io::Result<f64> {
+            self.tombstone_ratio_without(&BTreeSet::new())
+        }
+
+        // `tombstone_ratio` leaving the tombstones of the generations out, while their entries
+        // are still counted
+        fn tombstone_ratio_without(&self, left_out: &BTreeSet<DataGen>) -> io::Result<f64> {
+            let mut entries = 0;
+            let mut tombstones = 0;
+            for data_gen in self.looked_up_gens() {
+                let counts = match self.entry_counts.get(data_gen) {
+                    Some(counts) => counts,
+                    None => {
+                        let index = self.index_file(data_gen).read_all()?;
+                        self.entry_counts.record(data_gen, &index)
+                    }
+                };
+                entries += counts.0;
+                if !left_out.contains(&data_gen) {
+                    tombstones += counts.1;
+                }
+            }
+            if entries == 0 {
+                return Ok(0.0);
+            }
+            Ok(tombstones as f64 / entries as f64)
+        }
+    }
+
+    impl Disktable for FileDisktable {
+        fn find(&self, key: &str) -> io::Result<Option<String>> {
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => return Ok(Some(value.to_string())),
+                    memtable::GetResult::Deleted => return Ok(None),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            self.relisting(Option::is_some, |data_gens| {
+                self.try_find_in(data_gens, key)
+            })
+        }
+
+        fn find_detailed(&self, key: &str) -> io::Result<(Option<String>, FindSource)> {
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => {
+                        return Ok((Some(value.to_string()), FindSource::Flushing))
+                    }
+                    memtable::GetResult::Deleted => return Ok((None, FindSource::Flushing)),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            self.relisting(
+                |found: &(Option<String>, FindSource)| found.0.is_some(),
+                |data_gens| match self.try_find_index_entry_in(data_gens, key)? {
+                    Some(index_entry) => {
+                        let data_gen = index_entry.data_gen;
+                        Ok((
+                            self.try_value_of(key, index_entry)?,
+                            FindSource::Disk(data_gen),
+                        ))
+                    }
+                    None => Ok((None, FindSource::Miss)),
+                },
+            )
+        }
+
+        fn find_into(&self, key: &str, buf: &mut String) -> io::Result<bool> {
+            buf.clear();
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => {
+                        buf.push_str(value);
+                        return Ok(true);
+                    }
+                    memtable::GetResult::Deleted => return Ok(false),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            self.relisting(
+                |found| *found,
+                |data_gens| match self.try_find_index_entry_in(data_gens, key)? {
+                    Some(index_entry) => self.value_into(key, index_entry, buf),
+                    None => Ok(false),
+                },
+            )
+        }
+
+        fn find_with_deadline(&self, key: &str, deadline: Instant) -> io::Result<Option<String>> {
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => return Ok(Some(value.to_string())),
+                    memtable::GetResult::Deleted => return Ok(None),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            self.relisting(Option::is_some, |data_gens| {
+                for data_gen in self.routed(data_gens, key) {
+                    Timeout::check(deadline)?;
+                    if let Some(index_entry) = self.try_find_in_generation(data_gen, key)? {
+                        return self.try_value_of(key, index_entry);
+                    }
+                }
+                Ok(None)
+            })
+        }
+
+        fn contains(&self, key: &str) -> io::Result<bool> {
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(_) => return Ok(true),
+                    memtable::GetResult::Deleted => return Ok(false),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            self.relisting(
+                |found| *found,
+                |data_gens| {
+                    let index_entry = self.try_find_index_entry_in(data_gens, key)?;
+                    Ok(index_entry.is_some_and(|index_entry| index_entry.kind == EntryKind::Value))
+                },
+            )
+        }
+
+        fn find_versions(&self, key: &str) -> io::Result<Vec<Option<String>>> {
+            let flushing = self.flushing.as_ref().and_then(|mem_entries| {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => Some(Some(value.to_string())),
+                    memtable::GetResult::Deleted => Some(None),
+                    memtable::GetResult::NotFound => None,
+                }
+            });
+            let on_disk = self
+                .find_all_versions(key)?
+                .into_iter()
+                .map(|(_, value)| value);
+            Ok(flushing.into_iter().chain(on_disk).collect())
+        }
+
+        // the generation holding a record, rather than the one of its index entry, which
+        // may point into another generation's data file
+        fn find_all_versions(&self, key: &str) -> io::Result<Vec<(DataGen, Option<String>)>> {
+            let mut versions = vec![];
+            for data_gen in self.routed(&self.looked_up_gens(), key) {
+                if let Some(index_entry) = self.try_find_in_generation(data_gen, key)? {
+                    versions.push((data_gen, self.try_value_of(key, index_entry)?));
+                }
+            }
+            Ok(versions)
+        }
+
+        // the data file is read for an inlined value as well, as index entries carry no
+        // timestamps
+        fn find_with_meta(&self, key: &str) -> io::Result<Option<(String, SystemTime)>> {
+            let in_flushing = self.flushing.as_ref().is_some_and(|mem_entries| {
+                !matches!(
+                    mem_entries.get(&key.to_string()),
+                    memtable::GetResult::NotFound
+                )
+            });
+            if in_flushing || !self.data_file_options.entry_timestamps {
+                return Ok(None);
+            }
+            let index_entry = match self.try_find_index_entry(key)? {
+                Some(index_entry) if index_entry.kind == EntryKind::Value => index_entry,
+                _ => return Ok(None),
+            };
+            let entry = self
+                .fetch_entry(index_entry.data_gen, index_entry.offset)?
+                .filter(|entry| entry.key == key);
+            Ok(entry.and_then(|entry| {
+                let written_at = entry.written_at()?;
+                Some((entry.value?, written_at))
+            }))
+        }
+
+        fn locate(&self, key: &str) -> io::Result<Option<(DataGen, Offset)>> {
+            let in_flushing = self.flushing.as_ref().is_some_and(|mem_entries| {
+                !matches!(
+                    mem_entries.get(&key.to_string()),
+                    memtable::GetResult::NotFound
+                )
+            });
+            // not on disk yet
+            if in_flushing {
+                return Ok(None);
+            }
+            Ok(self
+                .try_find_index_entry(key)?
+                .filter(|index_entry| index_entry.kind == EntryKind::Value)
+                .map(|index_entry| (index_entry.data_gen, index_entry.offset)))
+        }
+
+        fn find_range(&self, key: &str, offset: usize, len: usize) -> io::Result<Option<Vec<u8>>> {
+            if let Some(mem_entries) = self.flushing.as_ref() {
+                match mem_entries.get(&key.to_string()) {
+                    memtable::GetResult::Found(value) => {
+                        return Ok(Some(value_range(value, offset, len)))
+                    }
+                    memtable::GetResult::Deleted => return Ok(None),
+                    memtable::GetResult::NotFound => {}
+                }
+            }
+            let index_entry = match self.try_find_index_entry(key)? {
+                Some(index_entry) if index_entry.kind == EntryKind::Value => index_entry,
+                _ => return Ok(None),
+            };
+            if let Some(value) = index_entry.inline_value.as_ref() {
+                return Ok(Some(value_range(value, offset, len)));
+            }
+            Counters::incr(&self.counters.data_reads);
+            let read = self.with_data_file(index_entry.data_gen, |df| {
+                self.retry_policy
+                    .run(|| df.read_value_range(index_entry.offset, offset, len))
+            })?;
+            match read {
+                Some((read_key, value)) if read_key == key => Ok(Some(value)),
+                // a tombstone of the key reads as nothing, as does no record of it, which
+                // fails as `try_value_of`
+                _ => self.try_value_of(key, index_entry).map(|_| None),
+            }
+        }
+
+        fn flush(
+            &mut self,
+            memtable_entries: MemtableEntries<String, String>,
+        ) -> Result<(), io::Error> {
+            self.check_writable()?;
+            self.flushing = Some(memtable_entries);
+
+            let next_data_gen = self.data_gen + 1;
+            self.write_generation(next_data_gen, self.flushing.as_ref().unwrap())?;
+
+            self.data_gen = next_data_gen;
+            self.live_gens.insert(next_data_gen);
+            self.write_manifest()?;
+            let flushed = self.flushing.take().unwrap();
+            let bytes = self.data_file(next_data_gen).file.handle.size()?;
+            self.subscribers.publish(StoreEvent::Flushed {
+                gen: next_data_gen,
+                keys: flushed.entries.len() + flushed.tombstones.len(),
+                bytes,
+            });
+            log::trace!(
+                "Disktable#flush has completed. next_data_gen: {}",
+                next_data_gen
+            );
+            // tombstones the compaction it triggers keeps don't trigger another one, which
+            // would keep them as well, until newer generations bring enough more
+            if let Some(threshold) = self.compact_on_tombstone_ratio {
+                let ratio = self.tombstone_ratio_without(&self.tombstones_kept)?;
+                if ratio > threshold {
+                    log::debug!(
+                        "Disktable#flush triggers compaction. tombstone ratio: {}, threshold: {}",
+                        ratio,
+                        threshold
+                    );
+                    self.compact(None)?;
+                    if self.tombstone_ratio()? > threshold {
+                        self.tombstones_kept = self.looked_up_gens().into_iter().collect();
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), io::Error> {
+            match self.clear_generations(false)?.into_iter().next() {
+                Some(failure) => Err(failure.error),
+                None => Ok(()),
+            }
+        }
+
+        fn clear_best_effort(&mut self) -> io::Result<Vec<ClearFailure>> {
+            self.clear_generations(true)
+        }
+
+        fn merge_generations(&mut self, a: DataGen, b: DataGen) -> io::Result<DataGen> {
+            self.check_writable()?;
+            let (older, newer) = if a < b { (a, b) } else { (b, a) };
+            let data_gens = self.data_gens()?;
+            if older == newer {
+                return invalid(format!("cannot merge generation {} with itself", a));
+            }
+            if let Some(missing) = [older, newer].iter().find(|gen| !data_gens.contains(gen)) {
+                return invalid(format!("generation {} does not exist", missing));
+            }
+            if self.keep_versions > 1 {
+                return invalid("merge_generations cannot be used along with keep_versions over 1");
+            }
+            // any generation in between would be shadowed by entries of the older one
+            if let Some(between) = data_gens.iter().find(|gen| older < **gen && **gen < newer) {
+                return invalid(format!(
+                    "generations {} and {} are not adjacent, {} lives in between",
+                    older, newer, between
+                ));
+            }
+
+            // tombstones are kept as older generations may still have the keys
+            let mut rate_limiter = self.compaction_rate_limiter();
+            let (mut merged, _) = self.read_generations(&[older, newer], &mut rate_limiter)?;
+            // the merge goes above the newest generation, so the records of keys the ones
+            // above the newer one hold are dropped rather than shadowing theirs
+            let above: Vec<DataGen> = data_gens
+                .into_iter()
+                .filter(|data_gen| *data_gen > newer)
+                .collect();
+            for key in self.shadowed_keys(&above, merged.entries.keys())? {
+                merged.entries.remove(&key);
+            }
+            for key in self.shadowed_keys(&above, merged.tombstones.iter())? {
+                merged.tombstones.remove(&key);
+            }
+            self.forget_index(older);
+            self.forget_index(newer);
+            let result = self.data_gen + 1;
+            let data_file = DataFile::write_stored(
+                &self.fs,
+                &self.dir_name,
+                result,
+                &merged,
+                self.comparator.as_ref(),
+                &self.data_file_options,
+                |written| rate_limiter.acquire(written as usize),
+            )?;
+            self.entry_counts.record(result, &data_file.index);
+            self.publish_generation(result, data_file, None)?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Merged)?;
+            self.live_gens.remove(&older);
+            self.live_gens.remove(&newer);
+            self.live_gens.insert(result);
+            self.data_gen = result;
+            self.write_manifest()?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Committed)?;
+            for data_gen in [older, newer].iter() {
+                self.retire_generation(*data_gen)?;
+                self.bloom_files.remove(*data_gen)?;
+                self.entry_counts.forget(*data_gen);
+            }
+            self.subscribers.publish(StoreEvent::Compacted {
+                merged: vec![older, newer],
+                result,
+            });
+            log::trace!(
+                "Disktable#merge_generations has completed. merged {} and {} into {}",
+                older,
+                newer,
+                result
+            );
+            Ok(result)
+        }
+
+        fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+            self.retry_policy = retry_policy;
+        }
+
+        fn sources(&self, start: &str, end: Option<&str>) -> io::Result<Vec<Source>> {
+            let mut sources: Vec<Source> = vec![];
+            if let Some(flushing) = self.flushing.as_ref() {
+                let records = scan::in_memory(
+                    flushing.keys(),
+                    |key| flushing.get(key),
+                    start,
+                    end,
+                    &self.comparator,
+                );
+                sources.push(scan::sorted(records, start, end, &self.comparator));
+            }
+            self.scanned_data_gens()?
+                .into_iter()
+                .rev()
+                .for_each(|data_gen| {
+                    let source = self.generation_source(data_gen, start, end);
+                    sources.push(self.pinned(&[data_gen], source));
+                });
+            Ok(sources)
+        }
+
+        fn lazy_sources(
+            &self,
+            start: &str,
+            end: Option<&str>,
+        ) -> io::Result<Vec<Source<ValueHandle>>> {
+            self.lazy_sources_in(start, end, false)
+        }
+
+        fn lazy_sources_rev(
+            &self,
+            start: &str,
+            end: Option<&str>,
+        ) -> io::Result<Vec<Source<ValueHandle>>> {
+            self.lazy_sources_in(start, end, true)
+        }
+
+        fn compact(&mut self, cancel: Option<&CancellationToken>) -> io::Result<CompactionReport> {
+            self.check_writable()?;
+            self.tombstones_kept.clear();
+            let mut data_gens = self.data_gens()?;
+            let latest = match data_gens.last() {
+                Some(latest) => *latest,
+                None => return Ok(CompactionReport::default()),
+            };
+            let mut report = CompactionReport {
+                merged: data_gens.clone(),
+                cleared_range_tombstones: self.range_tombstones.len(),
+                ..CompactionReport::default()
+            };
+            let mut bytes_before = 0;
+            for data_gen in &data_gens {
+                bytes_before += self.generation_bytes(*data_gen)?;
+            }
+            let mut rate_limiter = self.compaction_rate_limiter();
+            // waves merge runs of adjacent generations into the newest of each until
+            // few enough are left to be merged at once. with a manifest each run goes above
+            // the latest generation instead, oldest first so that their order is kept, and
+            // the shorter run left over is the oldest one, which is left where it is
+            let mut wave_dropped = BTreeSet::new();
+            let mut top = latest;
+            while let Some(fan_in) = self.compaction_fan_in.filter(|n| data_gens.len() > *n) {
+                let mut merged = vec![];
+                let chunks: Vec<&[DataGen]> = data_gens.rchunks(fan_in).rev().collect();
+                for chunk in chunks {
+                    let newest = *chunk.last().expect("chunks are never empty");
+                    if chunk.len() == 1 {
+                        merged.push(newest);
+                        continue;
+                    }
+                    let result = if self.manifest.is_some() {
+                        top += 1;
+                        top
+                    } else {
+                        newest
+                    };
+                    let dropped = self.merge_wave(chunk, result, cancel, &mut rate_limiter)?;
+                    wave_dropped.extend(dropped);
+                    merged.push(result);
+                }
+                data_gens = merged;
+            }
+            let sources = data_gens
+                .iter()
+                .rev()
+                .map(|data_gen| self.compaction_source(*data_gen))
+                .collect();
+
+            // the k-th newest version of every key goes into the k-th layer, which becomes
+            // generation `newest - k`. a generation holds a record of a key at most,
+            // so there are no more layers than generations and the numbers stay positive
+            let layers = self.keep_versions.min(data_gens.len());
+            // double buffered layers go above the latest generation instead,
+            // so the generations merged stay as they are until they're retired
+            let newest = if self.double_buffered_compaction || self.manifest.is_some() {
+                top + layers as DataGen
+            } else {
+                latest
+            };
+            let mut writers = (0..layers)
+                .map(|layer| {
+                    let writer = DataFileWriter::create_layer(
+                        &self.fs,
+                        &self.dir_name,
+                        &self.data_file_options,
+                        layer,
+                    )?;
+                    Ok(if self.dedup_values {
+                        writer.dedup_values()
+                    } else {
+                        writer
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            let mut merged = MergeIter::new(sources, self.comparator.clone());
+            let read_bytes = |key: &str, values: &[Option<StoredValue>]| -> usize {
+                values
+                    .iter()
+                    .map(|value| key.len() + value.as_ref().map_or(0, StoredValue::len))
+                    .sum()
+            };
+            // every version is taken to report the keys whose older ones are dropped
+            let written = std::iter::from_fn(|| merged.next_versions(data_gens.len()))
+                .try_for_each(|versions| {
+                    CancellationToken::check(cancel)?;
+                    let (key, mut values) = versions?;
+                    let read = read_bytes(&key, &values);
+                    if values.len() > layers {
+                        values.truncate(layers);
+                        report.dropped_versions.push(key.clone());
+                    }
+                    // no older version remains to be shadowed by the oldest tombstone
+                    if values.last() == Some(&None) {
+                        while values.last() == Some(&None) {
+                            values.pop();
+                        }
+                        report.collected_tombstones.push(key.clone());
+                    }
+                    rate_limiter.acquire(read);
+                    values
+                        .iter()
+                        .zip(writers.iter_mut())
+                        .try_for_each(|(value, writer)| {
+                            let before = writer.written_bytes();
+                            writer.append_stored(&key, value.as_ref())?;
+                            rate_limiter.acquire((writer.written_bytes() - before) as usize);
+                            Ok(())
+                        })
+                });
+            if let Err(err) = written {
+                log::debug!("Disktable#compact has been aborted. err: {:?}", err);
+                for writer in writers {
+                    writer.abort()?;
+                }
+                return Err(err);
+            }
+            if !wave_dropped.is_empty() {
+                let comparator = self.comparator.clone();
+                report.dropped_versions.extend(wave_dropped);
+                report
+                    .dropped_versions
+                    .sort_by(|a, b| comparator.compare(a, b));
+                report.dropped_versions.dedup();
+            }
+            self.forget_indexes();
+            self.entry_counts.clear();
+            let mut results = vec![];
+            // the newest layer holds every key left, so older layers are shadowed once it's
+            // published. layers which no key has that many versions for are dropped
+            for (layer, writer) in writers.into_iter().enumerate() {
+                if layer > 0 && writer.is_empty() {
+                    writer.abort()?;
+                    continue;
+                }
+                let data_gen = newest - layer as DataGen;
+                let data_file = writer.finish(data_gen)?;
+                self.entry_counts.record(data_gen, &data_file.index);
+                self.publish_generation(data_gen, data_file, None)?;
+                results.push(data_gen);
+            }
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Merged)?;
+            let mut bytes_after = 0;
+            for data_gen in &results {
+                bytes_after += self.generation_bytes(*data_gen)?;
+            }
+            report.bytes_reclaimed = bytes_before.saturating_sub(bytes_after);
+            self.audit(&report)?;
+
+            self.live_gens = results.iter().copied().collect();
+            self.write_manifest()?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Committed)?;
+            for data_gen in data_gens
+                .iter()
+                .filter(|data_gen| !results.contains(data_gen))
+            {
+                self.retire_generation(*data_gen)?;
+                self.bloom_files.remove(*data_gen)?;
+            }
+            // every record the tombstones covered has been dropped by the merge
+            self.range_tombstones.clear()?;
+            self.data_gen = self.data_gen.max(newest);
+            log::trace!(
+                "Disktable#compact has completed. merged {:?} into {}",
+                report.merged,
+                newest
+            );
+            self.subscribers.publish(StoreEvent::Compacted {
+                merged: report.merged.clone(),
+                result: newest,
+            });
+            Ok(report)
+        }
+
+        fn compact_incremental(&mut self) -> io::Result<CompactionReport> {
+            self.check_writable()?;
+            if self.keep_versions > 1 {
+                return invalid(
+                    "compact_incremental cannot be used along with keep_versions over 1",
+                );
+            }
+            let mut infos = self.generations()?;
+            let newest = match infos.pop() {
+                Some(newest) => newest,
+                None => return Ok(CompactionReport::default()),
+            };
+            let (mut min_key, mut max_key) = match (newest.min_key, newest.max_key) {
+                (Some(min_key), Some(max_key)) => (min_key, max_key),
+                _ => return Ok(CompactionReport::default()),
+            };
+            let comparator = self.comparator.clone();
+            // a key of a generation merged into the newest one would shadow the records of
+            // the key in generations in between, so every generation overlapping the range
+            // merged joins it, widening the range, until the ones left are out of it
+            let overlaps = |info: &GenerationInfo, min_key: &str, max_key: &str| match (
+                info.min_key.as_deref(),
+                info.max_key.as_deref(),
+            ) {
+                (Some(min), Some(max)) => {
+                    comparator.compare(min, max_key) != Ordering::Greater
+                        && comparator.compare(min_key, max) != Ordering::Greater
+                }
+                _ => false,
+            };
+            let mut merged = vec![newest.data_gen];
+            while let Some(position) = infos
+                .iter()
+                .position(|info| overlaps(info, &min_key, &max_key))
+            {
+                let info = infos.remove(position);
+                if let Some(min) = info
+                    .min_key
+                    .filter(|min| comparator.compare(min, &min_key) == Ordering::Less)
+                {
+                    min_key = min;
+                }
+                if let Some(max) = info
+                    .max_key
+                    .filter(|max| comparator.compare(max, &max_key) == Ordering::Greater)
+                {
+                    max_key = max;
+                }
+                merged.push(info.data_gen);
+            }
+            if merged.len() == 1 {
+                return Ok(CompactionReport::default());
+            }
+            merged.sort_unstable();
+            let mut bytes_before = 0;
+            for data_gen in &merged {
+                bytes_before += self.generation_bytes(*data_gen)?;
+            }
+
+            let mut rate_limiter = self.compaction_rate_limiter();
+            let (mut entries, dropped_versions) =
+                self.read_generations(&merged, &mut rate_limiter)?;
+            // no generation left has the keys tombstones would shadow
+            let collected_tombstones = std::mem::take(&mut entries.tombstones);
+            merged
+                .iter()
+                .for_each(|data_gen| self.forget_index(*data_gen));
+            // the merge goes above the newest generation rather than in its place with a
+            // manifest, which commits it. the ones left are out of its range either way
+            let result = if self.manifest.is_some() {
+                newest.data_gen + 1
+            } else {
+                newest.data_gen
+            };
+            let data_file = DataFile::write_stored(
+                &self.fs,
+                &self.dir_name,
+                result,
+                &entries,
+                self.comparator.as_ref(),
+                &self.data_file_options,
+                |written| rate_limiter.acquire(written as usize),
+            )?;
+            self.entry_counts.record(result, &data_file.index);
+            self.publish_generation(result, data_file, None)?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Merged)?;
+            let report = CompactionReport {
+                merged: merged.clone(),
+                collected_tombstones: collected_tombstones.into_iter().collect(),
+                dropped_versions,
+                cleared_range_tombstones: 0,
+                bytes_reclaimed: bytes_before.saturating_sub(self.generation_bytes(result)?),
+            };
+            self.audit(&report)?;
+            merged.iter().for_each(|data_gen| {
+                self.live_gens.remove(data_gen);
+            });
+            self.live_gens.insert(result);
+            self.data_gen = self.data_gen.max(result);
+            self.write_manifest()?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Committed)?;
+            for data_gen in merged.iter().filter(|data_gen| **data_gen != result) {
+                self.retire_generation(*data_gen)?;
+                self.bloom_files.remove(*data_gen)?;
+                self.entry_counts.forget(*data_gen);
+            }
+            log::trace!(
+                "Disktable#compact_incremental has completed. merged {:?} into {}",
+                merged,
+                result
+            );
+            self.subscribers
+                .publish(StoreEvent::Compacted { merged, result });
+            Ok(report)
+        }
+
+        fn vacuum(&mut self, data_gen: DataGen) -> io::Result<CompactionReport> {
+            self.check_writable()?;
+            if self.keep_versions > 1 {
+                return invalid("vacuum cannot be used along with keep_versions over 1");
+            }
+            let data_gens = self.data_gens()?;
+            if !data_gens.contains(&data_gen) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("generation {} does not exist", data_gen),
+                ));
+            }
+            let newer: Vec<DataGen> = data_gens
+                .into_iter()
+                .filter(|newer| *newer > data_gen)
+                .collect();
+            let mut rate_limiter = self.compaction_rate_limiter();
+            let (mut entries, _) = self.read_generations(&[data_gen], &mut rate_limiter)?;
+            let dropped_versions = self.shadowed_keys(&newer, entries.entries.keys())?;
+            // a tombstone shadows only older records of its key, which the newer one does
+            let collected_tombstones = self.shadowed_keys(&newer, entries.tombstones.iter())?;
+            if dropped_versions.is_empty() && collected_tombstones.is_empty() {
+                return Ok(CompactionReport::default());
+            }
+            dropped_versions.iter().for_each(|key| {
+                entries.entries.remove(key);
+            });
+            collected_tombstones.iter().for_each(|key| {
+                entries.tombstones.remove(key);
+            });
+
+            let bytes_before = self.generation_bytes(data_gen)?;
+            self.forget_index(data_gen);
+            // no newer generation holds a key left, so the rewrite goes above the newest one
+            let result = self.data_gen + 1;
+            let data_file = DataFile::write_stored(
+                &self.fs,
+                &self.dir_name,
+                result,
+                &entries,
+                self.comparator.as_ref(),
+                &self.data_file_options,
+                |written| rate_limiter.acquire(written as usize),
+            )?;
+            self.entry_counts.record(result, &data_file.index);
+            self.publish_generation(result, data_file, None)?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Merged)?;
+            let report = CompactionReport {
+                merged: vec![data_gen],
+                collected_tombstones,
+                dropped_versions,
+                cleared_range_tombstones: 0,
+                bytes_reclaimed: bytes_before.saturating_sub(self.generation_bytes(result)?),
+            };
+            self.audit(&report)?;
+            self.live_gens.remove(&data_gen);
+            self.live_gens.insert(result);
+            self.data_gen = result;
+            self.write_manifest()?;
+            fault::inject(self.fault_injector.as_ref(), CrashPoint::Committed)?;
+            self.retire_generation(data_gen)?;
+            self.bloom_files.remove(data_gen)?;
+            self.entry_counts.forget(data_gen);
+            log::trace!(
+                "Disktable#vacuum has completed. merged {} into {}, dropped: {}",
+                data_gen,
+                result,
+                report.dropped_versions.len() + report.collected_tombstones.len()
+            );
+            self.subscribers.publish(StoreEvent::Compacted {
+                merged: vec![data_gen],
+                result,
+            });
+            Ok(report)
+        }
+
+        fn collect_blobs(&mut self) -> io::Result<BlobGcReport> {
+            self.check_writable()?;
+            let mut report = BlobGcReport::default();
+            let blob_files = match self.data_file_options.blob_files.clone() {
+                Some(blob_files) => blob_files,
+                None => return Ok(report),
+            };
+            let data_gens = self.data_gens()?;
+            // values records in data files reference by blob file and offset, which
+            // records of other generations and references to earlier entries may share
+            let mut live: BTreeMap<usize, BTreeMap<Offset, BlobRef>> = BTreeMap::new();
+            for data_gen in &data_gens {
+                for entry in self.data_file(*data_gen).stored_entries() {
+                    if let Some(blob) = entry?.blob {
+                        live.entry(blob.file_id)
+                            .or_default()
+                            .insert(blob.offset, blob);
+                    }
+                }
+            }
+            let mut moved = vec![];
+            for file_id in blob_files.live_file_ids()? {
+                let size = blob_files.size(file_id)?;
+                let blobs = live.remove(&file_id).unwrap_or_default();
+                let live_bytes: u64 = blobs.values().map(|blob| blob.len as u64).sum();
+                if live_bytes == 0 {
+                    report.removed.push(file_id);
+                    report.bytes_reclaimed += size;
+                } else if (live_bytes as f64) < self.blob_gc_ratio * size as f64 {
+                    report.rewritten.push(file_id);
+                    report.bytes_reclaimed += size - live_bytes;
+                    moved.extend(blobs.into_values());
+                }
+            }
+
+            // the new blob file is durable before any data file references it, so that
+            // a crash leaves data files referencing either of the old and the new one
+            if !moved.is_empty() {
+                let relocated = blob_files.relocate(&moved, self.sync_on_flush)?;
+                for data_gen in &data_gens {
+                    let data_file = self
+                        .data_file(*data_gen)
+                        .relocate_blobs(&self.fs, &relocated)?;
+                    if let Some(data_file) = data_file {
+                        if self.sync_on_flush {
+                            data_file.sync()?;
+                        }
+                        data_file.publish()?;
+                    }
+                }
+                if self.sync_on_flush {
+                    self.fs.sync_dir(std::path::Path::new(&self.dir_name))?;
+                }
+            }
+            for file_id in report.removed.iter().chain(&report.rewritten) {
+                blob_files.retire(*file_id)?;
+            }
+            log::trace!(
+                "Disktable#collect_blobs has completed. removed {:?}, rewritten {:?}",
+                report.removed,
+                report.rewritten
+            );
+            Ok(report)
+        }
+
+        fn estimate_range_count(&self, start: &str, end: Option<&str>) -> io::Result<u64> {
+            let mut estimate = match self.flushing.as_ref() {
+                Some(flushing) => flushing
+                    .keys()
+                    .filter(|key| scan::in_range(&self.comparator, key, start, end))
+                    .count() as u64,
+                None => 0,
+            };
+            for data_gen in self.scanned_data_gens()? {
+                let index_file = match IndexFile::open(&self.fs, data_gen, &self.dir_name) {
+                    Ok(index_file) => index_file,
+                    // merged into a newer generation since it was listed
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+                let in_range = |key: &&String| scan::in_range(&self.comparator, key, start, end);
+                // a key sampled stands for the interval of keys up to it, and the keys past
+                // the last one sampled are read, which are all of a small generation
+                let (keys, skip_interval) = index_file.skip_samples()?;
+                let sampled = keys.iter().filter(in_range).count();
+                let unsampled = index_file.unsampled_keys()?.iter().filter(in_range).count();
+                estimate += (sampled * skip_interval + unsampled) as u64;
+            }
+            Ok(estimate)
+        }
+
+        fn skip_sampled_keys(&self) -> io::Result<Vec<String>> {
+            let mut sampled = vec![];
+            for data_gen in self.scanned_data_gens()? {
+                match IndexFile::open(&self.fs, data_gen, &self.dir_name) {
+                    Ok(index_file) => sampled.extend(index_file.skip_samples()?.0),
+                    // merged into a newer generation since it was listed
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+            sampled.sort_by(|a, b| self.comparator.compare(a, b));
+            sampled.dedup();
+            Ok(sampled)
+        }
+
+        fn size_histogram(&self, boundaries: &[usize]) -> io::Result<SizeHistogram> {
+            let mut histogram = SizeHistogram::new(boundaries);
+            for data_gen in self.data_gens()? {
+                for entry in self.data_file(data_gen).entries() {
+                    let entry = entry?;
+                    histogram.record(entry.key.len(), entry.value.map(|value| value.len()));
+                }
+            }
+            Ok(histogram)
+        }
+
+        fn warm(&self, config: &WarmConfig) -> io::Result<()> {
+            let data_gens = self.data_gens()?;
+            let warmed = config.generations.unwrap_or(data_gens.len());
+            for data_gen in data_gens.into_iter().rev().take(warmed) {
+                let index = self
+                    .retry_policy
+                    .run(|| self.index_file(data_gen).read_all())?;
+                // touch entries spread over the data files referenced by the index
+                if config.data_samples > 0 && !index.is_empty() {
+                    let step = (index.len() / config.data_samples).max(1);
+                    index
+                        .values()
+                        .step_by(step)
+                        .take(config.data_samples)
+                        .filter(|entry| entry.kind == EntryKind::Value)
+                        .try_for_each(|entry| {
+                            self.fetch(entry.data_gen, entry.offset).map(|_| ())
+                        })?;
+                }
+                self.index_cache.insert(data_gen, index);
+            }
+            log::trace!(
+                "Disktable#warm has completed. cached indexes: {}",
+                self.index_cache.len()
+            );
+            Ok(())
+        }
+
+        fn admit_write(&self) -> io::Result<()> {
+            self.check_writable()?;
+            match self.max_generations {
+                Some(max_generations) if self.throttled() => {
+                    Counters::incr(&self.counters.throttled_writes);
+                    log::debug!(
+                        "a write was throttled. generations: {}, max_generations: {}",
+                        self.live_gens.len(),
+                        max_generations
+                    );
+                    Err(Throttled {
+                        generations: self.live_gens.len(),
+                        max_generations,
+                    }
+                    .into())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn throttled(&self) -> bool {
+            self.max_generations
+                .is_some_and(|max_generations| self.live_gens.len() >= max_generations)
+        }
+
+        fn read_only(&self) -> bool {
+            self.read_only
+        }
+
+        fn newer_generations(&self, data_gen: DataGen) -> usize {
+            self.looked_up_gens()
+                .into_iter()
+                .filter(|looked_up| *looked_up > data_gen)
+                .count()
+        }
+
+        fn subscribe(&mut self) -> Receiver<StoreEvent> {
+            self.subscribers.subscribe()
+        }
+
+        fn delete_prefix(&mut self, prefix: &str) -> io::Result<()> {
+            self.check_writable()?;
+            self.range_tombstones.add(
+                RangeTombstone::prefix(prefix, self.data_gen),
+                self.sync_on_flush,
+            )
+        }
+
+        fn delete_range(&mut self, start: &str, end: &str) -> io::Result<()> {
+            self.check_writable()?;
+            if self.comparator.compare(start, end) == Ordering::Greater {
+                return invalid(format!(
+                    "range start {:?} is after its end {:?}",
+                    start, end
+                ));
+            }
+            self.range_tombstones.add(
+                RangeTombstone::range(start, end, self.data_gen),
+                self.sync_on_flush,
+            )
+        }
+
+        fn sync_all(&self) -> io::Result<DataGen> {
+            self.check_writable()?;
+            let dir = std::path::Path::new(&self.dir_name);
+            for entry in self.fs.read_dir(dir)? {
+                if entry.is_dir {
+                    continue;
+                }
+                match self.fs.open(&dir.join(&entry.name)) {
+                    Ok(file) => file.sync()?,
+                    // e.g. a blob file removed once the last reader pinning it was done
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+            self.fs.sync_dir(dir)?;
+            Ok(self.data_gen)
+        }
+
+        fn health_check(&self) -> HealthStatus {
+            let mut status = HealthStatus {
+                read_only: self.read_only,
+                ..HealthStatus::default()
+            };
+            if self.read_only {
+                if let Err(err) = self.fs.read_dir(std::path::Path::new(&self.dir_name)) {
+                    status
+                        .problems
+                        .push(format!("the directory isn't listable: {}", err));
+                }
+            } else {
+                let round_trip = self.sentinel_round_trip();
+                status.writable = Some(round_trip.is_ok());
+                if let Err(err) = round_trip {
+                    status
+                        .problems
+                        .push(format!("the directory isn't writable: {}", err));
+                }
+            }
+            let loaded = self
+                .data_gens()
+                .and_then(|data_gens| match data_gens.last() {
+                    Some(newest) => self.generation_sizes(*newest).map(|_| ()),
+                    None => Ok(()),
+                });
+            status.generations_loadable = loaded.is_ok();
+            if let Err(err) = loaded {
+                status
+                    .problems
+                    .push(format!("the generations don't load: {}", err));
+            }
+            status
+        }
+
+        fn refresh(&mut self) -> io::Result<Vec<DataGen>> {
+            let followed = match self.followed.as_ref() {
+                Some(followed) => followed,
+                None => return invalid(format!("disktable in {} isn't read only", self.dir_name)),
+            };
+            let mut found = HashMap::new();
+            for data_gen in self.data_gens()? {
+                match self.generation_stamp(data_gen) {
+                    Ok(stamp) => {
+                        found.insert(data_gen, stamp);
+                    }
+                    // merged into a newer generation since it was listed, or not yet
+                    // published along with its index
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+            // indexes and filters cached for generations which are gone or replaced
+            for (data_gen, stamp) in followed.iter() {
+                if found.get(data_gen) != Some(stamp) {
+                    self.forget_index(*data_gen);
+                }
+            }
+            let mut changed: Vec<DataGen> = found
+                .iter()
+                .filter(|(data_gen, stamp)| followed.get(data_gen) != Some(stamp))
+                .map(|(data_gen, _)| *data_gen)
+                .collect();
+            changed.sort_unstable();
+            self.range_tombstones =
+                RangeTombstones::load(self.fs.clone(), &self.dir_name, self.comparator.clone())?;
+            self.data_gen = found.keys().max().copied().unwrap_or(0);
+            self.live_gens = found.keys().copied().collect();
+            self.followed = Some(found);
+            Ok(changed)
+        }
+
+        fn describe_generation(&self, data_gen: DataGen) -> io::Result<GenerationInfo> {
+            if !self.data_gens()?.contains(&data_gen) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("generation {} does not exist", data_gen),
+                ));
+            }
+            self.generation_info(data_gen)
+        }
+
+        fn generations(&self) -> io::Result<Vec<GenerationInfo>> {
+            self.data_gens()?
+                .into_iter()
+                .map(|data_gen| self.generation_info(data_gen))
+                .collect()
+        }
+
+        fn stats(&self) -> io::Result<DisktableStats> {
+            Ok(DisktableStats {
+                generations: self.looked_up_gens().len(),
+                cached_indexes: self.index_cache.len(),
+                index_reads: Counters::get(&self.counters.index_reads),
+                data_reads: Counters::get(&self.counters.data_reads),
+                throttled_writes: Counters::get(&self.counters.throttled_writes),
+                bloom_skips: Counters::get(&self.counters.bloom_skips),
+                tombstone_ratio: self.tombstone_ratio()?,
+                compaction_throughput: match Counters::get(&self.counters.compaction_nanos) {
+                    0 => 0.0,
+                    nanos => {
+                        Counters::get(&self.counters.compaction_bytes) as f64 * 1e9 / nanos as f64
+                    }
+                },
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        data_file::*,
+        default::{FileDisktable, FileDisktableBuilder},
+        index_file::*,
+        manifest::Manifest,
+        *,
+    };
+    use crate::sst::bloom::BloomFilter;
+    use crate::sst::comparator::Lexicographic;
+    use crate::sst::fault::{CrashPoint, FaultInjector};
+    use file_system::{HandleReader, MemFileSystem, OsFileSystem, SharedFileSystem};
+    use std::{cell::RefCell, io::Read, rc::Rc, sync::Arc};
+
+    // run the test against the local file system, then against memory
+    fn on_each_file_system(test: impl Fn(SharedFileSystem)) {
+        test(Arc::new(OsFileSystem));
+        test(Arc::new(MemFileSystem::default()));
+    }
+
+    fn builder(dir: &str, fs: &SharedFileSystem) -> FileDisktableBuilder {
+        FileDisktableBuilder::new(dir).file_system(fs.clone())
+    }
+
+    fn read_bytes(data_file: &DataFile) -> Vec<u8> {
+        let mut bytes = vec![];
+        data_file.file.reader().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_index_referencing_another_data_file() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_shared_data";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let entries = MemtableEntries {
+                entries: (1..=3)
+                    .map(|i| (format!("key-{}", i), format!("value-{}", i)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            // generation 1 has only the data file, and generation 2 has only the index into it
+            let shared = DataFile::write(
+                &fs,
+                dir,
+                1,
+                &entries,
+                &Lexicographic,
+                &DataFileOptions::default(),
+            )
+            .unwrap();
+            let shared = {
+                let index = shared.index.clone();
+                shared.publish().unwrap();
+                index
+            };
+            assert!(shared.values().all(|entry| entry.data_gen == 1));
+            IndexFile::of(&fs, 2, dir).create_index(&shared).unwrap();
+            DataFile::of(&fs, dir, 2);
+
+            let disktable = builder(dir, &fs).build().unwrap();
+            (1..=3).for_each(|i| {
+                let key = format!("key-{}", i);
+                assert_eq!(disktable.find(&key).unwrap(), Some(format!("value-{}", i)));
+                assert_eq!(
+                    disktable.locate(&key).unwrap(),
+                    Some((1, shared[&key].offset)),
+                    "served by the data file of generation 1"
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn test_flush_interleaves_tombstones_in_key_order() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_interleaved";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let key = |i| format!("key-{:02}", i);
+            let entries = MemtableEntries {
+                entries: (0..40)
+                    .filter(|i| i % 3 != 0)
+                    .map(|i| (key(i), format!("value-{}", i)))
+                    .collect(),
+                tombstones: (0..40).filter(|i| i % 3 == 0).map(key).collect(),
+            };
+            disktable.flush(entries).unwrap();
+
+            let written: Vec<_> = DataFile::of(&fs, dir, 1)
+                .entries()
+                .map(|entry| entry.unwrap())
+                .collect();
+            let keys: Vec<_> = written.iter().map(|entry| entry.key.clone()).collect();
+            assert_eq!(keys, (0..40).map(key).collect::<Vec<_>>());
+            // offsets in the index grow with keys, so the index is sorted as a whole
+            let index = IndexFile::of(&fs, 1, dir).read_all().unwrap();
+            let offsets: Vec<_> = index.values().map(|entry| entry.offset).collect();
+            assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+            index
+                .iter()
+                .zip(written.iter())
+                .for_each(|((key, entry), data)| {
+                    assert_eq!(*key, data.key);
+                    assert_eq!(entry.offset, data.offset);
+                    let kind = match data.value {
+                        Some(_) => EntryKind::Value,
+                        None => EntryKind::Tombstone,
+                    };
+                    assert_eq!(entry.kind, kind);
+                });
+            (0..40).filter(|i| i % 3 == 0).for_each(|i| {
+                assert_eq!(disktable.find(&key(i)).unwrap(), None);
+            });
+        });
+    }
+
+    #[test]
+    fn test_unpublished_generation_is_invisible() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_unpublished";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let entries = |value: &str| MemtableEntries {
+                entries: vec![("key".to_string(), value.to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries("committed")).unwrap();
+
+            // a flush of generation 2 stopped between writing the index and publishing the data file
+            let pending = DataFile::write(
+                &fs,
+                dir,
+                2,
+                &entries("pending"),
+                &Lexicographic,
+                &DataFileOptions::default(),
+            )
+            .unwrap();
+            IndexFile::of(&fs, 2, dir)
+                .create_index(&pending.index)
+                .unwrap();
+            let reopened = builder(dir, &fs).build().unwrap();
+            [&disktable, &reopened].iter().for_each(|disktable| {
+                assert_eq!(
+                    disktable.find("key").unwrap(),
+                    Some("committed".to_string())
+                );
+                assert_eq!(
+                    disktable.locate("key").unwrap().map(|(gen, _)| gen),
+                    Some(1)
+                );
+                assert_eq!(disktable.stats().unwrap().generations, 1);
+            });
+
+            pending.publish().unwrap();
+            let reopened = builder(dir, &fs).build().unwrap();
+            assert_eq!(reopened.find("key").unwrap(), Some("pending".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_verify_on_open() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_verify";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            (1..=5).for_each(|gen| {
+                let entries = MemtableEntries {
+                    entries: vec![(format!("key-{}", gen), "value".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(entries).unwrap();
+            });
+
+            let reports = Rc::new(RefCell::new(vec![]));
+            let collected = reports.clone();
+            builder(dir, &fs)
+                .verify_on_open(2, move |progress| {
+                    collected.borrow_mut().push(progress.clone())
+                })
+                .build()
+                .unwrap();
+            let reports = reports.borrow();
+            let checked: Vec<_> = reports.iter().map(|p| p.generations_checked).collect();
+            assert_eq!(checked, vec![2, 4, 5]);
+            assert!(reports.iter().all(|p| p.total_generations == 5));
+            // every entry takes 12 bytes header + "key-N" + "value" + \0
+            let bytes: Vec<_> = reports.iter().map(|p| p.bytes_checked).collect();
+            assert_eq!(bytes, vec![2 * 23, 4 * 23, 5 * 23]);
+
+            // an index pointing into the middle of an entry
+            let mut index = IndexFile::of(&fs, 3, dir).read_all().unwrap();
+            index.values_mut().for_each(|entry| entry.offset += 1);
+            IndexFile::of(&fs, 3, dir).create_index(&index).unwrap();
+            let verified = builder(dir, &fs).verify_on_open(1, |_| {}).build();
+            assert_eq!(
+                verified.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidData)
+            );
+        });
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_indexes() {
+        let dir = "./test_tmp_mmap_indexes";
+        let mut disktable = FileDisktableBuilder::new(dir)
+            .mmap_indexes(true)
+            .build()
+            .unwrap();
+        disktable.clear().unwrap();
+        let key = |i| format!("key-{:02}", i);
+        [(0..50), (25..75)].iter().for_each(|keys| {
+            let entries = MemtableEntries {
+                entries: keys
+                    .clone()
+                    .map(|i| (key(i), format!("value-{}-{}", keys.start, i)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries).unwrap();
+        });
+        let assert_found = |disktable: &FileDisktable| {
+            [0, 24, 25, 49, 50, 74].iter().for_each(|&i| {
+                let gen = if i < 25 { 0 } else { 25 };
+                assert_eq!(
+                    disktable.find(&key(i)).unwrap(),
+                    Some(format!("value-{}-{}", gen, i))
+                );
+            });
+            assert_eq!(disktable.find(&key(75)).unwrap(), None);
+            assert_eq!(disktable.stats().unwrap().index_reads, 0);
+        };
+        assert_found(&disktable);
+
+        // the mappings of merged generations are dropped along with their files
+        disktable.merge_generations(1, 2).unwrap();
+        assert_found(&disktable);
+        disktable.compact(None).unwrap();
+        assert_found(&disktable);
+    }
+
+    #[test]
+    fn test_builder() {
+        let dir = "./test_tmp_builder";
+        let mut disktable = FileDisktableBuilder::new(dir).build().unwrap();
+        disktable.clear().unwrap();
+        let entries = MemtableEntries {
+            entries: (1..=3)
+                .map(|i| (format!("key-{}", i), format!("value-{}", i)))
+                .collect(),
+            tombstones: BTreeSet::new(),
+        };
+        disktable.flush(entries).unwrap();
+
+        // defaults are the same as `new`
+        let built = FileDisktableBuilder::new(dir).build().unwrap();
+        let opened = FileDisktable::new(dir).unwrap();
+        assert_eq!(built.stats().unwrap(), opened.stats().unwrap());
+        assert_eq!(built.find("key-2").unwrap(), opened.find("key-2").unwrap());
+
+        let invalid = FileDisktableBuilder::new(dir)
+            .read_only(true)
+            .compact_on_open(true)
+            .build();
+        assert_eq!(
+            invalid.err().map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+
+        let mut read_only = FileDisktableBuilder::new(dir)
+            .read_only(true)
+            .warm_on_open(WarmConfig::default())
+            .build()
+            .unwrap();
+        assert_eq!(read_only.stats().unwrap().cached_indexes, 1);
+        assert_eq!(
+            read_only.find("key-1").unwrap(),
+            Some("value-1".to_string())
+        );
+        assert_eq!(
+            read_only.clear().unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            read_only.compact(None).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_size_histogram() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_histogram";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let value_lens = [1, 7, 8, 10, 63, 100, 1000];
+            let entries = MemtableEntries {
+                entries: value_lens
+                    .iter()
+                    .map(|len| (format!("key-{:04}", len), "v".repeat(*len)))
+                    .collect(),
+                tombstones: vec!["key-tomb".to_string()].into_iter().collect(),
+            };
+            disktable.flush(entries).unwrap();
+            let entries = MemtableEntries {
+                entries: vec![("key-0001".to_string(), "v".repeat(64))]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries).unwrap();
+
+            let histogram = disktable.size_histogram(&[64, 8, 512]).unwrap();
+            assert_eq!(histogram.boundaries, vec![8, 64, 512]);
+            // keys are 8 bytes long
+            assert_eq!(histogram.keys, vec![0, 9, 0, 0]);
+            assert_eq!(histogram.values, vec![2, 3, 2, 1]);
+        });
+    }
+
+    #[test]
+    fn test_warm() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_warm";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            (1..=3).for_each(|gen| {
+                let entries = MemtableEntries {
+                    entries: (0..10)
+                        .map(|i| (format!("key-{}-{}", gen, i), format!("value-{}", i)))
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(entries).unwrap();
+            });
+
+            let disktable = builder(dir, &fs).build().unwrap();
+            disktable
+                .warm(&WarmConfig {
+                    generations: Some(2),
+                    data_samples: 3,
+                })
+                .unwrap();
+            let stats = disktable.stats().unwrap();
+            assert_eq!(stats.generations, 3);
+            assert_eq!(stats.cached_indexes, 2);
+            assert_eq!(stats.index_reads, 0);
+            assert_eq!(stats.data_reads, 3 * 2);
+
+            // generations 3 and 2 are served by the cache
+            assert_eq!(
+                disktable.find("key-2-5").unwrap(),
+                Some("value-5".to_string())
+            );
+            assert_eq!(disktable.stats().unwrap().index_reads, 0);
+            // generation 1 isn't warmed
+            assert_eq!(
+                disktable.find("key-1-5").unwrap(),
+                Some("value-5".to_string())
+            );
+            assert_eq!(disktable.stats().unwrap().index_reads, 1);
+        });
+    }
+
+    #[test]
+    fn test_block_size() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_block_size";
+            let block_size = 256;
+            let mut disktable = builder(dir, &fs).block_size(block_size).build().unwrap();
+            disktable.clear().unwrap();
+            // entries of key-00 to key-04 leave a byte at the end of the first block,
+            // which is narrower than a padding record
+            let value_lens = [0, 40, 40, 40, 40, 40, 100, 230, 600, 3];
+            let entries = MemtableEntries {
+                entries: value_lens
+                    .iter()
+                    .enumerate()
+                    .map(|(i, len)| (format!("key-{:02}", i), "v".repeat(*len)))
+                    .collect(),
+                tombstones: vec!["key-05-tomb".to_string()].into_iter().collect(),
+            };
+            disktable.flush(entries).unwrap();
+            let entries = MemtableEntries {
+                entries: vec![("key-01".to_string(), "w".repeat(300))]
+                    .into_iter()
+                    .collect(),
+                tombstones: vec!["key-03".to_string()].into_iter().collect(),
+            };
+            disktable.flush(entries).unwrap();
+
+            let assert_aligned = |data_gen: DataGen| {
+                let data_file = DataFile::of(&fs, dir, data_gen);
+                let len = data_file.file.handle.size().unwrap();
+                assert_eq!(len % block_size as u64, 0, "data_gen: {}", data_gen);
+                let index = IndexFile::of(&fs, data_gen, dir).read_all().unwrap();
+                data_file.entries().for_each(|entry| {
+                    let entry = entry.unwrap();
+                    assert_eq!(index[&entry.key].offset, entry.offset);
+                    // an entry smaller than a block lives within a block
+                    let block = entry.offset / block_size as u64;
+                    if entry.size < block_size {
+                        assert_eq!((entry.next_offset() - 1) / block_size as u64, block);
+                    } else {
+                        assert_eq!(entry.offset % block_size as u64, 0);
+                    }
+                });
+            };
+            let assert_found = |disktable: &FileDisktable| {
+                value_lens.iter().enumerate().for_each(|(i, len)| {
+                    let expected = match i {
+                        1 => Some("w".repeat(300)),
+                        3 => None,
+                        _ => Some("v".repeat(*len)),
+                    };
+                    assert_eq!(disktable.find(&format!("key-{:02}", i)).unwrap(), expected);
+                });
+                assert_eq!(disktable.find("key-05-tomb").unwrap(), None);
+            };
+            assert_aligned(1);
+            assert_aligned(2);
+            assert_found(&disktable);
+            // padding records are skipped by iteration
+            let keys: Vec<_> = DataFile::of(&fs, dir, 1)
+                .entries()
+                .map(|entry| entry.unwrap().key)
+                .collect();
+            assert_eq!(keys.len(), value_lens.len() + 1);
+
+            disktable.compact(None).unwrap();
+            assert_aligned(2);
+            assert_found(&disktable);
+
+            let invalid = builder(dir, &fs).block_size(16).build();
+            assert_eq!(
+                invalid.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        });
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encryption_key() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_encryption";
+            let key = [7u8; 32];
+            let encrypted = || builder(dir, &fs).encryption_key(key);
+            let value = |i| format!("secret-value-{}", i);
+            let mut disktable = encrypted().block_size(128).build().unwrap();
+            disktable.clear().unwrap();
+            (0..2).for_each(|gen| {
+                let entries = MemtableEntries {
+                    entries: (gen * 10..gen * 10 + 10)
+                        .map(|i| (format!("key-{:02}", i), value(i)))
+                        .collect(),
+                    // key-03 of the first generation is deleted by the second one
+                    tombstones: (gen == 1)
+                        .then(|| "key-03".to_string())
+                        .into_iter()
+                        .collect(),
+                };
+                disktable.flush(entries).unwrap();
+            });
+            disktable.compact(None).unwrap();
+
+            // no value is readable from the data file
+            let bytes = read_bytes(&DataFile::of(&fs, dir, 2));
+            assert_eq!(bytes.len() % 128, 0);
+            let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+            assert!(!contains(b"secret-value"));
+            (0..20).for_each(|i| assert!(!contains(format!("key-{:02}", i).as_bytes())));
+
+            let assert_found = |disktable: &FileDisktable| {
+                (0..20).for_each(|i| {
+                    let expected = if i == 3 { None } else { Some(value(i)) };
+                    assert_eq!(disktable.find(&format!("key-{:02}", i)).unwrap(), expected);
+                    let mut buf = String::new();
+                    assert_eq!(
+                        disktable
+                            .find_into(&format!("key-{:02}", i), &mut buf)
+                            .unwrap(),
+                        expected.is_some()
+                    );
+                    assert_eq!(buf, expected.unwrap_or_default());
+                });
+                assert_eq!(
+                    disktable.find_range("key-05", 7, 5).unwrap(),
+                    Some(b"value".to_vec())
+                );
+            };
+            assert_found(&disktable);
+            assert_found(&encrypted().block_size(128).build().unwrap());
+
+            // a wrong key fails on the tags of records
+            let wrong = builder(dir, &fs)
+                .encryption_key([8u8; 32])
+                .block_size(128)
+                .build()
+                .unwrap();
+            assert_eq!(
+                wrong.find("key-05").unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+            let verified = builder(dir, &fs)
+                .encryption_key([8u8; 32])
+                .block_size(128)
+                .verify_on_open(1, |_| {})
+                .build();
+            assert_eq!(
+                verified.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidData)
+            );
+        });
+    }
+
+    #[test]
+    fn test_dedup_values() {
+        on_each_file_system(|fs| {
+            let shared = "shared-value-".repeat(10);
+            let key = |i| format!("key-{:03}", i);
+            let count_shared = |dir: &str, data_gen| {
+                let bytes = read_bytes(&DataFile::of(&fs, dir, data_gen));
+                bytes
+                    .windows(shared.len())
+                    .filter(|w| *w == shared.as_bytes())
+                    .count()
+            };
+            let compacted = |dir: &str, dedup_values| {
+                let mut disktable = builder(dir, &fs)
+                    .dedup_values(dedup_values)
+                    .build()
+                    .unwrap();
+                disktable.clear().unwrap();
+                (0..4).for_each(|gen| {
+                    let entries = MemtableEntries {
+                        entries: (gen * 25..gen * 25 + 25)
+                            .map(|i| {
+                                let value = if i % 10 == 0 {
+                                    format!("own-value-{}", i)
+                                } else {
+                                    shared.clone()
+                                };
+                                (key(i), value)
+                            })
+                            .collect(),
+                        tombstones: BTreeSet::new(),
+                    };
+                    disktable.flush(entries).unwrap();
+                });
+                disktable.compact(None).unwrap();
+                disktable
+            };
+
+            let dir = "./test_tmp_dedup_values";
+            let disktable = compacted(dir, true);
+            assert_eq!(count_shared(dir, 4), 1);
+            (0..100).for_each(|i| {
+                let expected = if i % 10 == 0 {
+                    format!("own-value-{}", i)
+                } else {
+                    shared.clone()
+                };
+                assert_eq!(disktable.find(&key(i)).unwrap(), Some(expected));
+            });
+            assert_eq!(
+                disktable.find_range(&key(99), 13, 6).unwrap(),
+                Some(b"shared".to_vec())
+            );
+            let keys: Vec<_> = DataFile::of(&fs, dir, 4)
+                .entries()
+                .map(|entry| entry.unwrap().key)
+                .collect();
+            assert_eq!(keys, (0..100).map(key).collect::<Vec<_>>());
+
+            // every key repeats the value without the option
+            let dir = "./test_tmp_dedup_values_off";
+            compacted(dir, false);
+            assert_eq!(count_shared(dir, 4), 90);
+        });
+    }
+
+    #[test]
+    fn test_data_gens_skip_unrelated_entries() {
+        let dir = "./test_tmp_data_gens";
+        let _ = std::fs::remove_dir_all(dir);
+        let mut disktable = FileDisktable::new(dir).unwrap();
+        (1..=2).for_each(|gen| {
+            let entries = MemtableEntries {
+                entries: vec![(format!("key-{}", gen), "value".to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries).unwrap();
+        });
+        let dir_path = std::path::Path::new(dir);
+        [
+            "data_3_backup",
+            "old_data_4",
+            "data_x",
+            "notes.txt",
+            "data_99999999999",
+        ]
+        .iter()
+        .for_each(|name| std::fs::write(dir_path.join(name), "").unwrap());
+        std::fs::create_dir(dir_path.join("data_5")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let odd = std::ffi::OsStr::from_bytes(b"data_\xff6");
+            std::fs::write(dir_path.join(odd), "").unwrap();
+        }
+
+        let disktable = FileDisktable::new(dir).unwrap();
+        assert_eq!(disktable.stats().unwrap().generations, 2);
+        let mut disktable = FileDisktable::new(dir).unwrap();
+        let events = disktable.subscribe();
+        disktable.compact(None).unwrap();
+        match events.try_recv().unwrap() {
+            StoreEvent::Compacted { merged, result } => {
+                assert_eq!(merged, vec![1, 2]);
+                assert_eq!(result, 2);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert_eq!(disktable.find("key-1").unwrap(), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_compact_on_tombstone_ratio() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_tombstone_ratio";
+            let key = |i| format!("key-{}", i);
+            let mut disktable = builder(dir, &fs)
+                .compact_on_tombstone_ratio(0.4)
+                .build()
+                .unwrap();
+            disktable.clear().unwrap();
+            let events = disktable.subscribe();
+            let flush = |disktable: &mut FileDisktable, values: Vec<i32>, deleted: Vec<i32>| {
+                let entries = MemtableEntries {
+                    entries: values
+                        .into_iter()
+                        .map(|i| (key(i), "value".to_string()))
+                        .collect(),
+                    tombstones: deleted.into_iter().map(key).collect(),
+                };
+                disktable.flush(entries).unwrap();
+            };
+
+            flush(&mut disktable, (0..10).collect(), vec![]);
+            // 2 tombstones out of 14 entries
+            flush(&mut disktable, vec![10, 11], vec![0, 1]);
+            let stats = disktable.stats().unwrap();
+            assert_eq!(stats.generations, 2);
+            assert_eq!(stats.tombstone_ratio, 2.0 / 14.0);
+            // counts of generations on disk are read from their indexes on open
+            let reopened = builder(dir, &fs)
+                .compact_on_tombstone_ratio(0.4)
+                .build()
+                .unwrap();
+            assert_eq!(reopened.stats().unwrap().tombstone_ratio, 2.0 / 14.0);
+
+            // 10 tombstones out of 22 entries
+            flush(&mut disktable, vec![], (2..10).collect());
+            let events: Vec<_> = events.try_iter().collect();
+            assert!(matches!(
+                events.as_slice(),
+                [
+                    StoreEvent::Flushed { gen: 1, .. },
+                    StoreEvent::Flushed { gen: 2, .. },
+                    StoreEvent::Flushed { gen: 3, .. },
+                    StoreEvent::Compacted { result: 3, .. },
+                ]
+            ));
+            let stats = disktable.stats().unwrap();
+            assert_eq!(stats.generations, 1);
+            assert_eq!(stats.tombstone_ratio, 0.0);
+            assert_eq!(disktable.find(&key(10)).unwrap(), Some("value".to_string()));
+            assert_eq!(disktable.find(&key(5)).unwrap(), None);
+
+            // tombstones kept as older versions trigger a compaction once rather than on every
+            // flush after it
+            disktable.clear().unwrap();
+            let mut disktable = builder(dir, &fs)
+                .compact_on_tombstone_ratio(0.4)
+                .keep_versions(2)
+                .build()
+                .unwrap();
+            let events = disktable.subscribe();
+            flush(&mut disktable, (0..4).collect(), vec![]);
+            flush(&mut disktable, vec![], (0..4).collect());
+            flush(&mut disktable, vec![10], vec![]);
+            flush(&mut disktable, vec![11], vec![]);
+            let compacted = events
+                .try_iter()
+                .filter(|event| matches!(event, StoreEvent::Compacted { .. }))
+                .count();
+            assert_eq!(compacted, 1);
+            assert_eq!(disktable.stats().unwrap().tombstone_ratio, 4.0 / 10.0);
+            assert_eq!(disktable.find(&key(2)).unwrap(), None);
+            disktable.clear().unwrap();
+
+            let invalid = builder(dir, &fs).compact_on_tombstone_ratio(1.5).build();
+            assert_eq!(
+                invalid.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        });
+    }
+
+    #[test]
+    fn test_inline_threshold() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_inline_threshold";
+            let small = "small".to_string();
+            let large = "large".repeat(20);
+            let open = || builder(dir, &fs).inline_threshold(16).build().unwrap();
+            let mut disktable = open();
+            disktable.clear().unwrap();
+            (1..=2).for_each(|gen| {
+                let entries = MemtableEntries {
+                    entries: vec![
+                        (format!("small-{}", gen), small.clone()),
+                        (format!("large-{}", gen), large.clone()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(entries).unwrap();
+            });
+            let index = IndexFile::of(&fs, 1, dir).read_all().unwrap();
+            assert_eq!(index["small-1"].inline_value, Some(small.clone()));
+            assert_eq!(index["large-1"].inline_value, None);
+
+            let assert_reads = |disktable: &FileDisktable| {
+                let data_reads = || disktable.stats().unwrap().data_reads;
+                let before = data_reads();
+                assert_eq!(disktable.find("small-1").unwrap(), Some(small.clone()));
+                assert_eq!(disktable.find("small-2").unwrap(), Some(small.clone()));
+                assert_eq!(
+                    disktable.find_range("small-1", 1, 3).unwrap(),
+                    Some(b"mal".to_vec())
+                );
+                assert_eq!(data_reads(), before);
+                assert_eq!(disktable.find("large-1").unwrap(), Some(large.clone()));
+                assert_eq!(data_reads(), before + 1);
+            };
+            assert_reads(&open());
+            disktable.compact(None).unwrap();
+            assert_reads(&disktable);
+        });
+    }
+
+    #[test]
+    fn test_blob_threshold() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_blob_threshold";
+            let small = "small".to_string();
+            let large = |gen: DataGen| format!("large-{}-", gen).repeat(20);
+            let open = || builder(dir, &fs).blob_threshold(64).build().unwrap();
+            let mut disktable = open();
+            disktable.clear().unwrap();
+            (1..=2).for_each(|gen| {
+                let entries = MemtableEntries {
+                    entries: vec![
+                        (format!("small-{}", gen), small.clone()),
+                        (format!("large-{}", gen), large(gen)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(entries).unwrap();
+            });
+            let blob_files = || {
+                let mut names: Vec<String> = fs
+                    .read_dir(std::path::Path::new(dir))
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|entry| entry.name.into_string().ok())
+                    .filter(|name| name.starts_with("blob_"))
+                    .collect();
+                names.sort();
+                names
+                    .into_iter()
+                    .map(|name| {
+                        let file = file_system::OpenedFile::open(fs.as_ref(), dir, &name).unwrap();
+                        let mut bytes = vec![];
+                        file.reader().read_to_end(&mut bytes).unwrap();
+                        (name, bytes)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let written = blob_files();
+            assert_eq!(
+                written,
+                vec![
+                    ("blob_0".to_string(), large(1).into_bytes()),
+                    ("blob_1".to_string(), large(2).into_bytes()),
+                ]
+            );
+            // only a reference to the large value is in the data file
+            assert!(read_bytes(&DataFile::of(&fs, dir, 1)).len() < large(1).len());
+
+            let assert_reads = |disktable: &FileDisktable| {
+                assert_eq!(disktable.find("small-1").unwrap(), Some(small.clone()));
+                assert_eq!(disktable.find("large-1").unwrap(), Some(large(1)));
+                assert_eq!(disktable.find("large-2").unwrap(), Some(large(2)));
+                let mut buf = String::new();
+                assert!(disktable.find_into("large-2", &mut buf).unwrap());
+                assert_eq!(buf, large(2));
+                assert_eq!(
+                    disktable.find_range("large-1", 6, 8).unwrap(),
+                    Some(b"1-large-".to_vec())
+                );
+                let mut scanned: Vec<_> = disktable
+                    .sources("large", Some("large-9"))
+                    .unwrap()
+                    .into_iter()
+                    .flat_map(|source| source.map(|record| record.unwrap().1.unwrap()))
+                    .collect();
+                scanned.sort();
+                assert_eq!(scanned, vec![large(1), large(2)]);
+            };
+            assert_reads(&open());
+            disktable.compact(None).unwrap();
+            assert_reads(&disktable);
+            assert_reads(&open());
+            // the compaction copied references instead of rewriting the values
+            assert_eq!(blob_files(), written);
+
+            disktable.clear().unwrap();
+            assert!(blob_files().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_collect_blobs() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_collect_blobs";
+            let value = |round: usize, i: usize| format!("value-{}-{}-", round, i).repeat(8);
+            let open = || builder(dir, &fs).blob_threshold(32).build().unwrap();
+            let mut disktable = open();
+            disktable.clear().unwrap();
+            let flush = |disktable: &mut FileDisktable, round: usize, keys: usize| {
+                let entries = MemtableEntries {
+                    entries: (0..keys)
+                        .map(|i| (format!("key-{}", i), value(round, i)))
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(entries).unwrap();
+            };
+            let blob_names = || {
+                let mut names: Vec<String> = fs
+                    .read_dir(std::path::Path::new(dir))
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|entry| entry.name.into_string().ok())
+                    .filter(|name| name.starts_with("blob_"))
+                    .collect();
+                names.sort();
+                names
+            };
+            flush(&mut disktable, 1, 10);
+            flush(&mut disktable, 2, 9);
+            // every value is still referenced by a record of its generation
+            assert_eq!(disktable.collect_blobs().unwrap(), BlobGcReport::default());
+
+            // a scan started before the compaction reads the overwritten values
+            let snapshot = disktable.sources("", None).unwrap();
+            disktable.compact(None).unwrap();
+            let report = disktable.collect_blobs().unwrap();
+            assert_eq!(report.removed, Vec::<usize>::new());
+            assert_eq!(report.rewritten, vec![0]);
+            assert_eq!(report.bytes_reclaimed, 9 * value(1, 0).len() as u64);
+            assert_eq!(blob_names(), vec!["blob_0", "blob_1", "blob_2"]);
+            let scanned: Vec<_> = snapshot
+                .into_iter()
+                .flat_map(|source| source.map(|record| record.unwrap().1.unwrap()))
+                .collect();
+            assert!(scanned.contains(&value(1, 0)));
+            // the rewritten file goes once the scan is dropped
+            assert_eq!(blob_names(), vec!["blob_1", "blob_2"]);
+
+            let assert_live = |disktable: &FileDisktable| {
+                (0..9).for_each(|i| {
+                    assert_eq!(
+                        disktable.find(&format!("key-{}", i)).unwrap(),
+                        Some(value(2, i))
+                    )
+                });
+                assert_eq!(disktable.find("key-9").unwrap(), Some(value(1, 9)));
+            };
+            assert_live(&disktable);
+            assert_live(&open());
+
+            flush(&mut disktable, 3, 10);
+            disktable.compact(None).unwrap();
+            let report = disktable.collect_blobs().unwrap();
+            assert_eq!(report.removed, vec![1, 2]);
+            assert_eq!(blob_names(), vec!["blob_3"]);
+            (0..10).for_each(|i| {
+                assert_eq!(
+                    disktable.find(&format!("key-{}", i)).unwrap(),
+                    Some(value(3, i))
+                )
+            });
+        });
+    }
+
+    #[test]
+    fn test_sync_on_flush() {
+        let dir = "./test_tmp_sync_on_flush";
+        let fs = MemFileSystem::default();
+        let entries = |key: &str| MemtableEntries {
+            entries: vec![(key.to_string(), "value".to_string())]
+                .into_iter()
+                .collect(),
+            tombstones: BTreeSet::new(),
+        };
+        let open = |sync_on_flush| {
+            FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .sync_on_flush(sync_on_flush)
+                .build()
+                .unwrap()
+        };
+        let mut disktable = open(true);
+        disktable.flush(entries("synced")).unwrap();
+        fs.crash();
+        let mut disktable = open(false);
+        assert_eq!(disktable.find("synced").unwrap(), Some("value".to_string()));
+
+        // neither the data file nor its name is durable without syncs
+        disktable.flush(entries("unsynced")).unwrap();
+        assert_eq!(
+            disktable.find("unsynced").unwrap(),
+            Some("value".to_string())
+        );
+        fs.crash();
+        let disktable = open(false);
+        assert_eq!(disktable.find("unsynced").unwrap(), None);
+        assert_eq!(disktable.stats().unwrap().generations, 1);
+    }
+
+    #[test]
+    fn test_keep_versions() {
+        let dir = "./test_tmp_keep_versions";
+        let mut disktable = FileDisktableBuilder::new(dir)
+            .keep_versions(2)
+            .build()
+            .unwrap();
+        disktable.clear().unwrap();
+        let flush =
+            |disktable: &mut FileDisktable, values: Vec<(&str, &str)>, deleted: Vec<&str>| {
+                let entries = MemtableEntries {
+                    entries: values
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                    tombstones: deleted.into_iter().map(|key| key.to_string()).collect(),
+                };
+                disktable.flush(entries).unwrap();
+            };
+        flush(
+            &mut disktable,
+            vec![("key", "v1"), ("gone", "v1"), ("once", "v1")],
+            vec![],
+        );
+        flush(&mut disktable, vec![("key", "v2"), ("back", "v1")], vec![]);
+        flush(
+            &mut disktable,
+            vec![("key", "v3"), ("back", "v2")],
+            vec!["gone"],
+        );
+        flush(&mut disktable, vec![], vec!["back", "never"]);
+        disktable.compact(None).unwrap();
+
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(disktable.find("key").unwrap(), some("v3"));
+        assert_eq!(
+            disktable.find_versions("key").unwrap(),
+            vec![some("v3"), some("v2")]
+        );
+        assert_eq!(disktable.find_versions("once").unwrap(), vec![some("v1")]);
+        // a tombstone stays over the older value kept below it
+        assert_eq!(disktable.find("gone").unwrap(), None);
+        assert_eq!(
+            disktable.find_versions("gone").unwrap(),
+            vec![None, some("v1")]
+        );
+        assert_eq!(disktable.find("back").unwrap(), None);
+        assert_eq!(
+            disktable.find_versions("back").unwrap(),
+            vec![None, some("v2")]
+        );
+        assert!(disktable.find_versions("never").unwrap().is_empty());
+        let stats = disktable.stats().unwrap();
+        assert_eq!(stats.generations, 2);
+        assert_eq!(stats.tombstone_ratio, 2.0 / 7.0);
+
+        let invalid = FileDisktableBuilder::new(dir).keep_versions(0).build();
+        assert_eq!(
+            invalid.err().map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_bloom_filters() {
+        let dir = "./test_tmp_bloom_filters";
+        let fs = MemFileSystem::default();
+        let open = || {
+            FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .bloom_filters(0.01)
+                .build()
+                .unwrap()
+        };
+        let entries = |prefix: &str, count: usize| MemtableEntries {
+            entries: (0..count)
+                .map(|i| (format!("{}{}", prefix, i), "value".to_string()))
+                .collect(),
+            tombstones: BTreeSet::new(),
+        };
+        let mut disktable = open();
+        disktable.flush(entries("large", 10_000)).unwrap();
+        disktable.flush(entries("tiny", 3)).unwrap();
+
+        // filters are rebuilt from the counts in their headers
+        let read_filter = |data_gen: DataGen| {
+            let file = fs
+                .open(&std::path::Path::new(dir).join(format!("bloom_{}", data_gen)))
+                .map(|file| {
+                    let mut bytes = vec![];
+                    HandleReader::new(file.as_ref())
+                        .read_to_end(&mut bytes)
+                        .unwrap();
+                    bytes
+                });
+            file.map(|bytes| BloomFilter::from_bytes(&bytes).unwrap())
+        };
+        let large = read_filter(1).unwrap();
+        let tiny = read_filter(2).unwrap();
+        let false_positives = (0..10_000)
+            .filter(|i| large.contains(&format!("absent{}", i)))
+            .count();
+        assert!(
+            false_positives <= 150,
+            "{} false positives",
+            false_positives
+        );
+        assert!(large.num_bits() <= 10 * 10_000);
+        assert!(tiny.num_bits() <= 3 * 10, "{} bits", tiny.num_bits());
+        assert!((0..3).all(|i| tiny.contains(&format!("tiny{}", i))));
+
+        let disktable = open();
+
+        assert_eq!(
+            disktable.find("large42").unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(disktable.find("tiny2").unwrap(), Some("value".to_string()));
+        let before = disktable.stats().unwrap();
+        (0..100).for_each(|i| assert_eq!(disktable.find(&format!("absent{}", i)).unwrap(), None));
+        let stats = disktable.stats().unwrap();
+        let skips = stats.bloom_skips - before.bloom_skips;
+        assert!(skips >= 190, "{} skips", skips);
+
+        let mut disktable = disktable;
+        disktable.clear().unwrap();
+        assert_eq!(
+            read_filter(1).err().map(|err| err.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        for fpr in [0.0, 1.0] {
+            let invalid = FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .bloom_filters(fpr)
+                .build();
+            assert_eq!(
+                invalid.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+    }
+
+    #[test]
+    fn test_bloom_filters_with_comparator() {
+        let dir = "./test_tmp_bloom_filters_with_comparator";
+        let fs = MemFileSystem::default();
+        let mut disktable = FileDisktableBuilder::new(dir)
+            .file_system(fs.clone())
+            .comparator(crate::sst::comparator::Numeric)
+            .bloom_filters(0.01)
+            .build()
+            .unwrap();
+        disktable.clear().unwrap();
+        // keys the comparator orders unlike their bytes, including ones of the same number
+        let keys: Vec<String> = (0..1000)
+            .map(|i| (i * 7 - 500).to_string())
+            .chain(vec!["007".to_string(), "7".to_string(), "x".to_string()])
+            .collect();
+        disktable
+            .flush(MemtableEntries {
+                entries: keys
+                    .iter()
+                    .map(|key| (key.clone(), "value".to_string()))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            })
+            .unwrap();
+
+        let before = disktable.stats().unwrap();
+        keys.iter().for_each(|key| {
+            assert_eq!(
+                disktable.find(key).unwrap(),
+                Some("value".to_string()),
+                "{}",
+                key
+            )
+        });
+        assert_eq!(disktable.stats().unwrap().bloom_skips, before.bloom_skips);
+        assert_eq!(disktable.find("07").unwrap(), None);
+        // while the bounds of the generation follow the comparator
+        let info = disktable.describe_generation(1).unwrap();
+        assert_eq!(info.min_key, Some("-500".to_string()));
+        assert_eq!(info.max_key, Some("x".to_string()));
+    }
+
+    #[test]
+    fn test_double_buffered_compaction() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_double_buffered";
+            let mut disktable = builder(dir, &fs)
+                .double_buffered_compaction(true)
+                .build()
+                .unwrap();
+            disktable.clear().unwrap();
+            let key = |i: usize| format!("key{:03}", i);
+            let expected = |i: usize| Some(format!("v{}", i % 4)).filter(|_| !i.is_multiple_of(10));
+            // every round overwrites a part of the keys, and the last one deletes some
+            (0..4).for_each(|round| {
+                let entries = (0..100)
+                    .filter(|i| i % 4 >= round && !(round == 3 && i % 10 == 0))
+                    .map(|i| (key(i), format!("v{}", round)))
+                    .collect();
+                let tombstones = (0..100)
+                    .filter(|i| round == 3 && i % 10 == 0)
+                    .map(key)
+                    .collect();
+                disktable
+                    .flush(MemtableEntries {
+                        entries,
+                        tombstones,
+                    })
+                    .unwrap();
+            });
+            let reader = builder(dir, &fs).read_only(true).build().unwrap();
+
+            let compacted = std::sync::atomic::AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                let reads = scope.spawn(|| {
+                    let mut rounds = 0;
+                    while rounds == 0 || !compacted.load(std::sync::atomic::Ordering::SeqCst) {
+                        (0..100)
+                            .for_each(|i| assert_eq!(reader.find(&key(i)).unwrap(), expected(i)));
+                        rounds += 1;
+                    }
+                    rounds
+                });
+                // keys flushed in between move the entries read within the data files,
+                // and every compaction moves the generation to a new number
+                (0..20).for_each(|round| {
+                    let entries = (0..50)
+                        .map(|i| (format!("a{:02}{:02}", round, i), "v".to_string()))
+                        .collect();
+                    disktable
+                        .flush(MemtableEntries {
+                            entries,
+                            tombstones: BTreeSet::new(),
+                        })
+                        .unwrap();
+                    disktable.compact(None).unwrap();
+                });
+                compacted.store(true, std::sync::atomic::Ordering::SeqCst);
+                assert!(reads.join().unwrap() > 0);
+            });
+
+            assert_eq!(disktable.stats().unwrap().generations, 1);
+            (0..100).for_each(|i| assert_eq!(disktable.find(&key(i)).unwrap(), expected(i)));
+            // nothing of the retired generations is left behind, even by the reader
+            let names: Vec<String> = fs
+                .read_dir(std::path::Path::new(dir))
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .collect();
+            let count = |prefix: &str| names.iter().filter(|name| name.starts_with(prefix)).count();
+            assert_eq!(count("data_"), 1, "{:?}", names);
+            assert_eq!(count("index_"), 2, "{:?}", names);
+        });
+    }
+
+    #[test]
+    fn test_scan_read_ahead() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_scan_read_ahead";
+            // records within a block, across blocks and larger than the read ahead
+            for block_size in [None, Some(128)] {
+                let open = |read_ahead: Option<usize>| {
+                    let builder = builder(dir, &fs);
+                    let builder = match block_size {
+                        Some(block_size) => builder.block_size(block_size),
+                        None => builder,
+                    };
+                    match read_ahead {
+                        Some(bytes) => builder.scan_read_ahead(bytes),
+                        None => builder,
+                    }
+                    .build()
+                    .unwrap()
+                };
+                let mut disktable = open(None);
+                disktable.clear().unwrap();
+                for round in 0..3 {
+                    let entries = MemtableEntries {
+                        entries: (0..50)
+                            .filter(|i| i % 3 != round)
+                            .map(|i| (format!("key-{:02}", i), "v".repeat(i * 7 + round)))
+                            .collect(),
+                        tombstones: (0..50)
+                            .filter(|i| i % 3 == round && i % 2 == 0)
+                            .map(|i| format!("key-{:02}", i))
+                            .collect(),
+                    };
+                    disktable.flush(entries).unwrap();
+                }
+                let scan = |disktable: &FileDisktable| {
+                    disktable
+                        .sources("key-10", Some("key-40"))
+                        .unwrap()
+                        .into_iter()
+                        .map(|source| source.collect::<io::Result<Vec<_>>>().unwrap())
+                        .collect::<Vec<_>>()
+                };
+                let expected = scan(&disktable);
+                assert_eq!(expected.len(), 3);
+                assert!(expected.iter().all(|records| !records.is_empty()));
+                for bytes in [1, 5, 64, 1000, 1 << 20] {
+                    let disktable = open(Some(bytes));
+                    assert_eq!(scan(&disktable), expected, "read ahead: {}", bytes);
+                }
+
+                let mut disktable = open(Some(100));
+                disktable.compact(None).unwrap();
+                let compacted = scan(&disktable);
+                assert_eq!(scan(&open(None)), compacted);
+                assert_eq!(scan(&open(Some(1 << 20))), compacted);
+            }
+            let invalid = builder(dir, &fs).scan_read_ahead(0).build();
+            assert_eq!(
+                invalid.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        });
+    }
+
+    #[test]
+    fn test_duplicate_keys_in_a_batch() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_duplicate_keys";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            // writes to a memtable leave a record per key, the last written one
+            let mut batch = MemtableEntries {
+                entries: BTreeMap::new(),
+                tombstones: BTreeSet::new(),
+            };
+            [("a", "1"), ("b", "1"), ("a", "2")]
+                .iter()
+                .for_each(|(key, value)| {
+                    batch.entries.insert(key.to_string(), value.to_string());
+                });
+            disktable.flush(batch).unwrap();
+            let index = IndexFile::of(&fs, 1, dir).read_all().unwrap();
+            assert_eq!(index.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+            assert_eq!(disktable.find("a").unwrap(), Some("2".to_string()));
+
+            let mut writer = DataFileWriter::create(&fs, dir, &DataFileOptions::default()).unwrap();
+            writer.append("a", Some("1")).unwrap();
+            let err = writer.append("a", Some("2")).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            writer.abort().unwrap();
+
+            // a key both set and deleted is rejected rather than written twice
+            let conflicting = MemtableEntries {
+                entries: vec![("c".to_string(), "1".to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: vec!["c".to_string()].into_iter().collect(),
+            };
+            let err = disktable.flush(conflicting).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            assert_eq!(disktable.generations().unwrap().len(), 1);
+            assert_eq!(disktable.find("c").unwrap(), None);
+            let names: Vec<_> = fs
+                .read_dir(std::path::Path::new(dir))
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name.into_string().unwrap())
+                .collect();
+            assert!(
+                !names.iter().any(|name| name.starts_with("tmp")),
+                "{:?}",
+                names
+            );
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_verify_index_order() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_verify_index_order";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let batch = MemtableEntries {
+                entries: ["a", "b", "c"]
+                    .iter()
+                    .map(|key| (key.to_string(), "value".to_string()))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(batch).unwrap();
+            // the index written by a buggy writer with "c" before "b"
+            let index = IndexFile::of(&fs, 1, dir).read_all().unwrap();
+            let bytes: Vec<u8> = ["a", "c", "b"]
+                .iter()
+                .flat_map(|key| {
+                    let entry = &index[*key];
+                    [
+                        &byte_utils::ByteUtils::from_usize(key.len())[..],
+                        key.as_bytes(),
+                        &byte_utils::ByteUtils::from_i32(entry.data_gen),
+                        &byte_utils::ByteUtils::from_u64(entry.offset),
+                        &[0],
+                    ]
+                    .concat()
+                })
+                .collect();
+            let path = IndexFile::of(&fs, 1, dir).path();
+            fs.create(&path).unwrap().write(&bytes).unwrap();
+
+            let unsorted = builder(dir, &fs).verify_index_order(true).build().unwrap();
+            let err = unsorted.warm(&WarmConfig::default()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("at entry 2"), "{}", err);
+            let unchecked = builder(dir, &fs).verify_index_order(false).build().unwrap();
+            unchecked.warm(&WarmConfig::default()).unwrap();
+            assert_eq!(unchecked.stats().unwrap().cached_indexes, 1);
+        });
+    }
+
+    #[test]
+    fn test_front_coded_indexes() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_front_coded_indexes";
+            let mut plain = builder(dir, &fs).build().unwrap();
+            plain.clear().unwrap();
+            let index: BTreeMap<String, IndexEntry> = (0..100)
+                .map(|i| {
+                    let entry = IndexEntry {
+                        data_gen: 1,
+                        offset: i * 10,
+                        kind: if i % 7 == 0 {
+                            EntryKind::Tombstone
+                        } else {
+                            EntryKind::Value
+                        },
+                        inline_value: if i % 5 == 0 && i % 7 != 0 {
+                            Some(format!("value-{}", i))
+                        } else {
+                            None
+                        },
+                    };
+                    // keys sharing prefixes of various lengths, and none with the one before
+                    let key = match i % 3 {
+                        0 => format!("users/{:03}/profile", i),
+                        1 => format!("users/{:03}/profile/avatar", i - 1),
+                        _ => format!("x{}", i),
+                    };
+                    (key, entry)
+                })
+                .collect();
+            IndexFile::of(&fs, 1, dir).create_index(&index).unwrap();
+            let plain_bytes = fs.open(&IndexFile::of(&fs, 1, dir).path()).unwrap().size();
+            IndexFile::of(&fs, 2, dir)
+                .front_coded(true)
+                .create_index(&index)
+                .unwrap();
+            // read without the option, as entries tell whether they're front coded
+            let front_coded = IndexFile::of(&fs, 2, dir);
+            let front_coded_bytes = fs.open(&front_coded.path()).unwrap().size();
+            assert!(front_coded_bytes.unwrap() < plain_bytes.unwrap());
+
+            // every entry, including the ones right before and after restart points
+            assert_eq!(front_coded.read_all().unwrap(), index);
+            for (position, (key, entry)) in index.iter().enumerate() {
+                let found = front_coded.find_index(key).unwrap();
+                assert_eq!(found.as_ref(), Some(entry), "{} at {}", key, position);
+            }
+            for key in [
+                "users/",
+                "users/003/profile/",
+                "users/999/profile",
+                "x",
+                "y",
+            ] {
+                assert_eq!(front_coded.find_index(key).unwrap(), None, "{}", key);
+            }
+
+            let mut disktable = builder(dir, &fs).front_coded_indexes(true).build().unwrap();
+            disktable.clear().unwrap();
+            let batch = MemtableEntries {
+                entries: index.keys().map(|key| (key.clone(), key.clone())).collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(batch).unwrap();
+            index
+                .keys()
+                .for_each(|key| assert_eq!(disktable.find(key).unwrap().as_ref(), Some(key)));
+            disktable.warm(&WarmConfig::default()).unwrap();
+            assert_eq!(disktable.find("x98").unwrap(), Some("x98".to_string()));
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_find_with_generation_gap() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_generation_gap";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            (1..=3).for_each(|gen| {
+                let batch = MemtableEntries {
+                    entries: vec![
+                        (format!("key-{}", gen), format!("value-{}", gen)),
+                        ("shared".to_string(), format!("shared-{}", gen)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(batch).unwrap();
+            });
+            // generation 2 is gone, as after a compaction which stopped part way
+            drop(disktable);
+            DataFile::clear(&fs, dir, 2).unwrap();
+            IndexFile::clear(&fs, 2, dir).unwrap();
+
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            // only the generations left are probed
+            assert_eq!(
+                disktable.find("key-1").unwrap(),
+                Some("value-1".to_string())
+            );
+            assert_eq!(disktable.stats().unwrap().index_reads, 2);
+            let assert_found = |disktable: &FileDisktable| {
+                assert_eq!(
+                    disktable.find("key-1").unwrap(),
+                    Some("value-1".to_string())
+                );
+                assert_eq!(disktable.find("key-2").unwrap(), None);
+                assert_eq!(
+                    disktable.find("key-3").unwrap(),
+                    Some("value-3".to_string())
+                );
+                assert_eq!(
+                    disktable.find("shared").unwrap(),
+                    Some("shared-3".to_string())
+                );
+                assert_eq!(
+                    disktable.find_versions("shared").unwrap(),
+                    vec![Some("shared-3".to_string()), Some("shared-1".to_string())]
+                );
+            };
+            assert_found(&disktable);
+            assert_eq!(disktable.stats().unwrap().generations, 2);
+            let reader = builder(dir, &fs).read_only(true).build().unwrap();
+            assert_found(&reader);
+
+            let batch = MemtableEntries {
+                entries: vec![("key-4".to_string(), "value-4".to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(batch).unwrap();
+            assert_eq!(disktable.generations().unwrap().len(), 3);
+            assert_eq!(
+                disktable.find("key-4").unwrap(),
+                Some("value-4".to_string())
+            );
+            assert_found(&disktable);
+            disktable.clear().unwrap();
+        });
+    }
+
+    // MemFileSystem failing to remove the file with PermissionDenied while it's denied
+    struct DenyingRemoval {
+        fs: MemFileSystem,
+        denied: std::path::PathBuf,
+        deny: std::sync::atomic::AtomicBool,
+    }
+
+    impl FileSystem for DenyingRemoval {
+        fn open(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.open(path)
+        }
+        fn create(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            self.fs.create(path)
+        }
+        fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+            self.fs.rename(from, to)
+        }
+        fn remove(&self, path: &std::path::Path) -> io::Result<()> {
+            if path == self.denied && self.deny.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("permission denied: {:?}", path),
+                ));
+            }
+            self.fs.remove(path)
+        }
+        fn sync_dir(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.sync_dir(dir)
+        }
+        fn read_dir(&self, dir: &std::path::Path) -> io::Result<Vec<DirEntry>> {
+            self.fs.read_dir(dir)
+        }
+        fn create_dir_all(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.create_dir_all(dir)
+        }
+    }
+
+    #[test]
+    fn test_clear_strict_and_best_effort() {
+        let dir = "./test_tmp_clear_failures";
+        let denying = Arc::new(DenyingRemoval {
+            fs: MemFileSystem::default(),
+            denied: std::path::Path::new(dir).join("data_2"),
+            deny: std::sync::atomic::AtomicBool::new(false),
+        });
+        let fs: SharedFileSystem = denying.clone();
+        let mut disktable = builder(dir, &fs).build().unwrap();
+        let flush_generations = |disktable: &mut FileDisktable| {
+            (1..=3).for_each(|gen| {
+                let batch = MemtableEntries {
+                    entries: vec![(format!("key-{}", gen), format!("value-{}", gen))]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                };
+                disktable.flush(batch).unwrap();
+            });
+        };
+        let listed = |disktable: &FileDisktable| -> Vec<DataGen> {
+            disktable
+                .generations()
+                .unwrap()
+                .into_iter()
+                .map(|info| info.data_gen)
+                .collect()
+        };
+        flush_generations(&mut disktable);
+        denying
+            .deny
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+
+        // stops at generation 2, once generation 1 is removed
+        let err = disktable.clear().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(listed(&disktable), vec![2, 3]);
+        assert_eq!(disktable.find("key-1").unwrap(), None);
+        assert_eq!(
+            disktable.find("key-3").unwrap(),
+            Some("value-3".to_string())
+        );
+
+        // goes on to generation 3, and reports generation 2 alone
+        let failures = disktable.clear_best_effort().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].data_gen, 2);
+        assert_eq!(failures[0].error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(listed(&disktable), vec![2]);
+        assert_eq!(
+            disktable.find("key-2").unwrap(),
+            Some("value-2".to_string())
+        );
+        assert_eq!(disktable.find("key-3").unwrap(), None);
+        assert_eq!(disktable.stats().unwrap().generations, 1);
+
+        denying
+            .deny
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(disktable.clear_best_effort().unwrap().is_empty());
+        assert!(listed(&disktable).is_empty());
+        flush_generations(&mut disktable);
+        assert_eq!(listed(&disktable), vec![1, 2, 3]);
+        disktable.clear().unwrap();
+        assert!(listed(&disktable).is_empty());
+    }
+
+    #[test]
+    fn test_compact_incremental() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_compact_incremental";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let flush =
+                |disktable: &mut FileDisktable, gen: DataGen, keys: &[&str], deleted: &[&str]| {
+                    let batch = MemtableEntries {
+                        entries: keys
+                            .iter()
+                            .map(|key| (key.to_string(), format!("{}-{}", key, gen)))
+                            .collect(),
+                        tombstones: deleted.iter().map(|key| key.to_string()).collect(),
+                    };
+                    disktable.flush(batch).unwrap();
+                };
+            // generations of disjoint key ranges, as of keys written in order
+            flush(&mut disktable, 1, &["a1", "a2", "a3"], &[]);
+            flush(&mut disktable, 2, &["m1", "m2", "m3"], &[]);
+            flush(&mut disktable, 3, &["x1", "x2", "x3"], &[]);
+            let untouched: Vec<GenerationInfo> = [1, 3]
+                .iter()
+                .map(|data_gen| disktable.describe_generation(*data_gen).unwrap())
+                .collect();
+
+            // a small generation in the middle of the range of generation 2
+            flush(&mut disktable, 4, &["m2"], &["m3"]);
+            let report = disktable.compact_incremental().unwrap();
+            assert_eq!(report.merged, vec![2, 4]);
+            assert_eq!(
+                report.dropped_versions,
+                vec!["m2".to_string(), "m3".to_string()]
+            );
+            assert_eq!(report.collected_tombstones, vec!["m3".to_string()]);
+            let data_gens: Vec<DataGen> = disktable
+                .generations()
+                .unwrap()
+                .into_iter()
+                .map(|info| info.data_gen)
+                .collect();
+            assert_eq!(data_gens, vec![1, 3, 4]);
+            for info in &untouched {
+                assert_eq!(&disktable.describe_generation(info.data_gen).unwrap(), info);
+            }
+            assert_eq!(disktable.find("m1").unwrap(), Some("m1-2".to_string()));
+            assert_eq!(disktable.find("m2").unwrap(), Some("m2-4".to_string()));
+            assert_eq!(disktable.find("m3").unwrap(), None);
+            assert_eq!(disktable.find("a1").unwrap(), Some("a1-1".to_string()));
+            assert_eq!(disktable.find("x3").unwrap(), Some("x3-3".to_string()));
+            let info = disktable.describe_generation(4).unwrap();
+            assert_eq!((info.entries, info.tombstones), (2, 0));
+
+            // nothing overlaps a generation of a new range
+            flush(&mut disktable, 5, &["z1"], &[]);
+            assert_eq!(
+                disktable.compact_incremental().unwrap(),
+                CompactionReport::default()
+            );
+            // while a range spanning generations takes in every one it overlaps
+            flush(&mut disktable, 6, &["a25", "n1"], &[]);
+            let report = disktable.compact_incremental().unwrap();
+            assert_eq!(report.merged, vec![1, 4, 6]);
+            assert_eq!(disktable.find("a2").unwrap(), Some("a2-1".to_string()));
+            assert_eq!(disktable.find("n1").unwrap(), Some("n1-6".to_string()));
+            assert_eq!(disktable.generations().unwrap().len(), 3);
+
+            let mut versioned = builder(dir, &fs).keep_versions(2).build().unwrap();
+            assert_eq!(
+                versioned.compact_incremental().unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_vacuum() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_vacuum";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let key = |i: usize| format!("key-{:03}", i);
+            disktable
+                .flush(MemtableEntries {
+                    entries: (0..100).map(|i| (key(i), format!("old-{}", i))).collect(),
+                    tombstones: ["t1", "t2"].iter().map(|key| key.to_string()).collect(),
+                })
+                .unwrap();
+            // every key of generation 1 but the tombstone of t2 is overwritten or deleted
+            disktable
+                .flush(MemtableEntries {
+                    entries: (0..90)
+                        .map(|i| (key(i), format!("new-{}", i)))
+                        .chain(std::iter::once(("t1".to_string(), "revived".to_string())))
+                        .collect(),
+                    tombstones: (90..100).map(key).collect(),
+                })
+                .unwrap();
+            let bytes_before = disktable.describe_generation(1).unwrap().data_file_bytes;
+
+            let report = disktable.vacuum(1).unwrap();
+            assert_eq!(report.merged, vec![1]);
+            assert_eq!(
+                report.dropped_versions,
+                (0..100).map(key).collect::<Vec<_>>()
+            );
+            assert_eq!(report.collected_tombstones, vec!["t1".to_string()]);
+            assert!(report.bytes_reclaimed > 0);
+            // the records left go above generation 2, which takes its place
+            let listed: Vec<DataGen> = disktable
+                .generations()
+                .unwrap()
+                .into_iter()
+                .map(|info| info.data_gen)
+                .collect();
+            assert_eq!(listed, vec![2, 3]);
+            let info = disktable.describe_generation(3).unwrap();
+            assert_eq!((info.entries, info.tombstones), (1, 1));
+            assert!(info.data_file_bytes * 20 < bytes_before);
+            (0..90).for_each(|i| {
+                assert_eq!(disktable.find(&key(i)).unwrap(), Some(format!("new-{}", i)))
+            });
+            (90..100).for_each(|i| assert_eq!(disktable.find(&key(i)).unwrap(), None));
+            assert_eq!(disktable.find("t1").unwrap(), Some("revived".to_string()));
+            assert_eq!(disktable.find("t2").unwrap(), None);
+
+            // the newest generation has nothing above to shadow it, and the one vacuumed
+            // holds none of the keys of generation 2
+            assert_eq!(disktable.vacuum(3).unwrap(), CompactionReport::default());
+            assert_eq!(disktable.vacuum(2).unwrap(), CompactionReport::default());
+            assert_eq!(
+                disktable.vacuum(1).unwrap_err().kind(),
+                io::ErrorKind::NotFound
+            );
+
+            // an index of a newer generation which can't be read fails the vacuum, rather
+            // than leaving the keys it holds taken for unshadowed
+            disktable
+                .flush(MemtableEntries {
+                    entries: std::iter::once((key(0), "newest".to_string())).collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            let skip_index = std::path::Path::new(dir).join("index_4_skip");
+            let mut kept = vec![];
+            HandleReader::new(fs.open(&skip_index).unwrap().as_ref())
+                .read_to_end(&mut kept)
+                .unwrap();
+            fs.create(&skip_index).unwrap().write(b"garbled\n").unwrap();
+            assert_eq!(
+                disktable.vacuum(2).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+            fs.create(&skip_index).unwrap().write(&kept).unwrap();
+            assert_eq!(disktable.describe_generation(2).unwrap().entries, 101);
+            assert_eq!(disktable.vacuum(2).unwrap().dropped_versions, vec![key(0)]);
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_entry_timestamps() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_entry_timestamps";
+            let mut disktable = builder(dir, &fs)
+                .entry_timestamps(true)
+                .blob_threshold(16)
+                .build()
+                .unwrap();
+            disktable.clear().unwrap();
+            let long = "v".repeat(32);
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![
+                        ("a".to_string(), "a1".to_string()),
+                        ("b".to_string(), long.clone()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            let (_, first) = disktable.find_with_meta("a").unwrap().unwrap();
+            assert_eq!(
+                disktable.find_with_meta("b").unwrap(),
+                Some((long.clone(), first))
+            );
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![("a".to_string(), "a2".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: vec!["c".to_string()].into_iter().collect(),
+                })
+                .unwrap();
+            let (value, second) = disktable.find_with_meta("a").unwrap().unwrap();
+            assert_eq!(value, "a2");
+            assert!(second > first);
+            assert_eq!(disktable.find_with_meta("c").unwrap(), None);
+
+            // the newest record wins along with its timestamp
+            disktable.compact(None).unwrap();
+            assert_eq!(
+                disktable.find_with_meta("a").unwrap(),
+                Some(("a2".to_string(), second))
+            );
+            assert_eq!(
+                disktable.find_with_meta("b").unwrap(),
+                Some((long.clone(), first))
+            );
+            assert_eq!(disktable.find("b").unwrap(), Some(long.clone()));
+            assert_eq!(
+                disktable.find_range("a", 1, 4).unwrap(),
+                Some(b"2".to_vec())
+            );
+
+            // generations written with and without timestamps read back under either setting
+            drop(disktable);
+            let mut plain = builder(dir, &fs).blob_threshold(16).build().unwrap();
+            assert_eq!(plain.find_with_meta("a").unwrap(), None);
+            assert_eq!(plain.find("a").unwrap(), Some("a2".to_string()));
+            plain
+                .flush(MemtableEntries {
+                    entries: vec![("d".to_string(), "d1".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            assert_eq!(plain.find("b").unwrap(), Some(long));
+            drop(plain);
+            let mut disktable = builder(dir, &fs)
+                .entry_timestamps(true)
+                .blob_threshold(16)
+                .build()
+                .unwrap();
+            assert_eq!(disktable.find("d").unwrap(), Some("d1".to_string()));
+            assert_eq!(disktable.find_with_meta("d").unwrap(), None);
+            assert_eq!(
+                disktable.find_with_meta("a").unwrap(),
+                Some(("a2".to_string(), second))
+            );
+            disktable.compact(None).unwrap();
+            assert_eq!(disktable.find("d").unwrap(), Some("d1".to_string()));
+            assert_eq!(
+                disktable.find_range("a", 1, 4).unwrap(),
+                Some(b"2".to_vec())
+            );
+            disktable.clear().unwrap();
+
+            // a record claiming a timestamp and a key it doesn't hold is malformed
+            let record: Vec<u8> = [
+                &16u32.to_le_bytes()[..],
+                &(4u32 | 1 << 31).to_le_bytes(),
+                &0u32.to_le_bytes(),
+                b"abcd",
+                b"\0",
+            ]
+            .concat();
+            fs.create(&DataFile::path(dir, 0))
+                .unwrap()
+                .write(&record)
+                .unwrap();
+            let err = DataFile::of(&fs, dir, 0).read_entry(0).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            DataFile::clear(&fs, dir, 0).unwrap();
+        });
+    }
+
+    // flips the case of the first byte of the target in the written file once armed, as a disk
+    // corrupting a write would
+    struct CorruptWritten {
+        fs: SharedFileSystem,
+        path: std::path::PathBuf,
+        // bytes whose first one is flipped
+        target: &'static [u8],
+        armed: std::sync::atomic::AtomicBool,
+    }
+
+    impl FaultInjector for CorruptWritten {
+        fn at(&self, point: CrashPoint) -> io::Result<()> {
+            if point != CrashPoint::Written
+                || !self.armed.swap(false, std::sync::atomic::Ordering::SeqCst)
+            {
+                return Ok(());
+            }
+            let mut bytes = vec![];
+            HandleReader::new(self.fs.open(&self.path)?.as_ref()).read_to_end(&mut bytes)?;
+            let at = bytes
+                .windows(self.target.len())
+                .position(|window| window == self.target)
+                .unwrap();
+            bytes[at] = bytes[at].to_ascii_uppercase();
+            self.fs.create(&self.path)?.write(&bytes)
+        }
+    }
+
+    #[test]
+    fn test_verify_after_write() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_verify_after_write";
+            let corrupting_file = |verify: bool, file_name: &str, target: &'static [u8]| {
+                builder(dir, &fs)
+                    .verify_after_write(verify)
+                    .fault_injector(CorruptWritten {
+                        fs: fs.clone(),
+                        path: std::path::Path::new(dir).join(file_name),
+                        target,
+                        armed: std::sync::atomic::AtomicBool::new(true),
+                    })
+                    .build()
+                    .unwrap()
+            };
+            let corrupting = |verify: bool| corrupting_file(verify, "tmp_data", b"value");
+            let batch = || MemtableEntries {
+                entries: (1..=3)
+                    .map(|i| (format!("key-{}", i), format!("value-{}", i)))
+                    .collect(),
+                tombstones: vec!["gone".to_string()].into_iter().collect(),
+            };
+
+            // the corruption goes unnoticed without the flag
+            let mut disktable = corrupting(false);
+            disktable.clear().unwrap();
+            disktable.flush(batch()).unwrap();
+            assert_eq!(
+                disktable.find("key-1").unwrap(),
+                Some("Value-1".to_string())
+            );
+            disktable.clear().unwrap();
+            drop(disktable);
+
+            let mut disktable = corrupting(true);
+            let err = disktable.flush(batch()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(disktable.generations().unwrap().is_empty());
+            // a retry writes the batch intact
+            disktable.flush(batch()).unwrap();
+            assert_eq!(disktable.generations().unwrap().len(), 1);
+            assert_eq!(
+                disktable.find("key-1").unwrap(),
+                Some("value-1".to_string())
+            );
+            disktable.clear().unwrap();
+            drop(disktable);
+
+            // as is an index written differently from the one built in memory
+            let mut disktable = corrupting_file(true, "tmp_index_1", b"key-2");
+            let err = disktable.flush(batch()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(disktable.generations().unwrap().is_empty());
+            disktable.flush(batch()).unwrap();
+            assert_eq!(
+                disktable.find("key-2").unwrap(),
+                Some("value-2".to_string())
+            );
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_find_surfaces_read_errors() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_find_surfaces_read_errors";
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            disktable
+                .flush(MemtableEntries {
+                    entries: (1..=3)
+                        .map(|i| (format!("key-{}", i), format!("value-{}", i)))
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            drop(disktable);
+            // the data file is emptied behind the index's back
+            let path = std::path::Path::new(dir).join("data_1");
+            fs.create(&path).unwrap().write(&[]).unwrap();
+
+            let disktable = builder(dir, &fs).build().unwrap();
+            assert!(disktable.find("key-2").is_err());
+            assert!(disktable.find_versions("key-2").is_err());
+            assert!(disktable.find_all_versions("key-2").is_err());
+            let later = Instant::now() + std::time::Duration::from_secs(60);
+            let err = disktable.find_with_deadline("key-2", later).unwrap_err();
+            assert!(!Timeout::is_timeout(&err));
+            assert!(disktable.find_detailed("key-2").is_err());
+            assert!(disktable.find_into("key-2", &mut String::new()).is_err());
+            assert!(disktable.find_range("key-2", 0, 4).is_err());
+            // the index alone answers these
+            assert!(disktable.contains("key-2").unwrap());
+            assert!(disktable.locate("key-2").unwrap().is_some());
+            // a key the index doesn't list reads nothing off the data file
+            assert_eq!(disktable.find("key-4").unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_existence_records() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_existence_records";
+            let mut disktable = builder(dir, &fs).existence_records(true).build().unwrap();
+            disktable.clear().unwrap();
+            let flush = |disktable: &mut FileDisktable, keys: &[&str], tombstones: &[&str]| {
+                disktable
+                    .flush(MemtableEntries {
+                        entries: keys
+                            .iter()
+                            .map(|key| (key.to_string(), String::new()))
+                            .collect(),
+                        tombstones: tombstones.iter().map(|key| key.to_string()).collect(),
+                    })
+                    .unwrap();
+            };
+            flush(&mut disktable, &["a", "b", "c"], &[]);
+            flush(&mut disktable, &["d"], &["b"]);
+            flush(&mut disktable, &["b"], &["c", "e"]);
+
+            let data_reads = disktable.stats().unwrap().data_reads;
+            let assert_keyset = |disktable: &FileDisktable| {
+                ["a", "b", "d"]
+                    .iter()
+                    .for_each(|key| assert!(disktable.contains(key).unwrap(), "{}", key));
+                ["c", "e", "f"]
+                    .iter()
+                    .for_each(|key| assert!(!disktable.contains(key).unwrap(), "{}", key));
+                assert_eq!(disktable.find("a").unwrap(), Some(String::new()));
+            };
+            assert_keyset(&disktable);
+            assert_eq!(disktable.stats().unwrap().data_reads, data_reads);
+
+            disktable.compact(None).unwrap();
+            assert_keyset(&disktable);
+            // indexes are read the same without the setting
+            assert_keyset(&builder(dir, &fs).build().unwrap());
+
+            // an index of a newer format version, or of an entry kind no version has,
+            // fails to read rather than being misread
+            let data_gen = disktable.generations().unwrap()[0].data_gen;
+            let path = |suffix: &str| {
+                std::path::Path::new(dir).join(format!("index_{}{}", data_gen, suffix))
+            };
+            let read = |path: &std::path::Path| {
+                let handle = fs.open(path).unwrap();
+                let mut bytes = vec![0; handle.size().unwrap() as usize];
+                handle.read_at(0, &mut bytes).unwrap();
+                bytes
+            };
+            let rewrite = |path: &std::path::Path, bytes: &[u8]| {
+                fs.create(path).unwrap().write(bytes).unwrap();
+            };
+            let read_error = || {
+                let reopened = builder(dir, &fs).build().unwrap();
+                reopened.find("a").unwrap_err().kind()
+            };
+            let (skip_index, index) = (read(&path("_skip")), read(&path("")));
+            let skip_text = String::from_utf8(skip_index.clone()).unwrap();
+            let (header, rest) = skip_text.split_once('\n').unwrap();
+            assert!(header.ends_with("\t2"));
+            let newer = format!("{}3\n{}", &header[..header.len() - 1], rest);
+            rewrite(&path("_skip"), newer.as_bytes());
+            assert_eq!(read_error(), io::ErrorKind::InvalidData);
+            rewrite(&path("_skip"), &skip_index);
+            // [key len]["a"][data gen][offset][kind] of the first entry
+            let mut unknown_kind = index.clone();
+            unknown_kind[4 + 1 + 12] = 7;
+            rewrite(&path(""), &unknown_kind);
+            assert_eq!(read_error(), io::ErrorKind::InvalidData);
+            rewrite(&path(""), &index);
+            assert_keyset(&builder(dir, &fs).build().unwrap());
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_compaction_rate_limit() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_compaction_rate_limit";
+            let zero = builder(dir, &fs).compaction_rate_limit(0).build();
+            assert_eq!(
+                zero.err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+
+            let limit = 50_000;
+            let mut disktable = builder(dir, &fs)
+                .compaction_rate_limit(limit)
+                .build()
+                .unwrap();
+            disktable.clear().unwrap();
+            assert_eq!(disktable.stats().unwrap().compaction_throughput, 0.0);
+            let batch = |version: char| MemtableEntries {
+                entries: (0..100)
+                    .map(|i| (format!("key-{:03}", i), version.to_string().repeat(40)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(batch('a')).unwrap();
+            disktable.flush(batch('b')).unwrap();
+
+            // both generations are read, 47 bytes of key and value an entry, and the newer
+            // one is written, 60 bytes a record as encoded
+            let bytes = 100 * (2 * 47 + 60);
+            let started = std::time::Instant::now();
+            disktable.compact(None).unwrap();
+            let elapsed = started.elapsed();
+            assert!(
+                elapsed.as_secs_f64() >= bytes as f64 / limit as f64,
+                "{:?}",
+                elapsed
+            );
+            let throughput = disktable.stats().unwrap().compaction_throughput;
+            assert!(
+                0.0 < throughput && throughput <= limit as f64,
+                "{}",
+                throughput
+            );
+            assert_eq!(disktable.find("key-050").unwrap(), Some("b".repeat(40)));
+            disktable.clear().unwrap();
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = "./test_tmp_file_modes";
+        let mode_of =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let flushed_modes = |builder: FileDisktableBuilder| {
+            let _ = std::fs::remove_dir_all(dir);
+            let mut disktable = builder.build().unwrap();
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![("key".to_string(), "secret".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            let files: BTreeMap<String, u32> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    let name = entry.file_name().into_string().unwrap();
+                    (name, mode_of(&entry.path()))
+                })
+                .collect();
+            assert!(files.contains_key("data_1") && files.contains_key("index_1"));
+            (mode_of(std::path::Path::new(dir)), files)
+        };
+
+        // neither group nor others can read anything by default
+        let (dir_mode, files) = flushed_modes(FileDisktableBuilder::new(dir));
+        assert_eq!(dir_mode & 0o077, 0, "{:o}", dir_mode);
+        files
+            .iter()
+            .for_each(|(name, mode)| assert_eq!(mode & 0o077, 0, "{}: {:o}", name, mode));
+
+        // the bits the umask of the process keeps, from a file created with all of them
+        let probe = std::path::Path::new(dir).join("probe");
+        let mut options = std::fs::OpenOptions::new();
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o777);
+        options.write(true).create(true).open(&probe).unwrap();
+        let kept = mode_of(&probe);
+        let (dir_mode, files) = flushed_modes(
+            FileDisktableBuilder::new(dir)
+                .file_mode(0o640)
+                .dir_mode(0o750),
+        );
+        assert_eq!(dir_mode, 0o750 & kept);
+        files
+            .iter()
+            .for_each(|(name, mode)| assert_eq!(*mode, 0o640 & kept, "{}: {:o}", name, mode));
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let along_with_file_system = FileDisktableBuilder::new(dir)
+            .file_system(MemFileSystem::default())
+            .file_mode(0o600)
+            .build();
+        assert_eq!(
+            along_with_file_system.err().map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    // counts the data files open at once, and the most which have been
+    struct CountingDataFiles {
+        fs: MemFileSystem,
+        open: Arc<std::sync::atomic::AtomicUsize>,
+        peak: Arc<std::sync::atomic::AtomicUsize>,
+        // listings of directories
+        listed: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    struct CountedHandle {
+        handle: Box<dyn FileHandle>,
+        open: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl FileHandle for CountedHandle {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            self.handle.read_at(offset, buf)
+        }
+        fn write(&self, buf: &[u8]) -> io::Result<()> {
+            self.handle.write(buf)
+        }
+        fn sync(&self) -> io::Result<()> {
+            self.handle.sync()
+        }
+        fn size(&self) -> io::Result<u64> {
+            self.handle.size()
+        }
+        fn identity(&self) -> io::Result<Option<FileIdentity>> {
+            self.handle.identity()
+        }
+    }
+
+    impl Drop for CountedHandle {
+        fn drop(&mut self) {
+            self.open.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    impl CountingDataFiles {
+        fn counted(
+            &self,
+            path: &std::path::Path,
+            handle: Box<dyn FileHandle>,
+        ) -> Box<dyn FileHandle> {
+            let is_data_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("data_"));
+            if !is_data_file {
+                return handle;
+            }
+            let open = self.open.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            self.peak
+                .fetch_max(open, std::sync::atomic::Ordering::SeqCst);
+            Box::new(CountedHandle {
+                handle,
+                open: self.open.clone(),
+            })
+        }
+    }
+
+    impl FileSystem for CountingDataFiles {
+        fn open(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            Ok(self.counted(path, self.fs.open(path)?))
+        }
+        fn create(&self, path: &std::path::Path) -> io::Result<Box<dyn FileHandle>> {
+            Ok(self.counted(path, self.fs.create(path)?))
+        }
+        fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+            self.fs.rename(from, to)
+        }
+        fn remove(&self, path: &std::path::Path) -> io::Result<()> {
+            self.fs.remove(path)
+        }
+        fn sync_dir(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.sync_dir(dir)
+        }
+        fn read_dir(&self, dir: &std::path::Path) -> io::Result<Vec<DirEntry>> {
+            self.listed
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.fs.read_dir(dir)
+        }
+        fn create_dir_all(&self, dir: &std::path::Path) -> io::Result<()> {
+            self.fs.create_dir_all(dir)
+        }
+    }
+
+    fn counting_data_files() -> Arc<CountingDataFiles> {
+        Arc::new(CountingDataFiles {
+            fs: MemFileSystem::default(),
+            open: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            peak: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            listed: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+        })
+    }
+
+    #[test]
+    fn test_read_only_listing() {
+        let dir = "./test_tmp_read_only_listing";
+        let counting = counting_data_files();
+        let fs: SharedFileSystem = counting.clone();
+        let mut disktable = builder(dir, &fs).build().unwrap();
+        let flush = |disktable: &mut FileDisktable, key: &str| {
+            let entries = MemtableEntries {
+                entries: vec![(key.to_string(), "value".to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries).unwrap();
+        };
+        flush(&mut disktable, "a");
+        let mut reader = builder(dir, &fs).read_only(true).build().unwrap();
+
+        // hits never list the directory, and generations written since the last refresh
+        // aren't looked up
+        flush(&mut disktable, "b");
+        let listed = counting.listed.load(std::sync::atomic::Ordering::SeqCst);
+        (0..10).for_each(|_| assert_eq!(reader.find("a").unwrap(), Some("value".to_string())));
+        assert_eq!(
+            counting.listed.load(std::sync::atomic::Ordering::SeqCst),
+            listed
+        );
+        assert_eq!(reader.find("b").unwrap(), None);
+        assert_eq!(reader.refresh().unwrap(), vec![2]);
+        assert_eq!(reader.find("b").unwrap(), Some("value".to_string()));
+
+        // generations retired under the reader are replaced by the ones listed then
+        disktable.compact(None).unwrap();
+        assert_eq!(reader.find("a").unwrap(), Some("value".to_string()));
+        assert_eq!(
+            disktable.refresh().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_compaction_fan_in() {
+        let dir = "./test_tmp_compaction_fan_in";
+        let counting = counting_data_files();
+        let fs: SharedFileSystem = counting.clone();
+        let mut disktable = builder(dir, &fs).compaction_fan_in(8).build().unwrap();
+        // every generation overwrites a shared key, and deletes the key of the one before
+        (1..=100).for_each(|gen| {
+            let batch = MemtableEntries {
+                entries: vec![
+                    ("shared".to_string(), format!("value-{}", gen)),
+                    (format!("key-{:03}", gen), format!("value-{}", gen)),
+                ]
+                .into_iter()
+                .collect(),
+                tombstones: vec![format!("key-{:03}", gen - 1)].into_iter().collect(),
+            };
+            disktable.flush(batch).unwrap();
+        });
+        let open_before = counting.open.load(std::sync::atomic::Ordering::SeqCst);
+        counting
+            .peak
+            .store(open_before, std::sync::atomic::Ordering::SeqCst);
+
+        let report = disktable.compact(None).unwrap();
+        let peak = counting.peak.load(std::sync::atomic::Ordering::SeqCst) - open_before;
+        assert!(peak <= 8 + 1, "{} data files were open at once", peak);
+        assert_eq!(report.merged, (1..=100).collect::<Vec<DataGen>>());
+        assert_eq!(
+            report.dropped_versions,
+            (1..100)
+                .map(|gen| format!("key-{:03}", gen))
+                .chain(std::iter::once("shared".to_string()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            disktable
+                .generations()
+                .unwrap()
+                .into_iter()
+                .map(|info| info.data_gen)
+                .collect::<Vec<_>>(),
+            vec![100]
+        );
+        assert_eq!(
+            disktable.find("shared").unwrap(),
+            Some("value-100".to_string())
+        );
+        assert_eq!(
+            disktable.find("key-100").unwrap(),
+            Some("value-100".to_string())
+        );
+        (1..100).for_each(|gen| {
+            assert_eq!(disktable.find(&format!("key-{:03}", gen)).unwrap(), None);
+        });
+
+        let invalid = |builder: FileDisktableBuilder| builder.build().err().unwrap().kind();
+        assert_eq!(
+            invalid(builder(dir, &fs).compaction_fan_in(1)),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            invalid(builder(dir, &fs).compaction_fan_in(8).keep_versions(2)),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            invalid(
+                builder(dir, &fs)
+                    .compaction_fan_in(8)
+                    .double_buffered_compaction(true)
+            ),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_index_size_budget() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_index_size_budget";
+            let mut disktable = builder(dir, &fs).index_size_budget(0.01).build().unwrap();
+            disktable.clear().unwrap();
+            let generation = |num: usize, value_len: usize| MemtableEntries {
+                entries: (0..num)
+                    .map(|i| (format!("key-{:05}", i), "v".repeat(value_len)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            // many keys of small values, and a few of large ones
+            disktable.flush(generation(3000, 4)).unwrap();
+            disktable.flush(generation(20, 1000)).unwrap();
+            let skip_index = |data_gen: DataGen| -> Vec<String> {
+                let handle = fs
+                    .open(&std::path::Path::new(dir).join(format!("index_{}_skip", data_gen)))
+                    .unwrap();
+                let mut bytes = vec![0; handle.size().unwrap() as usize];
+                handle.read_at(0, &mut bytes).unwrap();
+                String::from_utf8(bytes)
+                    .unwrap()
+                    .lines()
+                    .map(str::to_string)
+                    .collect()
+            };
+            let interval = |lines: &[String]| -> usize {
+                let (key, header) = lines[0].split_once('\t').unwrap();
+                assert_eq!(key, "");
+                let (interval, _version) = header.split_once('\t').unwrap();
+                interval.parse().unwrap()
+            };
+            let (large_skip, small_skip) = (skip_index(1), skip_index(2));
+            let (large_interval, small_interval) = (interval(&large_skip), interval(&small_skip));
+            assert!(large_interval > 30, "interval {}", large_interval);
+            assert!(small_interval < 30, "interval {}", small_interval);
+            assert_eq!(large_skip.len() - 1, 3000 / large_interval);
+            assert_eq!(small_skip.len() - 1, 20 / small_interval);
+
+            // both are searched through their own intervals, the small one shadowing
+            // the first keys of the large one
+            (0..3000).for_each(|i| {
+                let value_len = if i < 20 { 1000 } else { 4 };
+                assert_eq!(
+                    disktable.find(&format!("key-{:05}", i)).unwrap(),
+                    Some("v".repeat(value_len))
+                );
+            });
+            assert_eq!(disktable.find("key-99999").unwrap(), None);
+
+            assert_eq!(
+                builder(dir, &fs)
+                    .index_size_budget(0.0)
+                    .build()
+                    .err()
+                    .unwrap()
+                    .kind(),
+                io::ErrorKind::InvalidInput
+            );
+        });
+    }
+
+    #[test]
+    fn test_clear_during_reads() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_clear_during_reads";
+            for _ in 0..3 {
+                let mut disktable = builder(dir, &fs).build().unwrap();
+                disktable.clear().unwrap();
+                // every generation overwrites a shared key and adds one of its own
+                (1..=10).for_each(|gen| {
+                    let batch = MemtableEntries {
+                        entries: vec![
+                            ("shared".to_string(), format!("shared-{}", gen)),
+                            (format!("key-{}", gen), format!("value-{}", gen)),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        tombstones: BTreeSet::new(),
+                    };
+                    disktable.flush(batch).unwrap();
+                });
+                let reader = builder(dir, &fs).read_only(true).build().unwrap();
+                let cleared = Arc::new(std::sync::atomic::AtomicBool::new(false));
+                let reading = {
+                    let cleared = cleared.clone();
+                    std::thread::spawn(move || {
+                        let mut reads = 0;
+                        // reads through the clear, and a few more after it
+                        while !cleared.load(std::sync::atomic::Ordering::SeqCst) || reads < 100 {
+                            let gen = reads % 10 + 1;
+                            let found = reader.find("shared").unwrap();
+                            assert!(
+                                found.is_none() || found.as_deref() == Some("shared-10"),
+                                "{:?}",
+                                found
+                            );
+                            let found = reader.find(&format!("key-{}", gen)).unwrap();
+                            assert!(
+                                found.is_none() || found == Some(format!("value-{}", gen)),
+                                "{:?}",
+                                found
+                            );
+                            reads += 1;
+                        }
+                        reader
+                    })
+                };
+                disktable.clear().unwrap();
+                cleared.store(true, std::sync::atomic::Ordering::SeqCst);
+                let reader = reading.join().unwrap();
+                assert_eq!(reader.find("shared").unwrap(), None);
+            }
+        });
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = "./test_tmp_manifest";
+        let mem = MemFileSystem::default();
+        let fs: SharedFileSystem = Arc::new(mem.clone());
+        let open = |manifest| builder(dir, &fs).manifest(manifest).build().unwrap();
+        let flush = |disktable: &mut FileDisktable, gen: usize| {
+            let batch = MemtableEntries {
+                entries: vec![
+                    (format!("key-{}", gen), format!("value-{}", gen)),
+                    ("shared".to_string(), format!("shared-{}", gen)),
+                ]
+                .into_iter()
+                .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(batch).unwrap();
+        };
+        let listed = |disktable: &FileDisktable| -> Vec<DataGen> {
+            disktable
+                .generations()
+                .unwrap()
+                .into_iter()
+                .map(|info| info.data_gen)
+                .collect()
+        };
+        let path = |name: &str| std::path::Path::new(dir).join(name);
+        let read = |name: &str| {
+            let handle = fs.open(&path(name)).unwrap();
+            let mut bytes = vec![0; handle.size().unwrap() as usize];
+            handle.read_at(0, &mut bytes).unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        let write = |name: &str, contents: &str| {
+            fs.create(&path(name))
+                .unwrap()
+                .write(contents.as_bytes())
+                .unwrap();
+        };
+        let mut disktable = open(true);
+        disktable.clear().unwrap();
+        (1..=3).for_each(|gen| flush(&mut disktable, gen));
+        mem.crash();
+        let mut disktable = open(true);
+        assert_eq!(listed(&disktable), vec![1, 2, 3]);
+
+        // a crash after a generation is published but before the manifest lists it
+        // leaves the generations as they were
+        let before = read(Manifest::FILE_NAME);
+        flush(&mut disktable, 4);
+        write(Manifest::FILE_NAME, &before);
+        let disktable = open(true);
+        assert_eq!(listed(&disktable), vec![1, 2, 3]);
+        assert_eq!(disktable.find("key-4").unwrap(), None);
+        assert_eq!(
+            disktable.find("shared").unwrap(),
+            Some("shared-3".to_string())
+        );
+
+        // as is a half-written generation
+        write("data_7", "half-written");
+        write("index_7", "half-written");
+        let mut disktable = open(true);
+        assert_eq!(listed(&disktable), vec![1, 2, 3]);
+        (1..=3).for_each(|gen| {
+            assert_eq!(
+                disktable.find(&format!("key-{}", gen)).unwrap(),
+                Some(format!("value-{}", gen))
+            );
+        });
+        flush(&mut disktable, 4);
+        // the compaction goes above the newest generation, and the files of the ones merged,
+        // which the crash brings back, are removed on opening
+        disktable.compact(None).unwrap();
+        mem.crash();
+        let disktable = open(true);
+        assert_eq!(listed(&disktable), vec![5]);
+        assert_eq!(
+            disktable.find("key-1").unwrap(),
+            Some("value-1".to_string())
+        );
+        assert_eq!(
+            disktable.find("shared").unwrap(),
+            Some("shared-4".to_string())
+        );
+        let hex = |key: &str| {
+            key.bytes()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+        assert_eq!(
+            read(Manifest::FILE_NAME),
+            format!("5\t{}\t{}\n", hex("key-1"), hex("shared"))
+        );
+        let data_files = fs
+            .read_dir(std::path::Path::new(dir))
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.name.to_str().unwrap().starts_with("data_"))
+            .count();
+        assert_eq!(data_files, 1);
+
+        // a writer without it can't open the directory while it has the manifest. once
+        // that's removed, the directory is listed, and the manifest is rebuilt from it
+        write("data_7", "half-written");
+        let without = builder(dir, &fs).build();
+        assert_eq!(
+            without.err().map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            read(Manifest::FILE_NAME),
+            format!("5\t{}\t{}\n", hex("key-1"), hex("shared"))
+        );
+        fs.remove(&path(Manifest::FILE_NAME)).unwrap();
+        let disktable = open(false);
+        assert_eq!(listed(&disktable), vec![5, 7]);
+        fs.remove(&path("data_7")).unwrap();
+        let mut disktable = open(true);
+        assert_eq!(listed(&disktable), vec![5]);
+        assert_eq!(
+            read(Manifest::FILE_NAME),
+            format!("5\t{}\t{}\n", hex("key-1"), hex("shared"))
+        );
+        disktable.clear().unwrap();
+        assert_eq!(read(Manifest::FILE_NAME), "");
+    }
+
+    // fails at the point once armed, as a crash there would stop the compaction
+    struct CrashAt {
+        point: CrashPoint,
+        armed: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl FaultInjector for CrashAt {
+        fn at(&self, point: CrashPoint) -> io::Result<()> {
+            if point == self.point && self.armed.swap(false, std::sync::atomic::Ordering::SeqCst) {
+                return Err(io::Error::other(format!("crash at {:?}", point)));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_manifest_compaction_crash() {
+        let dir = "./test_tmp_manifest_compaction_crash";
+        let key = |i: usize| format!("key-{}", i);
+        // the second round overwrites the even keys, and the last one deletes every fifth
+        let expected = |i: usize| match i {
+            _ if i.is_multiple_of(5) => None,
+            _ if i.is_multiple_of(2) => Some("v1".to_string()),
+            _ => Some("v0".to_string()),
+        };
+        let points = [
+            CrashPoint::Written,
+            CrashPoint::Synced,
+            CrashPoint::Merged,
+            CrashPoint::Committed,
+        ];
+        // a fan-in of 2 merges the two newer generations in a wave of their own first
+        let modes = [(false, None), (true, None), (false, Some(2))];
+        for point in points.iter() {
+            for (incremental, fan_in) in modes.iter() {
+                let mem = MemFileSystem::default();
+                let fs: SharedFileSystem = Arc::new(mem.clone());
+                let open = || {
+                    let open = builder(dir, &fs).manifest(true).sync_on_flush(true);
+                    match fan_in {
+                        Some(fan_in) => open.compaction_fan_in(*fan_in),
+                        None => open,
+                    }
+                };
+                let armed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+                let mut disktable = open()
+                    .fault_injector(CrashAt {
+                        point: *point,
+                        armed: armed.clone(),
+                    })
+                    .build()
+                    .unwrap();
+                (0..3).for_each(|round| {
+                    let entries = (0..10)
+                        .filter(|i| round == 0 || (round == 1 && i % 2 == 0))
+                        .map(|i| (key(i), format!("v{}", round)))
+                        .collect();
+                    let tombstones = (0..10)
+                        .filter(|i| round == 2 && i % 5 == 0)
+                        .map(key)
+                        .collect();
+                    disktable
+                        .flush(MemtableEntries {
+                            entries,
+                            tombstones,
+                        })
+                        .unwrap();
+                });
+                armed.store(true, std::sync::atomic::Ordering::SeqCst);
+                let compacted = if *incremental {
+                    disktable.compact_incremental()
+                } else {
+                    disktable.compact(None)
+                };
+                assert!(compacted.is_err(), "{:?}", point);
+                drop(disktable);
+                mem.crash();
+
+                // either the generations merged or the one they're merged into are listed,
+                // or the oldest one along with the wave merging the others
+                let mut disktable = open().build().unwrap();
+                let generations = disktable.generations().unwrap().len();
+                let waved = fan_in.is_some() && generations == 2;
+                assert!(generations == 3 || generations == 1 || waved, "{:?}", point);
+                (0..10).for_each(|i| {
+                    assert_eq!(disktable.find(&key(i)).unwrap(), expected(i), "{:?}", point)
+                });
+                disktable.compact(None).unwrap();
+                assert_eq!(disktable.generations().unwrap().len(), 1, "{:?}", point);
+                (0..10).for_each(|i| {
+                    assert_eq!(disktable.find(&key(i)).unwrap(), expected(i), "{:?}", point)
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_manifest_merge_and_vacuum_crash() {
+        let dir = "./test_tmp_manifest_merge_and_vacuum_crash";
+        let key = |i: usize| format!("key-{}", i);
+        // the second round overwrites the even keys, and the last one deletes every fifth
+        let expected = |i: usize| match i {
+            _ if i.is_multiple_of(5) => None,
+            _ if i.is_multiple_of(2) => Some("v1".to_string()),
+            _ => Some("v0".to_string()),
+        };
+        let points = [
+            CrashPoint::Written,
+            CrashPoint::Synced,
+            CrashPoint::Renamed,
+            CrashPoint::Merged,
+            CrashPoint::Committed,
+        ];
+        for point in points.iter() {
+            for vacuum in [false, true].iter() {
+                let mem = MemFileSystem::default();
+                let fs: SharedFileSystem = Arc::new(mem.clone());
+                let open = || builder(dir, &fs).manifest(true).sync_on_flush(true);
+                let armed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+                let mut disktable = open()
+                    .fault_injector(CrashAt {
+                        point: *point,
+                        armed: armed.clone(),
+                    })
+                    .build()
+                    .unwrap();
+                (0..3).for_each(|round| {
+                    let entries = (0..10)
+                        .filter(|i| round == 0 || (round == 1 && i % 2 == 0))
+                        .map(|i| (key(i), format!("v{}", round)))
+                        .collect();
+                    let tombstones = (0..10)
+                        .filter(|i| round == 2 && i % 5 == 0)
+                        .map(key)
+                        .collect();
+                    disktable
+                        .flush(MemtableEntries {
+                            entries,
+                            tombstones,
+                        })
+                        .unwrap();
+                });
+                armed.store(true, std::sync::atomic::Ordering::SeqCst);
+                let failed = if *vacuum {
+                    disktable.vacuum(1).is_err()
+                } else {
+                    disktable.merge_generations(1, 2).is_err()
+                };
+                assert!(failed, "{:?}", point);
+                drop(disktable);
+                mem.crash();
+
+                // either the generations rewritten or the one they're rewritten into are
+                // listed along with the others
+                let mut disktable = open().build().unwrap();
+                let listed: Vec<DataGen> = disktable
+                    .generations()
+                    .unwrap()
+                    .into_iter()
+                    .map(|info| info.data_gen)
+                    .collect();
+                let rewritten = if *vacuum { vec![2, 3, 4] } else { vec![3, 4] };
+                assert!(
+                    listed == vec![1, 2, 3] || listed == rewritten,
+                    "{:?} {:?}",
+                    point,
+                    listed
+                );
+                (0..10).for_each(|i| {
+                    assert_eq!(disktable.find(&key(i)).unwrap(), expected(i), "{:?}", point)
+                });
+                disktable.compact(None).unwrap();
+                (0..10).for_each(|i| {
+                    assert_eq!(disktable.find(&key(i)).unwrap(), expected(i), "{:?}", point)
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewrite_in_place_crash() {
+        let dir = "./test_tmp_rewrite_in_place_crash";
+        for point in [CrashPoint::Synced, CrashPoint::Renamed].iter() {
+            let mem = MemFileSystem::default();
+            let fs: SharedFileSystem = Arc::new(mem.clone());
+            let open = || builder(dir, &fs).sync_on_flush(true);
+            let armed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let mut disktable = open()
+                .fault_injector(CrashAt {
+                    point: *point,
+                    armed: armed.clone(),
+                })
+                .build()
+                .unwrap();
+            // the keys of the older generation sort first, so merging moves every record
+            // of the newer one to another offset
+            (0..2).for_each(|round| {
+                let entries = (0..40)
+                    .map(|i| (format!("key-{}-{:02}", round, i), format!("v{}", round)))
+                    .collect();
+                disktable
+                    .flush(MemtableEntries {
+                        entries,
+                        tombstones: BTreeSet::new(),
+                    })
+                    .unwrap();
+            });
+            armed.store(true, std::sync::atomic::Ordering::SeqCst);
+            // without a manifest the newest generation is rewritten in place
+            assert!(disktable.compact(None).is_err(), "{:?}", point);
+            drop(disktable);
+            mem.crash();
+
+            // the rewritten data file is read through its own index either way
+            let disktable = open().build().unwrap();
+            (0..2).for_each(|round| {
+                (0..40).for_each(|i| {
+                    assert_eq!(
+                        disktable.find(&format!("key-{}-{:02}", round, i)).unwrap(),
+                        Some(format!("v{}", round)),
+                        "{:?}",
+                        point
+                    )
+                })
+            });
+            let left: Vec<String> = fs
+                .read_dir(std::path::Path::new(dir))
+                .unwrap()
+                .into_iter()
+                .filter_map(|entry| entry.name.into_string().ok())
+                .filter(|name| name.starts_with("publishing") || name.starts_with("tmp_index"))
+                .collect();
+            assert!(left.is_empty(), "{:?} {:?}", point, left);
+        }
+    }
+
+    #[test]
+    fn test_pin_scanned_generations() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_pin_scanned_generations";
+            let mut disktable = builder(dir, &fs)
+                .pin_scanned_generations(true)
+                .build()
+                .unwrap();
+            disktable.clear().unwrap();
+            (1..=3).for_each(|round| {
+                let entries = (0..10)
+                    .map(|i| (format!("key-{}", i), format!("v{}", round)))
+                    .collect();
+                disktable
+                    .flush(MemtableEntries {
+                        entries,
+                        tombstones: BTreeSet::new(),
+                    })
+                    .unwrap();
+            });
+            let exists = |name: &str| fs.open(&std::path::Path::new(dir).join(name)).is_ok();
+
+            // the generations merged away are unlisted, while their files are left to the scan
+            // under retired names
+            let snapshot = disktable.sources("", None).unwrap();
+            disktable.compact(None).unwrap();
+            assert_eq!(disktable.generations().unwrap().len(), 1);
+            assert!(["data_1", "index_1", "data_2", "index_2"]
+                .iter()
+                .all(|name| !exists(name) && exists(&format!("retired_{}", name))));
+            assert_eq!(disktable.find("key-0").unwrap(), Some("v3".to_string()));
+            let scanned: Vec<_> = snapshot
+                .into_iter()
+                .flat_map(|source| source.map(|record| record.unwrap().1.unwrap()))
+                .collect();
+            assert_eq!(scanned.len(), 30);
+            assert!(scanned.contains(&"v1".to_string()));
+            // and go once it's dropped
+            assert!(["retired_data_1", "retired_index_1", "retired_data_2"]
+                .iter()
+                .all(|name| !exists(name)));
+            assert!(exists("data_3"));
+
+            // retired files left by an open which went before the scan are removed on reopen
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![("key-0".to_string(), "v4".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            let snapshot = disktable.sources("", None).unwrap();
+            disktable.compact(None).unwrap();
+            assert!(exists("retired_data_3"));
+            drop(disktable);
+            let mut disktable = builder(dir, &fs)
+                .pin_scanned_generations(true)
+                .build()
+                .unwrap();
+            assert!(!exists("retired_data_3") && !exists("retired_index_3_skip"));
+            assert_eq!(disktable.generations().unwrap().len(), 1);
+            drop(snapshot);
+
+            // without a scan they go at once
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![("key-0".to_string(), "v5".to_string())]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            let merged = disktable.generations().unwrap();
+            disktable.compact(None).unwrap();
+            assert!(merged[..merged.len() - 1]
+                .iter()
+                .all(|info| !exists(&format!("data_{}", info.data_gen))
+                    && !exists(&format!("retired_data_{}", info.data_gen))));
+            let scanned: Vec<_> = disktable
+                .sources("", None)
+                .unwrap()
+                .into_iter()
+                .flat_map(|source| source.map(|record| record.unwrap().1.unwrap()))
+                .collect();
+            assert_eq!(scanned.len(), 10);
+            disktable.clear().unwrap();
+        });
+    }
+
+    // the key i of the generation, where the generations hold disjoint key ranges in order
+    fn routed_key(data_gen: usize, i: usize) -> String {
+        format!("k{:02}-{}", data_gen, i)
+    }
+
+    // a disktable opened by `open` with the generations of 10 keys each, valued by their
+    // generation, for lookups routed through bloom filters
+    fn routed_generations(generations: usize, open: impl Fn() -> FileDisktable) -> FileDisktable {
+        let mut disktable = open();
+        for data_gen in 0..generations {
+            disktable
+                .flush(MemtableEntries {
+                    entries: (0..10)
+                        .map(|i| (routed_key(data_gen, i), format!("{}", data_gen)))
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+        }
+        disktable
+    }
+
+    // index reads and bloom filter skips of the lookup
+    fn lookup_reads(disktable: &FileDisktable, lookup: &dyn Fn(&FileDisktable)) -> (u64, u64) {
+        let before = disktable.stats().unwrap();
+        lookup(disktable);
+        let after = disktable.stats().unwrap();
+        (
+            after.index_reads - before.index_reads,
+            after.bloom_skips - before.bloom_skips,
+        )
+    }
+
+    #[test]
+    fn test_partitioned_bloom_filters() {
+        let dir = "./test_tmp_partitioned_bloom_filters";
+        let fs = MemFileSystem::default();
+        let open = || {
+            FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .bloom_filters(0.01)
+                .partitioned_bloom_filters(true)
+                .build()
+                .unwrap()
+        };
+        let key = routed_key;
+        let disktable = routed_generations(20, open);
+        let index_reads = |disktable: &FileDisktable, lookup: &dyn Fn(&FileDisktable)| {
+            lookup_reads(disktable, lookup).0
+        };
+
+        // ranges of generations written before opening are read once, and a key is looked
+        // up in the one generation owning it
+        for disktable in [disktable, open()] {
+            for data_gen in 0..20 {
+                for i in 0..10 {
+                    let reads = index_reads(&disktable, &|disktable| {
+                        assert_eq!(
+                            disktable.find(&key(data_gen, i)).unwrap(),
+                            Some(format!("{}", data_gen))
+                        );
+                    });
+                    assert_eq!(reads, 1, "{}", key(data_gen, i));
+                }
+            }
+            let reads = index_reads(&disktable, &|disktable| {
+                for absent in ["a", "k", "k05-", "k19-99", "z"] {
+                    assert_eq!(disktable.find(absent).unwrap(), None);
+                    assert!(!disktable.contains(absent).unwrap());
+                }
+            });
+            assert!(reads <= 1, "{} index reads", reads);
+            let reads = index_reads(&disktable, &|disktable| {
+                (0..100)
+                    .for_each(|i| assert_eq!(disktable.find(&key(i % 20, 10 + i)).unwrap(), None));
+            });
+            assert!(reads <= 5, "{} index reads", reads);
+        }
+
+        // a generation overlapping the others is looked up along with the owning one
+        let mut disktable = open();
+        disktable
+            .flush(MemtableEntries {
+                entries: (0..20)
+                    .map(|data_gen| (key(data_gen, 0), "new".to_string()))
+                    .collect(),
+                tombstones: std::iter::once(key(3, 1)).collect(),
+            })
+            .unwrap();
+        let reads = index_reads(&disktable, &|disktable| {
+            for data_gen in 0..20 {
+                assert_eq!(
+                    disktable.find(&key(data_gen, 0)).unwrap(),
+                    Some("new".to_string())
+                );
+                assert_eq!(
+                    disktable.find(&key(data_gen, 2)).unwrap(),
+                    Some(format!("{}", data_gen))
+                );
+            }
+            assert_eq!(disktable.find(&key(3, 1)).unwrap(), None);
+            assert_eq!(
+                disktable.find_all_versions(&key(3, 1)).unwrap(),
+                vec![(21, None), (4, Some("3".to_string()))]
+            );
+        });
+        assert!(reads <= 20 + 20 + 3 + 3, "{} index reads", reads);
+
+        let without_filters = FileDisktableBuilder::new(dir)
+            .file_system(fs.clone())
+            .partitioned_bloom_filters(true)
+            .build();
+        assert_eq!(
+            without_filters.err().map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_dictionary() {
+        let fs: SharedFileSystem = Arc::new(MemFileSystem::default());
+        let key = |i| format!("key-{:04}", i);
+        let value = |i| {
+            format!(
+                "{{\"id\":{},\"user\":\"user-{}\",\"status\":\"active\",\"plan\":\"premium\",\"region\":\"eu-west-1\"}}",
+                i,
+                i % 97
+            )
+        };
+        let entries = |range: std::ops::Range<usize>| MemtableEntries {
+            entries: range.map(|i| (key(i), value(i))).collect(),
+            tombstones: BTreeSet::new(),
+        };
+        let data_file_bytes = |builder: FileDisktableBuilder| {
+            let mut disktable = builder.build().unwrap();
+            disktable.flush(entries(0..2000)).unwrap();
+            disktable.describe_generation(1).unwrap().data_file_bytes
+        };
+        let plain = data_file_bytes(builder("./test_tmp_compression_plain", &fs));
+        let compressed =
+            data_file_bytes(builder("./test_tmp_compression_values", &fs).compress_values(3));
+        let dir = "./test_tmp_compression_dictionary";
+        let with_dictionary = || {
+            builder(dir, &fs)
+                .compress_values(3)
+                .compression_dictionary(1024)
+        };
+        let trained = data_file_bytes(with_dictionary());
+        assert!(compressed <= plain);
+        assert!(
+            trained * 4 < compressed * 3,
+            "{} bytes with a dictionary, {} without",
+            trained,
+            compressed
+        );
+
+        let assert_found = |disktable: &FileDisktable| {
+            (0..2000).step_by(7).for_each(|i| {
+                assert_eq!(disktable.find(&key(i)).unwrap(), Some(value(i)));
+                let mut buf = String::new();
+                assert!(disktable.find_into(&key(i), &mut buf).unwrap());
+                assert_eq!(buf, value(i));
+            });
+            assert_eq!(
+                disktable.find_range(&key(5), 1, 4).unwrap(),
+                Some(b"\"id\"".to_vec())
+            );
+            let scanned: Vec<String> = disktable
+                .sources("", None)
+                .unwrap()
+                .remove(0)
+                .map(|record| record.unwrap().1.unwrap())
+                .collect();
+            assert_eq!(scanned.len(), 2000);
+            assert_eq!(scanned[42], value(42));
+        };
+        let mut disktable = with_dictionary().build().unwrap();
+        assert_found(&disktable);
+        // generations are read the same without the options, and rewritten with dictionaries
+        // of their own by compactions
+        assert_found(&builder(dir, &fs).build().unwrap());
+        disktable.flush(entries(1000..1500)).unwrap();
+        disktable.compact(None).unwrap();
+        assert_found(&disktable);
+        assert_found(&with_dictionary().build().unwrap());
+
+        // the header is skipped in blocks and along with timestamps
+        let dir = "./test_tmp_compression_blocks";
+        let mut disktable = builder(dir, &fs)
+            .compress_values(3)
+            .compression_dictionary(1024)
+            .block_size(4096)
+            .entry_timestamps(true)
+            .build()
+            .unwrap();
+        disktable.flush(entries(0..2000)).unwrap();
+        assert_found(&disktable);
+        assert!(disktable.find_with_meta(&key(7)).unwrap().is_some());
+
+        for invalid in [
+            builder(dir, &fs).compression_dictionary(1024),
+            builder(dir, &fs).compress_values(1000),
+            builder(dir, &fs)
+                .compress_values(3)
+                .compression_dictionary(100),
+        ] {
+            assert_eq!(
+                invalid.build().err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_bloom_checks() {
+        let dir = "./test_tmp_batch_bloom_checks";
+        let fs = MemFileSystem::default();
+        let open = || {
+            FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .bloom_filters(0.0001)
+                .batch_bloom_checks(true)
+                .build()
+                .unwrap()
+        };
+        let key = routed_key;
+        let disktable = routed_generations(50, open);
+
+        // a key of the oldest generation only is found by a pass over the filters and the
+        // one index read of its generation, also with filters read from disk on opening.
+        // a key of the newest one skips none, as the older ones are never gone past
+        for disktable in [disktable, open()] {
+            let (index_reads, bloom_skips) = lookup_reads(&disktable, &|disktable| {
+                assert_eq!(disktable.find(&key(0, 3)).unwrap(), Some("0".to_string()));
+            });
+            assert_eq!((index_reads, bloom_skips), (1, 49));
+            let (index_reads, bloom_skips) = lookup_reads(&disktable, &|disktable| {
+                assert_eq!(disktable.find(&key(49, 3)).unwrap(), Some("49".to_string()));
+            });
+            assert_eq!((index_reads, bloom_skips), (1, 0));
+            let (index_reads, _) = lookup_reads(&disktable, &|disktable| {
+                (0..100).for_each(|i| {
+                    assert_eq!(disktable.find(&key(i % 50, 10 + i)).unwrap(), None);
+                    assert!(!disktable.contains(&key(i % 50, 10 + i)).unwrap());
+                });
+            });
+            // 200 lookups through 50 generations, which read an index on a false positive only
+            assert!(index_reads < 50, "{} index reads", index_reads);
+            for data_gen in 0..50 {
+                assert_eq!(
+                    disktable.find(&key(data_gen, 9)).unwrap(),
+                    Some(format!("{}", data_gen))
+                );
+            }
+        }
+
+        assert_eq!(
+            FileDisktableBuilder::new(dir)
+                .file_system(fs.clone())
+                .batch_bloom_checks(true)
+                .build()
+                .err()
+                .map(|err| err.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_value_cache_admission() {
+        let dir = "./test_tmp_value_cache_admission";
+        let fs: SharedFileSystem = Arc::new(MemFileSystem::default());
+        let small = |i: usize| format!("{:02}{}", i, "s".repeat(48));
+        let entries = |big: &str| MemtableEntries {
+            entries: (0..10)
+                .map(|i| (format!("small-{}", i), small(i)))
+                .chain(std::iter::once(("big".to_string(), big.to_string())))
+                .collect(),
+            tombstones: BTreeSet::new(),
+        };
+        let big = "b".repeat(900);
+        let data_reads = |disktable: &FileDisktable, lookup: &dyn Fn(&FileDisktable)| {
+            let before = disktable.stats().unwrap().data_reads;
+            lookup(disktable);
+            disktable.stats().unwrap().data_reads - before
+        };
+        let read_small = |disktable: &FileDisktable| {
+            (0..10).for_each(|i| {
+                assert_eq!(
+                    disktable.find(&format!("small-{}", i)).unwrap(),
+                    Some(small(i))
+                );
+                let mut buf = String::new();
+                assert!(disktable
+                    .find_into(&format!("small-{}", i), &mut buf)
+                    .unwrap());
+                assert_eq!(buf, small(i));
+            })
+        };
+        let read_big = |disktable: &FileDisktable| {
+            assert_eq!(disktable.find("big").unwrap(), Some(big.clone()));
+        };
+
+        // the big value is read from disk every time, and evicts none of the small ones
+        let mut disktable = builder(dir, &fs)
+            .value_cache(1000)
+            .value_cache_admission(0.5)
+            .build()
+            .unwrap();
+        disktable.flush(entries(&big)).unwrap();
+        assert_eq!(data_reads(&disktable, &read_small), 10);
+        assert_eq!(data_reads(&disktable, &read_small), 0);
+        assert_eq!(data_reads(&disktable, &read_big), 1);
+        assert_eq!(data_reads(&disktable, &read_big), 1);
+        assert_eq!(data_reads(&disktable, &read_small), 0);
+
+        // admitted, it pushes out most of them
+        let mut unbounded = builder("./test_tmp_value_cache_unbounded", &fs)
+            .value_cache(1000)
+            .build()
+            .unwrap();
+        unbounded.flush(entries(&big)).unwrap();
+        assert_eq!(data_reads(&unbounded, &read_small), 10);
+        assert_eq!(data_reads(&unbounded, &read_big), 1);
+        assert_eq!(data_reads(&unbounded, &read_big), 0);
+        assert!(data_reads(&unbounded, &read_small) >= 9);
+
+        // values cached for a generation rewritten in place aren't read for its new records
+        let bigger = "c".repeat(950);
+        unbounded.flush(entries(&bigger)).unwrap();
+        unbounded.compact(None).unwrap();
+        read_small(&unbounded);
+        assert_eq!(unbounded.find("big").unwrap(), Some(bigger.clone()));
+        disktable.clear().unwrap();
+        disktable
+            .flush(MemtableEntries {
+                entries: (0..10)
+                    .map(|i| (format!("small-{}", i), small(9 - i)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            })
+            .unwrap();
+        assert_eq!(disktable.find("small-0").unwrap(), Some(small(9)));
+
+        for invalid in [
+            builder(dir, &fs).value_cache(0),
+            builder(dir, &fs).value_cache_admission(0.5),
+            builder(dir, &fs)
+                .value_cache(1000)
+                .value_cache_admission(0.0),
+            builder(dir, &fs)
+                .value_cache(1000)
+                .value_cache_admission(1.5),
+        ] {
+            assert_eq!(
+                invalid.build().err().map(|err| err.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tombstone_only_generation() {
+        let fs = MemFileSystem::default();
+        let key = |i: usize| format!("key-{}", i);
+        let deleted = [2, 5, 7];
+        type Configure = fn(FileDisktableBuilder) -> FileDisktableBuilder;
+        let open = |dir: &str, configure: Configure| {
+            configure(FileDisktableBuilder::new(dir).file_system(fs.clone()))
+                .build()
+                .unwrap()
+        };
+        let configs: [(&str, Configure); 4] = [
+            ("./test_tmp_tombstone_only", |builder| builder),
+            ("./test_tmp_tombstone_only_bloom", |builder| {
+                builder.bloom_filters(0.01)
+            }),
+            ("./test_tmp_tombstone_only_batched", |builder| {
+                builder.bloom_filters(0.01).batch_bloom_checks(true)
+            }),
+            ("./test_tmp_tombstone_only_partitioned", |builder| {
+                builder
+                    .bloom_filters(0.01)
+                    .partitioned_bloom_filters(true)
+                    .manifest(true)
+            }),
+        ];
+        for (dir, configure) in configs.iter() {
+            let mut disktable = open(dir, *configure);
+            disktable
+                .flush(MemtableEntries {
+                    entries: (0..10).map(|i| (key(i), format!("value-{}", i))).collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+            // a bulk delete
+            disktable
+                .flush(MemtableEntries {
+                    entries: BTreeMap::new(),
+                    tombstones: deleted.iter().map(|i| key(*i)).collect(),
+                })
+                .unwrap();
+
+            let info = disktable.describe_generation(2).unwrap();
+            assert_eq!((info.entries, info.tombstones), (3, 3));
+            assert_eq!(info.min_key, Some(key(2)));
+            assert_eq!(info.max_key, Some(key(7)));
+            let assert_shadowed = |disktable: &FileDisktable| {
+                for i in 0..10 {
+                    let expected = if deleted.contains(&i) {
+                        None
+                    } else {
+                        Some(format!("value-{}", i))
+                    };
+                    assert_eq!(disktable.find(&key(i)).unwrap(), expected, "{}", dir);
+                    assert_eq!(disktable.contains(&key(i)).unwrap(), expected.is_some());
+                }
+                assert_eq!(disktable.sources("", None).unwrap().len(), 2);
+            };
+            assert_shadowed(&disktable);
+            assert_shadowed(&open(dir, *configure));
+        }
+
+        // the filter of the generation holds the deleted keys
+        let file = fs
+            .open(&std::path::Path::new("./test_tmp_tombstone_only_bloom").join("bloom_2"))
+            .unwrap();
+        let mut bytes = vec![];
+        HandleReader::new(file.as_ref())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let filter = BloomFilter::from_bytes(&bytes).unwrap();
+        deleted
+            .iter()
+            .for_each(|i| assert!(filter.contains(&key(*i))));
+    }
+
+    #[test]
+    fn test_lazy_sources_of_ranges() {
+        on_each_file_system(|fs| {
+            let dir = "./test_tmp_lazy_sources_of_ranges";
+            let key = |i: usize| format!("key-{:03}", i);
+            let ranges = [
+                (key(31), Some(key(65))),
+                (key(29), Some(key(30))),
+                (key(59), None),
+                ("key-1".to_string(), None),
+                (String::new(), None),
+            ];
+            for numeric in [false, true].iter() {
+                let mut disktable = if *numeric {
+                    builder(dir, &fs)
+                        .comparator(crate::sst::comparator::Numeric)
+                        .build()
+                        .unwrap()
+                } else {
+                    builder(dir, &fs).build().unwrap()
+                };
+                disktable.clear().unwrap();
+                // restart points of the index fall every 30 entries
+                disktable
+                    .flush(MemtableEntries {
+                        entries: (0..100)
+                            .filter(|i| *i != 40)
+                            .map(|i| (key(i), format!("v{}", i)))
+                            .collect(),
+                        tombstones: vec![key(40)].into_iter().collect(),
+                    })
+                    .unwrap();
+                let keys_in = |disktable: &FileDisktable, start: &str, end: Option<&str>| {
+                    let mut keys = vec![];
+                    for source in disktable.lazy_sources(start, end).unwrap() {
+                        for record in source {
+                            keys.push(record.unwrap().0);
+                        }
+                    }
+                    keys
+                };
+                for warmed in [false, true].iter() {
+                    if *warmed {
+                        disktable.warm(&WarmConfig::default()).unwrap();
+                    }
+                    for (start, end) in ranges.iter() {
+                        let expected: Vec<String> = (0..100)
+                            .map(key)
+                            .filter(|key| key >= start && end.as_ref().is_none_or(|end| key < end))
+                            .collect();
+                        assert_eq!(
+                            keys_in(&disktable, start, end.as_deref()),
+                            expected,
+                            "{} {} {:?}",
+                            numeric,
+                            start,
+                            end
+                        );
+                    }
+                }
+                disktable.clear().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_audit_before_commit() {
+        let dir = "./test_tmp_audit_before_commit";
+        let fs: SharedFileSystem = Arc::new(MemFileSystem::default());
+        let mut disktable = builder(dir, &fs)
+            .manifest(true)
+            .compaction_audit_log("./test_tmp_audit_before_commit_missing/audit_log")
+            .build()
+            .unwrap();
+        (0..2).for_each(|round| {
+            disktable
+                .flush(MemtableEntries {
+                    entries: vec![("key".to_string(), format!("v{}", round))]
+                        .into_iter()
+                        .collect(),
+                    tombstones: BTreeSet::new(),
+                })
+                .unwrap();
+        });
+        // no compaction is committed without its report in the log
+        assert!(disktable.compact(None).is_err());
+        let reopened = builder(dir, &fs).manifest(true).build().unwrap();
+        assert_eq!(
+            reopened
+                .generations()
+                .unwrap()
+                .iter()
+                .map(|info| info.data_gen)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(reopened.find("key").unwrap(), Some("v1".to_string()));
+    }
+
+    #[test]
+    fn test_open_without_write_probe() {
+        let dir = "./test_tmp_open_without_write_probe";
+        let denying = Arc::new(DenyingRemoval {
+            fs: MemFileSystem::default(),
+            denied: std::path::Path::new(dir).join("write_probe"),
+            deny: std::sync::atomic::AtomicBool::new(true),
+        });
+        let fs: SharedFileSystem = denying.clone();
+        // nothing is written into the directory on open unless something writes on open
+        let mut disktable = builder(dir, &fs).build().unwrap();
+        assert!(!disktable.read_only());
+        disktable
+            .flush(MemtableEntries {
+                entries: vec![("key".to_string(), "value".to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            })
+            .unwrap();
+        assert!(builder(dir, &fs).read_only(false).build().is_err());
+        assert!(builder(dir, &fs).writes_on_open().build().is_err());
+
+        // the first write to a read-only filesystem fails, whether it's found on open
+        // or not
+        denying.fs.set_read_only(true);
+        let mut reader = builder(dir, &fs).build().unwrap();
+        assert_eq!(reader.find("key").unwrap(), Some("value".to_string()));
+        let err = reader
+            .flush(MemtableEntries {
+                entries: BTreeMap::new(),
+                tombstones: vec!["key".to_string()].into_iter().collect(),
+            })
+            .unwrap_err();
+        assert!(file_system::denies_writes(&err), "{:?}", err);
+        assert!(builder(dir, &fs)
+            .writes_on_open()
+            .build()
+            .unwrap()
+            .read_only());
+    }
+
+    #[test]
+    fn test_refresh_of_replaced_generation() {
+        let dir = "./test_tmp_refresh_of_replaced_generation";
+        let fs: SharedFileSystem = Arc::new(MemFileSystem::default());
+        let flush = |dir: &str, value: &str| {
+            let mut disktable = builder(dir, &fs).build().unwrap();
+            disktable.clear().unwrap();
+            let entries = MemtableEntries {
+                entries: vec![("key".to_string(), value.to_string())]
+                    .into_iter()
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            };
+            disktable.flush(entries).unwrap();
+        };
+        flush(dir, "old");
+        let mut reader = builder(dir, &fs)
+            .read_only(true)
+            .value_cache(1024)
+            .build()
+            .unwrap();
+        assert_eq!(reader.find("key").unwrap(), Some("old".to_string()));
+
+        // files of the same size written over the generation, as a rewrite in place would
+        let other = "./test_tmp_refresh_of_replaced_generation_other";
+        flush(other, "new");
+        let (dir_path, other_path) = (std::path::Path::new(dir), std::path::Path::new(other));
+        for entry in fs.read_dir(other_path).unwrap() {
+            fs.rename(&other_path.join(&entry.name), &dir_path.join(&entry.name))
+                .unwrap();
+        }
+        assert_eq!(reader.refresh().unwrap(), vec![1]);
+        assert_eq!(reader.find("key").unwrap(), Some("new".to_string()));
+        assert!(reader.refresh().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sources_seek_to_start() {
+        let dir = "./test_tmp_sources_seek_to_start";
+        let fs = MemFileSystem::default();
+        let mut disktable = FileDisktableBuilder::new(dir)
+            .file_system(fs.clone())
+            .build()
+            .unwrap();
+        disktable
+            .flush(MemtableEntries {
+                entries: (0..50)
+                    .map(|i| (format!("key-{:02}", i), format!("value-{}", i)))
+                    .collect(),
+                tombstones: BTreeSet::new(),
+            })
+            .unwrap();
+        // the size of the first record runs past the end of the file, which only a scan from
+        // it reads
+        let path = DataFile::path(dir, 1);
+        let shared: SharedFileSystem = Arc::new(fs.clone());
+        let mut bytes = read_bytes(&DataFile::open(&shared, dir, 1).unwrap());
+        let size = byte_utils::ByteUtils::from_usize(bytes.len() + 1);
+        bytes[..4].copy_from_slice(&size);
+        fs.create(&path).unwrap().write(&bytes).unwrap();
+        let scan = |start: &str, end: Option<&str>| -> io::Result<Vec<crate::sst::scan::Record>> {
+            disktable
+                .sources(start, end)?
+                .into_iter()
+                .flatten()
+                .collect()
+        };
+        assert!(!scan("", None).is_ok_and(|scanned| scanned.len() == 50));
+        let scanned = scan("key-30", Some("key-40")).unwrap();
+        assert_eq!(scanned.len(), 10);
+        assert_eq!(scanned[0].0, "key-30");
+        assert_eq!(scan("key-45", None).unwrap().len(), 5);
+        assert!(scan("key-99", None).unwrap().is_empty());
     }
 }
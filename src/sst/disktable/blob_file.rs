@@ -0,0 +1,271 @@
+use super::{
+    byte_utils::ByteUtils,
+    file_system::{FileHandle, OpenedFile, SharedFileSystem},
+    Offset,
+};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+/// Where a value separated from its data file lives, i.e. `len` bytes at `offset`
+/// of the blob file `file_id`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BlobRef {
+    pub file_id: usize,
+    pub offset: Offset,
+    pub len: usize,
+}
+
+impl BlobRef {
+    // file id + offset + len
+    pub const LEN: usize = 16;
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        [
+            &ByteUtils::from_usize(self.file_id)[..],
+            &ByteUtils::from_u64(self.offset),
+            &ByteUtils::from_usize(self.len),
+        ]
+        .concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<BlobRef> {
+        if bytes.len() < Self::LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("truncated blob reference: {:?}", bytes),
+            ));
+        }
+        Ok(BlobRef {
+            file_id: ByteUtils::as_usize(&bytes[0..4]),
+            offset: ByteUtils::as_u64(&bytes[4..12]),
+            len: ByteUtils::as_usize(&bytes[12..16]),
+        })
+    }
+}
+
+/*
+File Layout:
+[value][value]... of values appended one after another without any framing,
+as the data file entries referencing them hold their offsets and lengths.
+*/
+// blob files of a disktable, which are appended to by writers of data files and never
+// rewritten, so compactions copy references to values in them rather than the values
+pub(crate) struct BlobFiles {
+    fs: SharedFileSystem,
+    dir: String,
+    next_id: AtomicUsize,
+    // handles of the files read from, opened on the first read
+    opened: RwLock<HashMap<usize, Arc<dyn FileHandle>>>,
+    pins: Mutex<Pins>,
+}
+
+// readers holding blob files as of when they started, and the files reclaimed since
+#[derive(Default)]
+struct Pins {
+    count: usize,
+    retired: Vec<usize>,
+}
+
+/// Keeps blob files a reader may still reference from being removed, as its data files
+/// may be older than the ones a garbage collection looked at. Files retired while any pin
+/// is held are removed once the last one is dropped.
+pub(crate) struct BlobPin {
+    blob_files: Arc<BlobFiles>,
+}
+
+impl Drop for BlobPin {
+    fn drop(&mut self) {
+        self.blob_files.unpin();
+    }
+}
+
+impl BlobFiles {
+    const FILE_NAME_PREFIX: &'static str = "blob";
+
+    pub fn open(fs: SharedFileSystem, dir: &str) -> io::Result<BlobFiles> {
+        let next_id = Self::file_ids(&fs, dir)?
+            .into_iter()
+            .max()
+            .map_or(0, |id| id + 1);
+        Ok(BlobFiles {
+            fs,
+            dir: dir.to_string(),
+            next_id: AtomicUsize::new(next_id),
+            opened: RwLock::new(HashMap::new()),
+            pins: Mutex::new(Pins::default()),
+        })
+    }
+
+    fn file_name(file_id: usize) -> String {
+        format!("{}_{}", Self::FILE_NAME_PREFIX, file_id)
+    }
+
+    pub fn path(&self, file_id: usize) -> PathBuf {
+        Path::new(&self.dir).join(Self::file_name(file_id))
+    }
+
+    /// Ids of the blob files in the directory, other than the ones waiting for pins to go.
+    pub fn live_file_ids(&self) -> io::Result<Vec<usize>> {
+        let pins = self.pins.lock().unwrap();
+        let mut file_ids = Self::file_ids(&self.fs, &self.dir)?;
+        file_ids.retain(|file_id| !pins.retired.contains(file_id));
+        file_ids.sort_unstable();
+        Ok(file_ids)
+    }
+
+    // ids of the blob files in the directory
+    fn file_ids(fs: &SharedFileSystem, dir: &str) -> io::Result<Vec<usize>> {
+        let entries = match fs.read_dir(Path::new(dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let prefix = format!("{}_", Self::FILE_NAME_PREFIX);
+        Ok(entries
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .filter_map(|entry| entry.name.to_str()?.strip_prefix(&prefix)?.parse().ok())
+            .collect())
+    }
+
+    /// A new blob file to append values to, with its id.
+    pub fn create(&self) -> io::Result<(usize, OpenedFile)> {
+        let file_id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let file = OpenedFile::create(self.fs.as_ref(), &self.dir, &Self::file_name(file_id))?;
+        Ok((file_id, file))
+    }
+
+    /// The bytes of the value the reference points at, `InvalidData` if the file is shorter.
+    pub fn read(&self, blob: BlobRef) -> io::Result<Vec<u8>> {
+        self.read_range(blob, 0, blob.len)
+    }
+
+    // bytes in [value_offset, value_offset + len) of the value, clamped to its end
+    pub fn read_range(
+        &self,
+        blob: BlobRef,
+        value_offset: usize,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let start = value_offset.min(blob.len);
+        let end = value_offset.saturating_add(len).min(blob.len);
+        let file = self.handle(blob.file_id)?;
+        let mut bytes = vec![0u8; end - start];
+        let mut read = 0;
+        while read < bytes.len() {
+            let offset = blob.offset + (start + read) as Offset;
+            match file.read_at(offset, &mut bytes[read..])? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("blob file is shorter than the reference: {:?}", blob),
+                    ))
+                }
+                n => read += n,
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn size(&self, file_id: usize) -> io::Result<u64> {
+        self.handle(file_id)?.size()
+    }
+
+    fn handle(&self, file_id: usize) -> io::Result<Arc<dyn FileHandle>> {
+        if let Some(handle) = self.opened.read().unwrap().get(&file_id) {
+            return Ok(handle.clone());
+        }
+        let handle: Arc<dyn FileHandle> = Arc::from(self.fs.open(&self.path(file_id))?);
+        self.opened.write().unwrap().insert(file_id, handle.clone());
+        Ok(handle)
+    }
+
+    /// Remove the blob file, e.g. the one of a writer which is aborted.
+    pub fn remove(&self, file_id: usize) -> io::Result<()> {
+        self.opened.write().unwrap().remove(&file_id);
+        self.fs.remove(&self.path(file_id))
+    }
+
+    /// Remove every blob file, and number new ones from 0 again.
+    pub fn clear(&self) -> io::Result<()> {
+        // retired files are gone as well, and their ids are taken by new files
+        self.pins.lock().unwrap().retired.clear();
+        for file_id in Self::file_ids(&self.fs, &self.dir)? {
+            self.remove(file_id)?;
+        }
+        self.next_id.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Copy the values into a new blob file, returning where each of them has moved
+    /// by the file id and offset it's been at.
+    pub fn relocate(
+        &self,
+        blobs: &[BlobRef],
+        sync: bool,
+    ) -> io::Result<HashMap<(usize, Offset), BlobRef>> {
+        let (file_id, file) = self.create()?;
+        let mut relocated = HashMap::new();
+        let mut offset = 0;
+        let copied = blobs.iter().try_for_each(|blob| {
+            file.handle.write(&self.read(*blob)?)?;
+            let moved = BlobRef {
+                file_id,
+                offset,
+                len: blob.len,
+            };
+            relocated.insert((blob.file_id, blob.offset), moved);
+            offset += blob.len as Offset;
+            Ok(())
+        });
+        if let Err(err) = copied.and_then(|_| if sync { file.handle.sync() } else { Ok(()) }) {
+            drop(file);
+            self.remove(file_id)?;
+            return Err(err);
+        }
+        Ok(relocated)
+    }
+
+    pub fn pin(self: &Arc<Self>) -> BlobPin {
+        self.pins.lock().unwrap().count += 1;
+        BlobPin {
+            blob_files: self.clone(),
+        }
+    }
+
+    /// Remove a blob file no data file references any more, or once no pin is held.
+    /// Returns whether it's removed already.
+    pub fn retire(&self, file_id: usize) -> io::Result<bool> {
+        let mut pins = self.pins.lock().unwrap();
+        if pins.count > 0 {
+            pins.retired.push(file_id);
+            return Ok(false);
+        }
+        self.remove(file_id)?;
+        Ok(true)
+    }
+
+    fn unpin(&self) {
+        let mut pins = self.pins.lock().unwrap();
+        pins.count -= 1;
+        if pins.count > 0 {
+            return;
+        }
+        for file_id in std::mem::take(&mut pins.retired) {
+            if let Err(err) = self.remove(file_id) {
+                log::error!(
+                    "failed to remove a retired blob file. file_id: {}, err: {:?}",
+                    file_id,
+                    err
+                );
+            }
+        }
+    }
+}
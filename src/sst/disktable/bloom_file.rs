@@ -0,0 +1,161 @@
+use super::{
+    file_system::{HandleReader, OpenedFile, SharedFileSystem},
+    manifest::KeyRange,
+    partitioned_bloom::PartitionedBloom,
+    DataGen,
+};
+use crate::sst::{bloom::BloomFilter, comparator::KeyComparator};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+// bloom filters of the keys of generations, each sized for the keys of its generation.
+// a file holds `BloomFilter::to_bytes`, whose header carries the hash and bit counts
+// it was built with, so it's read back without knowing the rate it was sized for
+pub(crate) struct BloomFiles {
+    fs: SharedFileSystem,
+    dir: String,
+    // None for a generation whose bloom file is missing or unreadable
+    filters: RwLock<HashMap<DataGen, Option<Arc<BloomFilter>>>>,
+    // key ranges of generations, recorded as they're written or read from their indexes
+    key_ranges: RwLock<HashMap<DataGen, KeyRange>>,
+    // the filters of the generations last looked up partitioned by their key ranges,
+    // dropped as any filter or range changes
+    partitioned: RwLock<Option<(Vec<DataGen>, Arc<PartitionedBloom>)>>,
+}
+
+impl BloomFiles {
+    const FILE_NAME_PREFIX: &'static str = "bloom";
+
+    pub fn new(fs: SharedFileSystem, dir: &str) -> BloomFiles {
+        BloomFiles {
+            fs,
+            dir: dir.to_string(),
+            filters: RwLock::new(HashMap::new()),
+            key_ranges: RwLock::new(HashMap::new()),
+            partitioned: RwLock::new(None),
+        }
+    }
+
+    fn file_name(data_gen: DataGen) -> String {
+        format!("{}_{}", Self::FILE_NAME_PREFIX, data_gen)
+    }
+
+    /// A filter of the keys at the rate with as few bits as that many keys need.
+    pub fn sized_for<'a>(keys: impl ExactSizeIterator<Item = &'a String>, fpr: f64) -> BloomFilter {
+        let mut filter = BloomFilter::new(keys.len(), fpr);
+        keys.for_each(|key| filter.insert(key));
+        filter
+    }
+
+    pub fn write(&self, data_gen: DataGen, filter: BloomFilter, sync: bool) -> io::Result<()> {
+        let file = OpenedFile::create(self.fs.as_ref(), &self.dir, &Self::file_name(data_gen))?;
+        file.handle.write(&filter.to_bytes())?;
+        if sync {
+            file.handle.sync()?;
+        }
+        self.filters
+            .write()
+            .unwrap()
+            .insert(data_gen, Some(Arc::new(filter)));
+        self.partitioned.write().unwrap().take();
+        Ok(())
+    }
+
+    pub fn record_range(&self, data_gen: DataGen, key_range: KeyRange) {
+        self.key_ranges.write().unwrap().insert(data_gen, key_range);
+        self.partitioned.write().unwrap().take();
+    }
+
+    /// The filters of the generations partitioned by their key ranges. A range which isn't
+    /// recorded is taken from `key_range`, which is None for one that can't be found.
+    pub fn partitioned(
+        &self,
+        data_gens: &[DataGen],
+        comparator: &dyn KeyComparator,
+        key_range: impl Fn(DataGen) -> Option<KeyRange>,
+    ) -> Arc<PartitionedBloom> {
+        if let Some((partitioned_gens, partitioned)) = self.partitioned.read().unwrap().as_ref() {
+            if partitioned_gens.as_slice() == data_gens {
+                return partitioned.clone();
+            }
+        }
+        let generations = data_gens
+            .iter()
+            .map(|data_gen| {
+                let recorded = self.key_ranges.read().unwrap().get(data_gen).cloned();
+                let range = recorded.or_else(|| {
+                    let found = key_range(*data_gen)?;
+                    self.key_ranges
+                        .write()
+                        .unwrap()
+                        .insert(*data_gen, found.clone());
+                    Some(found)
+                });
+                (*data_gen, self.get(*data_gen), range)
+            })
+            .collect();
+        let partitioned = Arc::new(PartitionedBloom::new(generations, comparator));
+        *self.partitioned.write().unwrap() = Some((data_gens.to_vec(), partitioned.clone()));
+        partitioned
+    }
+
+    /// None if the generation has no filter, e.g. it was written without one.
+    pub fn get(&self, data_gen: DataGen) -> Option<Arc<BloomFilter>> {
+        if let Some(filter) = self.filters.read().unwrap().get(&data_gen) {
+            return filter.clone();
+        }
+        let filter = self.read(data_gen).map(Arc::new);
+        self.filters
+            .write()
+            .unwrap()
+            .insert(data_gen, filter.clone());
+        filter
+    }
+
+    fn read(&self, data_gen: DataGen) -> Option<BloomFilter> {
+        let path = Path::new(&self.dir).join(Self::file_name(data_gen));
+        let read = self.fs.open(&path).and_then(|file| {
+            let mut bytes = vec![];
+            HandleReader::new(file.as_ref()).read_to_end(&mut bytes)?;
+            BloomFilter::from_bytes(&bytes)
+        });
+        match read {
+            Ok(filter) => Some(filter),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                log::warn!(
+                    "ignored an unreadable bloom filter. data_gen: {}, err: {:?}",
+                    data_gen,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Remove the filter of the generation if it has one.
+    pub fn remove(&self, data_gen: DataGen) -> io::Result<()> {
+        self.evict(data_gen);
+        let path = Path::new(&self.dir).join(Self::file_name(data_gen));
+        match self.fs.remove(&path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            removed => removed,
+        }
+    }
+
+    pub fn evict(&self, data_gen: DataGen) {
+        self.filters.write().unwrap().remove(&data_gen);
+        self.key_ranges.write().unwrap().remove(&data_gen);
+        self.partitioned.write().unwrap().take();
+    }
+
+    pub fn clear(&self) {
+        self.filters.write().unwrap().clear();
+        self.key_ranges.write().unwrap().clear();
+        self.partitioned.write().unwrap().take();
+    }
+}
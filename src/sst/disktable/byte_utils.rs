@@ -1,4 +1,7 @@
-use std::convert::TryInto;
+use std::{
+    convert::TryInto,
+    io::{self, Read},
+};
 
 pub(crate) struct ByteUtils;
 impl ByteUtils {
@@ -6,7 +9,10 @@ impl ByteUtils {
         u32::from_le_bytes(array.try_into().unwrap()) as usize
     }
     pub fn as_u64(array: &[u8]) -> u64 {
-        u64::from_le_bytes(array.try_into().unwrap()) as u64
+        u64::from_le_bytes(array.try_into().unwrap())
+    }
+    pub fn as_i32(array: &[u8]) -> i32 {
+        i32::from_le_bytes(array.try_into().unwrap())
     }
     pub fn as_string(array: &[u8]) -> String {
         std::str::from_utf8(array).unwrap().to_string()
@@ -14,7 +20,18 @@ impl ByteUtils {
     pub fn from_usize(n: usize) -> [u8; 4] {
         (n as u32).to_le_bytes()
     }
+    pub fn from_i32(n: i32) -> [u8; 4] {
+        n.to_le_bytes()
+    }
     pub fn from_u64(n: u64) -> [u8; 8] {
-        (n as u64).to_le_bytes()
+        n.to_le_bytes()
+    }
+    // false if the reader has reached EOF before filling the buffer
+    pub fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+        match reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
     }
 }
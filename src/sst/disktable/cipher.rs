@@ -0,0 +1,50 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use std::io;
+
+/// Seals records of data files with ChaCha20-Poly1305 under a key given at open time.
+/// A sealed record is a random nonce followed by the ciphertext and its tag,
+/// so a record read with a wrong key or altered on disk fails to open.
+pub(crate) struct DataCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl DataCipher {
+    const NONCE_LEN: usize = 12;
+    // bytes a sealed record takes in addition to the plaintext, i.e. the nonce and the tag
+    pub const OVERHEAD: usize = Self::NONCE_LEN + 16;
+
+    pub fn new(key: &[u8; 32]) -> DataCipher {
+        DataCipher {
+            cipher: ChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| io::Error::other("failed to encrypt a record"))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    // Err(InvalidData) if the tag doesn't match, e.g. for a wrong key
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "failed to decrypt a record, the key is wrong or the record is corrupted",
+            )
+        };
+        if sealed.len() < Self::OVERHEAD {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = sealed.split_at(Self::NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid())
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use zstd::{
+    bulk::{Compressor, Decompressor},
+    dict::DecoderDictionary,
+};
+
+/// How values of new generations are compressed with zstd. A record tells whether its
+/// value is compressed, so data files are read the same whether or not it's set.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ValueCompression {
+    pub level: i32,
+    // Some(max bytes) of a dictionary trained for every generation from a sample of its values
+    pub dictionary_bytes: Option<usize>,
+}
+
+impl ValueCompression {
+    // bytes of values sampled for every byte of a dictionary, as zstd suggests about 100
+    const SAMPLE_RATIO: usize = 100;
+
+    // bytes of values a writer holds back to train a dictionary on
+    pub fn sample_bytes(&self) -> Option<usize> {
+        self.dictionary_bytes
+            .map(|bytes| bytes.saturating_mul(Self::SAMPLE_RATIO))
+    }
+
+    // a dictionary of the samples, None if they're too few or too alike to train one
+    pub fn train(&self, samples: &[&[u8]]) -> Option<Vec<u8>> {
+        let max_bytes = self.dictionary_bytes?;
+        match zstd::dict::from_samples(samples, max_bytes) {
+            Ok(dictionary) => Some(dictionary),
+            Err(err) => {
+                log::debug!(
+                    "compressed values without a dictionary. samples: {}, err: {:?}",
+                    samples.len(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn compressor(&self, dictionary: Option<&[u8]>) -> io::Result<Compressor<'static>> {
+        match dictionary {
+            Some(dictionary) => Compressor::with_dictionary(self.level, dictionary),
+            None => Compressor::new(self.level),
+        }
+    }
+}
+
+// id of a dictionary, which the frames compressed with it carry, 0 for none
+pub(crate) fn dictionary_id(dictionary: &[u8]) -> u32 {
+    zstd::zstd_safe::get_dict_id_from_dict(dictionary).map_or(0, |id| id.get())
+}
+
+// id of the dictionary the frame was compressed with, 0 for none
+pub(crate) fn frame_dictionary_id(frame: &[u8]) -> u32 {
+    zstd::zstd_safe::get_dict_id_from_frame(frame).map_or(0, |id| id.get())
+}
+
+// dictionaries of data files by their paths, loaded once from the header of each file.
+// each is kept with its id, so that one left by a data file rewritten in place isn't used
+// for the frames of the new one
+#[derive(Default)]
+pub(crate) struct Dictionaries {
+    loaded: RwLock<HashMap<PathBuf, (u32, Arc<DecoderDictionary<'static>>)>>,
+}
+
+impl Dictionaries {
+    // the dictionary of the id the data file was loaded with, or the one `load` reads
+    pub fn get(
+        &self,
+        path: &Path,
+        id: u32,
+        load: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<DecoderDictionary<'static>>> {
+        if let Some((loaded_id, dictionary)) = self.loaded.read().unwrap().get(path) {
+            if *loaded_id == id {
+                return Ok(dictionary.clone());
+            }
+        }
+        let bytes = load()?;
+        if dictionary_id(&bytes) != id {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} has no dictionary of id {}", path, id),
+            ));
+        }
+        let dictionary = Arc::new(DecoderDictionary::copy(&bytes));
+        self.loaded
+            .write()
+            .unwrap()
+            .insert(path.to_path_buf(), (id, dictionary.clone()));
+        Ok(dictionary)
+    }
+
+    pub fn evict(&self, path: &Path) {
+        self.loaded.write().unwrap().remove(path);
+    }
+
+    pub fn clear(&self) {
+        self.loaded.write().unwrap().clear();
+    }
+}
+
+pub(crate) fn decompress(
+    frame: &[u8],
+    len: usize,
+    dictionary: Option<&DecoderDictionary<'static>>,
+) -> io::Result<Vec<u8>> {
+    let mut decompressor = match dictionary {
+        Some(dictionary) => Decompressor::with_prepared_dictionary(dictionary)?,
+        None => Decompressor::new()?,
+    };
+    let value = decompressor
+        .decompress(frame, len)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if value.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "a value decompressed into {} bytes, not {}",
+                value.len(),
+                len
+            ),
+        ));
+    }
+    Ok(value)
+}
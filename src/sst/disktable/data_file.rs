@@ -1,119 +1,1378 @@
 use super::*;
-use crate::sst::rich_file::*;
+use crate::sst::comparator::KeyComparator;
+use blob_file::{BlobFiles, BlobPin, BlobRef};
 use byte_utils::*;
+#[cfg(feature = "encryption")]
+use cipher::DataCipher;
+#[cfg(feature = "compression")]
+use compression::{Dictionaries, ValueCompression};
+use file_system::{HandleWriter, OpenedFile, SharedFileSystem};
+use index_file::{EntryKind, IndexEntry};
 use io::{BufWriter, Read, Seek, SeekFrom, Write};
-use std::slice::SliceIndex;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How data files are written and read, which has to be the same for both.
+#[derive(Clone, Default)]
+pub(crate) struct DataFileOptions {
+    // Some when entries are packed into blocks of the size and read by whole blocks
+    pub block_size: Option<usize>,
+    // Some when records are sealed by the cipher
+    #[cfg(feature = "encryption")]
+    pub cipher: Option<Arc<DataCipher>>,
+    // Some when values shorter than it are inlined in index entries as well
+    pub inline_threshold: Option<usize>,
+    // Some when values longer than it are written into blob files
+    pub blob_threshold: Option<usize>,
+    // blob files values referenced by entries are read from
+    pub blob_files: Option<Arc<BlobFiles>>,
+    // true when entries carry the time they were written in their header
+    pub entry_timestamps: bool,
+    // true when empty values are written as existence records, which index entries
+    // resolve without a read of the data file
+    pub existence_records: bool,
+    // Some when values are written compressed
+    #[cfg(feature = "compression")]
+    pub compression: Option<ValueCompression>,
+    // dictionaries of the data files compressed values are read from
+    #[cfg(feature = "compression")]
+    pub dictionaries: Arc<Dictionaries>,
+}
+
+impl DataFileOptions {
+    // bytes a record takes on disk in addition to its plaintext
+    fn overhead(&self) -> usize {
+        #[cfg(feature = "encryption")]
+        if self.cipher.is_some() {
+            return DataCipher::OVERHEAD;
+        }
+        0
+    }
+
+    // the bit set in the key length of the records written, telling the ones carrying a
+    // timestamp
+    fn timestamped(&self) -> usize {
+        if self.entry_timestamps {
+            DataFile::TIMESTAMPED
+        } else {
+            0
+        }
+    }
+
+    fn seal(&self, plaintext: Vec<u8>) -> io::Result<Vec<u8>> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.seal(&plaintext);
+        }
+        Ok(plaintext)
+    }
+
+    fn open(&self, sealed: Vec<u8>) -> io::Result<Vec<u8>> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.open(&sealed);
+        }
+        Ok(sealed)
+    }
+
+    fn blob_files(&self, blob: BlobRef) -> io::Result<&BlobFiles> {
+        self.blob_files.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no blob files to read a blob from: {:?}", blob),
+            )
+        })
+    }
+
+    fn read_blob(&self, blob: BlobRef) -> io::Result<Vec<u8>> {
+        self.blob_files(blob)?.read(blob)
+    }
+
+    fn read_blob_range(
+        &self,
+        blob: BlobRef,
+        value_offset: usize,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        self.blob_files(blob)?.read_range(blob, value_offset, len)
+    }
+}
+
+/// A value as it's stored in a data file, which compactions copy without reading blobs,
+/// along with the timestamp of its entry, which is kept as well.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StoredValue {
+    Plain(String, Option<u64>),
+    Blob(BlobRef, Option<u64>),
+}
+
+impl StoredValue {
+    // bytes of the value as a compaction copies it, which is the reference for a blob
+    pub fn len(&self) -> usize {
+        match self {
+            StoredValue::Plain(value, _) => value.len(),
+            StoredValue::Blob(..) => BlobRef::LEN,
+        }
+    }
+}
 
 pub(crate) struct DataFile {
-    pub data_gen: DataGen,
-    pub file: RichFile,
+    pub file: OpenedFile,
+    options: DataFileOptions,
+    // Some while blob files the data file references are kept from being removed
+    _blob_pin: Option<BlobPin>,
 }
 pub(crate) struct DataEntry {
-    pub data_gen: DataGen,
     pub offset: Offset,
     pub size: usize,
-    pub key_len: usize,
-    pub value_len: usize,
     pub key: String,
-    pub value: String,
+    // None means the key was deleted(tombstone), or its value is in a blob left unread
+    pub value: Option<String>,
+    // Some when the value is in a blob file
+    pub blob: Option<BlobRef>,
+    // nanoseconds since the unix epoch when the entry was written, if entries carry them
+    pub timestamp: Option<u64>,
+}
+
+impl DataEntry {
+    // the key and the value as it's stored, None for a tombstone
+    pub fn into_stored(self) -> (String, Option<StoredValue>) {
+        let timestamp = self.timestamp;
+        let value = match (self.blob, self.value) {
+            (Some(blob), _) => Some(StoredValue::Blob(blob, timestamp)),
+            (None, value) => value.map(|value| StoredValue::Plain(value, timestamp)),
+        };
+        (self.key, value)
+    }
+
+    pub fn next_offset(&self) -> Offset {
+        self.offset + (self.size + 1) as Offset
+    }
+
+    // when the entry was written, None unless entries carry timestamps
+    pub fn written_at(&self) -> Option<SystemTime> {
+        self.timestamp
+            .map(|timestamp| UNIX_EPOCH + Duration::from_nanos(timestamp))
+    }
+}
+
+// the time as a timestamp of entries, 0 for a time before the unix epoch
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
 }
 
 impl DataFile {
     pub const FILE_NAME_PREFIX: &'static str = "data";
+    // temporary file a data file with relocated blob references is written into
+    const RELOCATED_FILE_NAME: &'static str = "tmp_relocated";
+    // value length written for a tombstone instead of an actual length
+    const TOMBSTONE_VALUE_LEN: usize = u32::MAX as usize;
+    // value length written for a padding record which fills the rest of a block
+    const PADDING_VALUE_LEN: usize = u32::MAX as usize - 1;
+    // value length written for a reference to an earlier entry holding the same value
+    const REFERENCE_VALUE_LEN: usize = u32::MAX as usize - 2;
+    // value length written for a reference to a value in a blob file
+    const BLOB_VALUE_LEN: usize = u32::MAX as usize - 3;
+    // value length written for a value compressed by zstd
+    const COMPRESSED_VALUE_LEN: usize = u32::MAX as usize - 4;
+    // value length written for the header holding the dictionary values are compressed with
+    const DICTIONARY_VALUE_LEN: usize = u32::MAX as usize - 5;
+    // entry size + key length + value length + \0 of an empty padding record in plaintext
+    const MIN_PADDING_LEN: usize = 13;
+    // bit of the key length set for a record carrying a timestamp in its header
+    const TIMESTAMPED: usize = 1 << 31;
 
-    pub fn of(dir_name: &str, data_gen: DataGen) -> DataFile {
-        let file = RichFile::open_file(
-            dir_name,
-            format!("{}_{}", DataFile::FILE_NAME_PREFIX, data_gen),
-            FileOption::Append,
-        )
-        .expect("failed to open data file");
+    pub fn of(fs: &SharedFileSystem, dir_name: &str, data_gen: DataGen) -> DataFile {
+        let file = OpenedFile::open_or_create(fs.as_ref(), dir_name, &Self::file_name(data_gen))
+            .expect("failed to open data file");
+
+        DataFile {
+            file,
+            options: DataFileOptions::default(),
+            _blob_pin: None,
+        }
+    }
+
+    // `of` which fails with NotFound rather than creating a missing data file
+    pub fn open(fs: &SharedFileSystem, dir_name: &str, data_gen: DataGen) -> io::Result<DataFile> {
+        Ok(DataFile {
+            file: OpenedFile::open(fs.as_ref(), dir_name, &Self::file_name(data_gen))?,
+            options: DataFileOptions::default(),
+            _blob_pin: None,
+        })
+    }
+
+    // read entries as they're written with the options, see `DataFileWriter::create`
+    pub fn with_options(mut self, options: &DataFileOptions) -> DataFile {
+        self.options = options.clone();
+        self
+    }
+
+    // keep the blob files of the options while the data file is held, so that values it
+    // references stay readable after garbage collections of blobs, see `BlobPin`
+    pub fn pin_blobs(mut self) -> DataFile {
+        self._blob_pin = self.options.blob_files.as_ref().map(BlobFiles::pin);
+        self
+    }
+
+    // read at least the bytes at once and serve the following reads from them,
+    // which suits `entries` reading a record at a time
+    pub fn read_ahead(mut self, bytes: usize) -> DataFile {
+        self.file = self.file.read_ahead(bytes);
+        self
+    }
+
+    pub fn file_name(data_gen: DataGen) -> String {
+        format!("{}_{}", DataFile::FILE_NAME_PREFIX, data_gen)
+    }
 
-        DataFile { data_gen, file }
+    // where the data file of the generation is, whether or not it exists
+    pub fn path(dir_name: &str, data_gen: DataGen) -> PathBuf {
+        Path::new(dir_name).join(Self::file_name(data_gen))
     }
     /*
     Data Layout:
-    [key length][value length][ key data  ][value data ]\0
-    <--4 byte--><--4 byte----><--key_len--><-value_len->
+    [entry size][key length][value length][ key data  ][value data ]\0
+    <--4 byte--><--4 byte--><--4 byte----><--key_len--><-value_len->
+    or [entry size][key length][value length][timestamp][key data][value data]\0 with
+    entry timestamps, where the timestamp is 8 bytes of nanoseconds since the unix epoch
+    and the high bit of key length is set, so that every record tells whether it carries
+    one whatever the setting a file is read with.
+    value length is 0xFFFFFFFF and value data is empty for a tombstone, while an empty value
+    has a value length of 0. key length is never 0 but for a padding record, as keys aren't empty.
+    key length is 0, value length is 0xFFFFFFFE and value data is zeros for a padding record,
+    which fills a block up to its end in a file written with a block size.
+    value length is 0xFFFFFFFD and value data is the 8 byte offset of an earlier entry
+    for a reference, which shares the value of that entry instead of repeating it.
+    value length is 0xFFFFFFFC and value data is [blob file id][offset][length] of
+    4, 8 and 4 bytes for a value written into a blob file.
+    value length is 0xFFFFFFFB and value data is [length][zstd frame] for a value compressed
+    into the frame, where length is 4 bytes of the length of the value.
+    key length is 0, value length is 0xFFFFFFFA and value data is a zstd dictionary for
+    the header of a file whose values are compressed with it, which is its first record
+    and is skipped as a padding record is.
+    with a cipher, everything between entry size and \0 is sealed as a nonce,
+    the ciphertext and its tag.
     */
-    pub fn read_entry(&self, offset: Offset) -> Option<DataEntry> {
-        let mut data = &self.file.underlying;
-        data.seek(SeekFrom::Start(offset)).unwrap();
-        let mut size: [u8; 4] = [0; 4];
-        let res = data.read_exact(&mut size);
-        if res.is_err() {
-            return None;
+    // the entry at offset, or the first entry after it if a padding record sits at offset
+    pub fn read_entry(&self, offset: Offset) -> io::Result<Option<DataEntry>> {
+        self.read_entry_with(offset, true)
+    }
+
+    fn read_entry_with(
+        &self,
+        mut offset: Offset,
+        read_blobs: bool,
+    ) -> io::Result<Option<DataEntry>> {
+        loop {
+            let (size, bytes) = match self.read_record(offset)? {
+                Some((size, sealed)) => (size, self.options.open(sealed)?),
+                None => return Ok(None),
+            };
+            let value_len = bytes.get(4..8).map(ByteUtils::as_usize);
+            if value_len == Some(Self::PADDING_VALUE_LEN)
+                || value_len == Some(Self::DICTIONARY_VALUE_LEN)
+            {
+                offset += (size + 1) as Offset;
+                continue;
+            }
+            let (key_len, header_len, timestamp) = Self::record_header(offset, &bytes)?;
+            let key_end = header_len + key_len;
+            if value_len == Some(Self::COMPRESSED_VALUE_LEN) {
+                let value = self.decompress(&bytes[key_end..])?;
+                return Ok(Some(DataEntry {
+                    offset,
+                    size,
+                    key: ByteUtils::as_string(&bytes[header_len..key_end]),
+                    value: Some(ByteUtils::as_string(&value)),
+                    blob: None,
+                    timestamp,
+                }));
+            }
+            if value_len == Some(Self::REFERENCE_VALUE_LEN) {
+                let target = bytes
+                    .get(key_end..(key_end + 8))
+                    .ok_or_else(|| Self::malformed(offset))?;
+                let target = ByteUtils::as_u64(target);
+                let (value, blob) = match self.read_entry_with(target, read_blobs)? {
+                    Some(entry) => (entry.value, entry.blob),
+                    None => (None, None),
+                };
+                return Ok(Some(DataEntry {
+                    offset,
+                    size,
+                    key: ByteUtils::as_string(&bytes[header_len..key_end]),
+                    value,
+                    blob,
+                    timestamp,
+                }));
+            }
+            if value_len == Some(Self::BLOB_VALUE_LEN) {
+                let blob = BlobRef::from_bytes(&bytes[key_end..])?;
+                let value = if read_blobs {
+                    Some(ByteUtils::as_string(&self.options.read_blob(blob)?))
+                } else {
+                    None
+                };
+                return Ok(Some(DataEntry {
+                    offset,
+                    size,
+                    key: ByteUtils::as_string(&bytes[header_len..key_end]),
+                    value,
+                    blob: Some(blob),
+                    timestamp,
+                }));
+            }
+            return Self::parse_entry(offset, size, bytes).map(Some);
         }
-        let size = ByteUtils::as_usize(&size);
-        let mut bytes = vec![0u8; size - 4];
-        let res = data.read_exact(&mut bytes);
-        if res.is_err() {
-            return None;
+    }
+
+    // the size of the record at offset and its bytes following the size, which may be sealed
+    fn read_record(&self, offset: Offset) -> io::Result<Option<(usize, Vec<u8>)>> {
+        let mut bytes = Vec::new();
+        Ok(self
+            .read_record_into(offset, &mut bytes)?
+            .map(|size| (size, bytes)))
+    }
+
+    // `read_record` into the buffer, which is overwritten and keeps its capacity
+    fn read_record_into(&self, offset: Offset, bytes: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        bytes.clear();
+        let block_size = match self.options.block_size {
+            Some(block_size) => block_size as Offset,
+            None => {
+                let mut data = self.file.reader();
+                data.seek(SeekFrom::Start(offset))?;
+                let mut size: [u8; 4] = [0; 4];
+                if !ByteUtils::read_exact_or_eof(&mut data, &mut size)? {
+                    return Ok(None);
+                }
+                let size = ByteUtils::as_usize(&size);
+                bytes.resize(size - 4, 0);
+                if !ByteUtils::read_exact_or_eof(&mut data, bytes)? {
+                    return Ok(None);
+                }
+                return Ok(Some(size));
+            }
+        };
+        // read the block holding the head of the record, and the following blocks
+        // if the record runs over the end of it
+        let block_start = offset - offset % block_size;
+        let within = (offset - block_start) as usize;
+        let mut data = self.file.reader();
+        data.seek(SeekFrom::Start(block_start))?;
+        let blocks = bytes;
+        let mut read_blocks_up_to = |blocks: &mut Vec<u8>, end: usize| -> io::Result<bool> {
+            if end > blocks.len() {
+                let rest =
+                    (end as Offset).div_ceil(block_size) * block_size - blocks.len() as Offset;
+                (&mut data).take(rest).read_to_end(blocks)?;
+            }
+            Ok(end <= blocks.len())
+        };
+        // the size of a padding record may sit at the end of a block
+        if !read_blocks_up_to(blocks, within + 4)? {
+            return Ok(None);
+        }
+        let size = ByteUtils::as_usize(&blocks[within..(within + 4)]);
+        let end = within + size;
+        if !read_blocks_up_to(blocks, end)? {
+            return Ok(None);
+        }
+        blocks.truncate(end);
+        blocks.drain(..(within + 4));
+        Ok(Some(size))
+    }
+
+    // the value of the entry at offset into the buffer, which is overwritten and keeps its
+    // capacity rather than a value allocated per read. false, leaving the buffer empty,
+    // unless the entry is a value of the key
+    pub fn read_value_into(&self, offset: Offset, key: &str, buf: &mut String) -> io::Result<bool> {
+        let mut bytes = std::mem::take(buf).into_bytes();
+        let found = self.read_value_bytes(offset, key, &mut bytes);
+        if !matches!(found, Ok(true)) {
+            bytes.clear();
+        }
+        *buf = String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        found
+    }
+
+    fn read_value_bytes(
+        &self,
+        mut offset: Offset,
+        key: &str,
+        bytes: &mut Vec<u8>,
+    ) -> io::Result<bool> {
+        loop {
+            let size = match self.read_record_into(offset, bytes)? {
+                Some(size) => size,
+                None => return Ok(false),
+            };
+            if self.options.overhead() > 0 {
+                *bytes = self.options.open(std::mem::take(bytes))?;
+            }
+            let value_len = match bytes.get(4..8) {
+                Some(value_len) => ByteUtils::as_usize(value_len),
+                None => return Err(Self::malformed(offset)),
+            };
+            if value_len == Self::PADDING_VALUE_LEN || value_len == Self::DICTIONARY_VALUE_LEN {
+                offset += (size + 1) as Offset;
+                continue;
+            }
+            let (key_len, header_len, _) = Self::record_header(offset, bytes)?;
+            let key_end = header_len + key_len;
+            if bytes.get(header_len..key_end) != Some(key.as_bytes())
+                || value_len == Self::TOMBSTONE_VALUE_LEN
+            {
+                return Ok(false);
+            }
+            if value_len == Self::BLOB_VALUE_LEN {
+                let blob = BlobRef::from_bytes(&bytes[key_end..])?;
+                *bytes = self.options.read_blob(blob)?;
+                return Ok(true);
+            }
+            if value_len == Self::COMPRESSED_VALUE_LEN {
+                *bytes = self.decompress(&bytes[key_end..])?;
+                return Ok(true);
+            }
+            if value_len == Self::REFERENCE_VALUE_LEN {
+                let target = bytes
+                    .get(key_end..(key_end + 8))
+                    .ok_or_else(|| Self::malformed(offset))?;
+                let target = ByteUtils::as_u64(target);
+                return match self.read_entry(target)?.and_then(|entry| entry.value) {
+                    Some(value) => {
+                        bytes.clear();
+                        bytes.extend_from_slice(value.as_bytes());
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                };
+            }
+            if bytes.len() < key_end + value_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("truncated value at {}", offset),
+                ));
+            }
+            bytes.truncate(key_end + value_len);
+            bytes.drain(..key_end);
+            return Ok(true);
+        }
+    }
+
+    // the value of [length][zstd frame], decompressed with the dictionary in the header of
+    // the file if the frame was compressed with one
+    #[cfg(feature = "compression")]
+    fn decompress(&self, value_data: &[u8]) -> io::Result<Vec<u8>> {
+        let truncated = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("truncated compressed value in {:?}", self.file.path()),
+            )
+        };
+        let len = ByteUtils::as_usize(value_data.get(0..4).ok_or_else(truncated)?);
+        let frame = &value_data[4..];
+        match compression::frame_dictionary_id(frame) {
+            0 => compression::decompress(frame, len, None),
+            id => {
+                let dictionary = self
+                    .options
+                    .dictionaries
+                    .get(&self.file.path(), id, || self.read_dictionary())?;
+                compression::decompress(frame, len, Some(&dictionary))
+            }
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn decompress(&self, _: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{:?} holds compressed values, which need the compression feature",
+                self.file.path()
+            ),
+        ))
+    }
+
+    // the dictionary in the header of the file
+    #[cfg(feature = "compression")]
+    fn read_dictionary(&self) -> io::Result<Vec<u8>> {
+        let bytes = match self.read_record(0)? {
+            Some((_, sealed)) => self.options.open(sealed)?,
+            None => vec![],
+        };
+        match bytes.get(4..8).map(ByteUtils::as_usize) {
+            Some(Self::DICTIONARY_VALUE_LEN) => {
+                let (_, header_len, _) = Self::record_header(0, &bytes)?;
+                Ok(bytes[header_len..].to_vec())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} has no dictionary header", self.file.path()),
+            )),
+        }
+    }
+
+    fn malformed(offset: Offset) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed record at {}", offset),
+        )
+    }
+
+    // the key length, the length of the header before the key data and the timestamp of
+    // the record whose bytes follow its entry size. fails with InvalidData unless the
+    // header and the key are all there
+    fn record_header(offset: Offset, bytes: &[u8]) -> io::Result<(usize, usize, Option<u64>)> {
+        let key_len = ByteUtils::as_usize(bytes.get(0..4).ok_or_else(|| Self::malformed(offset))?);
+        let (key_len, header_len, timestamp) = if key_len & Self::TIMESTAMPED != 0 {
+            let timestamp = bytes.get(8..16).ok_or_else(|| Self::malformed(offset))?;
+            (
+                key_len & !Self::TIMESTAMPED,
+                16,
+                Some(ByteUtils::as_u64(timestamp)),
+            )
+        } else {
+            (key_len, 8, None)
+        };
+        if bytes.len() < header_len + key_len {
+            return Err(Self::malformed(offset));
         }
-        let expected = |prefix, range| {
-          format!("[{}]failed to read bytes({:?}) for range({:?})", prefix, bytes, range)
-        };
-        let key_len_bytes = bytes.get(0..4).unwrap_or_else(|| panic!(expected("key_len", 0..4)));
-        let key_len = ByteUtils::as_usize(key_len_bytes);
-        let value_len_bytes = bytes.get(4..8).unwrap_or_else(|| panic!(expected("value_len", 4..8)));
-        let value_len = ByteUtils::as_usize(value_len_bytes);
-        let key_data = bytes.get(8..(8 + key_len)).unwrap_or_else(|| panic!(expected("key_data", 8..(8 + key_len))));
-        let value_data = bytes.get((8 + key_len)..(8 + key_len + value_len)).unwrap_or_else(
-          || panic!(expected("value_data", (8 + key_len)..(8 + key_len + value_len)))
-        );
-        Some(DataEntry {
-            data_gen: self.data_gen,
+        Ok((key_len, header_len, timestamp))
+    }
+
+    fn parse_entry(offset: Offset, size: usize, bytes: Vec<u8>) -> io::Result<DataEntry> {
+        let (key_len, header_len, timestamp) = Self::record_header(offset, &bytes)?;
+        let value_len = ByteUtils::as_usize(&bytes[4..8]);
+        let key_end = header_len + key_len;
+        let value = if value_len == Self::TOMBSTONE_VALUE_LEN {
+            None
+        } else {
+            let value_data = bytes
+                .get(key_end..(key_end + value_len))
+                .ok_or_else(|| Self::malformed(offset))?;
+            Some(ByteUtils::as_string(value_data))
+        };
+        Ok(DataEntry {
             offset,
             size,
-            key_len,
-            value_len,
-            key: ByteUtils::as_string(&key_data),
-            value: ByteUtils::as_string(&value_data),
+            key: ByteUtils::as_string(&bytes[header_len..key_end]),
+            value,
+            blob: None,
+            timestamp,
         })
     }
 
-    pub fn create<'a>(
+    // the key and bytes in [value_offset, value_offset + len) of the value of the entry at offset,
+    // clamped to the end of the value. None for a tombstone.
+    pub fn read_value_range(
         &self,
-        memtable_entries: &'a MemtableEntries<String, String>,
-    ) -> io::Result<BTreeMap<&'a String, Offset>> {
+        offset: Offset,
+        value_offset: usize,
+        len: usize,
+    ) -> io::Result<Option<(String, Vec<u8>)>> {
+        if self.options.overhead() > 0 {
+            // the header is sealed together with the value
+            return Ok(self.read_entry(offset)?.and_then(|entry| {
+                let key = entry.key;
+                entry
+                    .value
+                    .map(|value| (key, value_range(&value, value_offset, len)))
+            }));
+        }
+        let mut data = self.file.reader();
+        data.seek(SeekFrom::Start(offset))?;
+        // entry size + key length + value length
+        let mut header: [u8; 12] = [0; 12];
+        if !ByteUtils::read_exact_or_eof(&mut data, &mut header)? {
+            return Ok(None);
+        }
+        let key_len = ByteUtils::as_usize(&header[4..8]);
+        let value_len = ByteUtils::as_usize(&header[8..12]);
+        if value_len == Self::TOMBSTONE_VALUE_LEN {
+            return Ok(None);
+        }
+        if key_len & Self::TIMESTAMPED != 0 {
+            data.seek(SeekFrom::Current(8))?;
+        }
+        let key_len = key_len & !Self::TIMESTAMPED;
+        let mut key_data = vec![0u8; key_len];
+        if !ByteUtils::read_exact_or_eof(&mut data, &mut key_data)? {
+            return Ok(None);
+        }
+        if value_len == Self::REFERENCE_VALUE_LEN {
+            let mut target: [u8; 8] = [0; 8];
+            if !ByteUtils::read_exact_or_eof(&mut data, &mut target)? {
+                return Ok(None);
+            }
+            let read = self.read_value_range(ByteUtils::as_u64(&target), value_offset, len)?;
+            return Ok(read.map(|(_, value)| (ByteUtils::as_string(&key_data), value)));
+        }
+        if value_len == Self::BLOB_VALUE_LEN {
+            let mut blob = [0u8; BlobRef::LEN];
+            if !ByteUtils::read_exact_or_eof(&mut data, &mut blob)? {
+                return Ok(None);
+            }
+            let blob = BlobRef::from_bytes(&blob)?;
+            let value = self.options.read_blob_range(blob, value_offset, len)?;
+            return Ok(Some((ByteUtils::as_string(&key_data), value)));
+        }
+        if value_len == Self::COMPRESSED_VALUE_LEN {
+            // the value is decompressed as a whole
+            return Ok(self.read_entry(offset)?.and_then(|entry| {
+                let key = entry.key;
+                entry
+                    .value
+                    .map(|value| (key, value_range(&value, value_offset, len)))
+            }));
+        }
+        let start = value_offset.min(value_len);
+        let end = value_offset.saturating_add(len).min(value_len);
+        data.seek(SeekFrom::Current(start as i64))?;
+        let mut value_data = vec![0u8; end - start];
+        if !ByteUtils::read_exact_or_eof(&mut data, &mut value_data)? {
+            return Ok(None);
+        }
+        Ok(Some((ByteUtils::as_string(&key_data), value_data)))
+    }
+
+    // iterate entries from the head of the file in written(key) order
+    pub fn entries(self) -> DataFileEntries {
+        DataFileEntries {
+            data_file: self,
+            offset: 0,
+            read_blobs: true,
+            done: false,
+        }
+    }
+
+    // `entries` leaving values in blob files unread, so that only references to them are read
+    pub fn stored_entries(self) -> DataFileEntries {
+        DataFileEntries {
+            read_blobs: false,
+            ..self.entries()
+        }
+    }
+
+    // write entries and tombstones as a single stream sorted by the comparator,
+    // so that a tombstone sits between its neighbours as a deletion marker.
+    // the data file of the generation isn't touched until the written file is published.
+    // a batch holds a record per key, the last one written into the memtable, and
+    // fails with InvalidInput for a key in both of entries and tombstones
+    pub fn write(
+        fs: &SharedFileSystem,
+        dir_name: &str,
+        data_gen: DataGen,
+        memtable_entries: &MemtableEntries<String, String>,
+        comparator: &dyn KeyComparator,
+        options: &DataFileOptions,
+    ) -> io::Result<UnpublishedDataFile> {
+        let append = |writer: &mut DataFileWriter, key: &str, value: Option<&String>| {
+            writer.append(key, value.map(|v| v.as_str()))
+        };
+        Self::write_with(
+            fs,
+            dir_name,
+            data_gen,
+            memtable_entries,
+            comparator,
+            options,
+            append,
+        )
+    }
+
+    // `write` of values as they're stored, so that values in blob files aren't rewritten.
+    // `written` is called with the bytes each record takes as encoded once it's appended
+    pub fn write_stored(
+        fs: &SharedFileSystem,
+        dir_name: &str,
+        data_gen: DataGen,
+        memtable_entries: &MemtableEntries<String, StoredValue>,
+        comparator: &dyn KeyComparator,
+        options: &DataFileOptions,
+        mut written: impl FnMut(u64),
+    ) -> io::Result<UnpublishedDataFile> {
+        let append = |writer: &mut DataFileWriter, key: &str, value: Option<&StoredValue>| {
+            let before = writer.written_bytes();
+            writer.append_stored(key, value)?;
+            written(writer.written_bytes() - before);
+            Ok(())
+        };
+        Self::write_with(
+            fs,
+            dir_name,
+            data_gen,
+            memtable_entries,
+            comparator,
+            options,
+            append,
+        )
+    }
+
+    fn write_with<V>(
+        fs: &SharedFileSystem,
+        dir_name: &str,
+        data_gen: DataGen,
+        memtable_entries: &MemtableEntries<String, V>,
+        comparator: &dyn KeyComparator,
+        options: &DataFileOptions,
+        mut append: impl FnMut(&mut DataFileWriter, &str, Option<&V>) -> io::Result<()>,
+    ) -> io::Result<UnpublishedDataFile> {
         let MemtableEntries {
             entries,
-            tombstones, // TODO: persist records marked as deleted
+            tombstones,
         } = memtable_entries;
 
-        let new_data_file = RichFile::open_file(&self.file.dir, "tmp_data", FileOption::New)?;
-        let mut data_writer = BufWriter::new(&new_data_file.underlying);
-        let mut offset: Offset = 0;
-
-        let mut new_index = BTreeMap::new();
-        entries.iter().for_each(|(key, value)| {
-            let key_bytes = key.as_bytes();
-            let value_bytes = value.as_bytes();
-            let size = 4 + 4 + key_bytes.len() + 4 + value_bytes.len();
-            let bytes: Vec<u8> =[
-              &ByteUtils::from_usize(size), 
-              &ByteUtils::from_usize(key_bytes.len()),
-              &ByteUtils::from_usize(value_bytes.len()),
-              key_bytes,
-              value_bytes,
-              b"\0"
-            ].concat();
-            if (size + 1) != bytes.len() {
-              panic!(format!("size is invalid. size: {}, bytes.len(): {}", size, bytes.len()));
-            }
-            data_writer.write(&bytes)
-                .expect("failed to to write bytes into BufWriter");
-            new_index.insert(key, offset);
-            offset += (size + 1) as u64;
-        });
-        data_writer.flush().expect("failed to write data");
-        std::fs::rename(new_data_file.path(), self.file.path())?;
-        return Ok(new_index);
-    }
-
-    pub fn clear(dir: &str, data_gen: DataGen) -> io::Result<()> {
-        let tmp = Self::of(dir, data_gen);
-        std::fs::remove_file(tmp.file.path())?;
+        // a key never lives in both of entries and tombstones
+        let mut sorted: Vec<(&String, Option<&V>)> = entries
+            .iter()
+            .map(|(k, v)| (k, Some(v)))
+            .chain(tombstones.iter().map(|key| (key, None)))
+            .collect();
+        sorted.sort_by(|(a, _), (b, _)| comparator.compare(a, b));
+
+        let mut writer = DataFileWriter::create(fs, dir_name, options)?;
+        let written = sorted
+            .into_iter()
+            .try_for_each(|(key, value)| append(&mut writer, key, value));
+        if let Err(err) = written {
+            writer.abort()?;
+            return Err(err);
+        }
+        writer.finish(data_gen)
+    }
+
+    // a copy of the data file with references to values moved between blob files updated,
+    // which is patched record by record so that offsets index entries hold stay valid.
+    // None when the data file references none of the moved values
+    pub fn relocate_blobs(
+        &self,
+        fs: &SharedFileSystem,
+        relocated: &HashMap<(usize, Offset), BlobRef>,
+    ) -> io::Result<Option<UnpublishedDataFile>> {
+        if self.options.overhead() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "blob references of a sealed data file can't be relocated",
+            ));
+        }
+        let mut bytes = vec![];
+        self.file.reader().read_to_end(&mut bytes)?;
+        let mut patched = false;
+        let mut offset = 0;
+        while let Some(entry) = self.read_entry_with(offset, false)? {
+            offset = entry.next_offset();
+            let start = entry.offset as usize;
+            // a reference to an earlier entry shares its blob, which is patched there
+            if ByteUtils::as_usize(&bytes[(start + 8)..(start + 12)]) != Self::BLOB_VALUE_LEN {
+                continue;
+            }
+            let moved = entry
+                .blob
+                .and_then(|blob| relocated.get(&(blob.file_id, blob.offset)));
+            if let Some(moved) = moved {
+                let (_, header_len, _) = Self::record_header(entry.offset, &bytes[(start + 4)..])?;
+                let blob_start = start + 4 + header_len + entry.key.len();
+                bytes[blob_start..(blob_start + BlobRef::LEN)].copy_from_slice(&moved.to_bytes());
+                patched = true;
+            }
+        }
+        if !patched {
+            return Ok(None);
+        }
+        let path = self.file.path();
+        let tmp_path = path.with_file_name(Self::RELOCATED_FILE_NAME);
+        fs.create(&tmp_path)?.write(&bytes)?;
+        Ok(Some(UnpublishedDataFile {
+            fs: fs.clone(),
+            tmp_path,
+            path,
+            blob_path: None,
+            index: BTreeMap::new(),
+        }))
+    }
+
+    pub fn clear(fs: &SharedFileSystem, dir: &str, data_gen: DataGen) -> io::Result<()> {
+        let tmp = Self::of(fs, dir, data_gen);
+        fs.remove(&tmp.file.path())
+    }
+}
+
+pub(crate) struct DataFileEntries {
+    data_file: DataFile,
+    offset: Offset,
+    read_blobs: bool,
+    done: bool,
+}
+
+impl DataFileEntries {
+    // entries from the record at the offset rather than from the first one
+    pub fn from(mut self, offset: Offset) -> DataFileEntries {
+        self.offset = offset;
+        self
+    }
+}
+
+impl Iterator for DataFileEntries {
+    type Item = io::Result<DataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.data_file.read_entry_with(self.offset, self.read_blobs) {
+            Ok(Some(entry)) => {
+                self.offset = entry.next_offset();
+                Some(Ok(entry))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writes sorted entries into a temporary file, which becomes a data file once it's published.
+pub(crate) struct DataFileWriter {
+    fs: SharedFileSystem,
+    dir_name: String,
+    tmp_path: PathBuf,
+    writer: BufWriter<HandleWriter>,
+    offset: Offset,
+    options: DataFileOptions,
+    index: BTreeMap<String, (Offset, EntryKind, Option<String>)>,
+    // Some when equal values are written once, holding where each value was written
+    written_values: Option<HashMap<String, Offset>>,
+    // Some once a value longer than the blob threshold is written
+    blob: Option<BlobWriter>,
+    // the timestamp of entries appended without one, which is when the writer was created
+    timestamp: u64,
+    // Some while values are compressed, with the dictionary of the file once it's trained
+    #[cfg(feature = "compression")]
+    compressor: Option<zstd::bulk::Compressor<'static>>,
+    // Some while entries are held back to train a dictionary on their values, which goes
+    // into the header of the file before any of them is written
+    #[cfg(feature = "compression")]
+    pending: Option<PendingEntries>,
+}
+
+// entries appended before a dictionary is trained, along with the bytes of their values
+#[cfg(feature = "compression")]
+#[derive(Default)]
+struct PendingEntries {
+    entries: Vec<(String, Option<StoredValue>)>,
+    bytes: usize,
+}
+
+// the blob file a writer appends values to, created with the first value written into it
+struct BlobWriter {
+    file_id: usize,
+    path: PathBuf,
+    writer: BufWriter<HandleWriter>,
+    offset: Offset,
+}
+
+impl DataFileWriter {
+    const TMP_FILE_NAME: &'static str = "tmp_data";
+
+    // with a block size, an entry which doesn't fit in the rest of the current block
+    // starts at the next block, and the file is padded up to a multiple of the block size.
+    // an entry larger than a block starts at a block and spans the following ones
+    pub fn create(
+        fs: &SharedFileSystem,
+        dir_name: &str,
+        options: &DataFileOptions,
+    ) -> io::Result<DataFileWriter> {
+        Self::create_layer(fs, dir_name, options, 0)
+    }
+
+    // `create` into a temporary file of the layer, so that writers of different layers
+    // are written at the same time
+    pub fn create_layer(
+        fs: &SharedFileSystem,
+        dir_name: &str,
+        options: &DataFileOptions,
+        layer: usize,
+    ) -> io::Result<DataFileWriter> {
+        let tmp_file_name = match layer {
+            0 => Self::TMP_FILE_NAME.to_string(),
+            _ => format!("{}_{}", Self::TMP_FILE_NAME, layer),
+        };
+        let tmp_file = OpenedFile::create(fs.as_ref(), dir_name, &tmp_file_name)?;
+        #[cfg(feature = "compression")]
+        let (compressor, pending) = match options.compression {
+            Some(compression) if compression.dictionary_bytes.is_some() => {
+                (None, Some(PendingEntries::default()))
+            }
+            Some(compression) => (Some(compression.compressor(None)?), None),
+            None => (None, None),
+        };
+        Ok(DataFileWriter {
+            fs: fs.clone(),
+            dir_name: dir_name.to_string(),
+            tmp_path: tmp_file.path(),
+            writer: BufWriter::new(HandleWriter(tmp_file.handle)),
+            offset: 0,
+            options: options.clone(),
+            index: BTreeMap::new(),
+            written_values: None,
+            blob: None,
+            timestamp: unix_nanos(SystemTime::now()),
+            #[cfg(feature = "compression")]
+            compressor,
+            #[cfg(feature = "compression")]
+            pending,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "compression")]
+        if let Some(pending) = self.pending.as_ref() {
+            return pending.entries.is_empty();
+        }
+        self.index.is_empty()
+    }
+
+    // bytes written so far into the data file and its blob file, as encoded. entries held
+    // back to train a dictionary count once they're written
+    pub fn written_bytes(&self) -> u64 {
+        self.offset + self.blob.as_ref().map_or(0, |blob| blob.offset)
+    }
+
+    // write a value equal to one written before as a reference to the earlier entry.
+    // every distinct value is kept in memory until the writer is finished
+    pub fn dedup_values(mut self) -> DataFileWriter {
+        self.written_values = Some(HashMap::new());
+        self
+    }
+
+    // fill the current block up to its end with a padding record. a gap too narrow for
+    // a padding record is filled together with the following block
+    fn pad_block(&mut self) -> io::Result<()> {
+        let block_size = match self.options.block_size {
+            Some(block_size) => block_size,
+            None => return Ok(()),
+        };
+        let used = (self.offset % block_size as Offset) as usize;
+        if used == 0 {
+            return Ok(());
+        }
+        let min_len = DataFile::MIN_PADDING_LEN + self.options.overhead();
+        let mut len = block_size - used;
+        if len < min_len {
+            len += block_size;
+        }
+        let body: Vec<u8> = [
+            &ByteUtils::from_usize(0)[..],
+            &ByteUtils::from_usize(DataFile::PADDING_VALUE_LEN),
+            &vec![0u8; len - min_len],
+        ]
+        .concat();
+        let bytes = self.record(body)?;
+        self.writer.write_all(&bytes)?;
+        self.offset += len as Offset;
+        Ok(())
+    }
+
+    // [entry size][body]\0 with the body sealed by the cipher if any
+    fn record(&self, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        let body = self.options.seal(body)?;
+        let size = 4 + body.len();
+        let bytes: Vec<u8> = [&ByteUtils::from_usize(size)[..], &body, b"\0"].concat();
+        if (size + 1) != bytes.len() {
+            panic!(
+                "size is invalid. size: {}, bytes.len(): {}",
+                size,
+                bytes.len()
+            );
+        }
+        Ok(bytes)
+    }
+
+    // fails with InvalidInput for an empty key, which would read back as a padding record,
+    // and for a key appended already, as the index holds an entry per key
+    fn check_key(&self, key: &str) -> io::Result<()> {
+        if key.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty key can't be written",
+            ));
+        }
+        if self.index.contains_key(key) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("key is written twice into a data file. key: {}", key),
+            ));
+        }
+        Ok(())
+    }
+
+    // a value longer than the blob threshold is appended to the blob file of the writer,
+    // and the entry holds a reference to it
+    pub fn append(&mut self, key: &str, value: Option<&str>) -> io::Result<()> {
+        self.append_stamped(key, value, self.timestamp)
+    }
+
+    // `append` of an entry written at the timestamp, which is dropped without entry timestamps
+    fn append_stamped(&mut self, key: &str, value: Option<&str>, timestamp: u64) -> io::Result<()> {
+        self.check_key(key)?;
+        #[cfg(feature = "compression")]
+        if self.pending.is_some() {
+            let value = value.map(|value| StoredValue::Plain(value.to_string(), Some(timestamp)));
+            return self.hold_back(key, value);
+        }
+        // a reference takes 8 bytes, so shorter values are never worth sharing
+        let reference = match (&self.written_values, value) {
+            (Some(written), Some(value)) if value.len() > 8 => written.get(value).copied(),
+            _ => None,
+        };
+        let blob = match (self.options.blob_threshold, value, reference) {
+            (Some(threshold), Some(value), None) if value.len() > threshold => {
+                Some(self.write_blob(value.as_bytes())?.to_bytes())
+            }
+            _ => None,
+        };
+        let reference_bytes = reference.map(ByteUtils::from_u64);
+        #[cfg(feature = "compression")]
+        let compressed = match (self.compressor.as_mut(), value, reference, &blob) {
+            (Some(compressor), Some(value), None, None) => {
+                Self::compress(compressor, value.as_bytes())?
+            }
+            _ => None,
+        };
+        #[cfg(not(feature = "compression"))]
+        let compressed: Option<Vec<u8>> = None;
+        let (value_bytes, value_len, kind) = match (value, &reference_bytes, &blob, &compressed) {
+            (Some(_), Some(target), _, _) => {
+                (&target[..], DataFile::REFERENCE_VALUE_LEN, EntryKind::Value)
+            }
+            (Some(_), None, Some(blob), _) => {
+                (&blob[..], DataFile::BLOB_VALUE_LEN, EntryKind::Value)
+            }
+            (Some(_), None, None, Some(compressed)) => (
+                &compressed[..],
+                DataFile::COMPRESSED_VALUE_LEN,
+                EntryKind::Value,
+            ),
+            (Some(value), None, None, None) => (value.as_bytes(), value.len(), EntryKind::Value),
+            (None, ..) => (
+                &b""[..],
+                DataFile::TOMBSTONE_VALUE_LEN,
+                EntryKind::Tombstone,
+            ),
+        };
+        let inline_value = match (self.options.inline_threshold, value) {
+            (_, Some("")) if self.options.existence_records => Some(String::new()),
+            (Some(threshold), Some(value)) if value.len() < threshold => Some(value.to_string()),
+            _ => None,
+        };
+        let offset =
+            self.write_entry(key, value_bytes, value_len, kind, inline_value, timestamp)?;
+        if let (Some(written), Some(value), None) = (&mut self.written_values, value, reference) {
+            if value.len() > 8 {
+                written.insert(value.to_string(), offset);
+            }
+        }
+        Ok(())
+    }
+
+    // append an entry referencing a value already in a blob file, which isn't copied
+    fn append_blob(&mut self, key: &str, blob: BlobRef, timestamp: u64) -> io::Result<()> {
+        self.check_key(key)?;
+        #[cfg(feature = "compression")]
+        if self.pending.is_some() {
+            return self.hold_back(key, Some(StoredValue::Blob(blob, Some(timestamp))));
+        }
+        let blob = blob.to_bytes();
+        let kind = EntryKind::Value;
+        self.write_entry(key, &blob, DataFile::BLOB_VALUE_LEN, kind, None, timestamp)?;
+        Ok(())
+    }
+
+    // append a value as it's stored, see `StoredValue`, keeping the timestamp it has
+    pub fn append_stored(&mut self, key: &str, value: Option<&StoredValue>) -> io::Result<()> {
+        match value {
+            Some(StoredValue::Blob(blob, timestamp)) => {
+                self.append_blob(key, *blob, timestamp.unwrap_or(self.timestamp))
+            }
+            Some(StoredValue::Plain(value, timestamp)) => {
+                self.append_stamped(key, Some(value), timestamp.unwrap_or(self.timestamp))
+            }
+            None => self.append(key, None),
+        }
+    }
+
+    // [length][zstd frame] of the value, None unless it's shorter than the value
+    #[cfg(feature = "compression")]
+    fn compress(
+        compressor: &mut zstd::bulk::Compressor<'static>,
+        value: &[u8],
+    ) -> io::Result<Option<Vec<u8>>> {
+        let frame = compressor.compress(value)?;
+        if 4 + frame.len() >= value.len() {
+            return Ok(None);
+        }
+        Ok(Some(
+            [&ByteUtils::from_usize(value.len())[..], &frame].concat(),
+        ))
+    }
+
+    // hold the entry back until the values held back are enough to train a dictionary on
+    #[cfg(feature = "compression")]
+    fn hold_back(&mut self, key: &str, value: Option<StoredValue>) -> io::Result<()> {
+        let sample_bytes = self
+            .options
+            .compression
+            .and_then(|compression| compression.sample_bytes())
+            .unwrap_or(0);
+        let pending = self
+            .pending
+            .as_mut()
+            .expect("entries are held back until a dictionary is trained");
+        if let Some(StoredValue::Plain(value, _)) = &value {
+            pending.bytes += value.len();
+        }
+        pending.entries.push((key.to_string(), value));
+        if pending.bytes >= sample_bytes {
+            self.train_dictionary()?;
+        }
         Ok(())
     }
+
+    // train the dictionary on the values held back and write it as the header, then write
+    // the entries held back. values are compressed without one if it can't be trained
+    #[cfg(feature = "compression")]
+    fn train_dictionary(&mut self) -> io::Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let compression = self
+            .options
+            .compression
+            .expect("entries are held back by a writer compressing values");
+        let samples: Vec<&[u8]> = pending
+            .entries
+            .iter()
+            .filter_map(|(_, value)| match value {
+                Some(StoredValue::Plain(value, _)) if !value.is_empty() => Some(value.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        let dictionary = compression.train(&samples);
+        self.compressor = Some(compression.compressor(dictionary.as_deref())?);
+        if let Some(dictionary) = dictionary {
+            let timestamp = ByteUtils::from_u64(self.timestamp);
+            let timestamp = if self.options.entry_timestamps {
+                &timestamp[..]
+            } else {
+                &[]
+            };
+            let body: Vec<u8> = [
+                &ByteUtils::from_usize(self.options.timestamped())[..],
+                &ByteUtils::from_usize(DataFile::DICTIONARY_VALUE_LEN),
+                timestamp,
+                &dictionary,
+            ]
+            .concat();
+            self.write_record(body)?;
+        }
+        pending
+            .entries
+            .into_iter()
+            .try_for_each(|(key, value)| self.append_stored(&key, value.as_ref()))
+    }
+
+    fn write_blob(&mut self, value: &[u8]) -> io::Result<BlobRef> {
+        if self.blob.is_none() {
+            let blob_files = self.options.blob_files.as_ref().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no blob files to write a blob into",
+                )
+            })?;
+            let (file_id, file) = blob_files.create()?;
+            self.blob = Some(BlobWriter {
+                file_id,
+                path: file.path(),
+                writer: BufWriter::new(HandleWriter(file.handle)),
+                offset: 0,
+            });
+        }
+        let blob = self.blob.as_mut().unwrap();
+        blob.writer.write_all(value)?;
+        let written = BlobRef {
+            file_id: blob.file_id,
+            offset: blob.offset,
+            len: value.len(),
+        };
+        blob.offset += value.len() as Offset;
+        Ok(written)
+    }
+
+    // write an entry record and add it to the index, returning where it was written
+    fn write_entry(
+        &mut self,
+        key: &str,
+        value_bytes: &[u8],
+        value_len: usize,
+        kind: EntryKind,
+        inline_value: Option<String>,
+        timestamp: u64,
+    ) -> io::Result<Offset> {
+        let key_bytes = key.as_bytes();
+        let timestamp = ByteUtils::from_u64(timestamp);
+        let timestamp = if self.options.entry_timestamps {
+            &timestamp[..]
+        } else {
+            &[]
+        };
+        let body: Vec<u8> = [
+            &ByteUtils::from_usize(key_bytes.len() | self.options.timestamped())[..],
+            &ByteUtils::from_usize(value_len),
+            timestamp,
+            key_bytes,
+            value_bytes,
+        ]
+        .concat();
+        let offset = self.write_record(body)?;
+        self.index
+            .insert(key.to_string(), (offset, kind, inline_value));
+        Ok(offset)
+    }
+
+    // write a record of the body, starting at the next block if it doesn't fit in the rest
+    // of the current one, and return where it was written
+    fn write_record(&mut self, body: Vec<u8>) -> io::Result<Offset> {
+        let bytes = self.record(body)?;
+        if let Some(block_size) = self.options.block_size {
+            let used = (self.offset % block_size as Offset) as usize;
+            if used + bytes.len() > block_size {
+                self.pad_block()?;
+            }
+        }
+        self.writer.write_all(&bytes)?;
+        let offset = self.offset;
+        self.offset += bytes.len() as Offset;
+        Ok(offset)
+    }
+
+    // complete the written file as the data file of the generation, which is still unpublished
+    pub fn finish(mut self, data_gen: DataGen) -> io::Result<UnpublishedDataFile> {
+        #[cfg(feature = "compression")]
+        self.train_dictionary()?;
+        self.pad_block()?;
+        self.writer.flush()?;
+        let blob_path = match self.blob {
+            Some(mut blob) => {
+                blob.writer.flush()?;
+                Some(blob.path)
+            }
+            None => None,
+        };
+        let index = self
+            .index
+            .into_iter()
+            .map(|(key, (offset, kind, inline_value))| {
+                let entry = IndexEntry {
+                    data_gen,
+                    offset,
+                    kind,
+                    inline_value,
+                };
+                (key, entry)
+            })
+            .collect();
+        Ok(UnpublishedDataFile {
+            fs: self.fs,
+            tmp_path: self.tmp_path,
+            path: DataFile::path(&self.dir_name, data_gen),
+            blob_path,
+            index,
+        })
+    }
+
+    // discard the written entries along with the values written into its blob file
+    pub fn abort(self) -> io::Result<()> {
+        let DataFileWriter {
+            fs,
+            tmp_path,
+            writer,
+            blob,
+            ..
+        } = self;
+        drop(writer);
+        if let Some(BlobWriter {
+            path, writer: blob, ..
+        }) = blob
+        {
+            drop(blob);
+            fs.remove(&path)?;
+        }
+        fs.remove(&tmp_path)
+    }
+}
+
+/// A written data file which readers can't see until `publish` renames it into place.
+pub(crate) struct UnpublishedDataFile {
+    fs: SharedFileSystem,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    // the blob file values were written into, if any
+    blob_path: Option<PathBuf>,
+    pub index: BTreeMap<String, IndexEntry>,
+}
+
+impl UnpublishedDataFile {
+    // the written file read with the options, e.g. to check it before it's published
+    pub fn read_back(&self, options: &DataFileOptions) -> io::Result<DataFile> {
+        let dir_name = self.tmp_path.parent().unwrap_or_else(|| Path::new(""));
+        let file_name = self.tmp_path.file_name().unwrap_or_default();
+        let file = OpenedFile::open(
+            self.fs.as_ref(),
+            &dir_name.to_string_lossy(),
+            &file_name.to_string_lossy(),
+        )?;
+        Ok(DataFile {
+            file,
+            options: options.clone(),
+            _blob_pin: None,
+        })
+    }
+
+    // bytes of the written file, without the blob file values went into
+    pub fn size(&self) -> io::Result<u64> {
+        self.fs.open(&self.tmp_path)?.size()
+    }
+
+    // make the written entries durable before they're published
+    pub fn sync(&self) -> io::Result<()> {
+        if let Some(blob_path) = &self.blob_path {
+            self.fs.open(blob_path)?.sync()?;
+        }
+        self.fs.open(&self.tmp_path)?.sync()
+    }
+
+    // where the file is written until it's published
+    pub fn tmp_path(&self) -> &Path {
+        &self.tmp_path
+    }
+
+    // replace the data file of the generation with the written file
+    pub fn publish(self) -> io::Result<()> {
+        self.fs.rename(&self.tmp_path, &self.path)
+    }
 }
@@ -0,0 +1,743 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+/// Where a disktable keeps its data and index files, the local file system by default.
+/// Paths are the directory of the disktable joined with file names.
+/// A handle keeps reading the file it opened after the file is renamed over or removed,
+/// as on Unix, which cursors rely on to outlive the generations they started with.
+pub trait FileSystem: Send + Sync {
+    /// Open an existing file for reading, `NotFound` if it's missing.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FileHandle>>;
+    /// Create an empty file for reading and appending, truncating it if it exists.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    /// Make renames and removals of files in the directory durable.
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+    /// Files and directories right under the directory, in no particular order.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>>;
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+
+    /// `open`, or `create` if the file is missing.
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        match self.open(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => self.create(path),
+            opened => opened,
+        }
+    }
+
+    /// Whether files can be created in the directory, found by creating and removing a file.
+    /// False if the directory is on a read-only filesystem or isn't writable to the process.
+    fn writable(&self, dir: &Path) -> io::Result<bool> {
+        let probe = dir.join(WRITE_PROBE_FILE_NAME);
+        match self.create(&probe) {
+            Ok(_) => self.remove(&probe).map(|_| true),
+            Err(err) if denies_writes(&err) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+const WRITE_PROBE_FILE_NAME: &str = "write_probe";
+
+// an error of a write to a read-only filesystem or a file which isn't writable
+pub(crate) fn denies_writes(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied
+    )
+}
+
+impl<F: FileSystem + ?Sized> FileSystem for Arc<F> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        (**self).open(path)
+    }
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        (**self).create(path)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        (**self).rename(from, to)
+    }
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        (**self).remove(path)
+    }
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        (**self).sync_dir(dir)
+    }
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        (**self).read_dir(dir)
+    }
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        (**self).create_dir_all(dir)
+    }
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        (**self).open_or_create(path)
+    }
+    fn writable(&self, dir: &Path) -> io::Result<bool> {
+        (**self).writable(dir)
+    }
+}
+
+/// An open file of a `FileSystem`. Reads take an offset, so a handle is shared
+/// by readers without a cursor, and writes append to the end of the file.
+pub trait FileHandle: Send + Sync {
+    /// Read into the buffer from the offset, returning how many bytes were read,
+    /// which is 0 at or past the end of the file.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+    fn write(&self, buf: &[u8]) -> io::Result<()>;
+    /// Make the written bytes durable.
+    fn sync(&self) -> io::Result<()>;
+    /// Bytes in the file.
+    fn size(&self) -> io::Result<u64>;
+    /// What tells the file apart from another one renamed over its path, and from itself
+    /// before it was last written to. None if the file system doesn't know, in which case
+    /// followers tell replaced index files by their bytes.
+    fn identity(&self) -> io::Result<Option<FileIdentity>> {
+        Ok(None)
+    }
+}
+
+/// The inode of a file and when it was last modified, in nanoseconds since the epoch, or
+/// whatever stands for them on a file system which has none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub id: u64,
+    pub modified: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: OsString,
+    pub is_dir: bool,
+}
+
+pub(crate) type SharedFileSystem = Arc<dyn FileSystem>;
+
+// a file of a disktable opened through its file system
+pub(crate) struct OpenedFile {
+    pub handle: Box<dyn FileHandle>,
+    path: PathBuf,
+}
+
+impl OpenedFile {
+    /// `NotFound` if the file is missing.
+    pub fn open(fs: &dyn FileSystem, dir_name: &str, file_name: &str) -> io::Result<OpenedFile> {
+        let path = Path::new(dir_name).join(file_name);
+        Ok(OpenedFile {
+            handle: fs.open(&path)?,
+            path,
+        })
+    }
+
+    pub fn open_or_create(
+        fs: &dyn FileSystem,
+        dir_name: &str,
+        file_name: &str,
+    ) -> io::Result<OpenedFile> {
+        let path = Path::new(dir_name).join(file_name);
+        Ok(OpenedFile {
+            handle: fs.open_or_create(&path)?,
+            path,
+        })
+    }
+
+    pub fn create(fs: &dyn FileSystem, dir_name: &str, file_name: &str) -> io::Result<OpenedFile> {
+        let path = Path::new(dir_name).join(file_name);
+        Ok(OpenedFile {
+            handle: fs.create(&path)?,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    // a reader from the head of the file
+    pub fn reader(&self) -> HandleReader<'_> {
+        HandleReader::new(self.handle.as_ref())
+    }
+
+    // the file read through a `ReadAheadHandle` of the capacity
+    pub fn read_ahead(self, capacity: usize) -> OpenedFile {
+        OpenedFile {
+            handle: Box::new(ReadAheadHandle::new(self.handle, capacity)),
+            path: self.path,
+        }
+    }
+}
+
+// a handle read through `Read` and `Seek` from a cursor of its own
+pub(crate) struct HandleReader<'a> {
+    handle: &'a dyn FileHandle,
+    position: u64,
+}
+
+impl<'a> HandleReader<'a> {
+    pub fn new(handle: &'a dyn FileHandle) -> HandleReader<'a> {
+        HandleReader {
+            handle,
+            position: 0,
+        }
+    }
+}
+
+impl Read for HandleReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.handle.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for HandleReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.handle.size()?.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset")
+        })?;
+        Ok(self.position)
+    }
+}
+
+// a handle written through `Write`, e.g. under a `BufWriter`
+pub(crate) struct HandleWriter(pub Box<dyn FileHandle>);
+
+impl Write for HandleWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a handle which reads at least `capacity` bytes at once and serves following reads
+// from them, for sequential readers which read a small record at a time
+pub(crate) struct ReadAheadHandle {
+    inner: Box<dyn FileHandle>,
+    capacity: usize,
+    // the offset of the bytes read ahead, and the bytes
+    window: Mutex<(u64, Vec<u8>)>,
+}
+
+impl ReadAheadHandle {
+    pub fn new(inner: Box<dyn FileHandle>, capacity: usize) -> ReadAheadHandle {
+        ReadAheadHandle {
+            inner,
+            capacity,
+            window: Mutex::new((0, vec![])),
+        }
+    }
+}
+
+impl FileHandle for ReadAheadHandle {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut window = self.window.lock().unwrap();
+        let (start, bytes) = &mut *window;
+        let covered = *start <= offset && offset + buf.len() as u64 <= *start + bytes.len() as u64;
+        if !covered {
+            // a short read is only at the end of the file
+            let mut read = vec![0u8; self.capacity.max(buf.len())];
+            let mut len = 0;
+            while len < read.len() {
+                match self.inner.read_at(offset + len as u64, &mut read[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            read.truncate(len);
+            *start = offset;
+            *bytes = read;
+        }
+        let from = (offset - *start) as usize;
+        let len = buf.len().min(bytes.len().saturating_sub(from));
+        buf[..len].copy_from_slice(&bytes[from..from + len]);
+        Ok(len)
+    }
+    fn write(&self, buf: &[u8]) -> io::Result<()> {
+        *self.window.lock().unwrap() = (0, vec![]);
+        self.inner.write(buf)
+    }
+    fn sync(&self) -> io::Result<()> {
+        self.inner.sync()
+    }
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+    fn identity(&self) -> io::Result<Option<FileIdentity>> {
+        self.inner.identity()
+    }
+}
+
+/// Files through `std::fs`. On Unix, files and directories it creates get 0o600 and
+/// 0o700 so that they're private to the owner, less the bits the umask of the process
+/// clears, see `with_modes` for others. Existing ones keep their modes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+impl OsFileSystem {
+    pub const DEFAULT_FILE_MODE: u32 = 0o600;
+    pub const DEFAULT_DIR_MODE: u32 = 0o700;
+
+    /// Files through `std::fs` created with `file_mode` and directories with `dir_mode` on
+    /// Unix, which are ignored elsewhere.
+    pub fn with_modes(file_mode: u32, dir_mode: u32) -> ModedFileSystem {
+        ModedFileSystem {
+            file_mode,
+            dir_mode,
+        }
+    }
+
+    const DEFAULT_MODES: ModedFileSystem = ModedFileSystem {
+        file_mode: Self::DEFAULT_FILE_MODE,
+        dir_mode: Self::DEFAULT_DIR_MODE,
+    };
+}
+
+/// `OsFileSystem` creating files and directories with the mode bits it's made with, see
+/// `OsFileSystem::with_modes`.
+#[derive(Debug, Clone, Copy)]
+pub struct ModedFileSystem {
+    file_mode: u32,
+    dir_mode: u32,
+}
+
+impl ModedFileSystem {
+    fn open_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, self.file_mode);
+        options
+    }
+}
+
+impl FileSystem for OsFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        Self::DEFAULT_MODES.open(path)
+    }
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        Self::DEFAULT_MODES.create(path)
+    }
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        Self::DEFAULT_MODES.open_or_create(path)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        Self::DEFAULT_MODES.rename(from, to)
+    }
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        Self::DEFAULT_MODES.remove(path)
+    }
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        Self::DEFAULT_MODES.sync_dir(dir)
+    }
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        Self::DEFAULT_MODES.read_dir(dir)
+    }
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        Self::DEFAULT_MODES.create_dir_all(dir)
+    }
+}
+
+// the cursor of the file is moved by every read, so reads are serialized
+struct OsFileHandle {
+    file: Mutex<File>,
+}
+
+impl FileSystem for ModedFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        Ok(Box::new(OsFileHandle {
+            file: Mutex::new(file),
+        }))
+    }
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        let file = self
+            .open_options()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(path)?;
+        Ok(Box::new(OsFileHandle {
+            file: Mutex::new(file),
+        }))
+    }
+    // `open` is read-only, so a file which is appended to is opened for writes as well
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        let file = self
+            .open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Box::new(OsFileHandle {
+            file: Mutex::new(file),
+        }))
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        File::open(dir)?.sync_all()
+    }
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        std::fs::read_dir(dir)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(DirEntry {
+                    name: entry.file_name(),
+                    is_dir: entry.file_type()?.is_dir(),
+                })
+            })
+            .collect()
+    }
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, self.dir_mode);
+        builder.create(dir)
+    }
+}
+
+impl FileHandle for OsFileHandle {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read(buf)
+    }
+    fn write(&self, buf: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(buf)
+    }
+    fn sync(&self) -> io::Result<()> {
+        self.file.lock().unwrap().sync_all()
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.lock().unwrap().metadata()?.len())
+    }
+    fn identity(&self) -> io::Result<Option<FileIdentity>> {
+        let metadata = self.file.lock().unwrap().metadata()?;
+        #[cfg(unix)]
+        let id = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let id = 0;
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_nanos())
+            .unwrap_or(0);
+        Ok(Some(FileIdentity { id, modified }))
+    }
+}
+
+/// Files in memory, shared by clones, for tests which shouldn't touch disks.
+/// Bytes which aren't synced and renames and removals in directories which aren't synced
+/// are lost by `crash`, as they may be on a real disk.
+#[derive(Clone, Default)]
+pub struct MemFileSystem {
+    state: Arc<Mutex<MemState>>,
+    // shared with handles, which fail writes as well while it's set
+    read_only: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct MemState {
+    dirs: BTreeSet<PathBuf>,
+    files: HashMap<PathBuf, Arc<RwLock<MemFile>>>,
+    // files by the names they had when their directories were synced last
+    durable: HashMap<PathBuf, Arc<RwLock<MemFile>>>,
+}
+
+struct MemFile {
+    bytes: Vec<u8>,
+    synced: Vec<u8>,
+    // unique to the file, and bumped by every write, for its identity
+    id: u64,
+    writes: u64,
+}
+
+// ids of files in memory, unique across file systems
+static NEXT_MEM_FILE_ID: AtomicU64 = AtomicU64::new(1);
+
+impl MemFile {
+    fn new() -> MemFile {
+        MemFile {
+            bytes: vec![],
+            synced: vec![],
+            id: NEXT_MEM_FILE_ID.fetch_add(1, Ordering::SeqCst),
+            writes: 0,
+        }
+    }
+}
+
+struct MemFileHandle {
+    file: Arc<RwLock<MemFile>>,
+    read_only: Arc<AtomicBool>,
+}
+
+impl MemFileSystem {
+    /// Drop everything which isn't durable, as a crash of the machine would.
+    /// Directories are always durable.
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.files = state.durable.clone();
+        state.files.values().for_each(|file| {
+            let mut file = file.write().unwrap();
+            file.bytes = file.synced.clone();
+            file.writes += 1;
+        });
+    }
+
+    /// Fail every change of files and directories with `ReadOnlyFilesystem` from now on,
+    /// as a filesystem mounted read-only would, or accept them again.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+
+    fn check_writable(read_only: &AtomicBool) -> io::Result<()> {
+        if read_only.load(Ordering::SeqCst) {
+            Err(io::Error::new(
+                io::ErrorKind::ReadOnlyFilesystem,
+                "read-only file system",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn handle(&self, file: Arc<RwLock<MemFile>>) -> Box<dyn FileHandle> {
+        Box::new(MemFileHandle {
+            file,
+            read_only: self.read_only.clone(),
+        })
+    }
+
+    fn parent_exists(state: &MemState, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !state.dirs.contains(parent) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such directory: {:?}", parent),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("no such file: {:?}", path))
+    }
+}
+
+impl FileSystem for MemFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        let state = self.state.lock().unwrap();
+        match state.files.get(path) {
+            Some(file) => Ok(self.handle(file.clone())),
+            None => Err(Self::not_found(path)),
+        }
+    }
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FileHandle>> {
+        Self::check_writable(&self.read_only)?;
+        let mut state = self.state.lock().unwrap();
+        Self::parent_exists(&state, path)?;
+        let file = match state.files.get(path) {
+            Some(file) => {
+                let mut truncated = file.write().unwrap();
+                truncated.bytes.clear();
+                truncated.writes += 1;
+                drop(truncated);
+                file.clone()
+            }
+            None => {
+                let file = Arc::new(RwLock::new(MemFile::new()));
+                state.files.insert(path.to_path_buf(), file.clone());
+                file
+            }
+        };
+        Ok(self.handle(file))
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        Self::check_writable(&self.read_only)?;
+        let mut state = self.state.lock().unwrap();
+        Self::parent_exists(&state, to)?;
+        let file = state
+            .files
+            .remove(from)
+            .ok_or_else(|| Self::not_found(from))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        Self::check_writable(&self.read_only)?;
+        let mut state = self.state.lock().unwrap();
+        state
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| Self::not_found(path))
+    }
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let MemState { files, durable, .. } = &mut *state;
+        durable.retain(|path, _| path.parent() != Some(dir));
+        files
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .for_each(|(path, file)| {
+                durable.insert(path.clone(), file.clone());
+            });
+        Ok(())
+    }
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        let state = self.state.lock().unwrap();
+        if !state.dirs.contains(dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such directory: {:?}", dir),
+            ));
+        }
+        let entry = |path: &PathBuf, is_dir| {
+            path.file_name()
+                .filter(|_| path.parent() == Some(dir))
+                .map(|name| DirEntry {
+                    name: name.to_os_string(),
+                    is_dir,
+                })
+        };
+        let files = state.files.keys().filter_map(|path| entry(path, false));
+        let dirs = state.dirs.iter().filter_map(|path| entry(path, true));
+        Ok(files.chain(dirs).collect())
+    }
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        // an existing directory is fine on a read-only filesystem as well
+        if !state.dirs.contains(dir) {
+            Self::check_writable(&self.read_only)?;
+        }
+        dir.ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .for_each(|ancestor| {
+                state.dirs.insert(ancestor.to_path_buf());
+            });
+        Ok(())
+    }
+}
+
+impl FileHandle for MemFileHandle {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let file = self.file.read().unwrap();
+        let start = (offset as usize).min(file.bytes.len());
+        let read = buf.len().min(file.bytes.len() - start);
+        buf[..read].copy_from_slice(&file.bytes[start..start + read]);
+        Ok(read)
+    }
+    fn write(&self, buf: &[u8]) -> io::Result<()> {
+        MemFileSystem::check_writable(&self.read_only)?;
+        let mut file = self.file.write().unwrap();
+        file.bytes.extend_from_slice(buf);
+        file.writes += 1;
+        Ok(())
+    }
+    fn sync(&self) -> io::Result<()> {
+        let mut file = self.file.write().unwrap();
+        file.synced = file.bytes.clone();
+        Ok(())
+    }
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.read().unwrap().bytes.len() as u64)
+    }
+    fn identity(&self) -> io::Result<Option<FileIdentity>> {
+        let file = self.file.read().unwrap();
+        Ok(Some(FileIdentity {
+            id: file.id,
+            modified: file.writes as u128,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSystem, HandleReader, MemFileSystem};
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        path::Path,
+    };
+
+    #[test]
+    fn test_mem_file_system() {
+        let fs = MemFileSystem::default();
+        let dir = Path::new("./store");
+        let path = dir.join("file");
+        assert!(fs.create(&path).is_err(), "the directory is missing");
+        fs.create_dir_all(dir).unwrap();
+        let file = fs.create(&path).unwrap();
+        file.write(b"hello ").unwrap();
+        file.write(b"world").unwrap();
+
+        let opened = fs.open(&path).unwrap();
+        let mut reader = HandleReader::new(opened.as_ref());
+        reader.seek(SeekFrom::Start(6)).unwrap();
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "world");
+        let mut buf = [0u8; 4];
+        assert_eq!(opened.read_at(20, &mut buf).unwrap(), 0);
+
+        fs.rename(&path, &dir.join("renamed")).unwrap();
+        assert!(fs.open(&path).is_err());
+        let names: Vec<_> = fs
+            .read_dir(dir)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["renamed"]);
+        fs.remove(&dir.join("renamed")).unwrap();
+        assert!(fs.read_dir(dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mem_file_system_crash() {
+        let fs = MemFileSystem::default();
+        let dir = Path::new("./store");
+        fs.create_dir_all(dir).unwrap();
+        let synced = fs.create(&dir.join("synced")).unwrap();
+        synced.write(b"durable").unwrap();
+        synced.sync().unwrap();
+        fs.sync_dir(dir).unwrap();
+        synced.write(b" and lost").unwrap();
+        fs.create(&dir.join("unsynced")).unwrap();
+        // the rename isn't durable until the directory is synced
+        fs.rename(&dir.join("synced"), &dir.join("moved")).unwrap();
+
+        fs.crash();
+        assert!(fs.open(&dir.join("unsynced")).is_err());
+        assert!(fs.open(&dir.join("moved")).is_err());
+        let file = fs.open(&dir.join("synced")).unwrap();
+        let mut read = String::new();
+        HandleReader::new(file.as_ref())
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, "durable");
+    }
+}
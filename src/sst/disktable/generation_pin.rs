@@ -0,0 +1,183 @@
+use super::{data_file::DataFile, file_system::SharedFileSystem, index_file::IndexFile, DataGen};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// generations open scans read from, whose files compactions move out of the way until the
+// scans are dropped, rather than removing them under the scans. their files are renamed with
+// `RETIRED_PREFIX`, so a reopen after a crash doesn't list them, and removes them
+pub(crate) struct GenerationPins {
+    fs: SharedFileSystem,
+    dir: String,
+    pins: Mutex<Pins>,
+}
+
+// scans holding each generation, and the generations retired while they were held
+#[derive(Default)]
+struct Pins {
+    counts: HashMap<DataGen, usize>,
+    retired: BTreeSet<DataGen>,
+}
+
+/// Keeps the data and index files of a generation from being removed while a scan reads
+/// them. A generation retired while any pin of it is held is renamed out of the listed
+/// generations, and removed once the last pin is dropped.
+pub(crate) struct GenerationPin {
+    pins: Arc<GenerationPins>,
+    data_gen: DataGen,
+}
+
+impl Drop for GenerationPin {
+    fn drop(&mut self) {
+        self.pins.unpin(self.data_gen);
+    }
+}
+
+// entries of a scan holding the generations it reads until it's dropped
+pub(crate) struct Pinned<I> {
+    entries: I,
+    _pins: Vec<GenerationPin>,
+}
+
+impl<I: Iterator> Iterator for Pinned<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.entries.next()
+    }
+}
+
+impl GenerationPins {
+    const RETIRED_PREFIX: &'static str = "retired_";
+
+    pub fn new(fs: SharedFileSystem, dir: &str) -> GenerationPins {
+        GenerationPins {
+            fs,
+            dir: dir.to_string(),
+            pins: Mutex::new(Pins::default()),
+        }
+    }
+
+    pub fn pin(self: &Arc<Self>, data_gen: DataGen) -> GenerationPin {
+        *self
+            .pins
+            .lock()
+            .unwrap()
+            .counts
+            .entry(data_gen)
+            .or_insert(0) += 1;
+        GenerationPin {
+            pins: self.clone(),
+            data_gen,
+        }
+    }
+
+    pub fn hold<I: Iterator>(entries: I, pins: Vec<GenerationPin>) -> Pinned<I> {
+        Pinned {
+            entries,
+            _pins: pins,
+        }
+    }
+
+    /// Remove the files of a generation no longer listed, or rename them to be removed once
+    /// no pin of it is held. The data file goes first, which unlists the generation.
+    pub fn retire(&self, data_gen: DataGen) -> io::Result<()> {
+        let mut pins = self.pins.lock().unwrap();
+        if !pins.counts.contains_key(&data_gen) {
+            return self.remove(data_gen);
+        }
+        pins.retired.insert(data_gen);
+        for (file_name, retired) in Self::retired_files(&self.dir, data_gen) {
+            self.fs
+                .rename(&Path::new(&self.dir).join(file_name), &retired)?;
+        }
+        self.fs.sync_dir(Path::new(&self.dir))
+    }
+
+    /// Remove the files of generations retired under pins which an earlier open left, e.g.
+    /// as it crashed before the scans were dropped.
+    pub fn remove_leftovers(fs: &SharedFileSystem, dir: &str) -> io::Result<()> {
+        for entry in fs.read_dir(Path::new(dir))? {
+            let retired = entry
+                .name
+                .to_str()
+                .is_some_and(|name| name.starts_with(Self::RETIRED_PREFIX));
+            if retired && !entry.is_dir {
+                log::debug!(
+                    "removed a retired file left over. dir: {}, name: {:?}",
+                    dir,
+                    entry.name
+                );
+                fs.remove(&Path::new(dir).join(&entry.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the generation waits for its pins to go.
+    pub fn is_retired(&self, data_gen: DataGen) -> bool {
+        self.pins.lock().unwrap().retired.contains(&data_gen)
+    }
+
+    /// Remove the files of retired generations without waiting for their pins, e.g. as a
+    /// clear removes them along with the rest.
+    pub fn remove_retired(&self) -> io::Result<()> {
+        let retired = std::mem::take(&mut self.pins.lock().unwrap().retired);
+        retired
+            .into_iter()
+            .try_for_each(|data_gen| self.remove_renamed(data_gen))
+    }
+
+    fn remove(&self, data_gen: DataGen) -> io::Result<()> {
+        DataFile::clear(&self.fs, &self.dir, data_gen)?;
+        IndexFile::clear(&self.fs, data_gen, &self.dir)
+    }
+
+    fn remove_renamed(&self, data_gen: DataGen) -> io::Result<()> {
+        for (_, retired) in Self::retired_files(&self.dir, data_gen) {
+            match self.fs.remove(&retired) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                removed => removed?,
+            }
+        }
+        Ok(())
+    }
+
+    // the files of the generation along with where they go once it's retired
+    fn retired_files(dir: &str, data_gen: DataGen) -> Vec<(String, PathBuf)> {
+        let mut file_names = vec![DataFile::file_name(data_gen)];
+        file_names.extend(IndexFile::file_names(data_gen));
+        file_names
+            .into_iter()
+            .map(|file_name| {
+                let retired = Path::new(dir).join(format!("{}{}", Self::RETIRED_PREFIX, file_name));
+                (file_name, retired)
+            })
+            .collect()
+    }
+
+    fn unpin(&self, data_gen: DataGen) {
+        let mut pins = self.pins.lock().unwrap();
+        let count = pins
+            .counts
+            .get_mut(&data_gen)
+            .expect("a pinned generation is counted");
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        pins.counts.remove(&data_gen);
+        if pins.retired.remove(&data_gen) {
+            if let Err(err) = self.remove_renamed(data_gen) {
+                log::error!(
+                    "failed to remove a retired generation. data_gen: {}, err: {:?}",
+                    data_gen,
+                    err
+                );
+            }
+        }
+    }
+}
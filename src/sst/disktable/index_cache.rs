@@ -0,0 +1,45 @@
+use super::{index_file::IndexEntry, DataGen};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+/// How much `warm` loads into memory ahead of reads.
+#[derive(Debug, Clone, Default)]
+pub struct WarmConfig {
+    /// Number of the newest generations whose indexes are cached, None(default) for all.
+    pub generations: Option<usize>,
+    /// Number of entries read from each warmed data file to pull it into the OS page cache.
+    pub data_samples: usize,
+}
+
+// whole indexes of generations kept in memory, so lookups don't read index files
+#[derive(Default)]
+pub(crate) struct IndexCache {
+    indexes: RwLock<HashMap<DataGen, Arc<BTreeMap<String, IndexEntry>>>>,
+}
+
+impl IndexCache {
+    pub fn get(&self, data_gen: DataGen) -> Option<Arc<BTreeMap<String, IndexEntry>>> {
+        self.indexes.read().unwrap().get(&data_gen).cloned()
+    }
+
+    pub fn insert(&self, data_gen: DataGen, index: BTreeMap<String, IndexEntry>) {
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(data_gen, Arc::new(index));
+    }
+
+    pub fn evict(&self, data_gen: DataGen) {
+        self.indexes.write().unwrap().remove(&data_gen);
+    }
+
+    pub fn clear(&self) {
+        self.indexes.write().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.indexes.read().unwrap().len()
+    }
+}
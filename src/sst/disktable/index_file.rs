@@ -1,13 +1,27 @@
 use super::*;
-use crate::sst::rich_file::*;
 use byte_utils::*;
+use file_system::{HandleReader, HandleWriter, OpenedFile, SharedFileSystem};
 use io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write};
-use std::{fmt::Debug, io::BufReader};
+use std::{
+    fmt::Debug,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
 
 pub(crate) struct IndexFile {
+    fs: SharedFileSystem,
+    dir: String,
     data_gen: DataGen,
-    file: RichFile,
-    skip_index_file: RichFile,
+    file: OpenedFile,
+    skip_index_file: OpenedFile,
+    // whether loading the whole index fails if its keys are out of order
+    verify_order: bool,
+    // whether keys are written as the suffix after the prefix shared with the previous key
+    front_coded: bool,
+    // whether entries inlining an empty value are written as existence records
+    existence_records: bool,
+    // entries between the ones the skip index written by `create_index` points at
+    skip_interval: usize,
 }
 
 impl Debug for IndexFile {
@@ -21,153 +35,579 @@ impl Debug for IndexFile {
         )
     }
 }
+/// Where an entry lives. `data_gen` is the generation of the data file holding the entry,
+/// which is usually the generation of the index itself. Yet an index may point into
+/// another generation's data file, e.g. a data file shared by compacted indexes,
+/// so readers must always fetch from `data_gen` rather than from the index's generation.
+/// A small value is also inlined in the entry, which saves a read of the data file.
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct IndexEntry {
-    pub key: String,
     pub data_gen: DataGen,
     pub offset: Offset,
+    pub kind: EntryKind,
+    pub inline_value: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EntryKind {
+    Value,
+    Tombstone,
+}
+impl EntryKind {
+    // written instead of the kind of a value inlined in the index entry
+    pub const INLINE_VALUE: u8 = 2;
+    // written instead of the kind of an existence record, a key with no value, which reads
+    // as an inlined empty value without a value length following it
+    pub const EXISTENCE: u8 = 3;
+
+    fn as_byte(&self) -> u8 {
+        match self {
+            EntryKind::Value => 0,
+            EntryKind::Tombstone => 1,
+        }
+    }
+    // Err(InvalidData) for a byte no format version this reads writes
+    pub fn from_byte(byte: u8) -> io::Result<EntryKind> {
+        match byte {
+            0 | Self::INLINE_VALUE | Self::EXISTENCE => Ok(EntryKind::Value),
+            1 => Ok(EntryKind::Tombstone),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown entry kind: {}", byte),
+            )),
+        }
+    }
 }
 
 impl IndexFile {
     const INDEX_DELIMITER: &'static str = "\t";
     const INDEX_FILE_NAME: &'static str = "index";
+    // the prefix of the file naming the data file renamed into place ahead of the index
+    // of its generation, left until the index is renamed after it
+    const PUBLISHING_FILE_PREFIX: &'static str = "publishing";
+    // the bit set in the key length of a front coded entry, whose other bits are the length
+    // of the prefix shared with the previous key
+    pub const FRONT_CODED: usize = 1 << 31;
+    // entries between the ones the skip index points at, which are restart points,
+    // unless the skip index says otherwise
+    const SKIP_INDEX_NUM: usize = 30;
+    // version of the layout of the indexes written, which the skip index records. 2 added
+    // existence records, and a skip index recording no version is of 1
+    const FORMAT_VERSION: usize = 2;
+    // bytes of a line of the skip index besides its key, assuming 8 digits of offset
+    const SKIP_LINE_OVERHEAD: usize = 10;
 
-    pub fn of(data_gen: DataGen, dir: &str) -> IndexFile {
+    pub fn of(fs: &SharedFileSystem, data_gen: DataGen, dir: &str) -> IndexFile {
         IndexFile {
+            fs: fs.clone(),
+            dir: dir.to_string(),
             data_gen,
-            file: Self::index_file(dir, &data_gen),
-            skip_index_file: Self::skip_index_file(dir, &data_gen),
+            file: OpenedFile::open_or_create(fs.as_ref(), dir, &Self::index_file_name(&data_gen))
+                .expect("failed to open index file"),
+            skip_index_file: OpenedFile::open_or_create(
+                fs.as_ref(),
+                dir,
+                &Self::skip_index_file_name(&data_gen),
+            )
+            .expect("failed to open skip index file"),
+            verify_order: false,
+            front_coded: false,
+            existence_records: false,
+            skip_interval: Self::SKIP_INDEX_NUM,
         }
     }
-    fn index_file(dir_name: &str, data_gen: &DataGen) -> RichFile {
-        RichFile::open_file(
-            dir_name,
-            format!("{}_{}", Self::INDEX_FILE_NAME, data_gen),
-            FileOption::Append,
-        )
-        .expect("failed to open index file")
+    // `of` which fails with NotFound rather than creating missing index files
+    pub fn open(fs: &SharedFileSystem, data_gen: DataGen, dir: &str) -> io::Result<IndexFile> {
+        Ok(IndexFile {
+            fs: fs.clone(),
+            dir: dir.to_string(),
+            data_gen,
+            file: OpenedFile::open(fs.as_ref(), dir, &Self::index_file_name(&data_gen))?,
+            skip_index_file: OpenedFile::open(
+                fs.as_ref(),
+                dir,
+                &Self::skip_index_file_name(&data_gen),
+            )?,
+            verify_order: false,
+            front_coded: false,
+            existence_records: false,
+            skip_interval: Self::SKIP_INDEX_NUM,
+        })
     }
 
-    fn skip_index_file(dir_name: &str, data_gen: &DataGen) -> RichFile {
-        RichFile::open_file(
-            dir_name,
-            format!("{}_{}_skip", Self::INDEX_FILE_NAME, data_gen),
-            FileOption::Append,
-        )
-        .expect("failed to open skip index file")
+    // keys of an index out of order, e.g. written by a bug, make searches of it miss keys
+    // silently, so they're caught when the index is loaded rather than looked up
+    pub fn verify_order(mut self, verify_order: bool) -> IndexFile {
+        self.verify_order = verify_order;
+        self
+    }
+
+    // keys written by `create_index` other than the ones of restart points, which the skip
+    // index seeks to, are front coded. indexes are read either way
+    pub fn front_coded(mut self, front_coded: bool) -> IndexFile {
+        self.front_coded = front_coded;
+        self
+    }
+
+    // entries of empty values inlined by a data file written with existence records are
+    // written as existence records by `create_index`. indexes are read either way
+    pub fn existence_records(mut self, existence_records: bool) -> IndexFile {
+        self.existence_records = existence_records;
+        self
+    }
+
+    // entries between the ones the skip index written by `create_index` points at.
+    // the index is read with the interval written in the skip index either way
+    pub fn skip_interval(mut self, skip_interval: usize) -> IndexFile {
+        self.skip_interval = skip_interval.max(1);
+        self
+    }
+
+    // the densest skip interval keeping the skip index of the entries within the fraction
+    // of the bytes of their data file, estimated from their number and average key length
+    pub fn skip_interval_within(
+        budget: f64,
+        index_entries: &BTreeMap<String, IndexEntry>,
+        data_bytes: u64,
+    ) -> usize {
+        let num = index_entries.len();
+        if num == 0 {
+            return Self::SKIP_INDEX_NUM;
+        }
+        let key_bytes: usize = index_entries.keys().map(String::len).sum();
+        let line_bytes = key_bytes as f64 / num as f64 + Self::SKIP_LINE_OVERHEAD as f64;
+        let lines = (budget * data_bytes as f64 / line_bytes).floor().max(1.0);
+        ((num as f64 / lines).ceil() as usize).max(1)
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn verifies_order(&self) -> bool {
+        self.verify_order
+    }
+
+    // Err(InvalidData) naming the position unless the key at it sorts at or after
+    // the previous one, byte-wise as in the file
+    pub fn check_order(&self, position: usize, previous: &[u8], key: &[u8]) -> io::Result<()> {
+        if previous <= key {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "index of generation {} is out of order at entry {}: {:?} after {:?}",
+                self.data_gen,
+                position,
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(previous)
+            ),
+        ))
+    }
+
+    fn index_file_name(data_gen: &DataGen) -> String {
+        format!("{}_{}", Self::INDEX_FILE_NAME, data_gen)
+    }
+
+    fn skip_index_file_name(data_gen: &DataGen) -> String {
+        format!("{}_{}_skip", Self::INDEX_FILE_NAME, data_gen)
+    }
+
+    // names of the index and skip index files of the generation
+    pub fn file_names(data_gen: DataGen) -> [String; 2] {
+        [
+            Self::index_file_name(&data_gen),
+            Self::skip_index_file_name(&data_gen),
+        ]
     }
 
     /* index file layout
-    [key len][key][offset in data file]\0...
+    [key len][key][data gen][offset in data file][entry kind]([value len][value])...
+    <-4 byte-><key_len><-4 byte-><------8 byte-------><-1 byte-><-4 byte--><value_len>
+    entry kind is 0 for a value, 1 for a tombstone and 2 for a value inlined
+    in the entry, which is followed by the value length and the value. from format version 2,
+    it's 3 for an existence record, an inlined empty value with no value length following it.
+    a front coded entry starts with [FRONT_CODED | shared len][suffix len][suffix] instead of
+    [key len][key], where the key is the first shared len bytes of the previous key followed
+    by the suffix. the first entry and the ones the skip index points at are never front coded
     */
-    pub fn find_index(&self, key: &str) -> Option<IndexEntry> {
-        let start_offset = self.find_index_seek_from(key);
-        let mut index = &self.file.underlying;
-        index.seek(SeekFrom::Start(start_offset)).unwrap();
-        loop {
-            let mut key_len: [u8; 4] = [0; 4];
-            let res = index.read_exact(&mut key_len);
-            if res.is_err() {
-                // log::trace!("failed to read. err: {:?}", res);
-                return None;
+    pub fn find_index(&self, key: &str) -> io::Result<Option<IndexEntry>> {
+        let (start_offset, skip_interval) = self.find_index_seek_from(key)?;
+        let mut index = self.file.reader();
+        index.seek(SeekFrom::Start(start_offset))?;
+        let mut index = BufReader::new(index);
+        let mut previous = vec![];
+        // the key lies at or before the entry the next line of the skip index points at
+        for _ in 0..=skip_interval {
+            let (_key, entry) = match Self::read_next(&mut index, &previous)? {
+                Some(next) => next,
+                None => break,
+            };
+            if _key == *key {
+                return Ok(Some(entry));
             }
+            previous = _key.into_bytes();
+        }
+        Ok(None)
+    }
 
-            let key_len = ByteUtils::as_usize(&key_len);
-            if key_len == 0 {
-                return None;
+    // every entry of the index in key order
+    pub fn read_all(&self) -> io::Result<BTreeMap<String, IndexEntry>> {
+        self.check_version()?;
+        let mut index = BufReader::new(self.file.reader());
+        let mut entries: BTreeMap<String, IndexEntry> = BTreeMap::new();
+        let mut position = 0;
+        let mut previous = String::new();
+        while let Some((key, entry)) = Self::read_next(&mut index, previous.as_bytes())? {
+            // keys before the first one out of order are sorted, so the last is the previous
+            if let Some((previous, _)) = entries.last_key_value().filter(|_| self.verify_order) {
+                self.check_order(position, previous.as_bytes(), key.as_bytes())?;
             }
-            let mut key_data = vec![0u8; key_len];
-            let res = index.read_exact(&mut key_data);
-            if res.is_err() {
-                // log::trace!("failed to read. err: {:?}", res);
-                return None;
-            } else if key_data.len() != key_len {
-                panic!(
-                    "invalid key. key_len: {}, key_data: {:?}",
-                    key_len, key_data
-                );
+            position += 1;
+            previous.clone_from(&key);
+            entries.insert(key, entry);
+        }
+        Ok(entries)
+    }
+
+    // entries of keys in [start, end) in key order, read from the restart point the skip
+    // index points at before the start rather than from the beginning
+    pub fn read_range(
+        &self,
+        start: &str,
+        end: Option<&str>,
+    ) -> io::Result<Vec<(String, IndexEntry)>> {
+        let (start_offset, _) = self.find_index_seek_from(start)?;
+        let mut index = self.file.reader();
+        index.seek(SeekFrom::Start(start_offset))?;
+        let mut index = BufReader::new(index);
+        let mut entries = vec![];
+        let mut position = 0;
+        let mut previous = String::new();
+        while let Some((key, entry)) = Self::read_next(&mut index, previous.as_bytes())? {
+            if end.is_some_and(|end| key.as_str() >= end) {
+                break;
             }
-            let _key = ByteUtils::as_string(&key_data);
-            if _key != *key {
-                index.read_exact(&mut [0; 9]).unwrap(); // offset + \0
-                continue;
+            if position > 0 && self.verify_order {
+                self.check_order(position, previous.as_bytes(), key.as_bytes())?;
             }
+            position += 1;
+            if key.as_str() >= start {
+                entries.push((key.clone(), entry));
+            }
+            previous = key;
+        }
+        Ok(entries)
+    }
 
-            let mut offset: [u8; 8] = [0; 8];
-            let res = index.read_exact(&mut offset);
-            if res.is_err() {
-                return None;
+    // the entry of the first key at least the start, read as `read_range` is
+    pub fn first_from(&self, start: &str) -> io::Result<Option<IndexEntry>> {
+        let (start_offset, _) = self.find_index_seek_from(start)?;
+        let mut index = self.file.reader();
+        index.seek(SeekFrom::Start(start_offset))?;
+        let mut index = BufReader::new(index);
+        let mut previous = String::new();
+        while let Some((key, entry)) = Self::read_next(&mut index, previous.as_bytes())? {
+            if key.as_str() >= start {
+                return Ok(Some(entry));
             }
-            let offset = ByteUtils::as_u64(&offset);
-            return Some(IndexEntry {
-                key: _key,
-                data_gen: self.data_gen,
-                offset,
-            });
+            previous = key;
         }
+        Ok(None)
+    }
+
+    // the next entry, whose key may be front coded against the previous one
+    fn read_next(
+        index: &mut impl Read,
+        previous: &[u8],
+    ) -> io::Result<Option<(String, IndexEntry)>> {
+        let mut key_len: [u8; 4] = [0; 4];
+        if !ByteUtils::read_exact_or_eof(index, &mut key_len)? {
+            return Ok(None);
+        }
+        let key_len = ByteUtils::as_usize(&key_len);
+        // keys are never empty, so a key length of 0 ends the index like the end of the file
+        if key_len == 0 {
+            return Ok(None);
+        }
+        let (shared, key_len) = if key_len & Self::FRONT_CODED != 0 {
+            let mut suffix_len: [u8; 4] = [0; 4];
+            if !ByteUtils::read_exact_or_eof(index, &mut suffix_len)? {
+                return Ok(None);
+            }
+            let shared = Self::shared_prefix(key_len, previous)?;
+            (shared, ByteUtils::as_usize(&suffix_len))
+        } else {
+            (&[][..], key_len)
+        };
+        let mut key_data = vec![0u8; key_len];
+        // data gen + offset + entry kind
+        let mut rest: [u8; 13] = [0; 13];
+        if !ByteUtils::read_exact_or_eof(index, &mut key_data)?
+            || !ByteUtils::read_exact_or_eof(index, &mut rest)?
+        {
+            return Ok(None);
+        }
+        let key_data = [shared, &key_data].concat();
+        let inline_value = if rest[12] == EntryKind::INLINE_VALUE {
+            let mut value_len: [u8; 4] = [0; 4];
+            if !ByteUtils::read_exact_or_eof(index, &mut value_len)? {
+                return Ok(None);
+            }
+            let mut value_data = vec![0u8; ByteUtils::as_usize(&value_len)];
+            if !ByteUtils::read_exact_or_eof(index, &mut value_data)? {
+                return Ok(None);
+            }
+            Some(ByteUtils::as_string(&value_data))
+        } else if rest[12] == EntryKind::EXISTENCE {
+            Some(String::new())
+        } else {
+            None
+        };
+        let entry = IndexEntry {
+            data_gen: ByteUtils::as_i32(&rest[0..4]),
+            offset: ByteUtils::as_u64(&rest[4..12]),
+            kind: EntryKind::from_byte(rest[12])?,
+            inline_value,
+        };
+        Ok(Some((ByteUtils::as_string(&key_data), entry)))
+    }
+
+    // the prefix of the previous key a front coded entry shares by its key length field,
+    // Err(InvalidData) if the previous key is shorter, e.g. the entry was read first
+    pub fn shared_prefix(key_len: usize, previous: &[u8]) -> io::Result<&[u8]> {
+        let shared = key_len & !Self::FRONT_CODED;
+        previous.get(..shared).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "front coded index entry shares {} bytes of a key of {}",
+                    shared,
+                    previous.len()
+                ),
+            )
+        })
     }
 
     /* skip file layout:
-    [key 0]\t[offset in index file]
-    [key N]\t[offset in index file]
-    [key2N]\t[offset in index file]
+    \t[N]\t[format version]
+    [key N-1]\t[offset in index file]
+    [key2N-1]\t[offset in index file]
     ...
+    the first line has no key, as keys are never empty, and holds the interval N along
+    with the format version of the index. skip files without it were written with an
+    interval of SKIP_INDEX_NUM, and ones without the version hold an index of version 1
     */
-    fn find_index_seek_from(&self, key: &str) -> Offset {
-        let mut lines = BufReader::new(&self.skip_index_file.underlying).lines();
+    // the offset in the index file to scan from for the key, along with the interval
+    fn find_index_seek_from(&self, key: &str) -> io::Result<(Offset, usize)> {
+        let lines = BufReader::new(self.skip_index_file.reader()).lines();
         let mut last_offset = 0;
-        lines
-            .find_map(|line| {
-                let line = line.expect("failed to read a line");
-
-                let res: Vec<_> = line.split(Self::INDEX_DELIMITER).collect();
-                let _key = res[0].to_string();
-                let offset = res[1].parse::<Offset>().unwrap();
-                if key <= _key.as_ref() {
-                    Some(last_offset)
-                } else {
-                    last_offset = offset;
-                    None
-                }
-            })
-            .unwrap_or(last_offset)
+        let mut skip_interval = Self::SKIP_INDEX_NUM;
+        for line in lines {
+            let line = line?;
+            let (_key, field) = self.split_skip_line(&line)?;
+            if _key.is_empty() {
+                skip_interval = self.parse_header(field)?;
+                continue;
+            }
+            let offset = field
+                .parse::<Offset>()
+                .map_err(|_| self.malformed_skip_line(&line))?;
+            if key <= _key {
+                break;
+            }
+            last_offset = offset;
+        }
+        Ok((last_offset, skip_interval))
     }
 
-    pub fn create_index(&self, index_entries: &BTreeMap<&String, Offset>) -> io::Result<()> {
-        let dir_name = &self.file.dir;
-        let new_index_file = RichFile::open_file(
-            dir_name,
-            format!("tmp_index_{}", self.data_gen),
-            FileOption::New,
-        )?;
-        let mut index_writer = BufWriter::new(&new_index_file.underlying);
+    fn malformed_skip_line(&self, line: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "malformed skip index line of generation {}: {:?}",
+                self.data_gen, line
+            ),
+        )
+    }
+
+    // the key and the field following it of a line of the skip index
+    fn split_skip_line<'a>(&self, line: &'a str) -> io::Result<(&'a str, &'a str)> {
+        line.split_once(Self::INDEX_DELIMITER)
+            .ok_or_else(|| self.malformed_skip_line(line))
+    }
+
+    // the interval of the header of the skip index, failing with InvalidData for an index
+    // of a format version newer than this reads, which may hold entries it can't read
+    fn parse_header(&self, field: &str) -> io::Result<usize> {
+        let (skip_interval, version) = match field.split_once(Self::INDEX_DELIMITER) {
+            Some((skip_interval, version)) => (skip_interval, version.parse().ok()),
+            None => (field, Some(1)),
+        };
+        let skip_interval = skip_interval
+            .parse()
+            .map_err(|_| self.malformed_skip_line(field))?;
+        match version {
+            Some(version) if version <= Self::FORMAT_VERSION => Ok(skip_interval),
+            Some(version) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index of generation {} is of format version {}, newer than {}",
+                    self.data_gen,
+                    version,
+                    Self::FORMAT_VERSION
+                ),
+            )),
+            None => Err(self.malformed_skip_line(field)),
+        }
+    }
+
+    // Err(InvalidData) unless the index is of a format version this reads, for a read of
+    // the index which doesn't go through the skip index otherwise
+    pub fn check_version(&self) -> io::Result<()> {
+        let mut header = String::new();
+        BufReader::new(self.skip_index_file.reader()).read_line(&mut header)?;
+        match self.split_skip_line(header.trim_end_matches('\n')) {
+            Ok(("", field)) => self.parse_header(field).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    // keys the skip index points at in key order, along with the interval between them
+    pub fn skip_samples(&self) -> io::Result<(Vec<String>, usize)> {
+        let mut keys = vec![];
+        let mut skip_interval = Self::SKIP_INDEX_NUM;
+        for line in BufReader::new(self.skip_index_file.reader()).lines() {
+            let line = line?;
+            let (key, field) = self.split_skip_line(&line)?;
+            if key.is_empty() {
+                skip_interval = self.parse_header(field)?;
+            } else {
+                keys.push(key.to_string());
+            }
+        }
+        Ok((keys, skip_interval))
+    }
+
+    // keys of the entries past the last one the skip index points at in key order, which
+    // are fewer than the interval. they're every key of an index shorter than it
+    pub fn unsampled_keys(&self) -> io::Result<Vec<String>> {
+        let mut last_offset = None;
+        for line in BufReader::new(self.skip_index_file.reader()).lines() {
+            let line = line?;
+            let (key, field) = self.split_skip_line(&line)?;
+            if key.is_empty() {
+                self.parse_header(field)?;
+                continue;
+            }
+            let offset = field
+                .parse::<Offset>()
+                .map_err(|_| self.malformed_skip_line(&line))?;
+            last_offset = Some(offset);
+        }
+        let mut index = self.file.reader();
+        index.seek(SeekFrom::Start(last_offset.unwrap_or(0)))?;
+        let mut index = BufReader::new(index);
+        let mut keys: Vec<String> = vec![];
+        while let Some((key, _)) =
+            Self::read_next(&mut index, keys.last().map_or(b"", |key| key.as_bytes()))?
+        {
+            keys.push(key);
+        }
+        // the entry the skip index points at last isn't past it
+        if last_offset.is_some() && !keys.is_empty() {
+            keys.remove(0);
+        }
+        Ok(keys)
+    }
+
+    // `write_unpublished` published right away, for indexes whose data files are in place
+    #[cfg(test)]
+    pub fn create_index(&self, index_entries: &BTreeMap<String, IndexEntry>) -> io::Result<()> {
+        self.write_unpublished(index_entries)?.publish()
+    }
 
-        let new_skip_index_file = RichFile::open_file(dir_name, "tmp_skip_index", FileOption::New)?;
-        let mut skip_index_writer = BufWriter::new(&new_skip_index_file.underlying);
+    // `create_index` into temporary files, which aren't read until they're published
+    pub fn write_unpublished(
+        &self,
+        index_entries: &BTreeMap<String, IndexEntry>,
+    ) -> io::Result<UnpublishedIndex> {
+        let fs = self.fs.as_ref();
+        let new_index_file =
+            OpenedFile::create(fs, &self.dir, &Self::tmp_index_file_name(self.data_gen))?;
+        let new_index_path = new_index_file.path();
+        let mut index_writer = BufWriter::new(HandleWriter(new_index_file.handle));
+
+        let new_skip_index_file = OpenedFile::create(
+            fs,
+            &self.dir,
+            &Self::tmp_skip_index_file_name(self.data_gen),
+        )?;
+        let new_skip_index_path = new_skip_index_file.path();
+        let mut skip_index_writer = BufWriter::new(HandleWriter(new_skip_index_file.handle));
         let mut index_offset = 0;
         let num = index_entries.len();
-        let skip_index_num = 30;
+        let skip_index_num = self.skip_interval;
+        let mut previous: &[u8] = &[];
+        skip_index_writer.write_all(
+            format!(
+                "{}{}{}{}\n",
+                Self::INDEX_DELIMITER,
+                skip_index_num,
+                Self::INDEX_DELIMITER,
+                Self::FORMAT_VERSION
+            )
+            .as_bytes(),
+        )?;
         (0..num)
             .zip(index_entries.iter())
-            .for_each(|(idx, (key, offset))| {
+            .for_each(|(idx, (key, entry))| {
                 let key_bytes = key.as_bytes();
-                let written_bytes = index_writer
-                    .write(&ByteUtils::from_usize(key_bytes.len()))
-                    .and_then(|size1| {
-                        index_writer.write(key_bytes).and_then(|size2| {
-                            index_writer
-                                .write(&ByteUtils::from_u64(*offset))
-                                .and_then(|size3| {
-                                    index_writer
-                                        .write(b"\0")
-                                        .map(|size4| size1 + size2 + size3 + size4)
-                                })
-                        })
-                    })
+                let restart = idx % skip_index_num == skip_index_num - 1;
+                let shared = if self.front_coded && !restart {
+                    key_bytes
+                        .iter()
+                        .zip(previous)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                } else {
+                    0
+                };
+                previous = key_bytes;
+                let key_field = if shared > 0 {
+                    [
+                        &ByteUtils::from_usize(Self::FRONT_CODED | shared)[..],
+                        &ByteUtils::from_usize(key_bytes.len() - shared),
+                        &key_bytes[shared..],
+                    ]
+                    .concat()
+                } else {
+                    [&ByteUtils::from_usize(key_bytes.len())[..], key_bytes].concat()
+                };
+                let (kind, inline_value) = match &entry.inline_value {
+                    Some(value) if value.is_empty() && self.existence_records => {
+                        (EntryKind::EXISTENCE, vec![])
+                    }
+                    Some(value) => (
+                        EntryKind::INLINE_VALUE,
+                        [&ByteUtils::from_usize(value.len())[..], value.as_bytes()].concat(),
+                    ),
+                    None => (entry.kind.as_byte(), vec![]),
+                };
+                let bytes: Vec<u8> = [
+                    &key_field[..],
+                    &ByteUtils::from_i32(entry.data_gen),
+                    &ByteUtils::from_u64(entry.offset),
+                    &[kind],
+                    &inline_value,
+                ]
+                .concat();
+                index_writer
+                    .write_all(&bytes)
                     .expect("failed to to write bytes into BufWriter");
+                let written_bytes = bytes.len();
 
                 if idx % skip_index_num == skip_index_num - 1 {
                     skip_index_writer
-                        .write(
+                        .write_all(
                             format!("{}{}{}\n", key, Self::INDEX_DELIMITER, index_offset)
                                 .as_bytes(),
                         )
@@ -179,15 +619,176 @@ impl IndexFile {
         index_writer.flush()?;
         skip_index_writer.flush()?;
 
-        std::fs::rename(new_index_file.path(), self.file.path())?;
-        std::fs::rename(new_skip_index_file.path(), self.skip_index_file.path())?;
+        Ok(UnpublishedIndex {
+            fs: self.fs.clone(),
+            dir: self.dir.clone(),
+            data_gen: self.data_gen,
+            tmp_index_path: new_index_path,
+            tmp_skip_index_path: new_skip_index_path,
+            index_path: self.file.path(),
+            skip_index_path: self.skip_index_file.path(),
+            intended: false,
+        })
+    }
+
+    fn tmp_index_file_name(data_gen: DataGen) -> String {
+        format!("tmp_{}_{}", Self::INDEX_FILE_NAME, data_gen)
+    }
+
+    fn tmp_skip_index_file_name(data_gen: DataGen) -> String {
+        format!("tmp_{}", Self::skip_index_file_name(&data_gen))
+    }
+
+    fn publishing_file_name(data_gen: DataGen) -> String {
+        format!("{}_{}", Self::PUBLISHING_FILE_PREFIX, data_gen)
+    }
+
+    // finish or undo the publishing of the generations a crash stopped between renaming
+    // their data files and their indexes into place. the index of a generation is published
+    // if its data file was renamed, which is whenever the temporary file is gone
+    pub fn recover(fs: &SharedFileSystem, dir: &str) -> io::Result<()> {
+        let pattern = format!("{}_", Self::PUBLISHING_FILE_PREFIX);
+        let mut recovered = false;
+        for entry in fs.read_dir(Path::new(dir))? {
+            let data_gen = match entry
+                .name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&pattern))
+                .and_then(|data_gen| data_gen.parse::<DataGen>().ok())
+            {
+                Some(data_gen) => data_gen,
+                None => continue,
+            };
+            let publishing_path = Path::new(dir).join(Self::publishing_file_name(data_gen));
+            let mut data_tmp_path = String::new();
+            HandleReader::new(fs.open(&publishing_path)?.as_ref())
+                .read_to_string(&mut data_tmp_path)?;
+            let renamed = match fs.open(Path::new(&data_tmp_path)) {
+                Ok(_) => false,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => true,
+                Err(err) => return Err(err),
+            };
+            let pending = [
+                (
+                    Self::tmp_index_file_name(data_gen),
+                    Self::index_file_name(&data_gen),
+                ),
+                (
+                    Self::tmp_skip_index_file_name(data_gen),
+                    Self::skip_index_file_name(&data_gen),
+                ),
+            ];
+            for (tmp_name, name) in pending.iter() {
+                let tmp_path = Path::new(dir).join(tmp_name);
+                let published = if renamed {
+                    fs.rename(&tmp_path, &Path::new(dir).join(name))
+                } else {
+                    fs.remove(&tmp_path)
+                };
+                match published {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            log::warn!(
+                "recovered the index of generation {} in {}. published: {}",
+                data_gen,
+                dir,
+                renamed
+            );
+            fs.remove(&publishing_path)?;
+            recovered = true;
+        }
+        if recovered {
+            fs.sync_dir(Path::new(dir))?;
+        }
         Ok(())
     }
 
-    pub fn clear(data_gen: DataGen, dir: &str) -> io::Result<()> {
-        let tmp = Self::of(data_gen, dir);
-        std::fs::remove_file(tmp.file.path())?;
-        std::fs::remove_file(tmp.skip_index_file.path())?;
+    pub fn path(&self) -> std::path::PathBuf {
+        self.file.path()
+    }
+
+    pub fn clear(fs: &SharedFileSystem, data_gen: DataGen, dir: &str) -> io::Result<()> {
+        let tmp = Self::of(fs, data_gen, dir);
+        fs.remove(&tmp.file.path())?;
+        fs.remove(&tmp.skip_index_file.path())
+    }
+}
+
+/// A written index and skip index which readers can't see until `publish` renames them into
+/// place, after the data file they point into is published.
+pub(crate) struct UnpublishedIndex {
+    fs: SharedFileSystem,
+    dir: String,
+    data_gen: DataGen,
+    tmp_index_path: PathBuf,
+    tmp_skip_index_path: PathBuf,
+    index_path: PathBuf,
+    skip_index_path: PathBuf,
+    // whether the data file is recorded as being renamed ahead of the index
+    intended: bool,
+}
+
+impl UnpublishedIndex {
+    // the written index and skip index read as the index of the generation, e.g. to check
+    // them before they're published
+    pub fn read_back(&self) -> io::Result<IndexFile> {
+        Ok(IndexFile {
+            fs: self.fs.clone(),
+            dir: self.dir.clone(),
+            data_gen: self.data_gen,
+            file: OpenedFile::open(
+                self.fs.as_ref(),
+                &self.dir,
+                &IndexFile::tmp_index_file_name(self.data_gen),
+            )?,
+            skip_index_file: OpenedFile::open(
+                self.fs.as_ref(),
+                &self.dir,
+                &IndexFile::tmp_skip_index_file_name(self.data_gen),
+            )?,
+            verify_order: true,
+            front_coded: false,
+            existence_records: false,
+            skip_interval: IndexFile::SKIP_INDEX_NUM,
+        })
+    }
+
+    // make the written index and skip index durable before they're published
+    pub fn sync(&self) -> io::Result<()> {
+        self.fs.open(&self.tmp_index_path)?.sync()?;
+        self.fs.open(&self.tmp_skip_index_path)?.sync()
+    }
+
+    // record that the data file written to `data_tmp_path` is about to be renamed into place,
+    // so that `IndexFile::recover` publishes the index after a crash if the data file was
+    pub fn intend(&mut self, data_tmp_path: &Path, sync: bool) -> io::Result<()> {
+        let file = OpenedFile::create(
+            self.fs.as_ref(),
+            &self.dir,
+            &IndexFile::publishing_file_name(self.data_gen),
+        )?;
+        file.handle
+            .write(data_tmp_path.to_string_lossy().as_bytes())?;
+        if sync {
+            file.handle.sync()?;
+            self.fs.sync_dir(Path::new(&self.dir))?;
+        }
+        self.intended = true;
+        Ok(())
+    }
+
+    // replace the index and the skip index of the generation with the written ones
+    pub fn publish(self) -> io::Result<()> {
+        self.fs.rename(&self.tmp_index_path, &self.index_path)?;
+        self.fs
+            .rename(&self.tmp_skip_index_path, &self.skip_index_path)?;
+        if self.intended {
+            self.fs.remove(
+                &Path::new(&self.dir).join(IndexFile::publishing_file_name(self.data_gen)),
+            )?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,174 @@
+use super::{
+    byte_utils::ByteUtils,
+    file_system::{FileSystem, HandleWriter, OpenedFile},
+    DataGen, Offset,
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/*
+The format data and index files had before any option changed them, which a directory
+holding one generation of live entries is exported in for deployments reading only it.
+
+Data Layout:
+[entry size][key length][value length][ key data  ][value data ]\0
+<--4 byte--><--4 byte--><--4 byte----><--key_len--><-value_len->
+where entry size is 4 + 4 + key_len + 4 + value_len, a byte short of the entry.
+
+Index Layout:
+[key len][key][offset in data file]\0... of keys in byte-wise order, along with a skip
+index of "[key]\t[offset in index file]\n" lines for every 30th key.
+*/
+const DATA_GEN: DataGen = 1;
+const SKIP_INDEX_NUM: usize = 30;
+
+/// Write the entries into the directory as generation 1 of the legacy format, returning
+/// how many were written. Fails with `AlreadyExists` if the directory holds a data file,
+/// and with `InvalidInput` for a key the skip index can't hold, i.e. one with a tab or
+/// a newline.
+pub(crate) fn export(
+    fs: &dyn FileSystem,
+    out_dir: &str,
+    entries: &BTreeMap<String, String>,
+) -> io::Result<usize> {
+    fs.create_dir_all(Path::new(out_dir))?;
+    let existing = fs.read_dir(Path::new(out_dir))?.into_iter().find(|entry| {
+        entry
+            .name
+            .to_str()
+            .is_some_and(|name| name.starts_with("data_"))
+    });
+    if let Some(existing) = existing {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} holds a data file already: {:?}", out_dir, existing.name),
+        ));
+    }
+    if let Some(key) = entries.keys().find(|key| key.contains(['\t', '\n'])) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a legacy skip index can't hold the key: {:?}", key),
+        ));
+    }
+
+    let data_file = OpenedFile::create(fs, out_dir, "tmp_legacy_data")?;
+    let index_file = OpenedFile::create(fs, out_dir, "tmp_legacy_index")?;
+    let skip_index_file = OpenedFile::create(fs, out_dir, "tmp_legacy_skip_index")?;
+    let (data_path, index_path, skip_index_path) =
+        (data_file.path(), index_file.path(), skip_index_file.path());
+    let mut data_writer = BufWriter::new(HandleWriter(data_file.handle));
+    let mut index_writer = BufWriter::new(HandleWriter(index_file.handle));
+    let mut skip_index_writer = BufWriter::new(HandleWriter(skip_index_file.handle));
+    let mut offset: Offset = 0;
+    let mut index_offset = 0;
+    for (idx, (key, value)) in entries.iter().enumerate() {
+        let (key_bytes, value_bytes) = (key.as_bytes(), value.as_bytes());
+        let size = 4 + 4 + key_bytes.len() + 4 + value_bytes.len();
+        data_writer.write_all(
+            &[
+                &ByteUtils::from_usize(size)[..],
+                &ByteUtils::from_usize(key_bytes.len()),
+                &ByteUtils::from_usize(value_bytes.len()),
+                key_bytes,
+                value_bytes,
+                b"\0",
+            ]
+            .concat(),
+        )?;
+        let index_entry = [
+            &ByteUtils::from_usize(key_bytes.len())[..],
+            key_bytes,
+            &ByteUtils::from_u64(offset),
+            b"\0",
+        ]
+        .concat();
+        index_writer.write_all(&index_entry)?;
+        if idx % SKIP_INDEX_NUM == SKIP_INDEX_NUM - 1 {
+            writeln!(skip_index_writer, "{}\t{}", key, index_offset)?;
+        }
+        offset += (size + 1) as Offset;
+        index_offset += index_entry.len();
+    }
+    for writer in [data_writer, index_writer, skip_index_writer] {
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .0
+            .sync()?;
+    }
+
+    // the data file goes last, as a generation is listed by it
+    let dir = Path::new(out_dir);
+    fs.rename(&index_path, &dir.join(format!("index_{}", DATA_GEN)))?;
+    fs.rename(
+        &skip_index_path,
+        &dir.join(format!("index_{}_skip", DATA_GEN)),
+    )?;
+    fs.rename(&data_path, &dir.join(format!("data_{}", DATA_GEN)))?;
+    fs.sync_dir(dir)?;
+    Ok(entries.len())
+}
+
+/// The entries of the legacy generation in the directory, read back as the original
+/// disktable read them: by the offsets in the index, with every skip index line checked
+/// against the index entry it points at. Fails with `InvalidData` where they disagree.
+#[cfg(test)]
+pub(crate) fn read(
+    fs: &super::file_system::SharedFileSystem,
+    dir: &str,
+) -> io::Result<Vec<(String, String)>> {
+    use super::{data_file::DataFile, file_system::HandleReader};
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let index = fs.open(&Path::new(dir).join(format!("index_{}", DATA_GEN)))?;
+    let data_file = DataFile::open(fs, dir, DATA_GEN)?;
+    // the key and offset of the index entry at the reader, None at the end of the index
+    fn read_index_entry(reader: &mut impl Read) -> io::Result<Option<(String, Offset)>> {
+        let mut key_len = [0u8; 4];
+        if !ByteUtils::read_exact_or_eof(reader, &mut key_len)? {
+            return Ok(None);
+        }
+        let mut key = vec![0u8; ByteUtils::as_usize(&key_len)];
+        // offset + \0
+        let mut rest = [0u8; 9];
+        reader.read_exact(&mut key)?;
+        reader.read_exact(&mut rest)?;
+        Ok(Some((
+            ByteUtils::as_string(&key),
+            ByteUtils::as_u64(&rest[..8]),
+        )))
+    }
+
+    let mut entries = vec![];
+    let mut reader = HandleReader::new(index.as_ref());
+    while let Some((key, offset)) = read_index_entry(&mut reader)? {
+        match data_file.read_entry(offset)? {
+            Some(entry) if entry.key == key => {
+                entries.push((key, entry.value.unwrap_or_default()));
+            }
+            _ => return Err(invalid(format!("no entry of {} at {}", key, offset))),
+        }
+    }
+    let skip_index = fs.open(&Path::new(dir).join(format!("index_{}_skip", DATA_GEN)))?;
+    for line in BufReader::new(HandleReader::new(skip_index.as_ref())).lines() {
+        let line = line?;
+        let (key, index_offset) = line
+            .split_once('\t')
+            .and_then(|(key, offset)| Some((key, offset.parse::<u64>().ok()?)))
+            .ok_or_else(|| invalid(format!("malformed skip index line: {:?}", line)))?;
+        reader.seek(SeekFrom::Start(index_offset))?;
+        match read_index_entry(&mut reader)? {
+            Some((indexed, _)) if indexed == key => {}
+            _ => {
+                return Err(invalid(format!(
+                    "no index entry of {} at {}",
+                    key, index_offset
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,180 @@
+use super::{
+    file_system::{FileHandle, FileIdentity, FileSystem, HandleReader, OpenedFile},
+    DataGen,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read},
+    path::Path,
+    sync::Mutex,
+};
+
+// the least and the greatest key of a generation in the order of the comparator,
+// None for a generation without keys
+pub(crate) type KeyRange = Option<(String, String)>;
+
+/*
+File Layout:
+[data gen]\t[min key]\t[max key]\n for each live generation from the oldest, where keys are
+in hex, and a generation without keys has - for both.
+*/
+// the generations of a disktable, replaced as a whole on every change, so that a directory
+// is read as the generations it lists rather than as the data files it holds
+pub(crate) struct Manifest {
+    generations: BTreeMap<DataGen, KeyRange>,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "MANIFEST";
+    const TMP_FILE_NAME: &'static str = "tmp_MANIFEST";
+    const NO_KEY: &'static str = "-";
+
+    pub fn new(generations: BTreeMap<DataGen, KeyRange>) -> Manifest {
+        Manifest { generations }
+    }
+
+    // None if the directory has no manifest
+    pub fn load(fs: &dyn FileSystem, dir: &str) -> io::Result<Option<Manifest>> {
+        match Self::open(fs, dir)? {
+            Some(file) => Self::read(file.as_ref()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn exists(fs: &dyn FileSystem, dir: &str) -> io::Result<bool> {
+        Ok(Self::open(fs, dir)?.is_some())
+    }
+
+    fn open(fs: &dyn FileSystem, dir: &str) -> io::Result<Option<Box<dyn FileHandle>>> {
+        match fs.open(&Path::new(dir).join(Self::FILE_NAME)) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read(file: &dyn FileHandle) -> io::Result<Manifest> {
+        let mut lines = String::new();
+        HandleReader::new(file).read_to_string(&mut lines)?;
+        let generations = lines
+            .lines()
+            .map(Self::parse_line)
+            .collect::<io::Result<_>>()?;
+        Ok(Manifest { generations })
+    }
+
+    pub fn contains(&self, data_gen: DataGen) -> bool {
+        self.generations.contains_key(&data_gen)
+    }
+
+    pub fn insert(&mut self, data_gen: DataGen, key_range: KeyRange) {
+        self.generations.insert(data_gen, key_range);
+    }
+
+    // write the generations kept as the manifest in place of the one before, by a rename,
+    // so that a crash leaves either of them whole
+    pub fn write(
+        &mut self,
+        fs: &dyn FileSystem,
+        dir: &str,
+        live_gens: &BTreeSet<DataGen>,
+        sync: bool,
+    ) -> io::Result<()> {
+        self.generations
+            .retain(|data_gen, _| live_gens.contains(data_gen));
+        let lines: String = self
+            .generations
+            .iter()
+            .map(|(data_gen, key_range)| Self::format_line(*data_gen, key_range))
+            .collect();
+        let tmp_file = OpenedFile::create(fs, dir, Self::TMP_FILE_NAME)?;
+        tmp_file.handle.write(lines.as_bytes())?;
+        if sync {
+            tmp_file.handle.sync()?;
+        }
+        fs.rename(&tmp_file.path(), &Path::new(dir).join(Self::FILE_NAME))?;
+        if sync {
+            fs.sync_dir(Path::new(dir))?;
+        }
+        Ok(())
+    }
+
+    fn format_line(data_gen: DataGen, key_range: &KeyRange) -> String {
+        let hex = |key: &str| {
+            key.bytes()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+        match key_range {
+            Some((min_key, max_key)) => {
+                format!("{}\t{}\t{}\n", data_gen, hex(min_key), hex(max_key))
+            }
+            None => format!("{}\t{}\t{}\n", data_gen, Self::NO_KEY, Self::NO_KEY),
+        }
+    }
+
+    fn parse_line(line: &str) -> io::Result<(DataGen, KeyRange)> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid manifest line: {:?}", line),
+            )
+        };
+        let key = |hex: &str| {
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                        .ok_or_else(invalid)
+                })
+                .collect::<io::Result<Vec<u8>>>()?;
+            String::from_utf8(bytes).map_err(|_| invalid())
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (data_gen, min_key, max_key) = match fields.as_slice() {
+            [data_gen, min_key, max_key] => (data_gen, min_key, max_key),
+            _ => return Err(invalid()),
+        };
+        let key_range = match (*min_key, *max_key) {
+            (Self::NO_KEY, Self::NO_KEY) => None,
+            (min_key, max_key) => Some((key(min_key)?, key(max_key)?)),
+        };
+        Ok((data_gen.parse().map_err(|_| invalid())?, key_range))
+    }
+}
+
+// the generations the manifest of a directory listed when it was last read, along with the
+// identity and the size of the file they were read from, so that the manifest is parsed
+// again only once it's been replaced. it's read every time on a file system without
+// identities
+#[derive(Default)]
+pub(crate) struct ListedGenerations {
+    read: Mutex<Option<(FileIdentity, u64, BTreeSet<DataGen>)>>,
+}
+
+impl ListedGenerations {
+    // None if the directory has no manifest
+    pub fn load(&self, fs: &dyn FileSystem, dir: &str) -> io::Result<Option<BTreeSet<DataGen>>> {
+        let file = match Manifest::open(fs, dir)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let stamp = match file.identity()? {
+            Some(identity) => Some((identity, file.size()?)),
+            None => None,
+        };
+        let mut read = self.read.lock().unwrap();
+        if let (Some((identity, size, listed)), Some(stamp)) = (read.as_ref(), stamp) {
+            if (*identity, *size) == stamp {
+                return Ok(Some(listed.clone()));
+            }
+        }
+        let listed: BTreeSet<DataGen> = Manifest::read(file.as_ref())?
+            .generations
+            .into_keys()
+            .collect();
+        *read = stamp.map(|(identity, size)| (identity, size, listed.clone()));
+        Ok(Some(listed))
+    }
+}
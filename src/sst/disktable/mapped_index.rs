@@ -0,0 +1,246 @@
+use super::{
+    byte_utils::ByteUtils,
+    index_file::{EntryKind, IndexEntry, IndexFile},
+    DataGen,
+};
+use memmap2::Mmap;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::File,
+    io,
+    sync::{Arc, RwLock},
+};
+
+/// An index file mapped into memory, which is binary searched without reading the file.
+/// The mapping has to be dropped before the file is removed or replaced.
+pub(crate) struct MappedIndex {
+    // None for an empty index, which can't be mapped
+    map: Option<Mmap>,
+    // where each entry starts in the map, in key order
+    starts: Vec<usize>,
+    // positions in `starts` of the entries holding their whole keys, which are searched
+    // before the front coded entries following them are decoded
+    restarts: Vec<usize>,
+}
+
+impl MappedIndex {
+    pub fn open(index_file: &IndexFile) -> io::Result<MappedIndex> {
+        index_file.check_version()?;
+        let file = File::open(index_file.path())?;
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedIndex {
+                map: None,
+                starts: vec![],
+                restarts: vec![],
+            });
+        }
+        // the index file is replaced by rename rather than written in place,
+        // so the mapped bytes never change under the mapping
+        let map = unsafe { Mmap::map(&file)? };
+        let mut starts = vec![];
+        let mut restarts = vec![];
+        let mut previous = vec![];
+        let mut start = 0;
+        while start + 4 <= map.len() {
+            let key_len = ByteUtils::as_usize(&map[start..start + 4]);
+            let front_coded = key_len & IndexFile::FRONT_CODED != 0;
+            let key_end = if front_coded {
+                if start + 8 > map.len() {
+                    break;
+                }
+                IndexFile::shared_prefix(key_len, &previous)?;
+                start + 8 + ByteUtils::as_usize(&map[start + 4..start + 8])
+            } else {
+                start + 4 + key_len
+            };
+            let mut next = key_end + 13;
+            if key_len == 0 || next > map.len() {
+                break;
+            }
+            // so that `entry_at` reads known kinds alone
+            EntryKind::from_byte(map[next - 1])?;
+            if map[next - 1] == EntryKind::INLINE_VALUE {
+                if next + 4 > map.len() {
+                    break;
+                }
+                next += 4 + ByteUtils::as_usize(&map[next..next + 4]);
+                if next > map.len() {
+                    break;
+                }
+            }
+            let (key, _) = Self::key_at(&map, start, &previous);
+            if index_file.verifies_order() && !starts.is_empty() {
+                index_file.check_order(starts.len(), &previous, &key)?;
+            }
+            if !front_coded {
+                restarts.push(starts.len());
+            }
+            starts.push(start);
+            previous = key;
+            start = next;
+        }
+        Ok(MappedIndex {
+            map: Some(map),
+            starts,
+            restarts,
+        })
+    }
+
+    // the key of the entry at the start, decoded against the previous key if it's front
+    // coded, and where the rest of the entry follows it
+    fn key_at(map: &[u8], start: usize, previous: &[u8]) -> (Vec<u8>, usize) {
+        let key_len = ByteUtils::as_usize(&map[start..start + 4]);
+        if key_len & IndexFile::FRONT_CODED == 0 {
+            return (
+                map[start + 4..start + 4 + key_len].to_vec(),
+                start + 4 + key_len,
+            );
+        }
+        let shared = key_len & !IndexFile::FRONT_CODED;
+        let suffix_end = start + 8 + ByteUtils::as_usize(&map[start + 4..start + 8]);
+        let key = [&previous[..shared], &map[start + 8..suffix_end]].concat();
+        (key, suffix_end)
+    }
+
+    pub fn find(&self, key: &str) -> Option<IndexEntry> {
+        let map = self.map.as_ref()?;
+        let key = key.as_bytes();
+        let whole_key_of = |start: usize| {
+            let key_len = ByteUtils::as_usize(&map[start..start + 4]);
+            &map[start + 4..start + 4 + key_len]
+        };
+        // the last restart at or before the key, then the entries up to the next one
+        let restart = match self
+            .restarts
+            .binary_search_by(|position| whole_key_of(self.starts[*position]).cmp(key))
+        {
+            Ok(found) => found,
+            Err(0) => return None,
+            Err(following) => following - 1,
+        };
+        let from = self.restarts[restart];
+        let to = self
+            .restarts
+            .get(restart + 1)
+            .copied()
+            .unwrap_or(self.starts.len());
+        let mut previous = vec![];
+        for start in &self.starts[from..to] {
+            let (entry_key, rest) = Self::key_at(map, *start, &previous);
+            match entry_key.as_slice().cmp(key) {
+                Ordering::Less => previous = entry_key,
+                Ordering::Equal => return Some(Self::entry_at(map, rest)),
+                Ordering::Greater => return None,
+            }
+        }
+        None
+    }
+
+    // the entry whose fields after the key start at `rest`
+    fn entry_at(map: &[u8], rest: usize) -> IndexEntry {
+        let inline_value = if map[rest + 12] == EntryKind::INLINE_VALUE {
+            let value_len = ByteUtils::as_usize(&map[rest + 13..rest + 17]);
+            Some(ByteUtils::as_string(&map[rest + 17..rest + 17 + value_len]))
+        } else if map[rest + 12] == EntryKind::EXISTENCE {
+            Some(String::new())
+        } else {
+            None
+        };
+        IndexEntry {
+            data_gen: ByteUtils::as_i32(&map[rest..rest + 4]),
+            offset: ByteUtils::as_u64(&map[rest + 4..rest + 12]),
+            kind: EntryKind::from_byte(map[rest + 12])
+                .expect("entry kinds are checked when the index is mapped"),
+            inline_value,
+        }
+    }
+}
+
+// mapped indexes of generations, mapped on the first lookup
+#[derive(Default)]
+pub(crate) struct MappedIndexes {
+    indexes: RwLock<HashMap<DataGen, Arc<MappedIndex>>>,
+}
+
+impl MappedIndexes {
+    pub fn get_or_map(
+        &self,
+        index_file: &IndexFile,
+        data_gen: DataGen,
+    ) -> io::Result<Arc<MappedIndex>> {
+        if let Some(mapped) = self.indexes.read().unwrap().get(&data_gen) {
+            return Ok(mapped.clone());
+        }
+        let mapped = Arc::new(MappedIndex::open(index_file)?);
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(data_gen, mapped.clone());
+        Ok(mapped)
+    }
+
+    pub fn unmap(&self, data_gen: DataGen) {
+        self.indexes.write().unwrap().remove(&data_gen);
+    }
+
+    pub fn clear(&self) {
+        self.indexes.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::file_system::*, *};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_binary_search() {
+        for front_coded in [false, true] {
+            binary_search(front_coded);
+        }
+    }
+
+    fn binary_search(front_coded: bool) {
+        let dir = "./test_tmp_mapped_index";
+        std::fs::create_dir_all(dir).unwrap();
+        let fs: SharedFileSystem = Arc::new(OsFileSystem);
+        let index_file = IndexFile::of(&fs, 1, dir);
+        let index: BTreeMap<String, IndexEntry> = (0..100)
+            .map(|i| {
+                let entry = IndexEntry {
+                    data_gen: 1,
+                    offset: i * 10,
+                    kind: if i % 10 == 0 {
+                        EntryKind::Tombstone
+                    } else {
+                        EntryKind::Value
+                    },
+                    inline_value: if i % 10 == 5 {
+                        Some(format!("value-{}", i))
+                    } else {
+                        None
+                    },
+                };
+                (format!("key-{:03}", i * 2), entry)
+            })
+            .collect();
+        index_file
+            .front_coded(front_coded)
+            .create_index(&index)
+            .unwrap();
+
+        let mapped = MappedIndex::open(&IndexFile::of(&fs, 1, dir)).unwrap();
+        // the first, the last and every key in between
+        index.iter().for_each(|(key, entry)| {
+            assert_eq!(mapped.find(key).as_ref(), Some(entry), "{}", key);
+        });
+        ["key-", "key-001", "key-099", "key-199", "key-999"]
+            .iter()
+            .for_each(|key| assert_eq!(mapped.find(key), None, "{}", key));
+
+        IndexFile::clear(&fs, 1, dir).unwrap();
+        let empty = MappedIndex::open(&IndexFile::of(&fs, 1, dir)).unwrap();
+        assert_eq!(empty.find("key-000"), None);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{manifest::KeyRange, DataGen};
+use crate::sst::{bloom::BloomFilter, comparator::KeyComparator};
+use std::{cmp::Ordering, sync::Arc};
+
+// the bloom filters of a set of generations together with their key ranges, so that a
+// lookup hashes the key once and is routed to the generations which may hold it, rather
+// than testing the filter of every generation in turn
+pub(crate) struct PartitionedBloom {
+    // generations with a known key range, by their least key
+    segments: Vec<Segment>,
+    // generations whose key range is unknown, which only their filters rule out
+    unranged: Vec<(DataGen, Option<Arc<BloomFilter>>)>,
+    // whether no two segments overlap, so that a key falls in one of them at most
+    disjoint: bool,
+}
+
+struct Segment {
+    data_gen: DataGen,
+    min_key: String,
+    max_key: String,
+    // None for a generation written without a filter
+    filter: Option<Arc<BloomFilter>>,
+}
+
+impl PartitionedBloom {
+    /// Generations with their filters and key ranges, None for a range which isn't known.
+    /// A generation known to hold no key is never routed to.
+    pub fn new(
+        generations: Vec<(DataGen, Option<Arc<BloomFilter>>, Option<KeyRange>)>,
+        comparator: &dyn KeyComparator,
+    ) -> PartitionedBloom {
+        let mut segments = vec![];
+        let mut unranged = vec![];
+        for (data_gen, filter, key_range) in generations {
+            match key_range {
+                Some(Some((min_key, max_key))) => segments.push(Segment {
+                    data_gen,
+                    min_key,
+                    max_key,
+                    filter,
+                }),
+                Some(None) => {}
+                None => unranged.push((data_gen, filter)),
+            }
+        }
+        segments.sort_by(|a, b| comparator.compare(&a.min_key, &b.min_key));
+        let disjoint = segments
+            .windows(2)
+            .all(|pair| comparator.compare(&pair[0].max_key, &pair[1].min_key) == Ordering::Less);
+        PartitionedBloom {
+            segments,
+            unranged,
+            disjoint,
+        }
+    }
+
+    /// Generations which may hold the key, oldest first. A generation left out either
+    /// ranges over no key equal to it, or has a filter which rules it out.
+    pub fn candidates(&self, key: &str, comparator: &dyn KeyComparator) -> Vec<DataGen> {
+        let hash = BloomFilter::hash(key);
+        let passes = |filter: &Option<Arc<BloomFilter>>| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| filter.contains_hashed(hash))
+        };
+        let in_range = |segment: &&Segment| {
+            comparator.compare(&segment.min_key, key) != Ordering::Greater
+                && comparator.compare(key, &segment.max_key) != Ordering::Greater
+        };
+        let segments: Box<dyn Iterator<Item = &Segment>> = if self.disjoint {
+            // the last segment starting at or before the key is the only one in range
+            let after = self.segments.partition_point(|segment| {
+                comparator.compare(&segment.min_key, key) != Ordering::Greater
+            });
+            Box::new(
+                after
+                    .checked_sub(1)
+                    .map(|at| &self.segments[at])
+                    .into_iter(),
+            )
+        } else {
+            Box::new(self.segments.iter())
+        };
+        let mut candidates: Vec<DataGen> = segments
+            .filter(in_range)
+            .filter(|segment| passes(&segment.filter))
+            .map(|segment| segment.data_gen)
+            .chain(
+                self.unranged
+                    .iter()
+                    .filter(|(_, filter)| passes(filter))
+                    .map(|(data_gen, _)| *data_gen),
+            )
+            .collect();
+        candidates.sort_unstable();
+        candidates
+    }
+}
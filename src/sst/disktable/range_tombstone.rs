@@ -0,0 +1,269 @@
+use super::{
+    file_system::{HandleReader, OpenedFile, SharedFileSystem},
+    DataGen,
+};
+use crate::sst::comparator::{KeyComparator, SharedComparator};
+use std::{
+    cmp::Ordering,
+    io::{self, Read},
+    path::Path,
+};
+
+/// How keys are compared against the bounds of a range tombstone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RangeOrder {
+    // byte-wise whatever the comparator, as a prefix is a byte-wise notion
+    Bytes,
+    // by the comparator of the store, as scans bound keys
+    Comparator,
+}
+
+/// Deletion of the keys in [start, end) in the generations up to `data_gen`, compared in
+/// the order of the tombstone. An `end` of None is no upper bound.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Option<Vec<u8>>,
+    pub data_gen: DataGen,
+    pub order: RangeOrder,
+}
+
+impl RangeTombstone {
+    // every key starting with the prefix
+    pub fn prefix(prefix: &str, data_gen: DataGen) -> RangeTombstone {
+        RangeTombstone {
+            start: prefix.as_bytes().to_vec(),
+            end: prefix_upper_bound(prefix.as_bytes()),
+            data_gen,
+            order: RangeOrder::Bytes,
+        }
+    }
+
+    // every key in [start, end) in the order of the comparator
+    pub fn range(start: &str, end: &str, data_gen: DataGen) -> RangeTombstone {
+        RangeTombstone {
+            start: start.as_bytes().to_vec(),
+            end: Some(end.as_bytes().to_vec()),
+            data_gen,
+            order: RangeOrder::Comparator,
+        }
+    }
+
+    pub fn covers(&self, key: &str, data_gen: DataGen, comparator: &dyn KeyComparator) -> bool {
+        let compare = |bound: &[u8]| match self.order {
+            RangeOrder::Bytes => key.as_bytes().cmp(bound),
+            // bounds are written from keys, so they're valid UTF-8 but for a corrupt file
+            RangeOrder::Comparator => match std::str::from_utf8(bound) {
+                Ok(bound) => comparator.compare(key, bound),
+                Err(_) => key.as_bytes().cmp(bound),
+            },
+        };
+        data_gen <= self.data_gen
+            && compare(&self.start) != Ordering::Less
+            && self
+                .end
+                .as_ref()
+                .is_none_or(|end| compare(end) == Ordering::Less)
+    }
+}
+
+/// The smallest bytes above every bytes starting with the prefix, i.e. the prefix without
+/// trailing 0xFF bytes and with the last byte left incremented. None if no bytes are,
+/// as the prefix is empty or made of 0xFF only.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
+/*
+File Layout:
+[data gen]\t[start]\t[end]\t[order]\n for each tombstone, where start and end are in hex
+and end is - for no upper bound. order is b for bytes and c for the comparator, and
+a line without it is in bytes.
+*/
+// range tombstones of a disktable, kept until a compaction has dropped the keys they cover
+pub(crate) struct RangeTombstones {
+    fs: SharedFileSystem,
+    dir: String,
+    tombstones: Vec<RangeTombstone>,
+    comparator: SharedComparator,
+}
+
+impl RangeTombstones {
+    const FILE_NAME: &'static str = "range_tombstones";
+    const NO_END: &'static str = "-";
+    const ORDER_BYTES: &'static str = "b";
+    const ORDER_COMPARATOR: &'static str = "c";
+
+    pub fn load(
+        fs: SharedFileSystem,
+        dir: &str,
+        comparator: SharedComparator,
+    ) -> io::Result<RangeTombstones> {
+        let path = Path::new(dir).join(Self::FILE_NAME);
+        let mut lines = String::new();
+        match fs.open(&path) {
+            Ok(file) => {
+                HandleReader::new(file.as_ref()).read_to_string(&mut lines)?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let tombstones = lines
+            .lines()
+            .map(Self::parse_line)
+            .collect::<io::Result<_>>()?;
+        Ok(RangeTombstones {
+            fs,
+            dir: dir.to_string(),
+            tombstones,
+            comparator,
+        })
+    }
+
+    pub fn add(&mut self, tombstone: RangeTombstone, sync: bool) -> io::Result<()> {
+        let file = OpenedFile::open_or_create(self.fs.as_ref(), &self.dir, Self::FILE_NAME)?;
+        file.handle
+            .write(Self::format_line(&tombstone).as_bytes())?;
+        if sync {
+            file.handle.sync()?;
+        }
+        self.tombstones.push(tombstone);
+        Ok(())
+    }
+
+    /// Whether a tombstone deletes the key in the generation.
+    pub fn covers(&self, key: &str, data_gen: DataGen) -> bool {
+        self.tombstones
+            .iter()
+            .any(|tombstone| tombstone.covers(key, data_gen, self.comparator.as_ref()))
+    }
+
+    // the tombstones over records of the generation, as a test of whether one covers a key
+    pub fn of_generation(&self, data_gen: DataGen) -> impl Fn(&str) -> bool + Send + 'static {
+        let tombstones: Vec<RangeTombstone> = self
+            .tombstones
+            .iter()
+            .filter(|tombstone| data_gen <= tombstone.data_gen)
+            .cloned()
+            .collect();
+        let comparator = self.comparator.clone();
+        move |key| {
+            tombstones
+                .iter()
+                .any(|tombstone| tombstone.covers(key, data_gen, comparator.as_ref()))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.tombstones.clear();
+        match self.fs.remove(&Path::new(&self.dir).join(Self::FILE_NAME)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            removed => removed,
+        }
+    }
+
+    fn format_line(tombstone: &RangeTombstone) -> String {
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+        let end = match tombstone.end.as_ref() {
+            Some(end) => hex(end),
+            None => Self::NO_END.to_string(),
+        };
+        let order = match tombstone.order {
+            RangeOrder::Bytes => Self::ORDER_BYTES,
+            RangeOrder::Comparator => Self::ORDER_COMPARATOR,
+        };
+        format!(
+            "{}\t{}\t{}\t{}\n",
+            tombstone.data_gen,
+            hex(&tombstone.start),
+            end,
+            order
+        )
+    }
+
+    fn parse_line(line: &str) -> io::Result<RangeTombstone> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid range tombstone: {:?}", line),
+            )
+        };
+        let bytes = |hex: &str| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                        .ok_or_else(invalid)
+                })
+                .collect::<io::Result<Vec<u8>>>()
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (data_gen, start, end, order) = match fields.as_slice() {
+            [data_gen, start, end] => (data_gen, start, end, RangeOrder::Bytes),
+            [data_gen, start, end, order] => {
+                let order = match *order {
+                    Self::ORDER_BYTES => RangeOrder::Bytes,
+                    Self::ORDER_COMPARATOR => RangeOrder::Comparator,
+                    _ => return Err(invalid()),
+                };
+                (data_gen, start, end, order)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(RangeTombstone {
+            data_gen: data_gen.parse().map_err(|_| invalid())?,
+            start: bytes(start)?,
+            end: match *end {
+                Self::NO_END => None,
+                end => Some(bytes(end)?),
+            },
+            order,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefix_upper_bound, RangeTombstone};
+    use crate::sst::comparator::Lexicographic;
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(
+            prefix_upper_bound(b"user:123:"),
+            Some(b"user:123;".to_vec())
+        );
+        assert_eq!(prefix_upper_bound(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+
+        let tombstone = RangeTombstone::prefix("user:123:", 3);
+        assert!(tombstone.covers("user:123:", 3, &Lexicographic));
+        assert!(tombstone.covers("user:123:name", 1, &Lexicographic));
+        assert!(
+            !tombstone.covers("user:123:name", 4, &Lexicographic),
+            "written after the tombstone"
+        );
+        assert!(!tombstone.covers("user:123", 1, &Lexicographic));
+        assert!(!tombstone.covers("user:1234:name", 1, &Lexicographic));
+        assert!(!tombstone.covers("user:124:", 1, &Lexicographic));
+        assert!(RangeTombstone::prefix("", 1).covers("anything", 1, &Lexicographic));
+    }
+}
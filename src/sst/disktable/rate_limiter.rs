@@ -0,0 +1,57 @@
+use super::stats::Counters;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Token bucket of the bytes a compaction reads and writes, refilled at `bytes_per_sec` and
+/// holding a tenth of a second's worth at most. It starts empty, and `acquire` sleeps while
+/// it's in debt, so that the bytes of a compaction average at most the rate from its start.
+/// Without a rate bytes are only counted. Either way the throughput of the compaction so far
+/// is kept in the counters, which `stats` reads.
+pub(crate) struct RateLimiter {
+    // None when bytes are counted without a limit
+    bytes_per_sec: Option<u64>,
+    tokens: f64,
+    refilled: Instant,
+    started: Instant,
+    taken: u64,
+    counters: Arc<Counters>,
+}
+
+impl RateLimiter {
+    pub fn start(bytes_per_sec: Option<u64>, counters: Arc<Counters>) -> RateLimiter {
+        counters.compaction_bytes.store(0, Ordering::Relaxed);
+        counters.compaction_nanos.store(0, Ordering::Relaxed);
+        let now = Instant::now();
+        RateLimiter {
+            bytes_per_sec,
+            tokens: 0.0,
+            refilled: now,
+            started: now,
+            taken: 0,
+            counters,
+        }
+    }
+
+    pub fn acquire(&mut self, bytes: usize) {
+        self.taken += bytes as u64;
+        if let Some(bytes_per_sec) = self.bytes_per_sec {
+            let rate = bytes_per_sec as f64;
+            let now = Instant::now();
+            let refill = now.duration_since(self.refilled).as_secs_f64() * rate;
+            self.tokens = (self.tokens + refill).min(rate / 10.0) - bytes as f64;
+            self.refilled = now;
+            if self.tokens < 0.0 {
+                thread::sleep(Duration::from_secs_f64(-self.tokens / rate));
+            }
+        }
+        self.counters
+            .compaction_bytes
+            .store(self.taken, Ordering::Relaxed);
+        self.counters
+            .compaction_nanos
+            .store(self.started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
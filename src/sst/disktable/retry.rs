@@ -0,0 +1,112 @@
+use std::{io, thread, time::Duration};
+
+/// How many times a disk read is attempted when it fails with a transient error,
+/// such as EINTR or EAGAIN on a networked filesystem.
+/// The wait before the n-th retry is `backoff * n`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    // no retry
+    fn default() -> Self {
+        RetryPolicy::new(1, Duration::from_millis(0))
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize, backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff,
+        }
+    }
+
+    pub fn is_transient(kind: io::ErrorKind) -> bool {
+        matches!(
+            kind,
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    }
+
+    pub(crate) fn run<T>(&self, mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(err) if attempt < self.max_attempts && Self::is_transient(err.kind()) => {
+                    log::debug!(
+                        "retrying a transient error. attempt: {}, err: {:?}",
+                        attempt,
+                        err
+                    );
+                    thread::sleep(self.backoff * attempt as u32);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::{
+        io::{self, Cursor, Read},
+        time::Duration,
+    };
+
+    // fails with the given error kind a fixed number of times, then reads from the cursor
+    struct FlakyReader {
+        failures: usize,
+        kind: io::ErrorKind,
+        attempts: usize,
+        underlying: Cursor<Vec<u8>>,
+    }
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.attempts += 1;
+            if self.attempts <= self.failures {
+                Err(io::Error::new(self.kind, "flaky"))
+            } else {
+                self.underlying.read(buf)
+            }
+        }
+    }
+    fn flaky(failures: usize, kind: io::ErrorKind) -> FlakyReader {
+        FlakyReader {
+            failures,
+            kind,
+            attempts: 0,
+            underlying: Cursor::new(b"value".to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_retry_transient_errors() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(1));
+        let mut reader = flaky(3, io::ErrorKind::WouldBlock);
+        let mut buf = [0u8; 5];
+        assert!(policy.run(|| reader.read_exact(&mut buf)).is_ok());
+        assert_eq!(&buf, b"value");
+        assert_eq!(reader.attempts, 4);
+
+        // out of the retry budget
+        let mut reader = flaky(4, io::ErrorKind::TimedOut);
+        let res = policy.run(|| reader.read_exact(&mut buf));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(reader.attempts, 4);
+    }
+
+    #[test]
+    fn test_retry_permanent_errors() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(1));
+        let mut reader = flaky(1, io::ErrorKind::PermissionDenied);
+        let mut buf = [0u8; 5];
+        let res = policy.run(|| reader.read_exact(&mut buf));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(reader.attempts, 1);
+    }
+}
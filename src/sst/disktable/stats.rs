@@ -0,0 +1,253 @@
+use super::{
+    index_file::{EntryKind, IndexEntry},
+    DataGen,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+/// Counts of key and value byte lengths in buckets split by `boundaries`.
+/// Bucket `i` counts lengths in `[boundaries[i - 1], boundaries[i])`,
+/// and the last bucket counts lengths at or above the last boundary.
+/// Tombstones are counted in `keys` only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeHistogram {
+    pub boundaries: Vec<usize>,
+    pub keys: Vec<u64>,
+    pub values: Vec<u64>,
+}
+
+impl SizeHistogram {
+    pub fn new(boundaries: &[usize]) -> SizeHistogram {
+        let mut boundaries = boundaries.to_vec();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        SizeHistogram {
+            keys: vec![0; boundaries.len() + 1],
+            values: vec![0; boundaries.len() + 1],
+            boundaries,
+        }
+    }
+
+    fn bucket(&self, len: usize) -> usize {
+        self.boundaries
+            .iter()
+            .position(|boundary| len < *boundary)
+            .unwrap_or(self.boundaries.len())
+    }
+
+    pub(crate) fn record(&mut self, key_len: usize, value_len: Option<usize>) {
+        let bucket = self.bucket(key_len);
+        self.keys[bucket] += 1;
+        if let Some(value_len) = value_len {
+            let bucket = self.bucket(value_len);
+            self.values[bucket] += 1;
+        }
+    }
+}
+
+/// Snapshot of a disktable's state and counters since it was opened.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DisktableStats {
+    pub generations: usize,
+    pub cached_indexes: usize,
+    /// Index files searched on disk, lookups served by the index cache aren't counted.
+    pub index_reads: u64,
+    /// Entries read from data files.
+    pub data_reads: u64,
+    /// Writes rejected with `Throttled`.
+    pub throttled_writes: u64,
+    /// Generations a lookup skipped as their bloom filters ruled the key out. Under batched
+    /// or partitioned checks, only the ones newer than where the lookup stopped count.
+    pub bloom_skips: u64,
+    /// Tombstones out of all entries of the generations, from 0 to 1.
+    pub tombstone_ratio: f64,
+    /// Bytes per second of keys and values the latest compaction has read and written,
+    /// which `compaction_rate_limit` of the builder caps. 0 before any compaction.
+    pub compaction_throughput: f64,
+}
+
+/// What's in a generation, read from its index and file metadata without reading
+/// its data file. Data files aren't checksummed besides the tags of records under
+/// encryption, and only values are compressed, under the `compression` feature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationInfo {
+    pub data_gen: DataGen,
+    /// Entries including tombstones.
+    pub entries: u64,
+    pub tombstones: u64,
+    /// The smallest and the largest keys by the comparator, None for an empty generation.
+    pub min_key: Option<String>,
+    pub max_key: Option<String>,
+    pub data_file_bytes: u64,
+    pub index_file_bytes: u64,
+    /// Options data files are read with, which aren't recorded in the files.
+    pub block_size: Option<usize>,
+    pub encrypted: bool,
+}
+
+/// What a `compact` removed, for an audit of what's gone from disk.
+/// Keys dropped by range tombstones aren't listed one by one, only the tombstones.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompactionReport {
+    /// Generations merged, empty if there was nothing to compact.
+    pub merged: Vec<DataGen>,
+    /// Keys whose tombstones were dropped, as no older record remains to be shadowed.
+    pub collected_tombstones: Vec<String>,
+    /// Keys which had more records than `keep_versions`, whose older ones were dropped.
+    pub dropped_versions: Vec<String>,
+    pub cleared_range_tombstones: usize,
+    /// Bytes of data and index files before the compaction less those after it.
+    pub bytes_reclaimed: u64,
+}
+
+impl CompactionReport {
+    /// A JSON object of the fields with keys in order, e.g. for an audit log.
+    pub fn to_json(&self) -> String {
+        let strings = |keys: &[String]| {
+            keys.iter()
+                .map(|key| json_string(key))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "{{\"merged\":[{}],\"collected_tombstones\":[{}],\"dropped_versions\":[{}],\
+             \"cleared_range_tombstones\":{},\"bytes_reclaimed\":{}}}",
+            self.merged
+                .iter()
+                .map(|data_gen| data_gen.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            strings(&self.collected_tombstones),
+            strings(&self.dropped_versions),
+            self.cleared_range_tombstones,
+            self.bytes_reclaimed
+        )
+    }
+}
+
+/// What a `collect_blobs` reclaimed from blob files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlobGcReport {
+    /// Blob files no live entry referenced, which were dropped.
+    pub removed: Vec<usize>,
+    /// Blob files below the live ratio, whose live values were copied into a new one.
+    pub rewritten: Vec<usize>,
+    /// Bytes of blob files dropped less the live bytes copied out of them.
+    pub bytes_reclaimed: u64,
+}
+
+/// A generation `clear_best_effort` failed to remove, and the first error it failed with.
+/// Its files may be removed in part, and it's looked up as long as its data file is left.
+#[derive(Debug)]
+pub struct ClearFailure {
+    pub data_gen: DataGen,
+    pub error: io::Error,
+}
+
+// the string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// What a health check found, e.g. for the liveness or readiness probe of a service.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HealthStatus {
+    /// Whether it's opened read only, when the directory is only listed, not written to.
+    pub read_only: bool,
+    /// Whether a sentinel file written into the directory was read back as written,
+    /// None if read only.
+    pub writable: Option<bool>,
+    /// Whether the manifest and the files of the newest generation were loaded.
+    pub generations_loadable: bool,
+    /// Whether the check was skipped as the disktable was held by another operation, e.g.
+    /// a flush or a compaction, rather than waiting for it. It's a problem too, while the
+    /// other fields are left as they are by default.
+    pub busy: bool,
+    /// What failed, a line for each check.
+    pub problems: Vec<String>,
+}
+
+impl HealthStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// How far the verification of generations on open has got.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyProgress {
+    pub generations_checked: usize,
+    pub total_generations: usize,
+    /// Bytes of data files read so far.
+    pub bytes_checked: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub index_reads: AtomicU64,
+    pub data_reads: AtomicU64,
+    pub throttled_writes: AtomicU64,
+    pub bloom_skips: AtomicU64,
+    // bytes the latest compaction has moved, and for how long it has run
+    pub compaction_bytes: AtomicU64,
+    pub compaction_nanos: AtomicU64,
+}
+
+impl Counters {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+}
+
+// (entries, tombstones) of each generation, counted from its index once
+#[derive(Debug, Default)]
+pub(crate) struct EntryCounts {
+    counts: RwLock<HashMap<DataGen, (u64, u64)>>,
+}
+
+impl EntryCounts {
+    pub fn get(&self, data_gen: DataGen) -> Option<(u64, u64)> {
+        self.counts.read().unwrap().get(&data_gen).copied()
+    }
+
+    pub fn record(&self, data_gen: DataGen, index: &BTreeMap<String, IndexEntry>) -> (u64, u64) {
+        let tombstones = index
+            .values()
+            .filter(|entry| entry.kind == EntryKind::Tombstone)
+            .count() as u64;
+        let counts = (index.len() as u64, tombstones);
+        self.counts.write().unwrap().insert(data_gen, counts);
+        counts
+    }
+
+    pub fn forget(&self, data_gen: DataGen) {
+        self.counts.write().unwrap().remove(&data_gen);
+    }
+
+    pub fn clear(&self) {
+        self.counts.write().unwrap().clear();
+    }
+}
@@ -0,0 +1,34 @@
+use std::{error::Error, fmt, io};
+
+/// The error wrapped in `io::Error` when a write is rejected because too many generations
+/// are waiting for compaction. Writes are accepted again once `compact` or
+/// `merge_generations` brings the generations down.
+#[derive(Debug)]
+pub struct Throttled {
+    pub generations: usize,
+    pub max_generations: usize,
+}
+
+impl Throttled {
+    pub fn is_throttled(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<Throttled>())
+    }
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "write was throttled. generations: {}, max_generations: {}",
+            self.generations, self.max_generations
+        )
+    }
+}
+
+impl Error for Throttled {}
+
+impl From<Throttled> for io::Error {
+    fn from(throttled: Throttled) -> io::Error {
+        io::Error::other(throttled)
+    }
+}
@@ -0,0 +1,102 @@
+use super::{DataGen, Offset};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+// values read from data files by where their records sit, evicting the least recently read
+// ones past the capacity in bytes. values over `max_value_bytes` aren't admitted, so that
+// a single large value doesn't evict the many small ones read often
+pub(crate) struct ValueCache {
+    capacity: usize,
+    max_value_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    // values along with the tick they were read at last
+    values: HashMap<(DataGen, Offset), (String, u64)>,
+    // where the values sit by the tick they were read at last, the least recent first
+    recency: BTreeMap<u64, (DataGen, Offset)>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, at: (DataGen, Offset)) -> Option<&String> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (value, read_at) = self.values.get_mut(&at)?;
+        self.recency.remove(read_at);
+        self.recency.insert(tick, at);
+        *read_at = tick;
+        Some(value)
+    }
+
+    fn remove(&mut self, at: (DataGen, Offset)) {
+        if let Some((value, read_at)) = self.values.remove(&at) {
+            self.recency.remove(&read_at);
+            self.bytes -= value.len();
+        }
+    }
+}
+
+impl ValueCache {
+    /// Values up to `capacity` bytes in all, of which a value is admitted only if it's at
+    /// most `max_fraction` of them.
+    pub fn new(capacity: usize, max_fraction: f64) -> ValueCache {
+        ValueCache {
+            capacity,
+            max_value_bytes: (capacity as f64 * max_fraction) as usize,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn get(&self, data_gen: DataGen, offset: Offset) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .touch((data_gen, offset))
+            .cloned()
+    }
+
+    pub fn insert(&self, data_gen: DataGen, offset: Offset, value: &str) {
+        if value.len() > self.max_value_bytes {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let at = (data_gen, offset);
+        state.remove(at);
+        while state.bytes + value.len() > self.capacity {
+            match state.recency.values().next() {
+                Some(least_recent) => {
+                    let least_recent = *least_recent;
+                    state.remove(least_recent);
+                }
+                None => break,
+            }
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.recency.insert(tick, at);
+        state.values.insert(at, (value.to_string(), tick));
+        state.bytes += value.len();
+    }
+
+    // drop the values of a generation before its data file is replaced or removed
+    pub fn evict(&self, data_gen: DataGen) {
+        let mut state = self.state.lock().unwrap();
+        let evicted: Vec<(DataGen, Offset)> = state
+            .values
+            .keys()
+            .filter(|at| at.0 == data_gen)
+            .copied()
+            .collect();
+        evicted.into_iter().for_each(|at| state.remove(at));
+    }
+
+    pub fn clear(&self) {
+        *self.state.lock().unwrap() = CacheState::default();
+    }
+}
@@ -0,0 +1,105 @@
+use super::{
+    data_file::{DataEntry, DataFile},
+    retry::RetryPolicy,
+    stats::Counters,
+    DataGen, Offset,
+};
+use std::{fmt, io, sync::Arc};
+
+/// A value yielded by `SSTable::scan_lazy`, which is read only when `load` is called.
+/// A value on disk is held as the generation and offset of its record along with its data
+/// file held open, so it loads the value as of the scan even after a compaction.
+pub struct ValueHandle {
+    inner: Inner,
+}
+
+enum Inner {
+    // a value in a memtable or inlined in an index entry
+    Loaded(String),
+    OnDisk {
+        key: String,
+        data_gen: DataGen,
+        offset: Offset,
+        data_file: Arc<DataFile>,
+        retry_policy: RetryPolicy,
+        counters: Arc<Counters>,
+    },
+}
+
+impl ValueHandle {
+    pub(crate) fn loaded(value: String) -> ValueHandle {
+        ValueHandle {
+            inner: Inner::Loaded(value),
+        }
+    }
+
+    pub(crate) fn on_disk(
+        key: String,
+        data_gen: DataGen,
+        offset: Offset,
+        data_file: Arc<DataFile>,
+        retry_policy: RetryPolicy,
+        counters: Arc<Counters>,
+    ) -> ValueHandle {
+        ValueHandle {
+            inner: Inner::OnDisk {
+                key,
+                data_gen,
+                offset,
+                data_file,
+                retry_policy,
+                counters,
+            },
+        }
+    }
+
+    /// Where the value lives on disk, None if it was scanned from memory or inlined.
+    pub fn location(&self) -> Option<(DataGen, Offset)> {
+        match &self.inner {
+            Inner::Loaded(_) => None,
+            Inner::OnDisk {
+                data_gen, offset, ..
+            } => Some((*data_gen, *offset)),
+        }
+    }
+
+    /// Read the value. Fails with `InvalidData` if the record at the offset isn't a value
+    /// of the key, e.g. the data file is corrupted.
+    pub fn load(self) -> io::Result<String> {
+        match self.inner {
+            Inner::Loaded(value) => Ok(value),
+            Inner::OnDisk {
+                key,
+                data_gen,
+                offset,
+                data_file,
+                retry_policy,
+                counters,
+            } => {
+                Counters::incr(&counters.data_reads);
+                match retry_policy.run(|| data_file.read_entry(offset))? {
+                    Some(DataEntry {
+                        key: found,
+                        value: Some(value),
+                        ..
+                    }) if found == key => Ok(value),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "no value of the key at the offset. key: {}, data_gen: {}, offset: {}",
+                            key, data_gen, offset
+                        ),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ValueHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueHandle")
+            .field("location", &self.location())
+            .finish()
+    }
+}
@@ -0,0 +1,49 @@
+use crate::sst::disktable::DataGen;
+use log;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+/// Changes on disk, sent to receivers returned by `SSTable::subscribe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreEvent {
+    /// A memtable was written into generation `gen` with `keys` entries and tombstones.
+    Flushed {
+        gen: DataGen,
+        keys: usize,
+        bytes: u64,
+    },
+    /// Generations in `merged` were merged into `result`.
+    Compacted {
+        merged: Vec<DataGen>,
+        result: DataGen,
+    },
+    Cleared,
+}
+
+// senders of subscribed channels. A full channel drops new events rather than blocking the store
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Vec<SyncSender<StoreEvent>>,
+}
+
+impl Subscribers {
+    const CHANNEL_CAPACITY: usize = 64;
+
+    pub fn subscribe(&mut self) -> Receiver<StoreEvent> {
+        let (sender, receiver) = mpsc::sync_channel(Self::CHANNEL_CAPACITY);
+        self.senders.push(sender);
+        receiver
+    }
+
+    pub fn publish(&mut self, event: StoreEvent) {
+        self.senders
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(dropped)) => {
+                    log::debug!("a subscriber is full, dropped {:?}", dropped);
+                    true
+                }
+                // the receiver is gone
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
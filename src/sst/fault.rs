@@ -0,0 +1,37 @@
+use std::{io, sync::Arc};
+
+/// Points of a flush or a compaction in order, where a crash leaves the files as they are
+/// at the point. Compactions go through `Written` and `Synced` for each generation they write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CrashPoint {
+    /// The data and index files of the new generation are written but not synced.
+    Written,
+    /// The files of the new generation are durable but the data file isn't renamed into place.
+    Synced,
+    /// The data file of the new generation is renamed into place but its index isn't.
+    Renamed,
+    /// The new generation is published but the WAL still covers its entries.
+    Published,
+    /// The generations written by a compaction are published but the manifest doesn't list
+    /// them in place of the ones merged.
+    Merged,
+    /// The manifest lists the generations written by a compaction but the files of the ones
+    /// merged are left.
+    Committed,
+}
+
+/// Injects faults into flushes and compactions to test crash consistency.
+/// A point failing with an error stops the flush or the compaction there, as a crash would.
+pub(crate) trait FaultInjector: Send + Sync {
+    fn at(&self, point: CrashPoint) -> io::Result<()>;
+}
+
+pub(crate) type SharedFaultInjector = Arc<dyn FaultInjector>;
+
+// Ok(()) without an injector
+pub(crate) fn inject(injector: Option<&SharedFaultInjector>, point: CrashPoint) -> io::Result<()> {
+    match injector {
+        Some(injector) => injector.at(point),
+        None => Ok(()),
+    }
+}
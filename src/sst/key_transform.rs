@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+/// Normalization applied to every key before it's written or looked up.
+/// `name` identifies the transform, and is recorded in the directory so that
+/// a store is never opened with another normalization than it was written with.
+pub trait KeyTransform: Send + Sync {
+    fn name(&self) -> &str;
+    fn transform(&self, key: &str) -> String;
+}
+
+pub(crate) type SharedKeyTransform = Arc<dyn KeyTransform>;
+
+/// Lowercases keys, for case-insensitive keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lowercase;
+
+impl KeyTransform for Lowercase {
+    fn name(&self) -> &str {
+        "lowercase"
+    }
+    fn transform(&self, key: &str) -> String {
+        key.to_lowercase()
+    }
+}
+
+/// Removes leading and trailing whitespaces of keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trim;
+
+impl KeyTransform for Trim {
+    fn name(&self) -> &str {
+        "trim"
+    }
+    fn transform(&self, key: &str) -> String {
+        key.trim().to_string()
+    }
+}
@@ -1,154 +1,243 @@
 mod wal;
+use crate::memtable::BTreeMemtable as InMemory;
+pub(crate) use crate::memtable::{GetResult, Memtable, MemtableEntries, MemtableOnFlush};
 use log;
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    hash::Hash,
-    io,
-};
-
-pub(crate) trait Memtable {
-    type Key;
-    type Value;
-    fn get(&self, key: &Self::Key) -> GetResult<&Self::Value>;
-    fn set(
-        &mut self,
-        key: Self::Key,
-        value: Self::Value,
-    ) -> MemtableOnFlush<Self::Key, Self::Value>;
-    fn delete(&mut self, key: Self::Key) -> ();
-    fn clear(&mut self) -> ();
-    fn restore(&mut self) -> io::Result<()>;
-}
-pub(crate) enum GetResult<T> {
-    Found(T),
-    Deleted,
-    NotFound,
-}
-pub(crate) struct MemtableOnFlush<Key, Value> {
-    flushed: Option<MemtableEntries<Key, Value>>,
+use std::{io, time::Duration};
+pub use wal::WalSyncPolicy;
+
+/// `Memtable` of `sst`, whose writes go into a WAL and whose flush threshold may follow
+/// the latency of flushes.
+pub(crate) trait WalMemtable: Memtable {
+    fn record_flush_latency(&mut self, latency: Duration);
+    /// Rewrite the WAL to hold exactly the current entries and tombstones.
+    fn checkpoint_wal(&mut self) -> io::Result<()>;
 }
-pub(crate) struct MemtableEntries<Key, Value> {
-    pub entries: Box<BTreeMap<Key, Value>>,
-    pub tombstones: Box<BTreeSet<Key>>,
+
+/// Flush threshold which follows the latency of recent flushes.
+/// It grows while flushes finish within `target_latency` to amortize them,
+/// and shrinks when they get slower to bound the time a write stalls on a flush.
+#[derive(Debug, Clone)]
+pub struct AdaptiveThreshold {
+    min_entry: usize,
+    max_entry: usize,
+    target_latency: Duration,
+    current: usize,
+    ewma_latency: Option<Duration>,
 }
 
-impl<K: Hash + Eq + Ord + From<String>, V: From<String>> MemtableEntries<K, V> {
-    pub fn get(&self, key: &K) -> GetResult<&V> {
-        if self.tombstones.get(key).is_none() {
-            self.entries
-                .get(key)
-                .map(GetResult::Found)
-                .unwrap_or(GetResult::NotFound)
-        } else {
-            GetResult::Deleted
+impl AdaptiveThreshold {
+    // weight of the latest flush in the moving average
+    const SMOOTHING: f64 = 0.3;
+
+    pub fn new(min_entry: usize, max_entry: usize, target_latency: Duration) -> AdaptiveThreshold {
+        assert!(
+            0 < min_entry && min_entry <= max_entry,
+            "invalid bounds. min_entry: {}, max_entry: {}",
+            min_entry,
+            max_entry
+        );
+        AdaptiveThreshold {
+            min_entry,
+            max_entry,
+            target_latency,
+            current: min_entry,
+            ewma_latency: None,
         }
     }
-}
 
-impl<Key, Value> MemtableOnFlush<Key, Value> {
-    pub fn on_flush(
-        self,
-        f: impl FnOnce(MemtableEntries<Key, Value>) -> io::Result<()>,
-    ) -> io::Result<()> {
-        match self.flushed {
-            Some(flushed) => f(flushed),
-            None => Ok(()),
-        }
+    pub fn threshold(&self) -> usize {
+        self.current
+    }
+
+    pub fn ewma_latency(&self) -> Option<Duration> {
+        self.ewma_latency
+    }
+
+    pub fn record_flush(&mut self, latency: Duration) {
+        let ewma = match self.ewma_latency {
+            Some(prev) => latency.mul_f64(Self::SMOOTHING) + prev.mul_f64(1.0 - Self::SMOOTHING),
+            None => latency,
+        };
+        self.ewma_latency = Some(ewma);
+        let next = if ewma <= self.target_latency {
+            self.current + (self.current / 2).max(1)
+        } else {
+            self.current / 2
+        };
+        self.current = next.max(self.min_entry).min(self.max_entry);
+        log::trace!(
+            "flush latency: {:?}, ewma: {:?}, next threshold: {}",
+            latency,
+            ewma,
+            self.current
+        );
     }
 }
 
 pub(crate) mod default {
     use super::*;
-    use std::{
-        collections::{BTreeMap, BTreeSet},
-        hash::Hash,
-    };
+    use std::hash::Hash;
     use wal::WriteAheadLog;
 
     pub struct BTreeMemtable<K, V> {
-        max_entry: usize,
-        underlying: BTreeMap<K, V>,
-        tombstone: BTreeSet<K>,
-        wal: WriteAheadLog,
+        adaptive: Option<AdaptiveThreshold>,
+        // entries handed over on flush aren't on disk yet while they're frozen
+        keep_wal_on_flush: bool,
+        memtable: InMemory<K, V>,
+        // None for a read only memtable, whose writes are never logged
+        wal: Option<WriteAheadLog>,
     }
-    impl<K: Hash + Eq + Ord + From<String>, V: From<String>> BTreeMemtable<K, V> {
+    impl<
+            K: Hash + Eq + Ord + Clone + ToString + From<String>,
+            V: Clone + ToString + From<String>,
+        > BTreeMemtable<K, V>
+    {
+        /// A memtable holding up to `max_entry` entries, which is flushed by the set filling
+        /// it, so that a `max_entry` of 1 flushes on every set. Panics for a `max_entry` of 0.
         pub fn new(dir_name: &str, max_entry: usize) -> BTreeMemtable<K, V> {
-            let (underlying, tombstone) = Self::restore_from_wal(dir_name);
+            let memtable = InMemory::with_entries(max_entry, Self::restore_from_wal(dir_name));
             let wal = WriteAheadLog::create(dir_name);
+            let mut memtable = BTreeMemtable {
+                adaptive: None,
+                keep_wal_on_flush: false,
+                wal: Some(wal),
+                memtable,
+            };
+            // the new WAL has to cover the restored entries as well
+            memtable
+                .rewrite_wal()
+                .expect("failed to write restored entries into WAL");
+            memtable
+        }
+
+        /// A memtable restored from the WAL in the directory like `new`, which never writes
+        /// the WAL, for a store opened read only. Its writes are kept in memory only.
+        pub fn read_only(dir_name: &str, max_entry: usize) -> BTreeMemtable<K, V> {
+            BTreeMemtable {
+                adaptive: None,
+                keep_wal_on_flush: false,
+                wal: None,
+                memtable: InMemory::with_entries(max_entry, Self::restore_from_wal(dir_name)),
+            }
+        }
+
+        /// An empty memtable which never reads or writes a WAL, for a follower, as the WAL
+        /// in the directory holds entries of its writer which aren't flushed yet.
+        pub fn detached(max_entry: usize) -> BTreeMemtable<K, V> {
             BTreeMemtable {
-                max_entry,
-                wal,
-                underlying,
-                tombstone,
+                adaptive: None,
+                keep_wal_on_flush: false,
+                wal: None,
+                memtable: InMemory::new(max_entry),
+            }
+        }
+
+        pub fn with_adaptive_threshold(
+            dir_name: &str,
+            adaptive: AdaptiveThreshold,
+        ) -> BTreeMemtable<K, V> {
+            let mut memtable = Self::new(dir_name, adaptive.threshold());
+            memtable.adaptive = Some(adaptive);
+            memtable
+        }
+
+        /// Keep entries in the WAL when they are flushed, until `checkpoint_wal` is called.
+        pub fn keep_wal_on_flush(mut self, keep: bool) -> BTreeMemtable<K, V> {
+            self.keep_wal_on_flush = keep;
+            self
+        }
+
+        /// When appends to the WAL are synced, `WalSyncPolicy::Never` by default.
+        pub fn wal_sync_policy(mut self, sync_policy: WalSyncPolicy) -> BTreeMemtable<K, V> {
+            if let Some(wal) = self.wal.as_mut() {
+                wal.set_sync_policy(sync_policy)
+                    .expect("failed to sync WAL");
+            }
+            self
+        }
+
+        // drop the memtable, losing the bytes of the WAL which aren't durable
+        #[cfg(test)]
+        pub(crate) fn crash(self) {
+            if let Some(wal) = self.wal {
+                wal.crash();
             }
         }
 
-        fn restore_from_wal(dir_name: &str) -> (BTreeMap<K, V>, BTreeSet<K>) {
-            let mut underlying = BTreeMap::new();
-            let mut tombstone = BTreeSet::new();
-            match WriteAheadLog::restore(dir_name) {
-                Some(restored) => restored.for_each(|entry| match entry {
+        fn rewrite_wal(&mut self) -> io::Result<()> {
+            let entries = self.memtable.entries();
+            let inserted = entries
+                .entries
+                .iter()
+                .map(|(key, value)| wal::Entry::Inserted {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                });
+            let deleted = entries.tombstones.iter().map(|key| wal::Entry::Deleted {
+                key: key.to_string(),
+            });
+            let entries: Vec<_> = inserted.chain(deleted).collect();
+            match self.wal.as_mut() {
+                Some(wal) => wal.rewrite(&entries),
+                None => Ok(()),
+            }
+        }
+
+        fn restore_from_wal(dir_name: &str) -> MemtableEntries<K, V> {
+            let mut restored_entries = MemtableEntries::new();
+            // the last write of a key wins as in `set` and `delete`, so that a key never
+            // lives in both of entries and tombstones
+            if let Some(restored) = WriteAheadLog::restore(dir_name) {
+                restored.for_each(|entry| match entry {
                     Ok(wal::Entry::Inserted { key, value }) => {
-                        underlying.insert(From::from(key), From::from(value));
+                        restored_entries.set(From::from(key), From::from(value));
                     }
                     Ok(wal::Entry::Deleted { key }) => {
-                        tombstone.insert(From::from(key));
+                        restored_entries.delete(From::from(key));
+                    }
+                    Ok(wal::Entry::DeletedPrefix { prefix }) => {
+                        restored_entries.delete_prefix(&prefix);
+                    }
+                    Ok(wal::Entry::Renamed { from, to, value }) => {
+                        restored_entries.delete(From::from(from));
+                        restored_entries.set(From::from(to), From::from(value));
                     }
                     Err(message) => {
                         panic!("failed to restore a line. {}", message);
                     }
-                }),
-                None => (),
+                })
             };
-            (underlying, tombstone)
+            restored_entries
         }
 
-        fn is_deleted(&self, key: &K) -> bool {
-            self.tombstone.get(key).is_some()
-        }
-
-        fn with_check_tombstone<T>(
-            &self,
-            key: &K,
-            deleted: impl Fn() -> T,
-            not_deleted: impl Fn() -> T,
-        ) -> T {
-            if self.is_deleted(key) {
-                deleted()
-            } else {
-                not_deleted()
+        fn clear_wal(&mut self) {
+            if let Some(wal) = self.wal.as_mut() {
+                wal.clear().expect("failed to clear WAL");
             }
         }
 
-        fn flush(&mut self) -> MemtableEntries<K, V> {
-            let contents = std::mem::replace(&mut self.underlying, BTreeMap::new());
-            let deleted = std::mem::replace(&mut self.tombstone, BTreeSet::new());
-            self.wal.clear().expect("failed to clear WAL");
-            MemtableEntries {
-                entries: Box::new(contents),
-                tombstones: Box::new(deleted),
+        // the WAL goes along with entries handed over to be flushed
+        fn flushed(&mut self, on_flush: MemtableOnFlush<K, V>) -> MemtableOnFlush<K, V> {
+            if on_flush.is_flushed() {
+                log::trace!("flush!");
+                if !self.keep_wal_on_flush {
+                    self.clear_wal();
+                }
             }
+            on_flush
         }
     }
 
-    impl<K: Hash + Eq + Ord + ToString + From<String>, V: ToString + From<String>> Memtable
-        for BTreeMemtable<K, V>
+    impl<
+            K: Hash + Eq + Ord + Clone + ToString + From<String>,
+            V: Clone + ToString + From<String>,
+        > Memtable for BTreeMemtable<K, V>
     {
         type Key = K;
         type Value = V;
 
         fn get(&self, key: &Self::Key) -> GetResult<&Self::Value> {
-            // log::trace!("why memtable#find was called!");
-            self.with_check_tombstone(
-                key,
-                || GetResult::Deleted,
-                || {
-                    self.underlying
-                        .get(&key)
-                        .map(GetResult::Found)
-                        .unwrap_or(GetResult::NotFound)
-                },
-            )
+            self.memtable.get(key)
         }
 
         fn set(
@@ -156,34 +245,225 @@ pub(crate) mod default {
             key: Self::Key,
             value: Self::Value,
         ) -> MemtableOnFlush<Self::Key, Self::Value> {
-            self.tombstone.remove(&key);
-            self.wal
-                .insert((&key.to_string(), &value.to_string()))
-                .expect("failed to write WAL");
-            self.underlying.insert(key, value);
-            if self.underlying.len() > self.max_entry {
-                log::trace!("flush!");
-                MemtableOnFlush {
-                    flushed: Some(self.flush()),
-                }
-            } else {
-                MemtableOnFlush { flushed: None }
+            if let Some(wal) = self.wal.as_mut() {
+                wal.insert((&key.to_string(), &value.to_string()))
+                    .expect("failed to write WAL");
             }
+            let on_flush = self.memtable.set(key, value);
+            self.flushed(on_flush)
         }
-        fn delete(&mut self, key: Self::Key) -> () {
-            self.wal
-                .delete(&key.to_string())
-                .expect("failed to write WAL");
-            self.underlying.remove(&key);
-            self.tombstone.insert(key);
+        fn rename(
+            &mut self,
+            from: Self::Key,
+            to: Self::Key,
+            value: Self::Value,
+        ) -> MemtableOnFlush<Self::Key, Self::Value> {
+            if let Some(wal) = self.wal.as_mut() {
+                wal.rename(&from.to_string(), &to.to_string(), &value.to_string())
+                    .expect("failed to write WAL");
+            }
+            let on_flush = self.memtable.rename(from, to, value);
+            self.flushed(on_flush)
+        }
+        fn delete(&mut self, key: Self::Key) {
+            self.delete_returning(key);
+        }
+        fn delete_returning(&mut self, key: Self::Key) -> bool {
+            if let Some(wal) = self.wal.as_mut() {
+                wal.delete(&key.to_string()).expect("failed to write WAL");
+            }
+            self.memtable.delete_returning(key)
+        }
+        fn delete_prefix(&mut self, prefix: &str) {
+            if let Some(wal) = self.wal.as_mut() {
+                wal.delete_prefix(prefix).expect("failed to write WAL");
+            }
+            self.memtable.delete_prefix(prefix);
         }
-        fn clear(&mut self) -> () {
-            self.wal.clear().expect("failed to clear WAL");
-            self.underlying.clear();
-            self.tombstone.clear();
+        fn clear(&mut self) {
+            self.clear_wal();
+            self.memtable.clear();
+        }
+        fn range(
+            &self,
+            start: &Self::Key,
+            end: Option<&Self::Key>,
+        ) -> Vec<(Self::Key, Option<Self::Value>)> {
+            self.memtable.range(start, end)
+        }
+        fn tombstones(&self) -> Box<dyn Iterator<Item = &Self::Key> + '_> {
+            self.memtable.tombstones()
+        }
+        fn keys(&self) -> Box<dyn Iterator<Item = &Self::Key> + '_> {
+            self.memtable.keys()
+        }
+        fn freeze(&mut self) -> MemtableEntries<Self::Key, Self::Value> {
+            if !self.keep_wal_on_flush {
+                self.clear_wal();
+            }
+            self.memtable.freeze()
+        }
+    }
+
+    impl<
+            K: Hash + Eq + Ord + Clone + ToString + From<String>,
+            V: Clone + ToString + From<String>,
+        > WalMemtable for BTreeMemtable<K, V>
+    {
+        fn record_flush_latency(&mut self, latency: Duration) {
+            if let Some(adaptive) = self.adaptive.as_mut() {
+                adaptive.record_flush(latency);
+                self.memtable.set_max_entry(adaptive.threshold());
+            }
         }
-        fn restore(&mut self) -> io::Result<()> {
-            todo!()
+        fn checkpoint_wal(&mut self) -> io::Result<()> {
+            self.rewrite_wal()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{default::BTreeMemtable, AdaptiveThreshold, GetResult, Memtable, WalSyncPolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn test_delete_returning() {
+        let dir = "./test_tmp_memtable_delete";
+        std::fs::create_dir_all(dir).unwrap();
+        let mut memtable: BTreeMemtable<String, String> = BTreeMemtable::new(dir, 10);
+        memtable.clear();
+        memtable.set("key".to_string(), "value".to_string());
+        assert!(memtable.delete_returning("key".to_string()));
+        assert!(matches!(
+            memtable.get(&"key".to_string()),
+            GetResult::Deleted
+        ));
+        // deleting again or deleting a missing key is a no-op on values
+        assert!(!memtable.delete_returning("key".to_string()));
+        assert!(!memtable.delete_returning("missing".to_string()));
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let mut memtable: BTreeMemtable<String, String> = BTreeMemtable::detached(10);
+        memtable.set("b".to_string(), "1".to_string());
+        memtable.delete("b".to_string());
+        memtable.delete("a".to_string());
+        memtable.set("c".to_string(), "1".to_string());
+        let tombstones: Vec<&String> = memtable.tombstones().collect();
+        assert_eq!(tombstones, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut memtable: BTreeMemtable<String, String> = BTreeMemtable::detached(10);
+        memtable.set("a".to_string(), "1".to_string());
+        memtable.set("b".to_string(), "1".to_string());
+        memtable.delete("b".to_string());
+        memtable.delete("c".to_string());
+        let frozen = memtable.freeze();
+        assert_eq!(
+            frozen.entries.into_iter().collect::<Vec<_>>(),
+            vec![("a".to_string(), "1".to_string())]
+        );
+        assert_eq!(
+            frozen.tombstones.into_iter().collect::<Vec<_>>(),
+            vec!["b".to_string(), "c".to_string()]
+        );
+        assert!(memtable.range(&String::new(), None).is_empty());
+        assert!(matches!(
+            memtable.get(&"a".to_string()),
+            GetResult::NotFound
+        ));
+
+        memtable.set("d".to_string(), "2".to_string());
+        assert!(matches!(memtable.get(&"d".to_string()), GetResult::Found(value) if value == "2"));
+        assert_eq!(memtable.freeze().entries.len(), 1);
+    }
+
+    #[test]
+    fn test_restore_last_write_wins() {
+        let dir = "./test_tmp_memtable_restore";
+        std::fs::create_dir_all(dir).unwrap();
+        let mut memtable: BTreeMemtable<String, String> = BTreeMemtable::new(dir, 10);
+        memtable.clear();
+        memtable.set("a".to_string(), "1".to_string());
+        memtable.delete("a".to_string());
+        memtable.set("a".to_string(), "2".to_string());
+        memtable.set("b".to_string(), "1".to_string());
+        memtable.delete("b".to_string());
+        let expected = vec![
+            ("a".to_string(), Some("2".to_string())),
+            ("b".to_string(), None),
+        ];
+        assert_eq!(memtable.range(&String::new(), None), expected);
+        drop(memtable);
+
+        // a key restored from the WAL lives in either of entries and tombstones
+        let memtable: BTreeMemtable<String, String> = BTreeMemtable::new(dir, 10);
+        assert_eq!(memtable.range(&String::new(), None), expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_sync_policy() {
+        let dir = "./test_tmp_wal_sync_policy";
+        std::fs::create_dir_all(dir).unwrap();
+        let key = "key".to_string();
+        let survives = |sync_policy: WalSyncPolicy, wait: Duration| {
+            let mut memtable: BTreeMemtable<String, String> =
+                BTreeMemtable::new(dir, 10).wal_sync_policy(sync_policy);
+            memtable.clear();
+            memtable.set(key.clone(), "value".to_string());
+            std::thread::sleep(wait);
+            memtable.crash();
+            let restored: BTreeMemtable<String, String> = BTreeMemtable::new(dir, 10);
+            matches!(restored.get(&key), GetResult::Found(_))
+        };
+        assert!(survives(WalSyncPolicy::PerWrite, Duration::ZERO));
+        assert!(!survives(WalSyncPolicy::Never, Duration::ZERO));
+        let interval = Duration::from_millis(10);
+        assert!(survives(WalSyncPolicy::Interval(interval), interval * 20));
+
+        // the last write is lost while write-back hasn't happened, earlier synced ones aren't
+        let mut memtable: BTreeMemtable<String, String> =
+            BTreeMemtable::new(dir, 10).wal_sync_policy(WalSyncPolicy::PerWrite);
+        memtable.clear();
+        memtable.set(key.clone(), "synced".to_string());
+        let mut memtable = memtable.wal_sync_policy(WalSyncPolicy::Never);
+        memtable.set("unsynced".to_string(), "value".to_string());
+        memtable.crash();
+        let restored: BTreeMemtable<String, String> = BTreeMemtable::new(dir, 10);
+        assert!(matches!(restored.get(&key), GetResult::Found(value) if value == "synced"));
+        assert!(matches!(
+            restored.get(&"unsynced".to_string()),
+            GetResult::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_adaptive_threshold() {
+        let target = Duration::from_millis(10);
+        let mut adaptive = AdaptiveThreshold::new(4, 64, target);
+        assert_eq!(adaptive.threshold(), 4);
+
+        // fast flushes grow the threshold up to the max
+        let mut last = adaptive.threshold();
+        (0..20).for_each(|_| {
+            adaptive.record_flush(Duration::from_millis(1));
+            assert!(last <= adaptive.threshold() && adaptive.threshold() <= 64);
+            last = adaptive.threshold();
+        });
+        assert_eq!(adaptive.threshold(), 64);
+
+        // slow flushes shrink it down to the min once the average catches up
+        (0..20).for_each(|_| {
+            adaptive.record_flush(Duration::from_millis(100));
+            assert!(4 <= adaptive.threshold() && adaptive.threshold() <= last);
+            last = adaptive.threshold();
+        });
+        assert_eq!(adaptive.threshold(), 4);
+        assert!(adaptive.ewma_latency().unwrap() > target);
+    }
+}
@@ -3,17 +3,75 @@ use io::{BufRead, BufReader, Lines, Write};
 use std::{
     fs::File,
     io::{self, BufWriter},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
 };
 
+/// When appends to the WAL are synced, which decides the writes a crash of the machine loses.
+/// Every append reaches the OS before `insert` or `delete` returns, so a crash of the process
+/// alone loses no write under any policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalSyncPolicy {
+    /// Sync each append before the write returns, so every returned write survives.
+    PerWrite,
+    /// Sync the appends of each interval together in the background, so a crash loses
+    /// the writes returned within the last interval at most.
+    Interval(Duration),
+    /// Never sync appends(default), so a crash loses whatever the OS hasn't written back,
+    /// which may be any write since the WAL was last rewritten.
+    #[default]
+    Never,
+}
+
 pub(crate) enum Entry {
-    Inserted { key: String, value: String },
-    Deleted { key: String },
+    Inserted {
+        key: String,
+        value: String,
+    },
+    Deleted {
+        key: String,
+    },
+    DeletedPrefix {
+        prefix: String,
+    },
+    // the value written under `to` and a tombstone of `from` in a single line
+    Renamed {
+        from: String,
+        to: String,
+        value: String,
+    },
 }
 
 pub(crate) struct WriteAheadLog {
     dir_name: String,
-    writer: BufWriter<File>,
+    log: Arc<Mutex<LogFile>>,
+    sync_policy: WalSyncPolicy,
+    // dropped along with the log to stop the thread syncing it under `Interval`
+    stop_syncer: Option<Sender<()>>,
+}
+
+// the file appended to, shared with the syncing thread
+struct LogFile {
+    file: File,
+    // bytes in the file, and bytes of them known to be durable
+    written: u64,
+    synced: u64,
+}
+
+impl LogFile {
+    fn sync(&mut self) -> io::Result<()> {
+        if self.synced < self.written {
+            self.file.sync_data()?;
+            self.synced = self.written;
+        }
+        Ok(())
+    }
 }
+
 pub(crate) struct WalRestore {
     buf: Lines<BufReader<File>>,
 }
@@ -29,49 +87,182 @@ impl Iterator for WalRestore {
 
 impl WriteAheadLog {
     const FILE_NAME: &'static str = "wal.log";
+    const TMP_FILE_NAME: &'static str = "tmp_wal.log";
     const DELIMITER: &'static str = "\0";
     const TAG_DELETED: &'static str = "D";
     const TAG_INSERTED: &'static str = "I";
+    const TAG_DELETED_PREFIX: &'static str = "P";
+    const TAG_RENAMED: &'static str = "R";
 
     pub fn create(dir_name: &str) -> WriteAheadLog {
+        let file = Self::open_file(dir_name, FileOption::New).expect("failed to open WAL file");
         WriteAheadLog {
             dir_name: dir_name.into(),
-            writer: Self::writer(dir_name),
+            log: Arc::new(Mutex::new(LogFile {
+                file: file.underlying,
+                written: 0,
+                synced: 0,
+            })),
+            sync_policy: WalSyncPolicy::Never,
+            stop_syncer: None,
         }
     }
-    fn writer(dir_name: &str) -> BufWriter<File> {
-        let file = Self::open_file(dir_name, FileOption::New).expect("failed to open WAL file");
-        BufWriter::new(file.underlying)
+
+    // the current content is synced as well, as it was written under the previous policy
+    pub fn set_sync_policy(&mut self, sync_policy: WalSyncPolicy) -> io::Result<()> {
+        if let Some(stop) = self.stop_syncer.take() {
+            // the thread syncs a last time and stops, or has stopped on an error already
+            stop.send(()).ok();
+        }
+        self.sync_policy = sync_policy;
+        if sync_policy == WalSyncPolicy::Never {
+            return Ok(());
+        }
+        self.log.lock().unwrap().sync()?;
+        File::open(&self.dir_name)?.sync_all()?;
+        if let WalSyncPolicy::Interval(interval) = sync_policy {
+            let (stop, stopped) = mpsc::channel::<()>();
+            let log = self.log.clone();
+            thread::spawn(move || loop {
+                let stopping = stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout);
+                if let Err(err) = log.lock().unwrap().sync() {
+                    log::error!("failed to sync WAL. err: {:?}", err);
+                }
+                if stopping {
+                    break;
+                }
+            });
+            self.stop_syncer = Some(stop);
+        }
+        Ok(())
     }
     fn open_file(dir_name: &str, option: FileOption) -> io::Result<RichFile> {
         RichFile::open_file(dir_name, Self::FILE_NAME, option)
     }
 
-    pub fn insert(&mut self, entry: (&str, &str)) -> io::Result<()> {
-        let (key, value) = entry;
-        let str = format!(
+    fn format_insert(key: &str, value: &str) -> String {
+        format!(
             "{}{}{}{}{}\n",
             Self::TAG_INSERTED,
             Self::DELIMITER,
             key,
             Self::DELIMITER,
             value
-        );
-        self.writer.write(str.as_bytes()).unwrap();
-        self.writer.flush()
+        )
+    }
+
+    fn format_delete(key: &str) -> String {
+        format!("{}{}{}\n", Self::TAG_DELETED, Self::DELIMITER, key)
+    }
+
+    fn format_delete_prefix(prefix: &str) -> String {
+        format!(
+            "{}{}{}\n",
+            Self::TAG_DELETED_PREFIX,
+            Self::DELIMITER,
+            prefix
+        )
+    }
+
+    fn format_rename(from: &str, to: &str, value: &str) -> String {
+        format!(
+            "{}{}{}{}{}{}{}\n",
+            Self::TAG_RENAMED,
+            Self::DELIMITER,
+            from,
+            Self::DELIMITER,
+            to,
+            Self::DELIMITER,
+            value
+        )
+    }
+
+    pub fn insert(&mut self, entry: (&str, &str)) -> io::Result<()> {
+        let (key, value) = entry;
+        self.append(&Self::format_insert(key, value))
     }
 
     pub fn delete(&mut self, key: &str) -> io::Result<()> {
-        let str = format!("{}{}{}\n", Self::TAG_DELETED, Self::DELIMITER, key);
-        self.writer.write(str.as_bytes()).unwrap();
-        self.writer.flush()
+        self.append(&Self::format_delete(key))
+    }
+
+    pub fn delete_prefix(&mut self, prefix: &str) -> io::Result<()> {
+        self.append(&Self::format_delete_prefix(prefix))
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str, value: &str) -> io::Result<()> {
+        self.append(&Self::format_rename(from, to, value))
+    }
+
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        log.file.write_all(line.as_bytes())?;
+        log.written += line.len() as u64;
+        if self.sync_policy == WalSyncPolicy::PerWrite {
+            log.sync()?;
+        }
+        Ok(())
+    }
+
+    fn syncs(&self) -> bool {
+        self.sync_policy != WalSyncPolicy::Never
     }
 
     pub fn clear(&mut self) -> io::Result<()> {
-        self.writer = Self::writer(&self.dir_name);
+        let file = Self::open_file(&self.dir_name, FileOption::New)?.underlying;
+        // entries left over from before would be restored after a crash otherwise
+        if self.syncs() {
+            file.sync_all()?;
+        }
+        *self.log.lock().unwrap() = LogFile {
+            file,
+            written: 0,
+            synced: 0,
+        };
         Ok(())
     }
 
+    // replace the whole log with the given entries through a temporary file
+    pub fn rewrite(&mut self, entries: &[Entry]) -> io::Result<()> {
+        let tmp = RichFile::open_file(&self.dir_name, Self::TMP_FILE_NAME, FileOption::New)?;
+        let tmp_path = tmp.path();
+        let mut tmp_writer = BufWriter::new(tmp.underlying);
+        let mut written = 0;
+        for entry in entries {
+            let str = match entry {
+                Entry::Inserted { key, value } => Self::format_insert(key, value),
+                Entry::Deleted { key } => Self::format_delete(key),
+                Entry::DeletedPrefix { prefix } => Self::format_delete_prefix(prefix),
+                Entry::Renamed { from, to, value } => Self::format_rename(from, to, value),
+            };
+            tmp_writer.write_all(str.as_bytes())?;
+            written += str.len() as u64;
+        }
+        tmp_writer.flush()?;
+        if self.syncs() {
+            tmp_writer.get_ref().sync_data()?;
+        }
+        let wal = Self::open_file(&self.dir_name, FileOption::Append)?;
+        std::fs::rename(tmp_path, wal.path())?;
+        if self.syncs() {
+            File::open(&self.dir_name)?.sync_all()?;
+        }
+        // reopen as the previous handle still points to the replaced file
+        *self.log.lock().unwrap() = LogFile {
+            file: Self::open_file(&self.dir_name, FileOption::Append)?.underlying,
+            written,
+            synced: if self.syncs() { written } else { 0 },
+        };
+        Ok(())
+    }
+
+    // lose the bytes which aren't durable, as a crash of the machine may
+    #[cfg(test)]
+    pub fn crash(self) {
+        let log = self.log.lock().unwrap();
+        log.file.set_len(log.synced).unwrap();
+    }
+
     pub fn restore(dir_name: &str) -> Option<WalRestore> {
         match Self::open_file(dir_name, FileOption::ReadOnly) {
             Ok(file) => {
@@ -93,6 +284,16 @@ impl WriteAheadLog {
                 let key = res[1].to_string();
                 Ok(Entry::Deleted { key })
             }
+            Self::TAG_DELETED_PREFIX => {
+                let prefix = res[1].to_string();
+                Ok(Entry::DeletedPrefix { prefix })
+            }
+            Self::TAG_RENAMED => {
+                let from = res[1].to_string();
+                let to = res[2].to_string();
+                let value = res[3].to_string();
+                Ok(Entry::Renamed { from, to, value })
+            }
             _ => Err(format!(
                 "unknown tag({}) was written in line({:?})",
                 res[0], res
@@ -0,0 +1,46 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Count-min sketch of reads per key in `width * depth` counters, whatever the number of keys.
+/// A key counts in one counter of each of `depth` rows, and its estimate is the least of
+/// them, which is never below its actual reads and above them by at most `e / width` of
+/// all reads, with a probability of `1 - e^-depth`.
+pub(crate) struct ReadSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<AtomicU64>,
+}
+
+impl ReadSketch {
+    pub fn new(width: usize, depth: usize) -> ReadSketch {
+        ReadSketch {
+            width,
+            depth,
+            counters: (0..width * depth).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn record(&self, key: &str) {
+        (0..self.depth).for_each(|row| {
+            self.counters[self.cell(row, key)].fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    pub fn estimate(&self, key: &str) -> u64 {
+        (0..self.depth)
+            .map(|row| self.counters[self.cell(row, key)].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    // the counter of the key in the row, hashed with the row as the seed
+    fn cell(&self, row: usize, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        row * self.width + (hasher.finish() % self.width as u64) as usize
+    }
+}
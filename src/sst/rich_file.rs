@@ -1,7 +1,6 @@
 use std::{
     fs::{File, OpenOptions},
     io,
-    ops::Deref,
     path::{Path, PathBuf},
 };
 pub(crate) struct RichFile {
@@ -46,7 +45,7 @@ impl RichFile {
         let path = dir.join(&file_name_s);
         let file = option
             .open(&path)
-            .expect(format!("failed to open file({:?}), option: {:?}", &path, option).deref());
+            .unwrap_or_else(|_| panic!("failed to open file({:?}), option: {:?}", &path, option));
 
         Ok(RichFile {
             underlying: file,
@@ -0,0 +1,404 @@
+use crate::{
+    memtable::GetResult,
+    sst::{cancel::CancellationToken, comparator::SharedComparator},
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    io,
+};
+
+// (key, value), value is None for a tombstone. the value is a `ValueHandle` for a lazy scan
+pub(crate) type Record<V = String> = (String, Option<V>);
+// key-sorted records of a memtable or a generation
+pub(crate) type Source<V = String> = Box<dyn Iterator<Item = io::Result<Record<V>>> + Send>;
+
+// an empty start is no lower bound, as a comparator may not order it first
+fn before_start(comparator: &SharedComparator, key: &str, start: &str) -> bool {
+    !start.is_empty() && comparator.compare(key, start) == Ordering::Less
+}
+
+// whether the key is in [start, end) in the order of the comparator
+pub(crate) fn in_range(
+    comparator: &SharedComparator,
+    key: &str,
+    start: &str,
+    end: Option<&str>,
+) -> bool {
+    !before_start(comparator, key, start)
+        && end.is_none_or(|end| comparator.compare(key, end) == Ordering::Less)
+}
+
+// keep records in [start, end) of a source sorted by the comparator
+pub(crate) fn bounded<V: 'static>(
+    source: impl Iterator<Item = io::Result<Record<V>>> + Send + 'static,
+    start: &str,
+    end: Option<&str>,
+    comparator: &SharedComparator,
+) -> Source<V> {
+    let start = start.to_string();
+    let end = end.map(|end| end.to_string());
+    let (skip_cmp, take_cmp) = (comparator.clone(), comparator.clone());
+    Box::new(
+        source
+            .skip_while(move |record| {
+                matches!(record, Ok((key, _)) if before_start(&skip_cmp, key, &start))
+            })
+            .take_while(move |record| match (record, end.as_ref()) {
+                (Ok((key, _)), Some(end)) => take_cmp.compare(key, end) == Ordering::Less,
+                _ => true,
+            }),
+    )
+}
+
+// records in [start, end) of an in-memory table by its keys, copying only those rather than
+// the whole table. they're in key order, which `sorted` sorts by the comparator
+pub(crate) fn in_memory<'a>(
+    keys: impl Iterator<Item = &'a String>,
+    get: impl Fn(&String) -> GetResult<&'a String>,
+    start: &str,
+    end: Option<&str>,
+    comparator: &SharedComparator,
+) -> Vec<Record> {
+    keys.filter(|key| in_range(comparator, key, start, end))
+        .map(|key| match get(key) {
+            GetResult::Found(value) => (key.clone(), Some(value.clone())),
+            _ => (key.clone(), None),
+        })
+        .collect()
+}
+
+// records in [start, end) of an in-memory table, sorted by the comparator
+pub(crate) fn sorted<V: Send + 'static>(
+    records: Vec<Record<V>>,
+    start: &str,
+    end: Option<&str>,
+    comparator: &SharedComparator,
+) -> Source<V> {
+    let mut records: Vec<Record<V>> = records
+        .into_iter()
+        .filter(|(key, _)| in_range(comparator, key, start, end))
+        .collect();
+    records.sort_by(|(a, _), (b, _)| comparator.compare(a, b));
+    Box::new(records.into_iter().map(Ok))
+}
+
+// `sorted` in reverse order of the comparator, for `MergeIter` of `Reversed` of it
+pub(crate) fn sorted_rev<V: Send + 'static>(
+    records: Vec<Record<V>>,
+    start: &str,
+    end: Option<&str>,
+    comparator: &SharedComparator,
+) -> Source<V> {
+    let mut records: Vec<Record<V>> = records
+        .into_iter()
+        .filter(|(key, _)| in_range(comparator, key, start, end))
+        .collect();
+    records.sort_by(|(a, _), (b, _)| comparator.compare(b, a));
+    Box::new(records.into_iter().map(Ok))
+}
+
+// the smallest key above every key starting with the prefix in byte-wise order, i.e. the
+// prefix without trailing chars of `char::MAX` and with the last char left incremented.
+// None if no key is, as the prefix is empty or made of `char::MAX` only
+pub(crate) fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut successor: Vec<char> = prefix.chars().collect();
+    while let Some(last) = successor.pop() {
+        // the code points past the surrogates, which aren't chars
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            successor.push(next);
+            return Some(successor.into_iter().collect());
+        }
+    }
+    None
+}
+
+// the head of a source in the merge heap, ordered by key and then by recency of the source
+struct Head {
+    key: String,
+    idx: usize,
+    comparator: SharedComparator,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&self.key, &other.key)
+            .then(self.idx.cmp(&other.idx))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Merges sources sorted by the comparator into a single stream sorted by it.
+/// Sources are given newest first, and only the newest record of a key is yielded.
+/// Tombstones are yielded as well so that callers can tell them from missing keys.
+pub(crate) struct MergeIter<V = String> {
+    sources: Vec<Source<V>>,
+    // the value of the record at the head of each source
+    heads: Vec<Option<V>>,
+    heap: BinaryHeap<Reverse<Head>>,
+    comparator: SharedComparator,
+    failed: Option<io::Error>,
+}
+
+impl<V> MergeIter<V> {
+    pub fn new(sources: Vec<Source<V>>, comparator: SharedComparator) -> MergeIter<V> {
+        let mut merge = MergeIter {
+            heads: sources.iter().map(|_| None).collect(),
+            sources,
+            heap: BinaryHeap::new(),
+            comparator,
+            failed: None,
+        };
+        for idx in 0..merge.sources.len() {
+            if let Err(err) = merge.advance(idx) {
+                merge.failed = Some(err);
+                break;
+            }
+        }
+        merge
+    }
+
+    fn advance(&mut self, idx: usize) -> io::Result<()> {
+        match self.sources[idx].next() {
+            Some(Ok((key, value))) => {
+                self.heads[idx] = value;
+                self.heap.push(Reverse(Head {
+                    key,
+                    idx,
+                    comparator: self.comparator.clone(),
+                }));
+                Ok(())
+            }
+            Some(Err(err)) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<V> MergeIter<V> {
+    /// The next key with the values of its newest `versions` records, newest first.
+    /// Older records of the key are dropped.
+    pub fn next_versions(
+        &mut self,
+        versions: usize,
+    ) -> Option<io::Result<(String, Vec<Option<V>>)>> {
+        if let Some(err) = self.failed.take() {
+            self.heap.clear();
+            return Some(Err(err));
+        }
+        let Reverse(Head { key, idx, .. }) = self.heap.pop()?;
+        let mut values = vec![self.heads[idx].take()];
+        let mut advanced = self.advance(idx);
+        // records of the same key come from older sources in order
+        while advanced.is_ok() && matches!(self.heap.peek(), Some(Reverse(next)) if next.key == key)
+        {
+            let Reverse(older) = self.heap.pop().unwrap();
+            let value = self.heads[older.idx].take();
+            if values.len() < versions {
+                values.push(value);
+            }
+            advanced = self.advance(older.idx);
+        }
+        if let Err(err) = advanced {
+            self.failed = Some(err);
+        }
+        Some(Ok((key, values)))
+    }
+}
+
+impl<V> Iterator for MergeIter<V> {
+    type Item = io::Result<Record<V>>;
+
+    // records of the same key shadowed by the newest one are dropped
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_versions(1)
+            .map(|next| next.map(|(key, mut values)| (key, values.remove(0))))
+    }
+}
+
+/// Iterator over live key-values in the order of the store's comparator.
+/// Created by `SSTable::cursor`, it stops with `Err(Cancelled)` once its token is cancelled.
+/// It reads the store as it was when it was created, as memtables are copied into it and
+/// data files are held open, so writes, flushes and compactions after that don't show up.
+pub struct Cursor {
+    merged: MergeIter,
+    cancel: Option<CancellationToken>,
+    done: bool,
+}
+
+impl Cursor {
+    pub(crate) fn new(
+        sources: Vec<Source>,
+        comparator: SharedComparator,
+        cancel: Option<CancellationToken>,
+    ) -> Cursor {
+        Cursor {
+            merged: MergeIter::new(sources, comparator),
+            cancel,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = io::Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Err(err) = CancellationToken::check(self.cancel.as_ref()) {
+            log::debug!("cursor was cancelled");
+            self.done = true;
+            return Some(Err(err));
+        }
+        loop {
+            match self.merged.next() {
+                Some(Ok((key, Some(value)))) => return Some(Ok((key, value))),
+                Some(Ok((_, None))) => continue,
+                Some(Err(err)) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// A difference of `SSTable::diff_against` between the store and another dataset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diff {
+    /// A key only the store holds, along with its value there.
+    OnlyInStore { key: String, value: String },
+    /// A key only the other dataset holds, along with its value there.
+    OnlyInOther { key: String, value: String },
+    /// A key both hold with different values.
+    Differs {
+        key: String,
+        store: String,
+        other: String,
+    },
+}
+
+/// Merge-join of a `Cursor` over the store with another sorted stream of key-values,
+/// yielding their `Diff`s in key order while holding a record of each side at a time.
+/// It stops with `Err(InvalidInput)` once a key of the other stream isn't greater than
+/// the one before it in the order of the store's comparator.
+pub struct DiffIter<I> {
+    store: Cursor,
+    other: I,
+    comparator: SharedComparator,
+    // the records of each side read ahead to be compared with the other's
+    store_next: Option<(String, String)>,
+    other_next: Option<(String, String)>,
+    store_done: bool,
+    other_done: bool,
+    // the key of the other stream taken last, which the next one must be greater than
+    other_last: Option<String>,
+    done: bool,
+}
+
+impl<I: Iterator<Item = (String, String)>> DiffIter<I> {
+    pub(crate) fn new(store: Cursor, other: I, comparator: SharedComparator) -> DiffIter<I> {
+        DiffIter {
+            store,
+            other,
+            comparator,
+            store_next: None,
+            other_next: None,
+            store_done: false,
+            other_done: false,
+            other_last: None,
+            done: false,
+        }
+    }
+
+    // read the next record of each side which has none ahead
+    fn fill(&mut self) -> io::Result<()> {
+        if self.store_next.is_none() && !self.store_done {
+            match self.store.next() {
+                Some(record) => self.store_next = Some(record?),
+                None => self.store_done = true,
+            }
+        }
+        if self.other_next.is_none() && !self.other_done {
+            match self.other.next() {
+                Some((key, value)) => {
+                    if let Some(last) = self.other_last.as_ref() {
+                        if self.comparator.compare(last, &key) != Ordering::Less {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("the other stream is unsorted at {:?}", key),
+                            ));
+                        }
+                    }
+                    self.other_last = Some(key.clone());
+                    self.other_next = Some((key, value));
+                }
+                None => self.other_done = true,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = (String, String)>> Iterator for DiffIter<I> {
+    type Item = io::Result<Diff>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Err(err) = self.fill() {
+                self.done = true;
+                return Some(Err(err));
+            }
+            let ordering = match (self.store_next.as_ref(), self.other_next.as_ref()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((store_key, _)), Some((other_key, _))) => {
+                    self.comparator.compare(store_key, other_key)
+                }
+            };
+            let diff = match ordering {
+                Ordering::Less => {
+                    let (key, value) = self.store_next.take().unwrap();
+                    Diff::OnlyInStore { key, value }
+                }
+                Ordering::Greater => {
+                    let (key, value) = self.other_next.take().unwrap();
+                    Diff::OnlyInOther { key, value }
+                }
+                Ordering::Equal => {
+                    let (key, store) = self.store_next.take().unwrap();
+                    let (_, other) = self.other_next.take().unwrap();
+                    if store == other {
+                        continue;
+                    }
+                    Diff::Differs { key, store, other }
+                }
+            };
+            return Some(Ok(diff));
+        }
+        self.done = true;
+        None
+    }
+}
@@ -0,0 +1,313 @@
+use crate::sst::disktable::{FileHandle, FileSystem};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+/*
+File Layout:
+[magic: RSSNAP1\n][flags: 1 byte][block][block]... where a block is
+[records: u32][record][record]...[crc32 of the block bytes before it: u32], and a record is
+[shared: u32][suffix length: u32][suffix][value length: u32][value].
+
+Integers are little endian, and the checksum is the CRC-32 of IEEE 802.3. Keys are unique
+and sorted byte-wise. A key is the first `shared` bytes of the key before it in the block
+followed by its suffix, where `shared` is always 0 for the first record of a block, and for
+every record unless bit 0 of the flags is set for front coded keys.
+
+The sidecar index, named after the file with `.index` appended, is text with a line per block:
+[first key in hex]\t[offset of the block]\t[bytes of the block]\n
+*/
+
+/// How `SSTable::export_snapshot` writes a snapshot.
+#[derive(Debug, Clone)]
+pub struct SnapshotOptions {
+    /// Front code keys against the key before them in their block, which shrinks keys
+    /// sharing long prefixes. Off by default.
+    pub compressed: bool,
+    /// Records of each block, which `SnapshotReader::get` reads and checks as a whole.
+    /// 64 by default, and exports fail with `InvalidInput` for 0.
+    pub block_records: usize,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> SnapshotOptions {
+        SnapshotOptions {
+            compressed: false,
+            block_records: 64,
+        }
+    }
+}
+
+const MAGIC: &[u8; 8] = b"RSSNAP1\n";
+const FRONT_CODED: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+fn index_path(path: &Path) -> PathBuf {
+    let mut name: OsString = path.as_os_str().to_owned();
+    name.push(".index");
+    PathBuf::from(name)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name: OsString = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+fn invalid(path: &Path, detail: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{:?} isn't a valid snapshot: {}", path, detail),
+    )
+}
+
+// CRC-32 of IEEE 802.3, bit by bit as snapshots are written and read once in a while
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn shared_len(previous: &[u8], key: &[u8]) -> usize {
+    previous.iter().zip(key).take_while(|(a, b)| a == b).count()
+}
+
+fn hex(key: &str) -> String {
+    key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Write the entries as a snapshot at the path and its sidecar index, returning how many
+/// records are written. Both are written under temporary names and renamed into place once
+/// durable. Fails with `AlreadyExists` if the path exists.
+pub(crate) fn write(
+    fs: &dyn FileSystem,
+    path: &Path,
+    entries: &BTreeMap<String, String>,
+    options: &SnapshotOptions,
+) -> io::Result<usize> {
+    if options.block_records == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "block_records of a snapshot must be at least 1",
+        ));
+    }
+    match fs.open(path) {
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} exists already", path),
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let entries: Vec<(&String, &String)> = entries.iter().collect();
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(if options.compressed { FRONT_CODED } else { 0 });
+    let mut index = String::new();
+    for block in entries.chunks(options.block_records) {
+        let offset = bytes.len();
+        let mut encoded = (block.len() as u32).to_le_bytes().to_vec();
+        let mut previous: &[u8] = &[];
+        for (key, value) in block {
+            let key = key.as_bytes();
+            let shared = if options.compressed {
+                shared_len(previous, key)
+            } else {
+                0
+            };
+            encoded.extend_from_slice(&(shared as u32).to_le_bytes());
+            encoded.extend_from_slice(&((key.len() - shared) as u32).to_le_bytes());
+            encoded.extend_from_slice(&key[shared..]);
+            encoded.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            encoded.extend_from_slice(value.as_bytes());
+            previous = key;
+        }
+        encoded.extend_from_slice(&crc32(&encoded).to_le_bytes());
+        index.push_str(&format!(
+            "{}\t{}\t{}\n",
+            hex(block[0].0),
+            offset,
+            encoded.len()
+        ));
+        bytes.extend(encoded);
+    }
+
+    let index_path = index_path(path);
+    for (path, bytes) in [(path, bytes.as_slice()), (&index_path, index.as_bytes())] {
+        let tmp_path = tmp_path(path);
+        let file = fs.create(&tmp_path)?;
+        file.write(bytes)?;
+        file.sync()?;
+        fs.rename(&tmp_path, path)?;
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs.sync_dir(dir)?;
+    }
+    Ok(entries.len())
+}
+
+/// Reads a snapshot written by `SSTable::export_snapshot`, independently of the store it
+/// was exported from. The sidecar index is held in memory, and blocks are read and checked
+/// against their checksums as they're looked up, failing with `InvalidData` on a mismatch.
+pub struct SnapshotReader {
+    path: PathBuf,
+    file: Box<dyn FileHandle>,
+    front_coded: bool,
+    // the first key, offset and bytes of every block in key order
+    blocks: Vec<(String, u64, usize)>,
+}
+
+impl SnapshotReader {
+    /// Open the snapshot at the path on the local file system along with its sidecar index.
+    /// Fails with `InvalidData` unless both are of a snapshot.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<SnapshotReader> {
+        Self::open_in(&crate::sst::OsFileSystem, path.as_ref())
+    }
+
+    /// `open` of a snapshot on the file system.
+    pub fn open_in(fs: &dyn FileSystem, path: &Path) -> io::Result<SnapshotReader> {
+        let file = fs.open(path)?;
+        let mut header = [0; HEADER_LEN];
+        Self::read_exact_at(file.as_ref(), 0, &mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid(path, "no snapshot header"));
+        }
+        let index = fs.open(&index_path(path))?;
+        let mut lines = vec![0; index.size()? as usize];
+        Self::read_exact_at(index.as_ref(), 0, &mut lines)?;
+        let lines = String::from_utf8(lines).map_err(|_| invalid(path, "a malformed index"))?;
+        let parse = |line: &str| -> Option<(String, u64, usize)> {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                [key, offset, len] => Some((unhex(key)?, offset.parse().ok()?, len.parse().ok()?)),
+                _ => None,
+            }
+        };
+        let blocks = lines
+            .lines()
+            .map(|line| parse(line).ok_or_else(|| invalid(path, "a malformed index")))
+            .collect::<io::Result<_>>()?;
+        Ok(SnapshotReader {
+            path: path.to_path_buf(),
+            file,
+            front_coded: header[MAGIC.len()] & FRONT_CODED != 0,
+            blocks,
+        })
+    }
+
+    /// The value of the key, reading the one block which may hold it.
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        // the last block starting at or before the key
+        let block = match self
+            .blocks
+            .partition_point(|(first_key, ..)| first_key.as_str() <= key)
+        {
+            0 => return Ok(None),
+            after => after - 1,
+        };
+        Ok(self
+            .read_block(block)?
+            .into_iter()
+            .find(|(found, _)| found == key)
+            .map(|(_, value)| value))
+    }
+
+    /// Every record in key order, a block at a time.
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(String, String)>> + '_ {
+        (0..self.blocks.len()).flat_map(move |block| {
+            let records: Box<dyn Iterator<Item = _>> = match self.read_block(block) {
+                Ok(records) => Box::new(records.into_iter().map(Ok)),
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            };
+            records
+        })
+    }
+
+    /// Number of blocks, which the sidecar index has a line for each.
+    pub fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn read_block(&self, block: usize) -> io::Result<Vec<(String, String)>> {
+        let (_, offset, len) = &self.blocks[block];
+        let mut bytes = vec![0; *len];
+        Self::read_exact_at(self.file.as_ref(), *offset, &mut bytes)?;
+        let corrupted = || invalid(&self.path, &format!("block {} is corrupted", block));
+        if bytes.len() < 8 {
+            return Err(corrupted());
+        }
+        let (encoded, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(encoded).to_le_bytes() != checksum {
+            return Err(corrupted());
+        }
+        let mut at = 0;
+        let mut take = |len: usize| -> io::Result<&[u8]> {
+            let taken = encoded.get(at..at + len).ok_or_else(corrupted)?;
+            at += len;
+            Ok(taken)
+        };
+        let records = Self::le_u32(take(4)?);
+        let mut key: Vec<u8> = vec![];
+        let mut decoded = Vec::with_capacity(records.min(encoded.len()));
+        for _ in 0..records {
+            let shared = Self::le_u32(take(4)?);
+            let suffix_len = Self::le_u32(take(4)?);
+            if shared > key.len() || (shared > 0 && !self.front_coded) {
+                return Err(corrupted());
+            }
+            key.truncate(shared);
+            key.extend_from_slice(take(suffix_len)?);
+            let value_len = Self::le_u32(take(4)?);
+            let value = take(value_len)?.to_vec();
+            decoded.push((
+                String::from_utf8(key.clone()).map_err(|_| corrupted())?,
+                String::from_utf8(value).map_err(|_| corrupted())?,
+            ));
+        }
+        Ok(decoded)
+    }
+
+    fn le_u32(bytes: &[u8]) -> usize {
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+    }
+
+    fn read_exact_at(file: &dyn FileHandle, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read_at(offset + read as u64, &mut buf[read..])? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "a snapshot ends early",
+                    ))
+                }
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use std::{error::Error, fmt, io, time::Instant};
+
+/// The error of a read given up on as its deadline passed before it was done.
+/// It converts into an `io::Error` of `TimedOut`, which wraps it.
+#[derive(Debug)]
+pub struct Timeout;
+
+impl Timeout {
+    pub fn is_timeout(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<Timeout>())
+    }
+
+    // Err(Timeout) once the deadline has passed
+    pub(crate) fn check(deadline: Instant) -> Result<(), Timeout> {
+        if Instant::now() >= deadline {
+            Err(Timeout)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline passed")
+    }
+}
+
+impl Error for Timeout {}
+
+impl From<Timeout> for io::Error {
+    fn from(timeout: Timeout) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, timeout)
+    }
+}